@@ -0,0 +1,224 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module reads manual adjustments, i.e. events the records of Kraken lack,
+from a CSV file (`--adjustments`). They are merged into the trades before the
+lots are matched, so the holdings reflect what happened outside of Kraken:
+
+- `airdrop`, `fork` and `gift`: An amount received, at the given cost or at
+  no cost.
+- `transfer-in`: An amount deposited from elsewhere at the cost it was
+  acquired at.
+- `lost`: An amount lost, e.g. by a lost key or a hack, which disposes of the
+  lots at no proceeds.
+
+The file has the columns `date` (`YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS` in
+UTC), `asset` (e.g. XXBT, XBT or BTC), `type` and `amount`, and optionally
+`cost` (the total cost in the quote currency), `id` and `note`.
+*/
+
+use crate::kraken::Trade;
+use crate::precision::{AssetPair, Precision};
+use chrono::{NaiveDate, NaiveDateTime};
+use std::path::Path;
+
+/// The type of a manual adjustment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdjustmentType {
+    Airdrop,
+    Fork,
+    Gift,
+    TransferIn,
+    Lost,
+}
+
+impl AdjustmentType {
+    /// Parses a type like `airdrop` or `transfer-in`.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "airdrop" => Ok(AdjustmentType::Airdrop),
+            "fork" => Ok(AdjustmentType::Fork),
+            "gift" => Ok(AdjustmentType::Gift),
+            "transfer-in" => Ok(AdjustmentType::TransferIn),
+            "lost" => Ok(AdjustmentType::Lost),
+            _ => Err(format!(
+                "Unknown adjustment type '{}', expected airdrop, fork, gift, transfer-in or lost!",
+                name
+            )),
+        }
+    }
+
+    /// Returns the name of the type.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AdjustmentType::Airdrop => "airdrop",
+            AdjustmentType::Fork => "fork",
+            AdjustmentType::Gift => "gift",
+            AdjustmentType::TransferIn => "transfer-in",
+            AdjustmentType::Lost => "lost",
+        }
+    }
+}
+
+/// A manual event that changes the holdings of an asset.
+#[derive(Debug, Clone)]
+pub struct Adjustment {
+    /// The id of the adjustment, by default derived from its line.
+    pub id: String,
+    pub time: f64,
+    /// The asset as written in the file.
+    pub asset: String,
+    pub adjustment_type: AdjustmentType,
+    /// The amount received or lost.
+    pub amount: f64,
+    /// The total cost in the quote currency of an amount received.
+    pub cost: f64,
+}
+
+impl Adjustment {
+    /// Returns whether the adjustment refers to the base asset of a trading
+    /// pair by its name (e.g., XXBT), its code (e.g., XBT) or its common code
+    /// (e.g., BTC).
+    pub fn applies_to(&self, asset_pair: &AssetPair) -> bool {
+        let asset: String = self.asset.to_uppercase();
+        asset == asset_pair.base
+            || asset == asset_pair.base_code()
+            || asset_pair.wsname.split('/').next() == Some(asset.as_str())
+    }
+
+    /// Returns the adjustment as trade. Amounts received are trades of the
+    /// side `credit`, which the FIFO lot engine adds to the holdings at their
+    /// cost, and amounts lost are trades of the side `lost`, which dispose of
+    /// the lots at no proceeds.
+    ///
+    /// # Arguments
+    ///
+    /// * `pair` - The trading pair.
+    /// * `precision` - The precision of the base and quote currency.
+    ///
+    pub fn trade(&self, pair: &str, precision: &Precision) -> Trade {
+        let lost: bool = self.adjustment_type == AdjustmentType::Lost;
+        let amount: f64 = precision.base(self.amount);
+        let cost: f64 = if lost {
+            0f64
+        } else {
+            precision.quote(self.cost)
+        };
+        Trade {
+            txid: self.id.clone(),
+            ordertxid: self.id.clone(),
+            pair: pair.to_string(),
+            time: self.time,
+            side: if lost { "lost" } else { "credit" }.to_string(),
+            price: (cost / amount).to_string(),
+            fee: "0".to_string(),
+            vol: amount.to_string(),
+            cost: cost.to_string(),
+            ordertype: self.adjustment_type.name().to_string(),
+            margin: String::new(),
+            misc: String::new(),
+            postxid: String::new(),
+            maker: None,
+            base_fee: None,
+            source: None,
+        }
+    }
+}
+
+/// Parses the date of an adjustment as UNIX timestamp.
+fn parse_time(value: &str) -> Option<f64> {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .map(|time| time.and_utc().timestamp() as f64)
+}
+
+/// Reads the manual adjustments.
+///
+/// # Arguments
+///
+/// * `path` - The CSV file with the columns `date`, `asset`, `type`,
+///   `amount` and optionally `cost`, `id` and `note`.
+///
+/// # Returns
+///
+/// The adjustments sorted by time.
+///
+pub fn read_adjustments(path: &Path) -> Result<Vec<Adjustment>, String> {
+    let content: String = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or_else(|| format!("{} is empty!", path.display()))?
+        .1
+        .split(',')
+        .map(str::trim)
+        .collect();
+    let column = |name: &str| header.iter().position(|column| *column == name);
+    let required: Vec<usize> = ["date", "asset", "type", "amount"]
+        .into_iter()
+        .map(column)
+        .collect::<Option<Vec<usize>>>()
+        .ok_or_else(|| {
+            format!(
+                "{} lacks the columns date, asset, type and amount!",
+                path.display()
+            )
+        })?;
+    let (cost_column, id_column): (Option<usize>, Option<usize>) = (column("cost"), column("id"));
+
+    let mut adjustments: Vec<Adjustment> = Vec::new();
+    for (number, line) in lines {
+        let values: Vec<&str> = line.split(',').map(str::trim).collect();
+        let invalid = || format!("Invalid line {} in {}: {}", number, path.display(), line);
+        let field = |column: usize| {
+            values
+                .get(column)
+                .copied()
+                .filter(|value| !value.is_empty())
+        };
+        let optional = |column: Option<usize>| column.and_then(field);
+        let time: f64 = field(required[0])
+            .and_then(parse_time)
+            .ok_or_else(invalid)?;
+        let asset: &str = field(required[1]).ok_or_else(invalid)?;
+        let adjustment_type: AdjustmentType =
+            AdjustmentType::parse(field(required[2]).ok_or_else(invalid)?)
+                .map_err(|e| format!("{} ({})", invalid(), e))?;
+        let amount: f64 = field(required[3])
+            .and_then(|amount| amount.parse().ok())
+            .filter(|amount: &f64| *amount > 0f64)
+            .ok_or_else(invalid)?;
+        let cost: f64 = match optional(cost_column) {
+            Some(cost) => cost
+                .parse()
+                .ok()
+                .filter(|cost: &f64| *cost >= 0f64)
+                .ok_or_else(invalid)?,
+            None => 0f64,
+        };
+        adjustments.push(Adjustment {
+            id: optional(id_column)
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("adjustment-{}", number)),
+            time,
+            asset: asset.to_string(),
+            adjustment_type,
+            amount,
+            cost,
+        });
+    }
+    adjustments.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    Ok(adjustments)
+}
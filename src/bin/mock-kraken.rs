@@ -0,0 +1,975 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This binary is a mock of the Kraken REST API to run end-to-end scenarios of
+the calculator without an account, e.g. in CI, by passing
+`--api-url http://127.0.0.1:8765`. It is only built with the `mock-kraken`
+feature:
+
+    cargo run --features mock-kraken --bin mock-kraken -- --trades 300
+
+The served history is either synthetic, generated from a seed so every run
+serves the same trades, or recorded: a directory of responses named after
+their endpoint (e.g. `TradesHistory.json`, `Ticker.json`). The entries of
+recorded TradesHistory, ClosedOrders and Ledgers responses are paginated like
+the live API, all other recorded responses are served as they are.
+
+Private requests are neither authenticated nor signed, but they are rate
+limited like an account of the given tier, and `--reject-every` rejects every
+n-th private request in addition to test the retries of the client.
+`--fail-at` answers the first request of a page at an offset with an HTTP
+error, and `--drop-at` closes the connection instead, to test how the client
+copes with a failed page.
+
+The Futures REST API is mocked at the same address, e.g. for
+`futures --futures-url http://127.0.0.1:8765`: the fills at
+`/derivatives/api/v3/fills` and the account log at
+`/api/history/v3/account-log`, synthetic or recorded in `fills.json` and
+`account-log.json`. Its requests are not authenticated either.
+
+The WebSocket API is mocked at `ws://127.0.0.1:8765/ws`: a subscription to the
+`ownTrades` channel receives the fills of `ownTrades` (synthetic, or recorded
+in `ownTrades.json` as an array of updates), one per second, and heartbeats
+afterwards.
+*/
+
+use base64::{engine::general_purpose, Engine as _};
+use clap::{Arg, ArgMatches, Command};
+use serde_json::{json, Map, Value};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The first day of the synthetic history, 2024-01-01.
+const START: f64 = 1704067200f64;
+
+/// The paginated private endpoints, their result key and the field holding the
+/// time of an entry.
+const PAGINATED: [(&str, &str, &str); 3] = [
+    ("TradesHistory", "trades", "time"),
+    ("ClosedOrders", "closed", "closetm"),
+    ("Ledgers", "ledger", "time"),
+];
+
+/// A deterministic pseudo-random number generator, so the synthetic history
+/// only depends on the seed.
+struct Random(u64);
+
+impl Random {
+    /// Returns a number in the range [0, 1).
+    fn next(&mut self) -> f64 {
+        // The constants of Knuth's MMIX linear congruential generator.
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns a number in the range [from, to).
+    fn between(&mut self, from: f64, to: f64) -> f64 {
+        from + self.next() * (to - from)
+    }
+}
+
+/// The API counter of the mocked account, see `rate_limit.rs` of the
+/// calculator.
+struct Counter {
+    value: f64,
+    max: f64,
+    decay: f64,
+    updated: Instant,
+}
+
+impl Counter {
+    /// Returns whether a request of the given cost is accepted, adding the
+    /// cost to the counter if so.
+    fn accept(&mut self, cost: f64) -> bool {
+        let now: Instant = Instant::now();
+        self.value =
+            (self.value - now.duration_since(self.updated).as_secs_f64() * self.decay).max(0f64);
+        self.updated = now;
+        if self.value + cost > self.max {
+            return false;
+        }
+        self.value += cost;
+        true
+    }
+}
+
+/// The state shared by the connections.
+struct Server {
+    /// The responses of the endpoints by their name, e.g. `Ticker`. The
+    /// paginated endpoints hold the entries by id.
+    responses: HashMap<String, Value>,
+    page_size: usize,
+    /// The offset beyond which pages are rejected, as Kraken does for long
+    /// histories.
+    max_offset: Option<usize>,
+    reject_every: Option<usize>,
+    /// The offset whose page fails once, if any.
+    fail_at: Mutex<Option<usize>>,
+    /// The offset whose page is dropped once, if any.
+    drop_at: Mutex<Option<usize>>,
+    private_requests: Mutex<usize>,
+    counter: Mutex<Counter>,
+}
+
+impl Server {
+    /// Returns the response to a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the endpoint, e.g. `/0/public/Ticker`.
+    /// * `params` - The query or form parameters.
+    ///
+    fn respond(&self, path: &str, params: &HashMap<String, String>) -> Value {
+        let endpoint: &str = path.rsplit('/').next().unwrap_or_default();
+        match path {
+            "/derivatives/api/v3/fills" => return self.fills(params),
+            "/api/history/v3/account-log" => return self.account_log(params),
+            _ => {}
+        }
+        if path.starts_with("/0/private/") {
+            let requests: usize = {
+                let mut requests = self.private_requests.lock().unwrap();
+                *requests += 1;
+                *requests
+            };
+            let cost: f64 = match endpoint {
+                "TradesHistory" | "QueryTrades" | "Ledgers" | "QueryLedgers" => 2f64,
+                _ => 1f64,
+            };
+            let rejected: bool = self
+                .reject_every
+                .is_some_and(|every| requests.is_multiple_of(every));
+            if rejected || !self.counter.lock().unwrap().accept(cost) {
+                return error("EAPI:Rate limit exceeded");
+            }
+            if !params.contains_key("nonce") {
+                return error("EAPI:Invalid nonce");
+            }
+        } else if !path.starts_with("/0/public/") {
+            return error("EGeneral:Unknown method");
+        }
+
+        match PAGINATED.iter().find(|(name, _, _)| *name == endpoint) {
+            Some((_, key, time)) => self.page(endpoint, key, time, params),
+            None => match (endpoint, self.responses.get(endpoint)) {
+                ("AssetPairs", Some(pairs)) => filter_pairs(pairs, params.get("pair")),
+                (_, Some(response)) => response.clone(),
+                _ => error("EGeneral:Unknown method"),
+            },
+        }
+    }
+
+    /// Returns a page of a paginated endpoint, newest entries first.
+    fn page(
+        &self,
+        endpoint: &str,
+        key: &str,
+        time: &str,
+        params: &HashMap<String, String>,
+    ) -> Value {
+        let offset: usize = param(params, "ofs").unwrap_or(0f64) as usize;
+        if self
+            .max_offset
+            .is_some_and(|max_offset| offset >= max_offset)
+        {
+            return error("EGeneral:Invalid arguments");
+        }
+        let start: f64 = param(params, "start").unwrap_or(f64::MIN);
+        let end: f64 = param(params, "end").unwrap_or(f64::MAX);
+        let empty: Map<String, Value> = Map::new();
+        let entries: &Map<String, Value> = self
+            .responses
+            .get(endpoint)
+            .and_then(Value::as_object)
+            .unwrap_or(&empty);
+        let mut selected: Vec<(&String, &Value, f64)> = entries
+            .iter()
+            .map(|(id, entry)| (id, entry, entry[time].as_f64().unwrap_or_default()))
+            .filter(|(_, entry, time)| {
+                // The ledger entries can be selected by asset and type.
+                let selected = |field: &str| match (params.get(field), entry[field].as_str()) {
+                    (Some(wanted), Some(value)) if endpoint == "Ledgers" => {
+                        wanted == "all" || wanted.split(',').any(|wanted| wanted == value)
+                    }
+                    _ => true,
+                };
+                *time >= start && *time <= end && selected("asset") && selected("type")
+            })
+            .collect();
+        selected.sort_by(|a, b| b.2.total_cmp(&a.2));
+        let count: usize = selected.len();
+        let page: Map<String, Value> = selected
+            .into_iter()
+            .skip(offset)
+            .take(self.page_size)
+            .map(|(id, entry, _)| (id.clone(), entry.clone()))
+            .collect();
+        json!({"error": [], "result": {key: page, "count": count}})
+    }
+
+    /// Returns the fills of Kraken Futures up to `lastFillTime`, newest first.
+    fn fills(&self, params: &HashMap<String, String>) -> Value {
+        let last: f64 = params
+            .get("lastFillTime")
+            .map(|time| iso_timestamp(time))
+            .unwrap_or(f64::MAX);
+        let mut fills: Vec<&Value> = self.responses.get("fills").map_or(Vec::new(), |fills| {
+            fills["fills"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|fill| iso_timestamp(fill["fillTime"].as_str().unwrap_or_default()) <= last)
+                .collect()
+        });
+        fills.sort_by(|a, b| b["fillTime"].as_str().cmp(&a["fillTime"].as_str()));
+        fills.truncate(100);
+        json!({"result": "success", "fills": fills, "serverTime": "2025-01-01T00:00:00.000Z"})
+    }
+
+    /// Returns the entries of the account log of Kraken Futures.
+    fn account_log(&self, params: &HashMap<String, String>) -> Value {
+        let since: f64 = param(params, "since").unwrap_or(f64::MIN) / 1000f64;
+        let before: f64 = param(params, "before").unwrap_or(f64::MAX) / 1000f64;
+        let from: f64 = param(params, "from").unwrap_or(f64::MIN);
+        let count: usize = param(params, "count").unwrap_or(500f64) as usize;
+        let mut logs: Vec<&Value> = self.responses.get("account-log").map_or(Vec::new(), |log| {
+            log["logs"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|entry| {
+                    let time: f64 = iso_timestamp(entry["date"].as_str().unwrap_or_default());
+                    time >= since
+                        && time < before
+                        && entry["id"].as_f64().unwrap_or_default() >= from
+                })
+                .collect()
+        });
+        logs.sort_by(|a, b| a["id"].as_u64().cmp(&b["id"].as_u64()));
+        if params.get("sort").map(String::as_str) == Some("desc") {
+            logs.reverse();
+        }
+        logs.truncate(count);
+        json!({"accountUid": "MOCK", "logs": logs})
+    }
+}
+
+/// Parses a timestamp of the Futures API as UNIX timestamp.
+fn iso_timestamp(value: &str) -> f64 {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|time| time.timestamp_millis() as f64 / 1000f64)
+        .unwrap_or_default()
+}
+
+/// Formats a UNIX timestamp as timestamp of the Futures API.
+fn iso_time(time: f64) -> String {
+    chrono::DateTime::from_timestamp_millis((time * 1000f64) as i64)
+        .unwrap_or_default()
+        .to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+/// Generates the fills and the account log of Kraken Futures: a linear and an
+/// inverse perpetual contract of XBT, with the fees of the fills and a
+/// funding payment every day a position is open.
+fn synthetic_futures(random: &mut Random, seed: u64) -> (Value, Value) {
+    let mut fills: Vec<Value> = Vec::new();
+    let mut logs: Vec<Value> = Vec::new();
+    let mut price: f64 = 42000f64;
+    let mut positions: [f64; 2] = [0f64; 2];
+    let mut time: f64 = START;
+    let mut day: i64 = (START / 86400f64) as i64;
+    for index in 0..150 {
+        time += random.between(0.1, 1.5) * 86400f64;
+        price = (price * random.between(0.97, 1.035)).max(1000f64);
+        // The funding of the open positions of the days passed.
+        while day < (time / 86400f64) as i64 {
+            day += 1;
+            for (contract, position) in ["pf_xbtusd", "pi_xbtusd"].iter().zip(positions) {
+                if position == 0f64 {
+                    continue;
+                }
+                let rate: f64 = random.between(-0.0002, 0.0004);
+                let notional: f64 = if *contract == "pi_xbtusd" {
+                    position / price
+                } else {
+                    position * price
+                };
+                logs.push(json!({
+                    "id": logs.len() + 1,
+                    "date": iso_time(day as f64 * 86400f64),
+                    "asset": if *contract == "pi_xbtusd" { "xbt" } else { "usd" },
+                    "info": "funding rate change",
+                    "contract": contract,
+                    "funding_rate": rate,
+                    "realized_funding": -notional * rate,
+                    "fee": null,
+                }));
+            }
+        }
+        let inverse: bool = index % 3 == 0;
+        let (symbol, size): (&str, f64) = if inverse {
+            ("PI_XBTUSD", (random.between(1f64, 20f64).round() * 100f64))
+        } else {
+            (
+                "PF_XBTUSD",
+                (random.between(0.001, 0.05) * 10000f64).round() / 10000f64,
+            )
+        };
+        let position: &mut f64 = &mut positions[inverse as usize];
+        // The positions are mostly long, but also short at times.
+        let bias: f64 = match *position {
+            position if position > 0f64 => 0.1,
+            position if position < 0f64 => -0.1,
+            _ => 0f64,
+        };
+        let side: &str = if random.next() < 0.45 + bias {
+            "sell"
+        } else {
+            "buy"
+        };
+        *position += if side == "sell" { -size } else { size };
+        let fill_price: f64 = (price * 2f64).round() / 2f64;
+        let fee: f64 = 0.0005
+            * if inverse {
+                size / fill_price
+            } else {
+                size * fill_price
+            };
+        fills.push(json!({
+            "fill_id": format!("F{:05}-MOCK-{:06}", index, seed % 1000000),
+            "symbol": symbol,
+            "side": side,
+            "order_id": format!("FO{:05}-MOCK-{:06}", index, seed % 1000000),
+            "size": size,
+            "price": fill_price,
+            "fillTime": iso_time(time),
+            "fillType": if random.next() < 0.6 { "maker" } else { "taker" },
+        }));
+        logs.push(json!({
+            "id": logs.len() + 1,
+            "date": iso_time(time),
+            "asset": if inverse { "xbt" } else { "usd" },
+            "info": "futures trade",
+            "contract": symbol.to_lowercase(),
+            "trade_price": fill_price,
+            "fee": fee,
+            "realized_funding": null,
+        }));
+    }
+    (
+        json!({"result": "success", "fills": fills}),
+        json!({"accountUid": "MOCK", "logs": logs}),
+    )
+}
+
+/// Returns a response with an error.
+fn error(message: &str) -> Value {
+    json!({"error": [message]})
+}
+
+/// Returns a parameter as number.
+fn param(params: &HashMap<String, String>, name: &str) -> Option<f64> {
+    params.get(name).and_then(|value| value.parse().ok())
+}
+
+/// Returns the asset pairs matching the requested name, alternative name or
+/// websocket name.
+fn filter_pairs(pairs: &Value, requested: Option<&String>) -> Value {
+    let Some(requested) = requested else {
+        return pairs.clone();
+    };
+    let result: Map<String, Value> = pairs["result"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(name, pair)| {
+            requested.split(',').any(|requested| {
+                requested == name.as_str()
+                    || Some(requested) == pair["altname"].as_str()
+                    || Some(requested) == pair["wsname"].as_str()
+            })
+        })
+        .map(|(name, pair)| (name.clone(), pair.clone()))
+        .collect();
+    if result.is_empty() {
+        error("EQuery:Unknown asset pair")
+    } else {
+        json!({"error": [], "result": result})
+    }
+}
+
+/// Generates the responses of a synthetic history of XXBTZEUR.
+///
+/// # Arguments
+///
+/// * `trades` - The number of trades.
+/// * `seed` - The seed of the random number generator.
+///
+fn synthetic(trades: usize, seed: u64) -> HashMap<String, Value> {
+    let mut random: Random = Random(seed);
+    let mut history: Map<String, Value> = Map::new();
+    let mut orders: Map<String, Value> = Map::new();
+    let mut ledger: Map<String, Value> = Map::new();
+    let mut price: f64 = 40000f64;
+    let mut balance: f64 = 0f64;
+    let mut time: f64 = START;
+    for index in 0..trades {
+        time += random.between(0.5, 5f64).round() * 86400f64 + random.between(0f64, 86400f64);
+        price = (price * random.between(0.96, 1.045)).max(1000f64);
+        let vol: f64 = random.between(0.001, 0.02);
+        // Every third trade is a sell, unless there is nothing to sell.
+        let side: &str = if index % 3 == 2 && balance >= vol {
+            "sell"
+        } else {
+            "buy"
+        };
+        let cost: f64 = vol * price;
+        let maker: bool = random.next() < 0.6;
+        let fee: f64 = cost * if maker { 0.0016 } else { 0.0026 };
+        let txid: String = format!("T{:05}-MOCK-{:06}", index, seed % 1000000);
+        let ordertxid: String = format!("O{:05}-MOCK-{:06}", index, seed % 1000000);
+        let ordertype: &str = if maker { "limit" } else { "market" };
+        history.insert(
+            txid.clone(),
+            json!({
+                "ordertxid": ordertxid,
+                "postxid": "TKH2SE-M7IF5-CFI7LT",
+                "pair": "XXBTZEUR",
+                "time": time,
+                "type": side,
+                "ordertype": ordertype,
+                "price": format!("{:.1}", price),
+                "cost": format!("{:.5}", cost),
+                "fee": format!("{:.5}", fee),
+                "vol": format!("{:.8}", vol),
+                "margin": "0.00000",
+                "leverage": "0",
+                "misc": "",
+                "maker": maker,
+            }),
+        );
+        const USERREFS: [i32; 3] = [0, 1234, 5678];
+        // The limit orders are filled at a slightly better price than placed,
+        // while market orders state no price.
+        let order_price: f64 = match (ordertype, side) {
+            ("limit", "buy") => price * 1.0005,
+            ("limit", _) => price * 0.9995,
+            _ => 0f64,
+        };
+        let oflags: &str = if maker && random.next() < 0.5 {
+            "post,fciq"
+        } else {
+            "fciq"
+        };
+        orders.insert(
+            ordertxid.clone(),
+            json!({
+                "refid": null,
+                "userref": USERREFS[index % USERREFS.len()],
+                "cl_ord_id": format!("grid-{}", index % 4),
+                "status": "closed",
+                "opentm": time - 60f64,
+                "closetm": time,
+                "starttm": 0,
+                "expiretm": 0,
+                "descr": {
+                    "pair": "XBTEUR",
+                    "type": side,
+                    "ordertype": ordertype,
+                    "price": format!("{:.1}", order_price),
+                    "price2": "0",
+                    "leverage": "none",
+                    "order": format!("{} {:.8} XBTEUR @ {} {:.1}", side, vol, ordertype, order_price),
+                    "close": "",
+                },
+                "vol": format!("{:.8}", vol),
+                "vol_exec": format!("{:.8}", vol),
+                "cost": format!("{:.5}", cost),
+                "fee": format!("{:.5}", fee),
+                "price": format!("{:.1}", price),
+                "misc": "",
+                "oflags": oflags,
+            }),
+        );
+        let amount: f64 = if side == "sell" { -vol } else { vol };
+        balance += amount;
+        ledger.insert(
+            format!("L{:05}-MOCK-{:06}", index, seed % 1000000),
+            json!({
+                "refid": txid,
+                "time": time,
+                "type": "trade",
+                "subtype": "",
+                "aclass": "currency",
+                "asset": "XXBT",
+                "amount": format!("{:.10}", amount),
+                "fee": "0.0000000000",
+                "balance": format!("{:.10}", balance),
+            }),
+        );
+    }
+
+    // The fills pushed by the WebSocket API after the history.
+    let fills: Vec<Value> = (0..3)
+        .map(|index| {
+            price *= random.between(0.99, 1.01);
+            let vol: f64 = random.between(0.001, 0.005);
+            let side: &str = if index % 2 == 0 && balance >= vol {
+                "sell"
+            } else {
+                "buy"
+            };
+            balance += if side == "sell" { -vol } else { vol };
+            time += 3600f64;
+            json!([
+                [{format!("TW{:04}-MOCK-{:06}", index, seed % 1000000): {
+                    "ordertxid": format!("OW{:04}-MOCK-{:06}", index, seed % 1000000),
+                    "postxid": "TKH2SE-M7IF5-CFI7LT",
+                    "pair": "XBT/EUR",
+                    "time": format!("{:.6}", time),
+                    "type": side,
+                    "ordertype": "limit",
+                    "price": format!("{:.1}", price),
+                    "cost": format!("{:.5}", vol * price),
+                    "fee": format!("{:.5}", vol * price * 0.0016),
+                    "vol": format!("{:.8}", vol),
+                    "margin": "0.00000",
+                    "userref": 0,
+                }}],
+                "ownTrades",
+                {"sequence": index + 1},
+            ])
+        })
+        .collect();
+
+    // The daily candles of the most recent 720 days up to the last trade.
+    let last_day: i64 = (time / 86400f64) as i64 * 86400;
+    let candles: Vec<Value> = (0..720)
+        .rev()
+        .map(|day| {
+            let close: f64 = price * (1f64 + (day as f64 / 30f64).sin() * 0.05);
+            json!([
+                last_day - day * 86400,
+                format!("{:.1}", close),
+                format!("{:.1}", close * 1.01),
+                format!("{:.1}", close * 0.99),
+                format!("{:.1}", close),
+                format!("{:.1}", close),
+                "10.00000000",
+                100
+            ])
+        })
+        .collect();
+
+    let (futures_fills, futures_log): (Value, Value) = synthetic_futures(&mut random, seed);
+
+    HashMap::from([
+        ("fills".to_string(), futures_fills),
+        ("account-log".to_string(), futures_log),
+        ("TradesHistory".to_string(), Value::Object(history)),
+        ("ClosedOrders".to_string(), Value::Object(orders)),
+        ("Ledgers".to_string(), Value::Object(ledger)),
+        ("ownTrades".to_string(), Value::Array(fills)),
+        (
+            "GetWebSocketsToken".to_string(),
+            json!({"error": [], "result": {"token": "MOCK-TOKEN", "expires": 900}}),
+        ),
+        (
+            "Balance".to_string(),
+            json!({"error": [], "result": {"XXBT": format!("{:.10}", balance), "ZEUR": "1000.0000"}}),
+        ),
+        (
+            "Ticker".to_string(),
+            json!({"error": [], "result": {"XXBTZEUR": {"c": [format!("{:.1}", price), "0.01000000"]}}}),
+        ),
+        (
+            "OHLC".to_string(),
+            json!({"error": [], "result": {"XXBTZEUR": candles, "last": last_day}}),
+        ),
+        (
+            "AssetPairs".to_string(),
+            json!({"error": [], "result": {"XXBTZEUR": {
+                "altname": "XBTEUR",
+                "wsname": "XBT/EUR",
+                "base": "XXBT",
+                "quote": "ZEUR",
+                "pair_decimals": 1,
+                "lot_decimals": 8,
+                "cost_decimals": 5,
+            }}}),
+        ),
+        (
+            "Assets".to_string(),
+            json!({"error": [], "result": {
+                "XXBT": {"aclass": "currency", "altname": "XBT", "decimals": 10, "display_decimals": 5},
+                "ZEUR": {"aclass": "currency", "altname": "EUR", "decimals": 4, "display_decimals": 2},
+            }}),
+        ),
+    ])
+}
+
+/// Reads the recorded responses of a directory, one `<Endpoint>.json` file per
+/// endpoint.
+fn recorded(directory: &PathBuf) -> Result<HashMap<String, Value>, String> {
+    let mut responses: HashMap<String, Value> = HashMap::new();
+    let files = fs::read_dir(directory)
+        .map_err(|e| format!("Failed to read {}: {}", directory.display(), e))?;
+    for file in files.flatten() {
+        let path: PathBuf = file.path();
+        let Some(endpoint) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".json"))
+        else {
+            continue;
+        };
+        let response: Value = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        // The entries of the paginated endpoints are paginated anew.
+        let response: Value = match PAGINATED.iter().find(|(name, _, _)| *name == endpoint) {
+            Some((_, key, _)) if response["result"][key].is_object() => {
+                response["result"][key].clone()
+            }
+            Some((_, key, _)) => {
+                return Err(format!("{} has no result.{} object!", path.display(), key))
+            }
+            None => response,
+        };
+        responses.insert(endpoint.to_string(), response);
+    }
+    Ok(responses)
+}
+
+/// Serves the requests of a connection until the client closes it.
+fn handle(stream: TcpStream, server: &Server) -> std::io::Result<()> {
+    let mut reader: BufReader<TcpStream> = BufReader::new(stream.try_clone()?);
+    let mut stream: TcpStream = stream;
+    loop {
+        let mut request_line: String = String::new();
+        if reader.read_line(&mut request_line)? == 0 {
+            return Ok(());
+        }
+        let mut content_length: usize = 0;
+        let mut websocket_key: Option<String> = None;
+        loop {
+            let mut header: String = String::new();
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                } else if name.eq_ignore_ascii_case("sec-websocket-key") {
+                    websocket_key = Some(value.trim().to_string());
+                }
+            }
+        }
+        if let Some(key) = websocket_key {
+            eprintln!("GET /ws -> WebSocket");
+            return serve_websocket(reader, stream, &key, server);
+        }
+        let mut body: Vec<u8> = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let target: &str = request_line.split_whitespace().nth(1).unwrap_or("/");
+        let (path, query): (&str, &str) = target.split_once('?').unwrap_or((target, ""));
+        let mut params: HashMap<String, String> =
+            serde_urlencoded::from_str(query).unwrap_or_default();
+        params.extend(
+            serde_urlencoded::from_bytes::<HashMap<String, String>>(&body).unwrap_or_default(),
+        );
+        let offset: Option<usize> = params.get("ofs").and_then(|ofs| ofs.parse().ok());
+        if offset.is_some()
+            && server
+                .fail_at
+                .lock()
+                .unwrap()
+                .take_if(|at| Some(*at) == offset)
+                .is_some()
+        {
+            eprintln!("POST {} -> 502", path);
+            write!(
+                stream,
+                "HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n"
+            )?;
+            stream.flush()?;
+            continue;
+        }
+        if offset.is_some()
+            && server
+                .drop_at
+                .lock()
+                .unwrap()
+                .take_if(|at| Some(*at) == offset)
+                .is_some()
+        {
+            eprintln!("POST {} -> connection closed", path);
+            return Ok(());
+        }
+        let response: String = server.respond(path, &params).to_string();
+        eprintln!(
+            "{} {} -> {} bytes",
+            request_line.split_whitespace().next().unwrap_or_default(),
+            path,
+            response.len()
+        );
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            response.len(),
+            response
+        )?;
+        stream.flush()?;
+    }
+}
+
+/// Reads a frame of the WebSocket API, returning its opcode and payload.
+fn read_frame(reader: &mut impl Read) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut header: [u8; 2] = [0; 2];
+    reader.read_exact(&mut header)?;
+    let len: u64 = match header[1] & 0x7f {
+        126 => {
+            let mut len: [u8; 2] = [0; 2];
+            reader.read_exact(&mut len)?;
+            u64::from(u16::from_be_bytes(len))
+        }
+        127 => {
+            let mut len: [u8; 8] = [0; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => u64::from(len),
+    };
+    // Clients always mask their frames.
+    let mut mask: [u8; 4] = [0; 4];
+    reader.read_exact(&mut mask)?;
+    let mut payload: Vec<u8> = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+    Ok((header[0] & 0x0f, payload))
+}
+
+/// Sends an unmasked text frame of the WebSocket API.
+fn send_text(stream: &mut TcpStream, message: &Value) -> std::io::Result<()> {
+    let payload: Vec<u8> = message.to_string().into_bytes();
+    let mut frame: Vec<u8> = vec![0x81];
+    if payload.len() < 126 {
+        frame.push(payload.len() as u8);
+    } else {
+        frame.push(126);
+        frame.extend((payload.len() as u16).to_be_bytes());
+    }
+    frame.extend(payload);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+/// Serves a connection upgraded to the WebSocket API until the client closes
+/// it.
+fn serve_websocket(
+    mut reader: BufReader<TcpStream>,
+    mut stream: TcpStream,
+    key: &str,
+    server: &Server,
+) -> std::io::Result<()> {
+    let accept: String = general_purpose::STANDARD.encode(Sha1::digest(
+        format!("{}258EAFA5-E914-47DA-95CA-C5AB0DC85B11", key).as_bytes(),
+    ));
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    )?;
+    send_text(
+        &mut stream,
+        &json!({"event": "systemStatus", "status": "online"}),
+    )?;
+    let (_, payload) = read_frame(&mut reader)?;
+    let request: Value = serde_json::from_slice(&payload).unwrap_or_default();
+    if request["subscription"]["name"] != "ownTrades"
+        || !request["subscription"]["token"].is_string()
+    {
+        return send_text(
+            &mut stream,
+            &json!({"event": "subscriptionStatus", "status": "error", "errorMessage": "EGeneral:Invalid arguments"}),
+        );
+    }
+    send_text(
+        &mut stream,
+        &json!({"event": "subscriptionStatus", "status": "subscribed", "channelName": "ownTrades"}),
+    )?;
+    // The client only closes the connection, so reading it detects that.
+    let closed = Arc::new(Mutex::new(false));
+    {
+        let closed = Arc::clone(&closed);
+        thread::spawn(move || {
+            while matches!(read_frame(&mut reader), Ok((opcode, _)) if opcode != 0x8) {}
+            *closed.lock().unwrap() = true;
+        });
+    }
+    let empty: Vec<Value> = Vec::new();
+    let fills: &Vec<Value> = server
+        .responses
+        .get("ownTrades")
+        .and_then(Value::as_array)
+        .unwrap_or(&empty);
+    let mut fills = fills.iter();
+    while !*closed.lock().unwrap() {
+        thread::sleep(Duration::from_secs(1));
+        match fills.next() {
+            Some(fill) => send_text(&mut stream, fill)?,
+            None => send_text(&mut stream, &json!({"event": "heartbeat"}))?,
+        }
+    }
+    Ok(())
+}
+
+/// Builds the command-line interface.
+fn build_cli() -> Command {
+    Command::new("mock-kraken")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Serve a mock of the Kraken REST API for end-to-end tests")
+        .arg(
+            Arg::new("port")
+                .long("port")
+                .value_name("PORT")
+                .help("The port to listen on")
+                .default_value("8765")
+                .value_parser(clap::value_parser!(u16)),
+        )
+        .arg(
+            Arg::new("fixtures")
+                .long("fixtures")
+                .value_name("DIR")
+                .help("Serve the recorded responses of a directory (e.g. TradesHistory.json) instead of a synthetic history")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("trades")
+                .long("trades")
+                .value_name("COUNT")
+                .help("The number of trades of the synthetic history")
+                .default_value("120")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("The seed of the synthetic history")
+                .default_value("42")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("page-size")
+                .long("page-size")
+                .value_name("COUNT")
+                .help("The number of entries per page")
+                .default_value("50")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("max-offset")
+                .long("max-offset")
+                .value_name("OFFSET")
+                .help("Reject pages beyond this offset, as Kraken does for long histories")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("tier")
+                .long("tier")
+                .value_name("TIER")
+                .help("The account tier whose API counter limits the private requests")
+                .default_value("starter")
+                .value_parser(["starter", "intermediate", "pro"]),
+        )
+        .arg(
+            Arg::new("reject-every")
+                .long("reject-every")
+                .value_name("N")
+                .help("Reject every n-th private request with a rate limit error")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("fail-at")
+                .long("fail-at")
+                .value_name("OFFSET")
+                .help("Fail the first request of the page at this offset with an HTTP error")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("drop-at")
+                .long("drop-at")
+                .value_name("OFFSET")
+                .help("Close the connection on the first request of the page at this offset")
+                .value_parser(clap::value_parser!(usize)),
+        )
+}
+
+fn main() {
+    let matches: ArgMatches = build_cli().get_matches();
+    let responses: HashMap<String, Value> = match matches.get_one::<PathBuf>("fixtures") {
+        Some(directory) => recorded(directory).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }),
+        None => synthetic(
+            *matches.get_one("trades").unwrap(),
+            *matches.get_one("seed").unwrap(),
+        ),
+    };
+    let (max, decay): (f64, f64) = match matches.get_one::<String>("tier").unwrap().as_str() {
+        "intermediate" => (20f64, 0.5),
+        "pro" => (20f64, 1f64),
+        _ => (15f64, 0.33),
+    };
+    let server: Arc<Server> = Arc::new(Server {
+        responses,
+        page_size: *matches.get_one("page-size").unwrap(),
+        max_offset: matches.get_one("max-offset").copied(),
+        reject_every: matches.get_one("reject-every").copied(),
+        fail_at: Mutex::new(matches.get_one("fail-at").copied()),
+        drop_at: Mutex::new(matches.get_one("drop-at").copied()),
+        private_requests: Mutex::new(0),
+        counter: Mutex::new(Counter {
+            value: 0f64,
+            max,
+            decay,
+            updated: Instant::now(),
+        }),
+    });
+
+    let port: u16 = *matches.get_one("port").unwrap();
+    let listener: TcpListener = TcpListener::bind(("127.0.0.1", port)).unwrap_or_else(|e| {
+        eprintln!("Error: Failed to listen on port {}: {}", port, e);
+        std::process::exit(1);
+    });
+    eprintln!(
+        "Serving the mock of the Kraken API at http://127.0.0.1:{}",
+        port
+    );
+    for stream in listener.incoming().flatten() {
+        let server: Arc<Server> = Arc::clone(&server);
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &server) {
+                eprintln!("Connection failed: {}", e);
+            }
+        });
+    }
+}
@@ -0,0 +1,721 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module contains the local cache, a SQLite database that stores the trades
+and ledger entries fetched from the Kraken API, the details of the closed
+orders, the daily prices of the remote
+price sources, the registry of the completed runs and the last nonce used per
+API key, as well as the read-only query console on top of it.
+
+The file is located at `~/.cache/kraken-pnl/cache.sqlite` (or
+`$XDG_CACHE_HOME/kraken-pnl/cache.sqlite`) unless a different path is
+configured.
+*/
+
+use crate::kraken::{ClosedOrder, Trade};
+use crate::ledger::LedgerEntry;
+use crate::runs::Run;
+use chrono::NaiveDate;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OpenFlags};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The schema of the cache. Amounts are stored as text, exactly as returned by
+/// Kraken, so no precision is lost. Only the daily prices, which are parsed by
+/// the price sources, are stored as numbers.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS trades (
+    txid TEXT PRIMARY KEY,
+    ordertxid TEXT NOT NULL,
+    pair TEXT NOT NULL,
+    time REAL NOT NULL,
+    type TEXT NOT NULL,
+    price TEXT NOT NULL,
+    fee TEXT NOT NULL,
+    vol TEXT NOT NULL,
+    cost TEXT NOT NULL,
+    ordertype TEXT NOT NULL,
+    margin TEXT NOT NULL DEFAULT '0',
+    misc TEXT NOT NULL DEFAULT '',
+    postxid TEXT NOT NULL DEFAULT '',
+    maker INTEGER
+);
+-- The trades of a pair are read in the order of time, also row by row.
+CREATE INDEX IF NOT EXISTS trades_by_pair ON trades (pair, time);
+CREATE TABLE IF NOT EXISTS ledger (
+    id TEXT PRIMARY KEY,
+    refid TEXT NOT NULL,
+    asset TEXT NOT NULL,
+    time REAL NOT NULL,
+    type TEXT NOT NULL,
+    subtype TEXT NOT NULL DEFAULT '',
+    amount TEXT NOT NULL,
+    fee TEXT NOT NULL,
+    balance TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS orders (
+    ordertxid TEXT PRIMARY KEY,
+    userref INTEGER,
+    cl_ord_id TEXT,
+    status TEXT NOT NULL DEFAULT '',
+    opentm REAL NOT NULL DEFAULT 0,
+    closetm REAL NOT NULL DEFAULT 0,
+    price TEXT NOT NULL DEFAULT '',
+    price2 TEXT NOT NULL DEFAULT '',
+    avg_price TEXT NOT NULL DEFAULT ''
+);
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at TEXT NOT NULL,
+    account TEXT NOT NULL,
+    arguments TEXT NOT NULL,
+    symbols TEXT NOT NULL,
+    duration REAL NOT NULL,
+    requests INTEGER NOT NULL,
+    api_points REAL NOT NULL,
+    rate_limit_wait REAL NOT NULL,
+    rate_limited INTEGER NOT NULL,
+    outputs TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS prices (
+    pair TEXT NOT NULL,
+    source TEXT NOT NULL,
+    date TEXT NOT NULL,
+    close REAL NOT NULL,
+    PRIMARY KEY (pair, source, date)
+);
+CREATE TABLE IF NOT EXISTS nonces (
+    account TEXT PRIMARY KEY,
+    nonce INTEGER NOT NULL
+);
+";
+
+/// The columns added after the first release, which are added to the tables
+/// of caches created by previous versions.
+const ADDED_COLUMNS: [(&str, &str, &str); 11] = [
+    ("ledger", "subtype", "TEXT NOT NULL DEFAULT ''"),
+    ("trades", "margin", "TEXT NOT NULL DEFAULT '0'"),
+    ("trades", "misc", "TEXT NOT NULL DEFAULT ''"),
+    ("trades", "postxid", "TEXT NOT NULL DEFAULT ''"),
+    ("trades", "maker", "INTEGER"),
+    ("orders", "status", "TEXT NOT NULL DEFAULT ''"),
+    ("orders", "opentm", "REAL NOT NULL DEFAULT 0"),
+    ("orders", "closetm", "REAL NOT NULL DEFAULT 0"),
+    ("orders", "price", "TEXT NOT NULL DEFAULT ''"),
+    ("orders", "price2", "TEXT NOT NULL DEFAULT ''"),
+    ("orders", "avg_price", "TEXT NOT NULL DEFAULT ''"),
+];
+
+/// Returns the default location of the cache.
+pub fn default_cache_path() -> PathBuf {
+    let cache_dir: PathBuf = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .or_else(|| std::env::var_os("USERPROFILE"))
+                .map(|home| PathBuf::from(home).join(".cache"))
+        })
+        .unwrap_or_else(|| PathBuf::from("."));
+    cache_dir.join("kraken-pnl").join("cache.sqlite")
+}
+
+/// The local cache of the fetched data.
+pub struct Cache {
+    connection: Connection,
+}
+
+impl Cache {
+    /// Opens the cache, creating it if it doesn't exist yet.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let connection: Connection = Connection::open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        connection
+            .execute_batch(SCHEMA)
+            .map_err(|e| format!("Failed to create the schema: {}", e))?;
+        for (table, column, definition) in ADDED_COLUMNS {
+            let exists: bool = connection
+                .prepare("SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2")
+                .and_then(|mut statement| statement.exists(params![table, column]))
+                .map_err(|e| format!("Failed to inspect the schema: {}", e))?;
+            if !exists {
+                connection
+                    .execute_batch(&format!(
+                        "ALTER TABLE {} ADD COLUMN {} {}",
+                        table, column, definition
+                    ))
+                    .map_err(|e| format!("Failed to migrate the schema: {}", e))?;
+            }
+        }
+        Ok(Self { connection })
+    }
+
+    /// Stores the trades, replacing those that are already cached.
+    pub fn store_trades(&mut self, trades: &[Trade]) -> Result<(), rusqlite::Error> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT OR REPLACE INTO trades
+                 (txid, ordertxid, pair, time, type, price, fee, vol, cost, ordertype,
+                  margin, misc, postxid, maker)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            )?;
+            for trade in trades {
+                statement.execute(params![
+                    trade.txid,
+                    trade.ordertxid,
+                    trade.pair,
+                    trade.time,
+                    trade.side,
+                    trade.price,
+                    trade.fee,
+                    trade.vol,
+                    trade.cost,
+                    trade.ordertype,
+                    trade.margin,
+                    trade.misc,
+                    trade.postxid,
+                    trade.maker,
+                ])?;
+            }
+        }
+        transaction.commit()
+    }
+
+    /// Stores the ledger entries of an asset, replacing those that are already
+    /// cached.
+    pub fn store_ledger_entries(
+        &mut self,
+        asset: &str,
+        entries: &[LedgerEntry],
+    ) -> Result<(), rusqlite::Error> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT OR REPLACE INTO ledger
+                 (id, refid, asset, time, type, subtype, amount, fee, balance)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            for entry in entries {
+                statement.execute(params![
+                    entry.id,
+                    entry.refid,
+                    asset,
+                    entry.time,
+                    entry.entry_type,
+                    entry.subtype,
+                    entry.amount,
+                    entry.fee,
+                    entry.balance,
+                ])?;
+            }
+        }
+        transaction.commit()
+    }
+}
+
+/// The last nonce used per API key, shared by all runs using the cache, so
+/// the nonces keep increasing even if runs overlap or the clock goes back.
+pub struct NonceStore {
+    connection: Connection,
+    account: String,
+}
+
+impl NonceStore {
+    /// Opens the nonces of an account in the cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the cache.
+    /// * `account` - The account identifier.
+    ///
+    pub fn open(path: &Path, account: &str) -> Result<Self, String> {
+        let Cache { connection } = Cache::open(path)?;
+        // Runs in parallel wait for each other instead of failing.
+        connection
+            .busy_timeout(std::time::Duration::from_secs(10))
+            .map_err(|e| format!("Failed to configure the cache: {}", e))?;
+        Ok(Self {
+            connection,
+            account: account.to_string(),
+        })
+    }
+
+    /// Reserves the next nonce, i.e. the candidate, or the last one used plus
+    /// one if that is not lower. The nonce is reserved by a single statement,
+    /// so parallel runs never get the same one.
+    ///
+    /// # Arguments
+    ///
+    /// * `candidate` - The nonce derived from the current time.
+    ///
+    pub fn next(&self, candidate: u64) -> Result<u64, rusqlite::Error> {
+        self.connection.query_row(
+            "INSERT INTO nonces (account, nonce) VALUES (?1, ?2)
+             ON CONFLICT (account) DO UPDATE SET nonce = max(nonce + 1, excluded.nonce)
+             RETURNING nonce",
+            params![self.account, candidate as i64],
+            |row| row.get::<_, i64>(0).map(|nonce| nonce as u64),
+        )
+    }
+}
+
+impl Cache {
+    /// Loads the cached trades of a trading pair sorted by time.
+    ///
+    /// # Arguments
+    ///
+    /// * `pair` - The trading pair (e.g., XXBTZEUR).
+    /// * `start` - An optional start date for filtering the trades.
+    /// * `end` - An optional end date for filtering the trades.
+    ///
+    pub fn load_trades(
+        &self,
+        pair: &str,
+        start: Option<f64>,
+        end: Option<f64>,
+    ) -> Result<Vec<Trade>, rusqlite::Error> {
+        let mut trades: Vec<Trade> = Vec::new();
+        self.for_each_trade(pair, start, end, |trade| trades.push(trade))?;
+        Ok(trades)
+    }
+
+    /// Passes the cached trades of a trading pair to `f` in the order of time,
+    /// reading one row after the other, so they don't need to fit into
+    /// memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `pair` - The trading pair (e.g., XXBTZEUR).
+    /// * `start` - An optional start date for filtering the trades.
+    /// * `end` - An optional end date for filtering the trades.
+    /// * `f` - The function the trades are passed to.
+    ///
+    pub fn for_each_trade(
+        &self,
+        pair: &str,
+        start: Option<f64>,
+        end: Option<f64>,
+        mut f: impl FnMut(Trade),
+    ) -> Result<(), rusqlite::Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT txid, ordertxid, pair, time, type, price, fee, vol, cost, ordertype,
+                    margin, misc, postxid, maker
+             FROM trades
+             WHERE pair = ?1 AND time >= ?2 AND time <= ?3
+             ORDER BY time",
+        )?;
+        let trades = statement.query_map(
+            params![pair, start.unwrap_or(f64::MIN), end.unwrap_or(f64::MAX)],
+            |row| {
+                Ok(Trade {
+                    txid: row.get(0)?,
+                    ordertxid: row.get(1)?,
+                    pair: row.get(2)?,
+                    time: row.get(3)?,
+                    side: row.get(4)?,
+                    price: row.get(5)?,
+                    fee: row.get(6)?,
+                    vol: row.get(7)?,
+                    cost: row.get(8)?,
+                    ordertype: row.get(9)?,
+                    margin: row.get(10)?,
+                    misc: row.get(11)?,
+                    postxid: row.get(12)?,
+                    maker: row.get(13)?,
+                    base_fee: None,
+                    source: None,
+                })
+            },
+        )?;
+        for trade in trades {
+            f(trade?);
+        }
+        Ok(())
+    }
+
+    /// Returns the trading pairs with cached trades.
+    pub fn pairs(&self) -> Result<Vec<String>, rusqlite::Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT DISTINCT pair FROM trades ORDER BY pair")?;
+        let pairs = statement.query_map([], |row| row.get(0))?;
+        pairs.collect()
+    }
+
+    /// Returns the assets with cached ledger entries.
+    pub fn ledger_assets(&self) -> Result<Vec<String>, rusqlite::Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT DISTINCT asset FROM ledger ORDER BY asset")?;
+        let assets = statement.query_map([], |row| row.get(0))?;
+        assets.collect()
+    }
+
+    /// Loads the cached ledger entries of an asset sorted by time.
+    pub fn load_ledger_entries(
+        &self,
+        asset: &str,
+        start: Option<f64>,
+        end: Option<f64>,
+    ) -> Result<Vec<LedgerEntry>, rusqlite::Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, refid, time, type, subtype, amount, fee, balance
+             FROM ledger
+             WHERE asset = ?1 AND time >= ?2 AND time <= ?3
+             ORDER BY time",
+        )?;
+        let entries = statement.query_map(
+            params![asset, start.unwrap_or(f64::MIN), end.unwrap_or(f64::MAX)],
+            |row| {
+                Ok(LedgerEntry {
+                    id: row.get(0)?,
+                    refid: row.get(1)?,
+                    time: row.get(2)?,
+                    entry_type: row.get(3)?,
+                    subtype: row.get(4)?,
+                    amount: row.get(5)?,
+                    fee: row.get(6)?,
+                    balance: row.get(7)?,
+                })
+            },
+        )?;
+        entries.collect()
+    }
+
+    /// Stores the closed orders, replacing those that are already cached.
+    pub fn store_orders(
+        &self,
+        orders: &HashMap<String, ClosedOrder>,
+    ) -> Result<(), rusqlite::Error> {
+        let transaction = self.connection.unchecked_transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT OR REPLACE INTO orders
+                 (ordertxid, userref, cl_ord_id, status, opentm, closetm, price, price2,
+                  avg_price)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            for (ordertxid, order) in orders {
+                statement.execute(params![
+                    ordertxid,
+                    order.userref,
+                    order.cl_ord_id,
+                    order.status,
+                    order.opentm,
+                    order.closetm,
+                    order.price,
+                    order.price2,
+                    order.avg_price,
+                ])?;
+            }
+        }
+        transaction.commit()
+    }
+
+    /// Loads the cached closed orders by the id of the order.
+    pub fn load_orders(&self) -> Result<HashMap<String, ClosedOrder>, rusqlite::Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT ordertxid, userref, cl_ord_id, status, opentm, closetm, price, price2,
+                    avg_price
+             FROM orders",
+        )?;
+        let orders = statement.query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                ClosedOrder {
+                    userref: row.get(1)?,
+                    cl_ord_id: row.get(2)?,
+                    status: row.get(3)?,
+                    opentm: row.get(4)?,
+                    closetm: row.get(5)?,
+                    price: row.get(6)?,
+                    price2: row.get(7)?,
+                    avg_price: row.get(8)?,
+                },
+            ))
+        })?;
+        orders.collect()
+    }
+
+    /// Records a completed run in the registry.
+    pub fn record_run(&self, run: &Run) -> Result<(), rusqlite::Error> {
+        self.connection.execute(
+            "INSERT INTO runs
+             (started_at, account, arguments, symbols, duration, requests, api_points,
+              rate_limit_wait, rate_limited, outputs)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                run.started_at,
+                run.account,
+                run.arguments,
+                run.symbols.join(","),
+                run.duration,
+                run.requests as i64,
+                run.api_points,
+                run.rate_limit_wait,
+                run.rate_limited as i64,
+                run.outputs.join(","),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Stores the daily closing prices of a trading pair, replacing those that
+    /// are already cached.
+    ///
+    /// # Arguments
+    ///
+    /// * `pair` - The trading pair (e.g., XXBTZEUR).
+    /// * `source` - The name of the price source (e.g., kraken).
+    /// * `closes` - The closing prices by day.
+    ///
+    pub fn store_prices(
+        &self,
+        pair: &str,
+        source: &str,
+        closes: &BTreeMap<NaiveDate, f64>,
+    ) -> Result<(), rusqlite::Error> {
+        let transaction = self.connection.unchecked_transaction()?;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT OR REPLACE INTO prices (pair, source, date, close)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for (date, close) in closes {
+                statement.execute(params![pair, source, date.to_string(), close])?;
+            }
+        }
+        transaction.commit()
+    }
+
+    /// Loads the cached daily closing prices of a trading pair between two
+    /// days.
+    pub fn load_prices(
+        &self,
+        pair: &str,
+        source: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<BTreeMap<NaiveDate, f64>, rusqlite::Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT date, close FROM prices
+             WHERE pair = ?1 AND source = ?2 AND date >= ?3 AND date <= ?4",
+        )?;
+        let closes = statement.query_map(
+            params![pair, source, from.to_string(), to.to_string()],
+            |row| {
+                let date: String = row.get(0)?;
+                let date: NaiveDate =
+                    NaiveDate::parse_from_str(&date, "%Y-%m-%d").map_err(|e| {
+                        rusqlite::Error::FromSqlConversionFailure(
+                            0,
+                            rusqlite::types::Type::Text,
+                            Box::new(e),
+                        )
+                    })?;
+                Ok((date, row.get(1)?))
+            },
+        )?;
+        closes.collect()
+    }
+
+    /// Loads the completed runs sorted by their start.
+    pub fn load_runs(&self) -> Result<Vec<Run>, rusqlite::Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT started_at, account, arguments, symbols, duration, requests, api_points,
+                    rate_limit_wait, rate_limited, outputs
+             FROM runs ORDER BY id",
+        )?;
+        let split = |value: String| -> Vec<String> {
+            value
+                .split(',')
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        let runs = statement.query_map([], |row| {
+            Ok(Run {
+                started_at: row.get(0)?,
+                account: row.get(1)?,
+                arguments: row.get(2)?,
+                symbols: split(row.get(3)?),
+                duration: row.get(4)?,
+                requests: row.get::<_, i64>(5)? as usize,
+                api_points: row.get(6)?,
+                rate_limit_wait: row.get(7)?,
+                rate_limited: row.get::<_, i64>(8)? as usize,
+                outputs: split(row.get(9)?),
+            })
+        })?;
+        runs.collect()
+    }
+}
+
+/// The result of a query.
+#[derive(Debug)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Executes a read-only SQL statement against the cache.
+///
+/// # Arguments
+///
+/// * `path` - The path of the cache.
+/// * `sql` - The SQL statement (e.g., SELECT pair, COUNT(*) FROM trades GROUP
+///   BY pair).
+///
+/// # Returns
+///
+/// The columns and rows of the result, where every value is rendered as text
+/// and NULL as an empty string.
+///
+pub fn query(path: &Path, sql: &str) -> Result<QueryResult, String> {
+    if !path.exists() {
+        return Err(format!(
+            "There is no cache at {} yet, compute the PnL first!",
+            path.display()
+        ));
+    }
+    let connection: Connection =
+        Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut statement = connection.prepare(sql).map_err(|e| e.to_string())?;
+    if !statement.readonly() {
+        return Err("Only read-only statements are allowed!".to_string());
+    }
+
+    let columns: Vec<String> = statement
+        .column_names()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut result = statement.query([]).map_err(|e| e.to_string())?;
+    while let Some(row) = result.next().map_err(|e| e.to_string())? {
+        let mut values: Vec<String> = Vec::with_capacity(columns.len());
+        for index in 0..columns.len() {
+            let value: String = match row.get_ref(index).map_err(|e| e.to_string())? {
+                ValueRef::Null => String::new(),
+                ValueRef::Integer(value) => value.to_string(),
+                ValueRef::Real(value) => value.to_string(),
+                ValueRef::Text(value) => String::from_utf8_lossy(value).into_owned(),
+                ValueRef::Blob(value) => format!("<{} bytes>", value.len()),
+            };
+            values.push(value);
+        }
+        rows.push(values);
+    }
+    Ok(QueryResult { columns, rows })
+}
+
+/// Lists the most recent runs of the registry, newest first.
+///
+/// # Arguments
+///
+/// * `path` - The path of the cache.
+/// * `limit` - The maximum number of runs.
+///
+pub fn list_runs(path: &Path, limit: usize) -> Result<QueryResult, String> {
+    // Creates the registry if the cache predates it.
+    Cache::open(path)?;
+    query(
+        path,
+        &format!(
+            "SELECT id, started_at, account, symbols, arguments,
+                    printf('%.1fs', duration) AS duration, requests, api_points,
+                    printf('%.1fs', rate_limit_wait) AS waited, rate_limited, outputs
+             FROM runs ORDER BY id DESC LIMIT {}",
+            limit
+        ),
+    )
+}
+
+/// Prints the result of a query as a table.
+pub fn print_query_result(result: &QueryResult) {
+    let mut widths: Vec<usize> = result.columns.iter().map(|c| c.chars().count()).collect();
+    for row in &result.rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+    let format_row = |values: &[String]| {
+        values
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:<width$}", value, width = width))
+            .collect::<Vec<String>>()
+            .join(" | ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!("{}", format_row(&result.columns));
+    println!(
+        "{}",
+        widths
+            .iter()
+            .map(|width| "-".repeat(*width))
+            .collect::<Vec<String>>()
+            .join("-+-")
+    );
+    for row in &result.rows {
+        println!("{}", format_row(row));
+    }
+    println!("({} rows)", result.rows.len());
+}
+
+/// Writes the result of a query to a CSV or JSON file.
+///
+/// # Arguments
+///
+/// * `result` - The result of the query.
+/// * `file_path` - The path of the file. If the path ends with `.json`, an
+///   array with one object per row is written, otherwise a CSV file.
+///
+pub fn write_query_result(result: &QueryResult, file_path: &str) {
+    let mut file: File = File::create(file_path).expect("Could not create file");
+
+    if file_path.ends_with(".json") {
+        let rows: Vec<serde_json::Map<String, serde_json::Value>> = result
+            .rows
+            .iter()
+            .map(|row| {
+                result
+                    .columns
+                    .iter()
+                    .cloned()
+                    .zip(row.iter().cloned().map(serde_json::Value::String))
+                    .collect()
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut file, &rows).expect("Failed to write result to JSON!");
+        return;
+    }
+
+    let escape = |value: &String| {
+        if value.contains([',', '"', '\n']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.clone()
+        }
+    };
+    for row in std::iter::once(&result.columns).chain(&result.rows) {
+        writeln!(
+            file,
+            "{}",
+            row.iter().map(escape).collect::<Vec<String>>().join(",")
+        )
+        .expect("Failed to write result to CSV!");
+    }
+}
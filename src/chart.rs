@@ -0,0 +1,271 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module draws the equity curve of a trading pair as SVG or PNG chart via
+plotters: the cumulative realized PnL of the disposals and the balance of the
+base currency over time, in two panels that share the time axis.
+
+The font of the labels is built into the binary (DejaVu Sans, see
+assets/DejaVuSans-LICENSE.txt), so the PNG looks the same on every system,
+without depending on the fonts installed.
+*/
+
+use crate::fifo::Disposal;
+use crate::fiscal::YearStart;
+use crate::kraken::Trade;
+use crate::precision::{AssetPair, Precision};
+use chrono::DateTime;
+use log::warn;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use plotters::style::{register_font, FontStyle};
+use std::sync::Once;
+
+const WIDTH: u32 = 900;
+const TITLE_HEIGHT: u32 = 40;
+const PANEL_HEIGHT: u32 = 320;
+const TICKS: usize = 5;
+const FONT: &str = "sans-serif";
+const COLORS: [RGBColor; 2] = [RGBColor(0x1f, 0x77, 0xb4), RGBColor(0xff, 0x7f, 0x0e)];
+
+/// A step line of the chart.
+#[derive(Debug)]
+pub struct Series {
+    pub label: String,
+    /// The number of decimals of the axis labels.
+    pub decimals: usize,
+    /// The points as UNIX timestamp and value, sorted by time.
+    pub points: Vec<(f64, f64)>,
+}
+
+/// Returns the cumulative realized PnL and the balance over time.
+///
+/// # Arguments
+///
+/// * `disposals` - The per-lot disposals.
+/// * `trades` - The trades the PnL was computed from.
+/// * `year` - An optional year the disposals were filtered by, which the
+///   balance is limited to as well.
+/// * `year_start` - The first day of the fiscal years.
+/// * `asset_pair` - The assets of the trading pair.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn equity_curve(
+    disposals: &[Disposal],
+    trades: &[Trade],
+    year: Option<u32>,
+    year_start: YearStart,
+    asset_pair: &AssetPair,
+    precision: &Precision,
+) -> (Series, Series) {
+    let in_year = |time: f64| year_start.contains(year, time);
+
+    let mut balance: f64 = 0f64;
+    let mut balance_points: Vec<(f64, f64)> = Vec::new();
+    for trade in trades {
+        let amount: f64 = trade.vol.parse().unwrap();
+        let before: f64 = balance;
+        balance = match trade.side.as_str() {
+            "sell" | "lost" => precision.base(balance - amount),
+            // Transfers keep the holdings.
+            "withdrawal" | "deposit" => balance,
+            _ => precision.base(balance + amount),
+        };
+        if in_year(trade.time) {
+            // The balance entering the year starts the line.
+            if balance_points.is_empty() && year.is_some() {
+                balance_points.push((trade.time, before));
+            }
+            balance_points.push((trade.time, balance));
+        }
+    }
+
+    let mut pnl: f64 = 0f64;
+    let mut pnl_points: Vec<(f64, f64)> = Vec::new();
+    let start: Option<f64> = balance_points
+        .first()
+        .map(|(time, _)| *time)
+        .or(disposals.first().map(|disposal| disposal.disposal_time));
+    if let Some(start) = start {
+        pnl_points.push((start, 0f64));
+    }
+    for disposal in disposals {
+        pnl = precision.quote(pnl + disposal.gain);
+        pnl_points.push((disposal.disposal_time, pnl));
+    }
+    (
+        Series {
+            label: format!("Cumulative realized PnL ({})", asset_pair.quote),
+            decimals: precision.quote as usize,
+            points: pnl_points,
+        },
+        Series {
+            label: format!("Balance ({})", asset_pair.base),
+            decimals: precision.base as usize,
+            points: balance_points,
+        },
+    )
+}
+
+/// Writes the series to an SVG or PNG file, depending on its extension, one
+/// panel per series.
+///
+/// # Arguments
+///
+/// * `series` - The series to draw.
+/// * `title` - The title of the chart.
+/// * `file_path` - The path of the SVG or PNG file.
+///
+pub fn write_chart(series: &[Series], title: &str, file_path: &str) -> Result<(), String> {
+    let size: (u32, u32) = chart_size(series);
+    let result: Result<(), String> = if file_path.ends_with(".png") {
+        draw(
+            BitMapBackend::new(file_path, size).into_drawing_area(),
+            series,
+            title,
+        )
+    } else {
+        draw(
+            SVGBackend::new(file_path, size).into_drawing_area(),
+            series,
+            title,
+        )
+    };
+    result.map_err(|e| format!("Failed to write {}: {}", file_path, e))
+}
+
+/// Renders the series as SVG document, one panel per series, e.g. to embed it
+/// into an HTML report.
+///
+/// # Arguments
+///
+/// * `series` - The series to draw, at least one of them with a point.
+/// * `title` - The title of the chart.
+///
+pub fn render_chart(series: &[Series], title: &str) -> String {
+    let mut svg: String = String::new();
+    if let Err(e) = draw(
+        SVGBackend::with_string(&mut svg, chart_size(series)).into_drawing_area(),
+        series,
+        title,
+    ) {
+        warn!("Failed to render the chart: {}", e);
+    }
+    svg
+}
+
+/// Returns the width and height of the chart of the series.
+fn chart_size(series: &[Series]) -> (u32, u32) {
+    (
+        WIDTH,
+        TITLE_HEIGHT + series.len().max(1) as u32 * PANEL_HEIGHT,
+    )
+}
+
+/// Draws the series onto a backend, one panel per series.
+///
+/// # Arguments
+///
+/// * `root` - The drawing area of the whole chart.
+/// * `series` - The series to draw, at least one of them with a point.
+/// * `title` - The title of the chart.
+///
+fn draw<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    series: &[Series],
+    title: &str,
+) -> Result<(), String> {
+    static REGISTER_FONT: Once = Once::new();
+    REGISTER_FONT.call_once(|| {
+        let font: &'static [u8] = include_bytes!("../assets/DejaVuSans.ttf");
+        for style in [FontStyle::Normal, FontStyle::Bold] {
+            if register_font(FONT, style, font).is_err() {
+                unreachable!("The built-in font is valid.");
+            }
+        }
+    });
+
+    let points = series.iter().flat_map(|series| series.points.iter());
+    let (from, to): (f64, f64) = points.fold((f64::MAX, f64::MIN), |(from, to), (time, _)| {
+        (from.min(*time), to.max(*time))
+    });
+    // A single point in time is centered on a day.
+    let (from, to): (f64, f64) = if from >= to {
+        (from - 43200f64, to + 43200f64)
+    } else {
+        (from, to)
+    };
+
+    root.fill(&WHITE).map_err(|e| e.to_string())?;
+    let root: DrawingArea<DB, Shift> = root.titled(title, (FONT, 18)).map_err(|e| e.to_string())?;
+    let panels: Vec<DrawingArea<DB, Shift>> = root.split_evenly((series.len().max(1), 1));
+    for (index, (series, panel)) in series.iter().zip(panels.iter()).enumerate() {
+        let (low, high): (f64, f64) = series
+            .points
+            .iter()
+            .fold((0f64, 0f64), |(low, high), (_, value)| {
+                (low.min(*value), high.max(*value))
+            });
+        let (low, high): (f64, f64) = if low == high {
+            (low - 1f64, high + 1f64)
+        } else {
+            (low, high)
+        };
+        let mut chart = ChartBuilder::on(panel)
+            .caption(&series.label, (FONT, 14).into_font().style(FontStyle::Bold))
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(90)
+            .build_cartesian_2d(from..to, low..high)
+            .map_err(|e| e.to_string())?;
+        let decimals: usize = series.decimals.min(4);
+        chart
+            .configure_mesh()
+            .x_labels(TICKS + 1)
+            .y_labels(TICKS + 1)
+            .disable_x_mesh()
+            .light_line_style(WHITE)
+            .x_label_formatter(&|time: &f64| {
+                DateTime::from_timestamp(*time as i64, 0)
+                    .map(|time| time.format("%Y-%m-%d").to_string())
+                    .unwrap_or_default()
+            })
+            .y_label_formatter(&|value: &f64| format!("{:.*}", decimals, value))
+            .label_style((FONT, 12))
+            .draw()
+            .map_err(|e| e.to_string())?;
+        if low < 0f64 && high > 0f64 {
+            chart
+                .draw_series(LineSeries::new([(from, 0f64), (to, 0f64)], &BLACK.mix(0.5)))
+                .map_err(|e| e.to_string())?;
+        }
+
+        // The values change at the time of a trade, so they are drawn as steps.
+        let mut steps: Vec<(f64, f64)> = Vec::new();
+        for (time, value) in &series.points {
+            if let Some((_, previous)) = steps.last().copied() {
+                steps.push((*time, previous));
+            }
+            steps.push((*time, *value));
+        }
+        if let Some((_, previous)) = steps.last().copied() {
+            steps.push((to, previous));
+        }
+        chart
+            .draw_series(LineSeries::new(
+                steps,
+                COLORS[index % COLORS.len()].stroke_width(2),
+            ))
+            .map_err(|e| e.to_string())?;
+    }
+    root.present().map_err(|e| e.to_string())
+}
+
+/// Escapes the characters of a text that have a meaning in XML.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,458 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module implements the checkpoint of a fetch, so an interrupted fetch of
+a long history, e.g. by Ctrl+C, a crash or a dropped connection, is resumed
+by the next run instead of starting over, unless `--restart` is passed.
+
+Every page received from a paginated endpoint is appended to the checkpoint
+file next to the cache as soon as it arrives, so nothing but the page in
+flight is lost however the run ends. The pages are stored with their offset,
+the number of their entries and the SHA-256 digest of the response, so a page
+that was altered or cut off is detected. Only the pages that follow each other
+from the first one on are resumed, the others are requested again, so a gap
+can't drop the entries in between.
+
+Kraken returns the newest entries first, so entries added since the
+checkpoint was written shift every page by their number. A resumed fetch thus
+requests the first pages again, which hold the new entries, and checks that
+they line up with the first page of the checkpoint by their ids before the
+remaining pages are answered from it (see `fetch_all_pages`). The checkpoint
+is removed once all trading pairs were fetched.
+
+The API counter at the time a page was received is recorded as well, so the
+resumed run doesn't start from an empty counter that Kraken would reject. The
+pages carry the version of their layout, so a checkpoint written by an older
+version is migrated when it is resumed (see the `schema` module).
+*/
+
+use crate::schema::{upgrade, SCHEMA_VERSION};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A page as stored in the checkpoint file, one per line.
+#[derive(Serialize, Deserialize)]
+struct Page {
+    /// The endpoint and the parameters of the request, except the offset.
+    request: String,
+    /// The offset of the page in the entries as of the interrupted fetch.
+    offset: usize,
+    response: String,
+    /// The number of entries of the response.
+    entries: usize,
+    /// The SHA-256 digest of the response, to check its integrity.
+    digest: String,
+    /// The API counter after the request.
+    #[serde(default)]
+    counter: Option<f64>,
+    /// The UNIX timestamp the page was received at.
+    #[serde(default)]
+    time: Option<f64>,
+    /// The version of the layout of the response, zero for the pages stored
+    /// before it was recorded.
+    #[serde(default)]
+    schema: u32,
+}
+
+/// The pages of a request of the interrupted fetch, i.e. of a time window.
+pub struct Resumed {
+    /// The responses in the order of their offsets, starting at zero.
+    pub pages: Vec<String>,
+    /// The total number of entries the pages reported.
+    pub count: usize,
+    /// The ids of the entries of the first page.
+    pub first_ids: HashSet<String>,
+}
+
+/// The pages received by the fetch of a run.
+pub struct Checkpoint {
+    path: PathBuf,
+    /// The pages of the interrupted fetch that is resumed, by request.
+    resumed: HashMap<String, Vec<Page>>,
+    /// The API counter of the interrupted fetch and the time it was reached
+    /// at, by its most recent page.
+    counter: Option<(f64, f64)>,
+    file: Mutex<File>,
+    /// Whether the fetch is still in progress.
+    active: AtomicBool,
+}
+
+/// Returns the path of the checkpoint of an account.
+///
+/// # Arguments
+///
+/// * `cache_path` - The path of the cache, the checkpoint is created next to
+///   it.
+/// * `account` - The account identifier.
+///
+pub fn checkpoint_path(cache_path: &Path, account: &str) -> PathBuf {
+    cache_path.with_file_name(format!("checkpoint-{}.jsonl", account))
+}
+
+/// Returns the key of a request in the checkpoint.
+fn request_key(endpoint: &str, params: &[(&str, String)]) -> String {
+    format!(
+        "{}?{}",
+        endpoint,
+        serde_urlencoded::to_string(params).unwrap()
+    )
+}
+
+/// Returns the object of entries by id in the result of a page (`trades`,
+/// `ledger` or `closed`), if it has one.
+fn entries_of(response: &serde_json::Value) -> Option<&serde_json::Map<String, serde_json::Value>> {
+    response["result"]
+        .as_object()?
+        .values()
+        .find_map(|value| value.as_object())
+}
+
+/// Returns the SHA-256 digest of a response.
+fn digest(response: &str) -> String {
+    Sha256::digest(response.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Returns whether a stored page is intact, i.e. its response has the digest
+/// and the number of entries it was stored with.
+fn is_intact(page: &Page) -> bool {
+    let response: Option<serde_json::Value> = serde_json::from_str(&page.response).ok();
+    page.digest == digest(&page.response)
+        && response
+            .as_ref()
+            .and_then(entries_of)
+            .is_some_and(|entries| entries.len() == page.entries)
+}
+
+/// Returns the current UNIX timestamp.
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+impl Checkpoint {
+    /// Opens the checkpoint of a fetch.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the checkpoint file.
+    /// * `restart` - Whether to replace the existing checkpoint instead of
+    ///   resuming its fetch.
+    ///
+    pub fn open(path: &Path, restart: bool) -> Result<Self, String> {
+        let mut resumed: HashMap<String, Vec<Page>> = HashMap::new();
+        let mut counter: Option<(f64, f64)> = None;
+        if path.exists() && restart {
+            warn!(
+                "Starting over instead of resuming the interrupted fetch of {}.",
+                path.display()
+            );
+        } else if path.exists() {
+            let file: File = File::open(path)
+                .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+            let mut stored: HashMap<String, BTreeMap<usize, Page>> = HashMap::new();
+            for line in BufReader::new(file).lines() {
+                let line: String =
+                    line.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                // The last line may be incomplete if the run was killed while
+                // writing it.
+                let Ok(mut page) = serde_json::from_str::<Page>(&line) else {
+                    warn!("Skipping an incomplete page of {}", path.display());
+                    continue;
+                };
+                if !is_intact(&page) {
+                    warn!(
+                        "Requesting the page {} at offset {} again, it was altered in {}.",
+                        page.request,
+                        page.offset,
+                        path.display()
+                    );
+                    continue;
+                }
+                if page.schema != SCHEMA_VERSION {
+                    match upgrade(&page.response, page.schema) {
+                        Ok(response) => {
+                            page.digest = digest(&response);
+                            page.response = response;
+                            page.schema = SCHEMA_VERSION;
+                        }
+                        Err(e) => {
+                            warn!(
+                                "Requesting the page {} at offset {} again: {}",
+                                page.request, page.offset, e
+                            );
+                            continue;
+                        }
+                    }
+                }
+                stored
+                    .entry(page.request.clone())
+                    .or_default()
+                    .insert(page.offset, page);
+            }
+            for (request, mut pages) in stored {
+                // The pages are resumed up to the first gap.
+                let mut offset: usize = 0usize;
+                let mut prefix: Vec<Page> = Vec::new();
+                while let Some(page) = pages.remove(&offset) {
+                    offset += page.entries;
+                    let empty: bool = page.entries == 0;
+                    prefix.push(page);
+                    if empty {
+                        break;
+                    }
+                }
+                if !pages.is_empty() {
+                    warn!(
+                        "Requesting {} pages of {} again, the page at offset {} is missing in {}.",
+                        pages.len(),
+                        request,
+                        offset,
+                        path.display()
+                    );
+                }
+                for page in &prefix {
+                    if let (Some(value), Some(time)) = (page.counter, page.time) {
+                        if counter.is_none_or(|(_, latest)| time > latest) {
+                            counter = Some((value, time));
+                        }
+                    }
+                }
+                if !prefix.is_empty() {
+                    resumed.insert(request, prefix);
+                }
+            }
+            info!(
+                "Resuming the interrupted fetch with {} pages of {} entries from {}",
+                resumed.values().map(Vec::len).sum::<usize>(),
+                resumed
+                    .values()
+                    .flatten()
+                    .map(|page| page.entries)
+                    .sum::<usize>(),
+                path.display()
+            );
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        // The resumed pages are written again, so the checkpoint stays
+        // complete if the resumed fetch is interrupted, too.
+        let mut file: File = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        for page in resumed.values().flatten() {
+            writeln!(file, "{}", serde_json::to_string(page).unwrap())
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            resumed,
+            counter,
+            file: Mutex::new(file),
+            active: AtomicBool::new(true),
+        })
+    }
+
+    /// Returns the pages of a request of the resumed fetch, if any were
+    /// received before.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint of the request.
+    /// * `params` - The parameters of the request, except the offset.
+    ///
+    pub fn resume(&self, endpoint: &str, params: &[(&str, String)]) -> Option<Resumed> {
+        if !self.active.load(Ordering::SeqCst) {
+            return None;
+        }
+        let pages: &Vec<Page> = self.resumed.get(&request_key(endpoint, params))?;
+        let parsed = |page: &Page| serde_json::from_str::<serde_json::Value>(&page.response).ok();
+        // Without the count, the offsets of the pages can't be lined up.
+        let count: usize = pages
+            .last()
+            .and_then(parsed)
+            .and_then(|response| response["result"]["count"].as_u64())?
+            as usize;
+        let first_ids: HashSet<String> = pages
+            .first()
+            .and_then(parsed)
+            .as_ref()
+            .and_then(entries_of)
+            .map(|entries| entries.keys().cloned().collect())
+            .unwrap_or_default();
+        Some(Resumed {
+            pages: pages.iter().map(|page| page.response.clone()).collect(),
+            count,
+            first_ids,
+        })
+    }
+
+    /// Returns the API counter of the interrupted fetch and how long ago it
+    /// was reached, if the fetch is resumed.
+    pub fn counter(&self) -> Option<(f64, Duration)> {
+        self.counter
+            .map(|(value, time)| (value, Duration::from_secs_f64((now() - time).max(0f64))))
+    }
+
+    /// Appends a received page to the checkpoint file.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint of the request.
+    /// * `params` - The parameters of the request, except the offset.
+    /// * `offset` - The offset of the page in the entries as of the
+    ///   interrupted fetch, if it is resumed.
+    /// * `response` - The response.
+    /// * `entries` - The number of entries of the response.
+    /// * `counter` - The API counter after the request.
+    ///
+    pub fn store(
+        &self,
+        endpoint: &str,
+        params: &[(&str, String)],
+        offset: usize,
+        response: &str,
+        entries: usize,
+        counter: f64,
+    ) {
+        if !self.active.load(Ordering::SeqCst) {
+            return;
+        }
+        let page: Page = Page {
+            request: request_key(endpoint, params),
+            offset,
+            response: response.to_string(),
+            entries,
+            digest: digest(response),
+            counter: Some(counter),
+            time: Some(now()),
+            schema: SCHEMA_VERSION,
+        };
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", serde_json::to_string(&page).unwrap()) {
+            warn!("Failed to write {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Removes the checkpoint once the fetch completed. Later requests, e.g.
+    /// of the following iterations of the watch mode, are not checkpointed.
+    pub fn finish(&self) {
+        if self.active.swap(false, Ordering::SeqCst) {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn!("Failed to remove {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENDPOINT: &str = "/0/private/TradesHistory";
+
+    /// Returns a page of trades with the given ids.
+    fn page(ids: &[&str], count: usize) -> String {
+        let trades: serde_json::Map<String, serde_json::Value> = ids
+            .iter()
+            .map(|id| (id.to_string(), serde_json::json!({"pair": "XXBTZEUR"})))
+            .collect();
+        serde_json::json!({"error": [], "result": {"trades": trades, "count": count}}).to_string()
+    }
+
+    /// Returns the path of a checkpoint file of a test, which doesn't exist.
+    fn path(name: &str) -> PathBuf {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("kraken-pnl-checkpoint-{}.jsonl", name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    /// Stores two pages of three trades in a new checkpoint.
+    fn interrupted_fetch(path: &Path) {
+        let checkpoint: Checkpoint = Checkpoint::open(path, false).unwrap();
+        let params: Vec<(&str, String)> = vec![("trades", "true".to_string())];
+        checkpoint.store(ENDPOINT, &params, 0, &page(&["T1", "T2"], 3), 2, 4f64);
+        checkpoint.store(ENDPOINT, &params, 2, &page(&["T3"], 3), 1, 6f64);
+    }
+
+    #[test]
+    fn resumes_the_stored_pages() {
+        let path: PathBuf = path("resume");
+        interrupted_fetch(&path);
+        let checkpoint: Checkpoint = Checkpoint::open(&path, false).unwrap();
+        let params: Vec<(&str, String)> = vec![("trades", "true".to_string())];
+        let resumed: Resumed = checkpoint.resume(ENDPOINT, &params).unwrap();
+        assert_eq!(resumed.pages.len(), 2);
+        assert_eq!(resumed.count, 3);
+        assert_eq!(
+            resumed.first_ids,
+            HashSet::from(["T1".to_string(), "T2".to_string()])
+        );
+        assert_eq!(checkpoint.counter().unwrap().0, 6f64);
+        // Other requests, e.g. of another time window, aren't resumed.
+        assert!(checkpoint.resume(ENDPOINT, &[]).is_none());
+        checkpoint.finish();
+        assert!(!path.exists());
+        assert!(checkpoint.resume(ENDPOINT, &params).is_none());
+    }
+
+    #[test]
+    fn requests_altered_pages_again() {
+        let path: PathBuf = path("altered");
+        interrupted_fetch(&path);
+        let content: String = fs::read_to_string(&path).unwrap();
+        fs::write(&path, content.replacen("T1", "T9", 1)).unwrap();
+        let checkpoint: Checkpoint = Checkpoint::open(&path, false).unwrap();
+        // The first page was altered, so the second one can't be lined up.
+        assert!(checkpoint
+            .resume(ENDPOINT, &[("trades", "true".to_string())])
+            .is_none());
+        checkpoint.finish();
+    }
+
+    #[test]
+    fn skips_an_incomplete_last_page() {
+        let path: PathBuf = path("incomplete");
+        interrupted_fetch(&path);
+        let content: String = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &content[..content.len() - 20]).unwrap();
+        let checkpoint: Checkpoint = Checkpoint::open(&path, false).unwrap();
+        let resumed: Resumed = checkpoint
+            .resume(ENDPOINT, &[("trades", "true".to_string())])
+            .unwrap();
+        assert_eq!(resumed.pages.len(), 1);
+        checkpoint.finish();
+    }
+
+    #[test]
+    fn restart_discards_the_checkpoint() {
+        let path: PathBuf = path("restart");
+        interrupted_fetch(&path);
+        let checkpoint: Checkpoint = Checkpoint::open(&path, true).unwrap();
+        assert!(checkpoint
+            .resume(ENDPOINT, &[("trades", "true".to_string())])
+            .is_none());
+        assert!(checkpoint.counter().is_none());
+        checkpoint.finish();
+    }
+}
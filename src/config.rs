@@ -0,0 +1,382 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module loads the configuration file, which provides defaults for the
+command-line arguments, e.g. the trading pairs to compute the PnL for, the API
+tier and the output paths. Values passed on the command line always take
+precedence over the values of the file.
+
+The file is located at `~/.config/kraken-pnl/config.toml` (or
+`$XDG_CONFIG_HOME/kraken-pnl/config.toml`) unless a different path is passed
+via `--config`. It can be created by `kraken-pnl-calculator config init`.
+*/
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The content of a newly created configuration file.
+const TEMPLATE: &str = r#"# Configuration of the kraken-pnl-calculator. Arguments passed on the command
+# line take precedence over the values of this file.
+
+# The trading pairs to compute the PnL for if --symbol is not passed.
+symbols = ["XXBTZEUR"]
+
+# The API tier (starter, intermediate, or pro).
+tier = "starter"
+
+# Only consider profits made within a specific year.
+# year = 2024
+
+# The first day of the fiscal years, which may differ from the calendar years.
+# The fiscal year is named after the calendar year it starts in, e.g. the
+# fiscal year 2024 runs from 2024-04-01 until 2025-03-31.
+# fiscal_year_start = "04-01"
+
+# How credits in the ledger, i.e. fee rebates and referral rewards, are
+# treated: as income, offset against the fees by adding them to the holdings
+# at no cost, or ignored.
+# rebates = "income"
+
+# The amount of the base currency below which the lots left by a sell are
+# swept as dust, i.e. disposed of at no proceeds.
+# dust_threshold = 0.00000100
+
+# The source whose trade is kept if a trade imported via --import is also
+# fetched from the API (api or import).
+# prefer_source = "api"
+
+# The currency the PnL, fees and holdings of several pairs are consolidated in
+# for the portfolio summary, at the latest exchange rates.
+# portfolio_currency = "EUR"
+
+# The SQLite database the fetched data is stored in, which can be queried via
+# `kraken-pnl-calculator query "SELECT ..."` [default:
+# ~/.cache/kraken-pnl/cache.sqlite].
+# cache = "/path/to/cache.sqlite"
+
+[valuation]
+# The sources of historical prices in the order of preference: kraken (the most
+# recent 720 days), kraken-trades (the last public trade of a day, one request
+# per day), coingecko, or csv:PATH (columns: date,price[,pair]).
+sources = ["kraken", "kraken-trades", "coingecko"]
+# The CoinGecko ids of currencies that are not known by default.
+# coingecko_ids = { PEPE = "pepe" }
+
+[output]
+# Paths may contain {symbol}, which is replaced by the trading pair. When
+# computing the PnL of several pairs, paths without it are prefixed by the pair.
+# The file of --csv.
+trades = "trades.csv"
+# Write the lot report on every run, not only if --lot-report is passed.
+# lot_report = "{symbol}_disposals.csv"
+# The directory the files of --export are written to.
+exports_dir = "."
+# The layout of the trades, lot report and valuations CSV files, e.g. for a
+# spreadsheet software using the German locale.
+# csv_delimiter = ";"
+# decimal_comma = true
+# date_format = "%d.%m.%Y %H:%M:%S"
+
+[risk]
+# The value of the portfolio in the quote currency of the trading pairs, which
+# --exposure states the size of the open position relative to.
+# portfolio_value = 20000
+# The maximum size of a position as a percentage of the portfolio value, beyond
+# which --exposure warns.
+# max_position = 25
+# The maximum sizes of the positions in single trading pairs.
+# limits = { XETHZEUR = 10 }
+
+[credentials]
+# The environment variables holding the API key and its secret. They are also
+# read from a .env file in the working directory.
+api_key_env = "KRAKEN_API_KEY"
+secret_key_env = "KRAKEN_SECRET_KEY"
+# The second factor of API keys with two-factor authentication enabled: the
+# variable holding the static password, or the one holding the TOTP secret the
+# one-time passwords are generated from.
+otp_env = "KRAKEN_OTP"
+totp_secret_env = "KRAKEN_TOTP_SECRET"
+# The API key of Kraken Futures and its secret, used by the futures subcommand.
+futures_api_key_env = "KRAKEN_FUTURES_API_KEY"
+futures_secret_key_env = "KRAKEN_FUTURES_SECRET_KEY"
+# Read the credentials stored by `kraken-pnl-calculator login` from the OS
+# keyring if the environment variables are not set, as with --keyring.
+# keyring = false
+
+[notify]
+# The thresholds of the alerts in --watch and --stream mode, in the currency of
+# each trading pair's PnL. An alert is sent once a threshold is crossed, and
+# again only after the PnL went back in between.
+# realized_above = 1000
+# unrealized_below = -500
+# The channels --notify and the alerts are sent via. Post the notifications as
+# JSON to a webhook.
+# webhook_url = "https://example.com/hooks/pnl"
+# Send the notifications via a Telegram bot, whose token is read from the
+# environment variable.
+# telegram_chat_id = "123456789"
+# telegram_token_env = "TELEGRAM_BOT_TOKEN"
+# Send the notifications via email. The password is read from the environment
+# variable. The connection uses implicit TLS (usually port 465), STARTTLS with
+# starttls = true (usually port 587), or no encryption with tls = false, which
+# refuses to log in.
+# email = { server = "smtp.example.com", username = "me@example.com", from = "me@example.com", to = ["me@example.com"] }
+# The text of the notifications, with placeholders like {symbol},
+# {realized_pnl}, {realized_currency}, {unrealized_pnl}, {balance},
+# {warnings}, {time} and {text} for the default text, and {metric}, {value},
+# {threshold} and {currency} of the alerts. The first line is the subject of
+# the emails.
+# template = "[{symbol}] realized {realized_pnl} {realized_currency}"
+# Templates of single channels, which take precedence.
+# templates = { telegram = "{text}\nWarnings: {warnings}" }
+"#;
+
+/// The configuration file.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub symbols: Vec<String>,
+    pub tier: Option<String>,
+    pub year: Option<u32>,
+    pub fiscal_year_start: Option<String>,
+    pub rebates: Option<String>,
+    pub dust_threshold: Option<f64>,
+    pub prefer_source: Option<String>,
+    pub portfolio_currency: Option<String>,
+    pub cache: Option<PathBuf>,
+    pub valuation: ValuationConfig,
+    pub output: OutputConfig,
+    pub risk: RiskConfig,
+    pub credentials: CredentialsConfig,
+    pub notify: NotifyConfig,
+}
+
+/// The sources of historical prices.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValuationConfig {
+    pub sources: Vec<String>,
+    pub coingecko_ids: HashMap<String, String>,
+}
+
+impl Default for ValuationConfig {
+    fn default() -> Self {
+        Self {
+            sources: vec![
+                "kraken".to_string(),
+                "kraken-trades".to_string(),
+                "coingecko".to_string(),
+            ],
+            coingecko_ids: HashMap::new(),
+        }
+    }
+}
+
+/// The paths of the files written.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub trades: String,
+    pub lot_report: Option<String>,
+    pub exports_dir: String,
+    pub csv_delimiter: Option<String>,
+    pub decimal_comma: bool,
+    pub date_format: Option<String>,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            trades: "trades.csv".to_string(),
+            lot_report: None,
+            exports_dir: ".".to_string(),
+            csv_delimiter: None,
+            decimal_comma: false,
+            date_format: None,
+        }
+    }
+}
+
+/// The limits of the exposure check.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RiskConfig {
+    pub portfolio_value: Option<f64>,
+    pub max_position: Option<f64>,
+    pub limits: HashMap<String, f64>,
+}
+
+/// References to the credentials, which are never stored in the file itself.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct CredentialsConfig {
+    pub api_key_env: String,
+    pub secret_key_env: String,
+    pub otp_env: String,
+    pub totp_secret_env: String,
+    /// The variables holding the API key of Kraken Futures and its secret.
+    pub futures_api_key_env: String,
+    pub futures_secret_key_env: String,
+    pub keyring: bool,
+}
+
+impl Default for CredentialsConfig {
+    fn default() -> Self {
+        Self {
+            api_key_env: "KRAKEN_API_KEY".to_string(),
+            secret_key_env: "KRAKEN_SECRET_KEY".to_string(),
+            otp_env: "KRAKEN_OTP".to_string(),
+            totp_secret_env: "KRAKEN_TOTP_SECRET".to_string(),
+            futures_api_key_env: "KRAKEN_FUTURES_API_KEY".to_string(),
+            futures_secret_key_env: "KRAKEN_FUTURES_SECRET_KEY".to_string(),
+            keyring: false,
+        }
+    }
+}
+
+/// The thresholds and channels of the notifications.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifyConfig {
+    pub realized_above: Option<f64>,
+    pub unrealized_below: Option<f64>,
+    pub webhook_url: Option<String>,
+    pub telegram_chat_id: Option<String>,
+    pub telegram_token_env: String,
+    pub email: Option<EmailConfig>,
+    pub template: Option<String>,
+    pub templates: HashMap<String, String>,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            realized_above: None,
+            unrealized_below: None,
+            webhook_url: None,
+            telegram_chat_id: None,
+            telegram_token_env: "TELEGRAM_BOT_TOKEN".to_string(),
+            email: None,
+            template: None,
+            templates: HashMap::new(),
+        }
+    }
+}
+
+/// The SMTP server the notifications are sent via.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmailConfig {
+    pub server: String,
+    pub port: u16,
+    /// Whether to connect via implicit TLS.
+    pub tls: bool,
+    /// Whether to upgrade a plain connection via STARTTLS instead, which is
+    /// required to succeed.
+    pub starttls: bool,
+    pub username: Option<String>,
+    pub password_env: String,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        Self {
+            server: String::new(),
+            port: 465,
+            tls: true,
+            starttls: false,
+            username: None,
+            password_env: "SMTP_PASSWORD".to_string(),
+            from: String::new(),
+            to: Vec::new(),
+        }
+    }
+}
+
+/// Returns the default location of the configuration file.
+pub fn default_config_path() -> PathBuf {
+    let config_dir: PathBuf = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .or_else(|| std::env::var_os("USERPROFILE"))
+                .map(|home| PathBuf::from(home).join(".config"))
+        })
+        .unwrap_or_else(|| PathBuf::from("."));
+    config_dir.join("kraken-pnl").join("config.toml")
+}
+
+/// Loads the configuration file.
+///
+/// # Arguments
+///
+/// * `path` - The path of the configuration file.
+/// * `required` - Whether a missing file is an error. Otherwise, the defaults
+///   are used.
+///
+pub fn load_config(path: &Path, required: bool) -> Result<Config, String> {
+    if !path.exists() && !required {
+        return Ok(Config::default());
+    }
+    let content: String = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    toml::from_str(&content).map_err(|e| format!("Invalid {}: {}", path.display(), e))
+}
+
+/// Creates a configuration file with the default values.
+///
+/// # Arguments
+///
+/// * `path` - The path of the configuration file.
+/// * `force` - Whether to overwrite an existing file.
+///
+pub fn init_config(path: &Path, force: bool) -> Result<(), String> {
+    if path.exists() && !force {
+        return Err(format!(
+            "{} already exists, pass --force to overwrite it!",
+            path.display()
+        ));
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    fs::write(path, TEMPLATE).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Resolves an output path for a trading pair.
+///
+/// # Arguments
+///
+/// * `path` - The configured path, which may contain `{symbol}`.
+/// * `symbol` - The trading pair.
+/// * `several` - Whether the PnL of several trading pairs is computed, in
+///   which case paths without `{symbol}` are prefixed by the trading pair so
+///   they don't overwrite each other.
+///
+pub fn output_path(path: &str, symbol: &str, several: bool) -> String {
+    if path.contains("{symbol}") {
+        return path.replace("{symbol}", symbol);
+    }
+    if !several {
+        return path.to_string();
+    }
+    let path: &Path = Path::new(path);
+    let file_name: String = format!(
+        "{}_{}",
+        symbol,
+        path.file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default()
+    );
+    path.with_file_name(file_name)
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,308 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module defines the strategies selecting the lots a sell disposes of,
+which the lot engine is built around (`--method`):
+
+- `fifo`: The oldest lots first.
+- `lifo`: The newest lots first.
+- `hifo`: The lots of the highest cost per unit first.
+- `lofo`: The lots of the lowest cost per unit first.
+- `acb`: The adjusted cost base, e.g. of Canada: The lots are pooled at their
+  average cost per unit before every sell, which disposes of them in FIFO
+  order, so the lots keep their acquisition time.
+- `spec-id`: The lots assigned to the sells, then a rule (see the `spec_id`
+  module).
+
+Other rules, e.g. of other jurisdictions, implement `CostBasisStrategy`.
+`--compare-methods` computes the PnL of several strategies over the same
+trades.
+*/
+
+use crate::fifo::{Lot, PnL};
+use crate::kraken::Trade;
+use crate::precision::Precision;
+use crate::report::print_table;
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// The names of the strategies accepted by `--method` and
+/// `--compare-methods`, except `spec-id`, which needs further options.
+pub const METHODS: [&str; 5] = ["fifo", "lifo", "hifo", "acb", "lofo"];
+
+/// A strategy selecting the lots a sell disposes of.
+pub trait CostBasisStrategy: Debug + Send + Sync {
+    /// Returns the name of the strategy, as stated in the reports.
+    fn name(&self) -> String;
+
+    /// Prepares the open lots before a sell disposes of them, e.g. to pool
+    /// their cost. Nothing is done by default.
+    ///
+    /// # Arguments
+    ///
+    /// * `lots` - The open lots, in the order they were acquired, including
+    ///   short lots with a negative amount.
+    /// * `precision` - The precision of the base and quote currency.
+    ///
+    fn prepare(&self, _lots: &mut VecDeque<Lot>, _precision: &Precision) {}
+
+    /// Selects the lots a sell disposes of.
+    ///
+    /// # Arguments
+    ///
+    /// * `lots` - The open lots, in the order they were acquired, including
+    ///   short lots with a negative amount, which can't be selected.
+    /// * `trade` - The sell.
+    /// * `amount` - The amount to dispose of.
+    /// * `precision` - The precision of the base and quote currency.
+    ///
+    /// # Returns
+    ///
+    /// The index of each lot and the amount taken from it, in the order of
+    /// the disposals. The amounts don't exceed those of the lots, and may
+    /// fall short of `amount` if the lots do.
+    ///
+    fn select(
+        &self,
+        lots: &VecDeque<Lot>,
+        trade: &Trade,
+        amount: f64,
+        precision: &Precision,
+    ) -> Vec<(usize, f64)>;
+}
+
+/// Takes the amount from the lots in the given order.
+///
+/// # Arguments
+///
+/// * `lots` - The open lots.
+/// * `order` - The indices of the lots in the order to take them.
+/// * `amount` - The amount to take.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn take_in_order(
+    lots: &VecDeque<Lot>,
+    order: impl Iterator<Item = usize>,
+    amount: f64,
+    precision: &Precision,
+) -> Vec<(usize, f64)> {
+    let mut selected: Vec<(usize, f64)> = Vec::new();
+    let mut remaining: f64 = amount;
+    for index in order.filter(|index| lots[*index].amount > 0f64) {
+        if remaining <= 0f64 {
+            break;
+        }
+        let taken: f64 = remaining.min(lots[index].amount);
+        remaining = precision.base(remaining - taken);
+        selected.push((index, taken));
+    }
+    selected
+}
+
+/// Returns the indices of the lots sorted by their cost per unit, the
+/// highest first if `highest`. Ties keep the oldest lot first.
+fn by_unit_cost(lots: &VecDeque<Lot>, highest: bool) -> impl Iterator<Item = usize> {
+    let mut indices: Vec<usize> = (0..lots.len())
+        .filter(|index| lots[*index].amount > 0f64)
+        .collect();
+    let unit_cost = |index: &usize| lots[*index].cost / lots[*index].amount;
+    indices.sort_by(|a, b| match highest {
+        true => unit_cost(b).total_cmp(&unit_cost(a)),
+        false => unit_cost(a).total_cmp(&unit_cost(b)),
+    });
+    indices.into_iter()
+}
+
+/// The oldest lots first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fifo;
+
+impl CostBasisStrategy for Fifo {
+    fn name(&self) -> String {
+        "FIFO".to_string()
+    }
+
+    fn select(
+        &self,
+        lots: &VecDeque<Lot>,
+        _trade: &Trade,
+        amount: f64,
+        precision: &Precision,
+    ) -> Vec<(usize, f64)> {
+        take_in_order(lots, 0..lots.len(), amount, precision)
+    }
+}
+
+/// The newest lots first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lifo;
+
+impl CostBasisStrategy for Lifo {
+    fn name(&self) -> String {
+        "LIFO".to_string()
+    }
+
+    fn select(
+        &self,
+        lots: &VecDeque<Lot>,
+        _trade: &Trade,
+        amount: f64,
+        precision: &Precision,
+    ) -> Vec<(usize, f64)> {
+        take_in_order(lots, (0..lots.len()).rev(), amount, precision)
+    }
+}
+
+/// The lots of the highest cost per unit first, which realizes the smallest
+/// gain or the largest loss.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hifo;
+
+impl CostBasisStrategy for Hifo {
+    fn name(&self) -> String {
+        "HIFO".to_string()
+    }
+
+    fn select(
+        &self,
+        lots: &VecDeque<Lot>,
+        _trade: &Trade,
+        amount: f64,
+        precision: &Precision,
+    ) -> Vec<(usize, f64)> {
+        take_in_order(lots, by_unit_cost(lots, true), amount, precision)
+    }
+}
+
+/// The lots of the lowest cost per unit first, which realizes the largest
+/// gain.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lofo;
+
+impl CostBasisStrategy for Lofo {
+    fn name(&self) -> String {
+        "LOFO".to_string()
+    }
+
+    fn select(
+        &self,
+        lots: &VecDeque<Lot>,
+        _trade: &Trade,
+        amount: f64,
+        precision: &Precision,
+    ) -> Vec<(usize, f64)> {
+        take_in_order(lots, by_unit_cost(lots, false), amount, precision)
+    }
+}
+
+/// The adjusted cost base: Every sell disposes of the lots at their average
+/// cost per unit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Acb;
+
+impl CostBasisStrategy for Acb {
+    fn name(&self) -> String {
+        "ACB".to_string()
+    }
+
+    fn prepare(&self, lots: &mut VecDeque<Lot>, precision: &Precision) {
+        let (amount, cost): (f64, f64) = lots
+            .iter()
+            .filter(|lot| lot.amount > 0f64)
+            .fold((0f64, 0f64), |(amount, cost), lot| {
+                (amount + lot.amount, cost + lot.cost)
+            });
+        if amount <= 0f64 {
+            return;
+        }
+        for lot in lots.iter_mut().filter(|lot| lot.amount > 0f64) {
+            lot.cost = precision.quote(lot.amount * cost / amount);
+        }
+    }
+
+    fn select(
+        &self,
+        lots: &VecDeque<Lot>,
+        _trade: &Trade,
+        amount: f64,
+        precision: &Precision,
+    ) -> Vec<(usize, f64)> {
+        take_in_order(lots, 0..lots.len(), amount, precision)
+    }
+}
+
+/// Returns the strategy of one of `METHODS`.
+pub fn strategy(name: &str) -> Result<Arc<dyn CostBasisStrategy>, String> {
+    match name {
+        "fifo" => Ok(Arc::new(Fifo)),
+        "lifo" => Ok(Arc::new(Lifo)),
+        "hifo" => Ok(Arc::new(Hifo)),
+        "lofo" => Ok(Arc::new(Lofo)),
+        "acb" => Ok(Arc::new(Acb)),
+        _ => Err(format!(
+            "Unknown method '{}', expected one of {}!",
+            name,
+            METHODS.join(", ")
+        )),
+    }
+}
+
+/// How the lots of the sells are selected. The default is FIFO.
+#[derive(Debug, Clone)]
+pub struct LotSelection {
+    pub strategy: Arc<dyn CostBasisStrategy>,
+}
+
+impl Default for LotSelection {
+    fn default() -> Self {
+        LotSelection {
+            strategy: Arc::new(Fifo),
+        }
+    }
+}
+
+impl LotSelection {
+    /// Returns the name of the method, as stated in the reports.
+    pub fn method(&self) -> String {
+        self.strategy.name()
+    }
+}
+
+/// Prints the PnL of several strategies computed over the same trades.
+///
+/// # Arguments
+///
+/// * `results` - The names of the strategies and their PnL.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn print_comparison(results: &[(String, PnL)], precision: &Precision) {
+    println!("Comparison of the methods:");
+    let header: Vec<String> = [
+        "Method",
+        "Realized PnL",
+        "Unrealized PnL",
+        "Cost of Sold",
+        "Disposals",
+        "Open Lots",
+    ]
+    .iter()
+    .map(|column| column.to_string())
+    .collect();
+    let rows: Vec<Vec<String>> = results
+        .iter()
+        .map(|(method, pnl)| {
+            vec![
+                method.clone(),
+                precision.fmt_quote(pnl.realized_pnl),
+                precision.fmt_quote(pnl.unrealized_pnl),
+                precision.fmt_quote(pnl.total_cost_of_sold_assets),
+                pnl.disposals.len().to_string(),
+                pnl.open_lots.len().to_string(),
+            ]
+        })
+        .collect();
+    print_table(&header, &rows, 1);
+}
@@ -0,0 +1,91 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module reads the API credentials. They are taken from the environment,
+which is extended by the variables of a `.env` file in the working directory,
+or from the OS keyring (macOS Keychain, Windows Credential Manager or the
+Secret Service on Linux), where they are stored by the `login` subcommand.
+*/
+
+use keyring::Entry;
+use log::warn;
+
+/// The service the credentials are stored under in the OS keyring.
+const SERVICE: &str = "kraken-pnl";
+
+/// Loads the variables of the `.env` file in the working directory, if any,
+/// without overriding variables that are already set.
+pub fn load_dotenv() {
+    match dotenvy::dotenv() {
+        Ok(_) => {}
+        Err(e) if e.not_found() => {}
+        Err(e) => warn!("Failed to load the .env file: {}", e),
+    }
+}
+
+/// Returns the entry of a credential in the OS keyring.
+fn entry(name: &str) -> Result<Entry, String> {
+    Entry::new(SERVICE, name).map_err(|e| format!("Failed to access the keyring: {}", e))
+}
+
+/// Reads a credential from the environment or the OS keyring.
+///
+/// # Arguments
+///
+/// * `name` - The name of the environment variable (e.g., KRAKEN_API_KEY),
+///   which is also the name of the entry in the keyring.
+/// * `keyring` - Whether to read the keyring if the variable is not set.
+///
+/// # Returns
+///
+/// The credential, or `None` if it is neither set nor stored.
+///
+pub fn read_credential(name: &str, keyring: bool) -> Result<Option<String>, String> {
+    if let Ok(value) = std::env::var(name) {
+        return Ok(Some(value));
+    }
+    if !keyring {
+        return Ok(None);
+    }
+    match entry(name)?.get_password() {
+        Ok(value) => Ok(Some(value)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(format!("Failed to read {} from the keyring: {}", name, e)),
+    }
+}
+
+/// Prompts for the credentials without echoing them and stores them in the OS
+/// keyring.
+///
+/// # Arguments
+///
+/// * `names` - The names of the credentials (e.g., KRAKEN_API_KEY).
+///
+pub fn login(names: &[&str]) -> Result<(), String> {
+    for name in names {
+        let value: String = rpassword::prompt_password(format!("{}: ", name))
+            .map_err(|e| format!("Failed to read {}: {}", name, e))?;
+        let value: &str = value.trim();
+        if value.is_empty() {
+            return Err(format!("{} must not be empty!", name));
+        }
+        entry(name)?
+            .set_password(value)
+            .map_err(|e| format!("Failed to store {} in the keyring: {}", name, e))?;
+    }
+    Ok(())
+}
+
+/// Removes the credentials from the OS keyring. Credentials that are not
+/// stored are skipped.
+pub fn logout(names: &[&str]) -> Result<(), String> {
+    for name in names {
+        match entry(name)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => {}
+            Err(e) => return Err(format!("Failed to remove {} from the keyring: {}", name, e)),
+        }
+    }
+    Ok(())
+}
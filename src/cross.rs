@@ -0,0 +1,263 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module merges the trades of other pairs of the account into the computed
+pair, as legs that acquire or dispose of its base currency:
+
+- With `--cross-pairs`, the pairs quoted in its base currency (e.g., XETHXXBT
+  for XXBTZEUR). Selling ETH for BTC disposes of ETH and acquires BTC, which
+  most tax regimes treat as two events, so their sells acquire the base
+  currency and their buys dispose of it. The legs are valued at the daily
+  price of the computed pair.
+- With `--cross-pairs` or `--by-asset`, the pairs of its base currency quoted
+  in a crypto currency or stablecoin (e.g., XETHXXBT or ETH/USDT for
+  XETHZEUR), whose trades acquire or dispose of the base currency like its
+  own trades. Their prices are converted into the quote currency at the daily
+  price of the pair of both quote currencies (e.g., XXBTZEUR).
+- With `--by-asset`, the pairs of its base currency quoted in another fiat
+  currency (e.g., XXBTZUSD for XXBTZEUR), converted at the daily exchange
+  rates of `--fx-source`. Together with the former, the lots are kept per
+  asset instead of per pair, since tax law cares about the asset, not the
+  pair it was traded on.
+
+The prices are taken from the chain of `--price-source`, so the legs are
+valued in the quote currency and, with `--tax-currency`, converted like the
+own trades. The currencies are compared by their common codes (e.g., BTC for
+XXBT and XBT), so the pairs match regardless of the names Kraken uses.
+*/
+
+use crate::cache::Cache;
+use crate::fx::{fetch_fx_rates, FxRates, FxSource};
+use crate::kraken::{KrakenAPI, Trade};
+use crate::margin::is_margin_trade;
+use crate::precision::{AssetPair, PairAliases, Precision};
+use crate::prices::{fetch_prices, PriceSource, Prices};
+use chrono::{DateTime, NaiveDate};
+use log::warn;
+use std::collections::{BTreeMap, HashMap};
+
+/// The common codes of the fiat currencies traded on Kraken.
+pub const FIAT: [&str; 8] = ["EUR", "USD", "GBP", "CAD", "JPY", "CHF", "AUD", "AED"];
+
+/// Returns whether a currency is a fiat currency, by its common code.
+pub fn is_fiat(code: &str) -> bool {
+    FIAT.contains(&code)
+}
+
+/// The trades of the other pairs that are merged into the computed pair.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Scope {
+    /// Whether the pairs quoted in the base currency are merged.
+    pub quoted: bool,
+    /// Whether the pairs of the base currency quoted in crypto currencies or
+    /// stablecoins are merged.
+    pub crypto: bool,
+    /// Whether the pairs of the base currency quoted in fiat currencies are
+    /// merged.
+    pub fiat: bool,
+}
+
+impl Scope {
+    /// Returns whether any trades of other pairs are merged.
+    pub fn any(&self) -> bool {
+        self.quoted || self.crypto || self.fiat
+    }
+}
+
+/// How the trades of another pair are turned into legs of the computed pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Leg {
+    /// The other pair is quoted in the base currency, so its trades move the
+    /// base currency in the opposite direction.
+    Quote,
+    /// The other pair trades the base currency against a crypto currency or
+    /// stablecoin, so its trades move it in the same direction.
+    Base,
+    /// The other pair trades the base currency against another fiat currency,
+    /// converted at the exchange rates.
+    Fiat,
+}
+
+/// The trades valued at the daily prices or rates of the same pair.
+type Group<'a> = (AssetPair, Vec<&'a Trade>);
+
+/// Returns the days of the trades, i.e. those that need a price.
+fn period(trades: &[&Trade]) -> Option<(NaiveDate, NaiveDate)> {
+    let days = trades
+        .iter()
+        .filter_map(|trade| DateTime::from_timestamp(trade.time as i64, 0))
+        .map(|time| time.date_naive());
+    Some((days.clone().min()?, days.max()?))
+}
+
+/// Returns the day of a trade for the error messages.
+fn day(trade: &Trade) -> String {
+    DateTime::from_timestamp(trade.time as i64, 0)
+        .map(|time| time.date_naive().to_string())
+        .unwrap_or_default()
+}
+
+/// Returns the legs of the trades of other pairs that acquire or dispose of
+/// the base currency of a trading pair.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `cache` - The cache of the prices, if available.
+/// * `asset_pair` - The computed trading pair.
+/// * `trades` - The trades of the other pairs of the account.
+/// * `aliases` - The trading pairs listed by Kraken.
+/// * `scope` - The trades of the other pairs that are merged.
+/// * `chain` - The sources of the daily prices in the order of preference.
+/// * `coingecko_ids` - The CoinGecko ids by currency code (e.g., BTC).
+/// * `fx_source` - The source of the daily exchange rates.
+/// * `precision` - The precision of the base and quote currency.
+///
+/// # Returns
+///
+/// The legs as trades of the computed pair, sorted by time, or an error if
+/// a leg can't be valued.
+///
+#[allow(clippy::too_many_arguments)]
+pub async fn cross_pair_trades(
+    api: &KrakenAPI,
+    cache: Option<&Cache>,
+    asset_pair: &AssetPair,
+    trades: &[Trade],
+    aliases: &PairAliases,
+    scope: Scope,
+    chain: &[PriceSource],
+    coingecko_ids: &HashMap<String, String>,
+    fx_source: FxSource,
+    precision: &Precision,
+) -> Result<Vec<Trade>, String> {
+    let (base, quote): (String, String) = (asset_pair.base_code(), asset_pair.quote_code());
+    // The trades are grouped by the pair their legs are valued at.
+    let mut groups: BTreeMap<(Leg, String), Group> = BTreeMap::new();
+    for trade in trades.iter().filter(|trade| !is_margin_trade(trade)) {
+        let Some(other) = aliases.get(&trade.pair) else {
+            warn!(
+                "Unknown trading pair {}, leaving out its trade {}.",
+                trade.pair, trade.txid
+            );
+            continue;
+        };
+        if other.name == asset_pair.name {
+            continue;
+        }
+        let other_quote: String = other.quote_code();
+        let (leg, valuation): (Leg, AssetPair) = if scope.quoted && other_quote == base {
+            (Leg::Quote, asset_pair.clone())
+        } else if other.base_code() != base || other_quote == quote {
+            continue;
+        } else if is_fiat(&other_quote) {
+            if !scope.fiat {
+                continue;
+            }
+            (Leg::Fiat, other.clone())
+        } else {
+            if !scope.crypto {
+                continue;
+            }
+            let name: String = format!("{}/{}", other_quote, quote);
+            let valuation: &AssetPair = aliases.get(&name).ok_or_else(|| {
+                format!(
+                    "Kraken lists no pair {} to value the trades of {} in {}!",
+                    name, other.name, quote
+                )
+            })?;
+            (Leg::Base, valuation.clone())
+        };
+        groups
+            .entry((leg, valuation.name.clone()))
+            .or_insert_with(|| (valuation, Vec::new()))
+            .1
+            .push(trade);
+    }
+
+    let mut legs: Vec<Trade> = Vec::new();
+    for ((leg, _), (valuation, group)) in &groups {
+        let Some((from, to)) = period(group) else {
+            continue;
+        };
+        // The value of one unit of the currency the trades are quoted in, in
+        // the quote currency of the computed pair, by trade.
+        let rates: Vec<f64> = if *leg == Leg::Fiat {
+            let rates: FxRates =
+                fetch_fx_rates(api, cache, valuation, &quote, (from, to), fx_source).await?;
+            group
+                .iter()
+                .map(|trade| rates.required_rate_at(trade.time))
+                .collect::<Result<_, String>>()?
+        } else {
+            let prices: Prices =
+                fetch_prices(api, cache, valuation, chain, coingecko_ids, from, to).await;
+            group
+                .iter()
+                .map(|trade| {
+                    prices
+                        .price_at_time(trade.time)
+                        .map(|(price, _)| price)
+                        .ok_or_else(|| {
+                            format!(
+                                "No price of {} available for {} to value the trade {} of {}!",
+                                valuation.name,
+                                day(trade),
+                                trade.txid,
+                                trade.pair
+                            )
+                        })
+                })
+                .collect::<Result<_, String>>()?
+        };
+        for (trade, rate) in group.iter().zip(rates) {
+            let number = |value: &str| value.parse::<f64>().unwrap_or(0f64);
+            let (vol, cost, fee): (f64, f64, f64) =
+                (number(&trade.vol), number(&trade.cost), number(&trade.fee));
+            legs.push(match leg {
+                // The value in the base currency is the amount of the leg, and
+                // the fee, charged in it, changes the amount moved.
+                Leg::Quote => Trade {
+                    txid: trade.txid.clone(),
+                    ordertxid: trade.ordertxid.clone(),
+                    pair: asset_pair.name.clone(),
+                    time: trade.time,
+                    side: if trade.side == "sell" { "buy" } else { "sell" }.to_string(),
+                    price: rate.to_string(),
+                    fee: precision.quote(fee * rate).to_string(),
+                    vol: precision.base(cost).to_string(),
+                    cost: precision.quote(cost * rate).to_string(),
+                    ordertype: trade.ordertype.clone(),
+                    margin: String::new(),
+                    misc: trade.misc.clone(),
+                    postxid: String::new(),
+                    maker: trade.maker,
+                    base_fee: (fee > 0f64).then(|| precision.base(fee)),
+                    source: None,
+                },
+                Leg::Base | Leg::Fiat => Trade {
+                    txid: trade.txid.clone(),
+                    ordertxid: trade.ordertxid.clone(),
+                    pair: asset_pair.name.clone(),
+                    time: trade.time,
+                    side: trade.side.clone(),
+                    price: (number(&trade.price) * rate).to_string(),
+                    fee: precision.quote(fee * rate).to_string(),
+                    vol: precision.base(vol).to_string(),
+                    cost: precision.quote(cost * rate).to_string(),
+                    ordertype: trade.ordertype.clone(),
+                    margin: String::new(),
+                    misc: trade.misc.clone(),
+                    postxid: String::new(),
+                    maker: trade.maker,
+                    base_fee: trade.base_fee,
+                    source: None,
+                },
+            });
+        }
+    }
+    legs.sort_by(|a, b| a.time.total_cmp(&b.time));
+    Ok(legs)
+}
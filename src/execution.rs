@@ -0,0 +1,188 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module breaks the realized PnL and the fees down by the type of the
+orders (e.g., limit or market) and whether they made or took liquidity, which
+shows how much of the result is paid for taking liquidity.
+
+The realized PnL of a disposal counts towards the order that disposed of the
+lot, i.e. the sell, or the short sale for a short lot that was closed. The fees
+count towards the trades they were charged for, the buys included. The
+liquidity is taken from the trade history if Kraken states it, otherwise it is
+derived from the order type like in the fee summary.
+*/
+
+use crate::fees::{is_maker, FeeTotals};
+use crate::fifo::Disposal;
+use crate::fiscal::YearStart;
+use crate::kraken::Trade;
+use crate::precision::Precision;
+use crate::report::print_table;
+use std::collections::BTreeMap;
+
+/// The realized PnL and the fees of the orders of a type and liquidity.
+#[derive(Debug, Default)]
+pub struct ExecutionTotals {
+    pub fees: FeeTotals,
+    pub disposals: usize,
+    pub proceeds: f64,
+    pub cost: f64,
+    pub gain: f64,
+}
+
+/// The breakdown by order type and liquidity.
+#[derive(Debug, Default)]
+pub struct Execution {
+    /// The totals by order type and liquidity (maker or taker).
+    pub groups: BTreeMap<(String, &'static str), ExecutionTotals>,
+    /// The number of trades whose liquidity was derived from the order type.
+    pub derived: usize,
+}
+
+/// Returns the liquidity of an order as stated or derived.
+fn liquidity(maker: Option<bool>, ordertype: &str) -> &'static str {
+    if is_maker(maker, ordertype) {
+        "maker"
+    } else {
+        "taker"
+    }
+}
+
+/// Sums up the disposals and the fees by the type and liquidity of their
+/// orders.
+///
+/// # Arguments
+///
+/// * `disposals` - The per-lot disposals.
+/// * `trades` - The trades the PnL was computed from. Credits and opening
+///   balances are ignored.
+/// * `year` - An optional year to filter the fees by, like the disposals.
+/// * `year_start` - The first day of the fiscal years.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn group_by_execution(
+    disposals: &[Disposal],
+    trades: &[Trade],
+    year: Option<u32>,
+    year_start: YearStart,
+    precision: &Precision,
+) -> Execution {
+    let mut execution: Execution = Execution::default();
+    for trade in trades {
+        if !year_start.contains(year, trade.time) || (trade.side != "buy" && trade.side != "sell") {
+            continue;
+        }
+        if trade.maker.is_none() {
+            execution.derived += 1;
+        }
+        execution
+            .groups
+            .entry((
+                trade.ordertype.clone(),
+                liquidity(trade.maker, &trade.ordertype),
+            ))
+            .or_default()
+            .fees
+            .add(
+                trade.cost.parse().unwrap(),
+                trade.fee.parse().unwrap(),
+                precision,
+            );
+    }
+    for disposal in disposals {
+        let totals: &mut ExecutionTotals = execution
+            .groups
+            .entry((
+                disposal.disposal_ordertype.clone(),
+                liquidity(disposal.disposal_maker, &disposal.disposal_ordertype),
+            ))
+            .or_default();
+        totals.disposals += 1;
+        totals.proceeds = precision.quote(totals.proceeds + disposal.proceeds);
+        totals.cost = precision.quote(totals.cost + disposal.acquisition_cost);
+        totals.gain = precision.quote(totals.gain + disposal.gain);
+    }
+    execution
+}
+
+/// Prints the realized PnL and the fees by order type and liquidity, and what
+/// the taker trades would have cost at the fee rate of the maker trades.
+///
+/// # Arguments
+///
+/// * `execution` - The totals by order type and liquidity.
+/// * `quote` - The quote currency the fees are valued in.
+/// * `gain_currency` - The currency the gains are valued in.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn print_execution_summary(
+    execution: &Execution,
+    quote: &str,
+    gain_currency: &str,
+    precision: &Precision,
+) {
+    println!("Realized PnL and fees by order type and liquidity of the orders:");
+    let header: Vec<String> = vec![
+        "Order type".to_string(),
+        "Liquidity".to_string(),
+        "Trades".to_string(),
+        format!("Volume ({})", quote),
+        format!("Fees ({})", quote),
+        "Fees (%)".to_string(),
+        "Disposals".to_string(),
+        format!("Proceeds ({})", gain_currency),
+        format!("Cost ({})", gain_currency),
+        format!("Gain ({})", gain_currency),
+    ];
+    let rows: Vec<Vec<String>> = execution
+        .groups
+        .iter()
+        .map(|((ordertype, liquidity), totals)| {
+            vec![
+                ordertype.clone(),
+                liquidity.to_string(),
+                totals.fees.trades.to_string(),
+                precision.fmt_quote(totals.fees.volume),
+                precision.fmt_quote(totals.fees.fees),
+                format!("{:.4}", totals.fees.percentage()),
+                totals.disposals.to_string(),
+                precision.fmt_quote(totals.proceeds),
+                precision.fmt_quote(totals.cost),
+                precision.fmt_quote(totals.gain),
+            ]
+        })
+        .collect();
+    print_table(&header, &rows, 2);
+    if execution.derived > 0 {
+        println!(
+            "The liquidity of {} trades is derived from the order type, since Kraken doesn't state it.",
+            execution.derived
+        );
+    }
+
+    // The volume and the fees of all orders of a liquidity.
+    let totals = |side: &str| -> (f64, f64) {
+        execution
+            .groups
+            .iter()
+            .filter(|((_, liquidity), _)| *liquidity == side)
+            .fold((0f64, 0f64), |(volume, fees), (_, totals)| {
+                (volume + totals.fees.volume, fees + totals.fees.fees)
+            })
+    };
+    let ((maker_volume, maker_fees), (taker_volume, taker_fees)) =
+        (totals("maker"), totals("taker"));
+    if maker_volume > 0f64 && taker_volume > 0f64 {
+        let maker_rate: f64 = maker_fees / maker_volume;
+        println!(
+            "Taking liquidity cost {} {} in fees, {} {} more than at the fee rate of the maker trades ({:.4}%).",
+            precision.fmt_quote(taker_fees),
+            quote,
+            precision.fmt_quote(taker_fees - taker_volume * maker_rate),
+            quote,
+            maker_rate * 100f64
+        );
+    }
+}
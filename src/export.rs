@@ -0,0 +1,149 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module exports the disposals in the CSV layouts expected by tax forms and
+tax software. In contrast to the other reports, the exported files do not
+contain the report metadata, since the importing tools do not accept comment
+lines.
+*/
+
+use crate::fifo::Disposal;
+use crate::precision::{AssetPair, Precision};
+use crate::tax::TaxRules;
+use chrono::DateTime;
+use std::fs::File;
+use std::io::Write;
+
+/// The supported export formats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// IRS Form 8949 line items, as accepted by most US tax software.
+    Form8949,
+    /// Koinly's universal CSV format.
+    Koinly,
+    /// CoinTracking's CSV import format.
+    CoinTracking,
+}
+
+impl ExportFormat {
+    /// The values accepted by `--export`.
+    pub const NAMES: [&'static str; 3] = ["8949", "koinly", "cointracking"];
+
+    /// Returns the export format for one of `NAMES`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "8949" => Some(Self::Form8949),
+            "koinly" => Some(Self::Koinly),
+            "cointracking" => Some(Self::CoinTracking),
+            _ => None,
+        }
+    }
+
+    /// The name of the file the export is written to.
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Self::Form8949 => "form_8949.csv",
+            Self::Koinly => "koinly.csv",
+            Self::CoinTracking => "cointracking.csv",
+        }
+    }
+}
+
+/// Formats a UNIX timestamp with the given format string.
+fn format_date(time: f64, format: &str) -> String {
+    DateTime::from_timestamp_nanos((time * 1e9) as i64)
+        .format(format)
+        .to_string()
+}
+
+/// Writes the disposals to a CSV file in the given export format.
+///
+/// # Arguments
+///
+/// * `disposals` - The disposals to be exported.
+/// * `format` - The export format.
+/// * `file_path` - The path of the CSV file.
+/// * `asset_pair` - The assets of the trading pair, used to name the
+///   currencies.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn write_export(
+    disposals: &[Disposal],
+    format: ExportFormat,
+    file_path: &str,
+    asset_pair: &AssetPair,
+    precision: &Precision,
+) {
+    let mut file: File = File::create(file_path).expect("Could not create file");
+    let base: String = asset_pair.base_code();
+    let quote: String = asset_pair.quote_code();
+    let acquired = |disposal: &Disposal, format: &str, unknown: &str| {
+        disposal
+            .acquisition_time
+            .map(|time| format_date(time, format))
+            .unwrap_or_else(|| unknown.to_string())
+    };
+
+    let header: &str = match format {
+        ExportFormat::Form8949 => {
+            "Description,Date Acquired,Date Sold,Proceeds,Cost Basis,Adjustment Code,Adjustment Amount,Gain or Loss,Term"
+        }
+        ExportFormat::Koinly => {
+            "Date,Sent Amount,Sent Currency,Received Amount,Received Currency,Fee Amount,Fee Currency,Net Worth Amount,Net Worth Currency,Label,Description,TxHash"
+        }
+        ExportFormat::CoinTracking => {
+            "Type,Buy Amount,Buy Currency,Sell Amount,Sell Currency,Fee,Fee Currency,Exchange,Trade-Group,Comment,Date"
+        }
+    };
+    writeln!(file, "{}", header).expect("Failed to write header to CSV!");
+
+    for disposal in disposals {
+        let amount: String = precision.fmt_base(disposal.amount);
+        let proceeds: String = precision.fmt_quote(disposal.proceeds);
+        let cost: String = precision.fmt_quote(disposal.acquisition_cost);
+        let row: String = match format {
+            ExportFormat::Form8949 => format!(
+                "{} {},{},{},{},{},,,{},{}",
+                amount,
+                base,
+                acquired(disposal, "%m/%d/%Y", "VARIOUS"),
+                format_date(disposal.disposal_time, "%m/%d/%Y"),
+                proceeds,
+                cost,
+                precision.fmt_quote(disposal.gain),
+                // The term is always based on the US holding period, regardless
+                // of the selected tax rules.
+                match TaxRules::Us.classify(disposal) {
+                    "long-term" => "Long",
+                    _ => "Short",
+                },
+            ),
+            ExportFormat::Koinly => format!(
+                "{},{},{},{},{},,,,,,Acquired {} at a cost of {} {},{}",
+                format_date(disposal.disposal_time, "%Y-%m-%d %H:%M:%S UTC"),
+                amount,
+                base,
+                proceeds,
+                quote,
+                acquired(disposal, "%Y-%m-%d", "unknown"),
+                cost,
+                quote,
+                disposal.disposal_ordertxid,
+            ),
+            ExportFormat::CoinTracking => format!(
+                "Trade,{},{},{},{},,,Kraken,,Acquired {} at a cost of {} {},{}",
+                proceeds,
+                quote,
+                amount,
+                base,
+                acquired(disposal, "%Y-%m-%d", "unknown"),
+                cost,
+                quote,
+                format_date(disposal.disposal_time, "%Y-%m-%d %H:%M:%S"),
+            ),
+        };
+        writeln!(file, "{}", row).expect("Failed to write export to CSV!");
+    }
+}
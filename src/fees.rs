@@ -0,0 +1,181 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module summarizes the fees paid on a trading pair, which otherwise only
+show up implicitly in the cost basis and the proceeds of the disposals.
+
+Kraken's trade history states whether a trade made or took liquidity only for
+recent trades. For the others, it is derived from the order type: limit orders
+are counted as maker, all other orders as taker. Limit orders that are filled
+immediately are taker trades nevertheless, so the split is an approximation.
+*/
+
+use crate::fiscal::YearStart;
+use crate::kraken::Trade;
+use crate::precision::{AssetPair, Precision};
+use crate::report::print_table;
+
+/// Returns whether a trade made liquidity, as stated by Kraken or derived from
+/// the order type.
+///
+/// # Arguments
+///
+/// * `maker` - Whether the trade made liquidity, if Kraken states it.
+/// * `ordertype` - The type of the order of the trade.
+///
+pub fn is_maker(maker: Option<bool>, ordertype: &str) -> bool {
+    maker.unwrap_or(ordertype == "limit")
+}
+
+/// The fees of a group of trades.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FeeTotals {
+    pub trades: usize,
+    /// The traded volume in the quote currency.
+    pub volume: f64,
+    /// The fees valued in the quote currency.
+    pub fees: f64,
+}
+
+impl FeeTotals {
+    pub fn add(&mut self, volume: f64, fee: f64, precision: &Precision) {
+        self.trades += 1;
+        self.volume = precision.quote(self.volume + volume);
+        self.fees = precision.quote(self.fees + fee);
+    }
+
+    /// Returns the fees as a percentage of the traded volume.
+    pub fn percentage(&self) -> f64 {
+        if self.volume == 0f64 {
+            0f64
+        } else {
+            self.fees / self.volume * 100f64
+        }
+    }
+}
+
+/// The fees paid on a trading pair.
+#[derive(Debug, Default)]
+pub struct FeeSummary {
+    pub maker: FeeTotals,
+    pub taker: FeeTotals,
+    pub total: FeeTotals,
+    /// The fees charged in the quote currency.
+    pub quote_fees: f64,
+    /// The fees charged in the base currency and their value in the quote
+    /// currency, known if the fee currency was taken from the ledger.
+    pub base_fees: (f64, f64),
+}
+
+/// Summarizes the fees of the trades of a trading pair.
+///
+/// # Arguments
+///
+/// * `trades` - The trades. Credits and opening balances are ignored.
+/// * `year` - An optional year to filter the trades by.
+/// * `year_start` - The first day of the fiscal years.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn summarize_fees(
+    trades: &[Trade],
+    year: Option<u32>,
+    year_start: YearStart,
+    precision: &Precision,
+) -> FeeSummary {
+    let mut summary: FeeSummary = FeeSummary::default();
+    for trade in trades {
+        let in_year: bool = year_start.contains(year, trade.time);
+        if !in_year || (trade.side != "buy" && trade.side != "sell") {
+            continue;
+        }
+        let volume: f64 = trade.cost.parse().unwrap();
+        // The fee states the value in the quote currency, even if it was
+        // charged in the base currency.
+        let fee: f64 = trade.fee.parse().unwrap();
+        if is_maker(trade.maker, &trade.ordertype) {
+            summary.maker.add(volume, fee, precision);
+        } else {
+            summary.taker.add(volume, fee, precision);
+        }
+        summary.total.add(volume, fee, precision);
+        match trade.base_fee {
+            Some(base_fee) => {
+                summary.base_fees = (
+                    precision.base(summary.base_fees.0 + base_fee),
+                    precision.quote(summary.base_fees.1 + fee),
+                );
+            }
+            None => summary.quote_fees = precision.quote(summary.quote_fees + fee),
+        }
+    }
+    summary
+}
+
+/// Prints the fees by liquidity and by fee currency.
+///
+/// # Arguments
+///
+/// * `summary` - The summarized fees.
+/// * `asset_pair` - The assets of the trading pair.
+/// * `precision` - The precision of the base and quote currency.
+/// * `from_ledger` - Whether the fee currency was taken from the ledger.
+///
+pub fn print_fee_summary(
+    summary: &FeeSummary,
+    asset_pair: &AssetPair,
+    precision: &Precision,
+    from_ledger: bool,
+) {
+    println!("Fees (maker/taker as stated by Kraken, else derived from the order type):");
+    let header: Vec<String> = vec![
+        "Liquidity".to_string(),
+        "Trades".to_string(),
+        format!("Volume ({})", asset_pair.quote),
+        format!("Fees ({})", asset_pair.quote),
+        "Fees (%)".to_string(),
+    ];
+    let rows: Vec<Vec<String>> = [
+        ("maker", &summary.maker),
+        ("taker", &summary.taker),
+        ("total", &summary.total),
+    ]
+    .iter()
+    .map(|(label, totals)| {
+        vec![
+            label.to_string(),
+            totals.trades.to_string(),
+            precision.fmt_quote(totals.volume),
+            precision.fmt_quote(totals.fees),
+            format!("{:.4}", totals.percentage()),
+        ]
+    })
+    .collect();
+    print_table(&header, &rows, 1);
+
+    let header: Vec<String> = vec![
+        "Fee currency".to_string(),
+        "Amount".to_string(),
+        format!("Value ({})", asset_pair.quote),
+    ];
+    let mut rows: Vec<Vec<String>> = vec![vec![
+        asset_pair.quote.clone(),
+        precision.fmt_quote(summary.quote_fees),
+        precision.fmt_quote(summary.quote_fees),
+    ]];
+    if summary.base_fees.0 > 0f64 {
+        rows.push(vec![
+            asset_pair.base.clone(),
+            precision.fmt_base(summary.base_fees.0),
+            precision.fmt_quote(summary.base_fees.1),
+        ]);
+    }
+    print_table(&header, &rows, 1);
+    if !from_ledger {
+        println!(
+            "Fees charged in {} are only known with --ledger-fees.",
+            asset_pair.base
+        );
+    }
+}
@@ -0,0 +1,860 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module contains the lot engine. Every buy opens a lot, every sell
+consumes the oldest lots first (FIFO), or the lots selected by another
+strategy of the `cost_basis` module, e.g. LIFO or the specific identification
+method (`--method spec-id`). Each consumed (part of a) lot is recorded as a
+disposal, which allows tracing the realized PnL back to the individual
+purchases.
+
+A sell exceeding the holdings, due to shorting or a missing history, opens a
+short lot with a negative amount for the excess, which the following buys
+close before they open lots of their own. If the history starts in the middle
+of a position, an opening balance seeds the lots instead.
+
+The residuals below a dust threshold that a sell leaves of the lots it
+consumed are swept, i.e. disposed of at no proceeds along with the sell. Small
+lots the sell didn't touch stay open.
+*/
+
+use crate::cost_basis::LotSelection;
+use crate::fiscal::YearStart;
+use crate::kraken::Trade;
+use crate::precision::Precision;
+use crate::wash_sale::{WashSale, WashSaleRule, WashSales};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// An open position created by a buy, or a short lot created by a sell that
+/// exceeds the holdings.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Lot {
+    /// The remaining amount of the base currency, negative for short lots.
+    pub amount: f64,
+    /// The remaining cost of the lot in the quote currency, including fees.
+    /// Short lots have the negative of their proceeds as cost.
+    pub cost: f64,
+    /// The time of the acquisition as UNIX timestamp.
+    pub time: f64,
+    /// The order the lot was acquired by.
+    pub ordertxid: String,
+    /// The type of this order, e.g. limit or market.
+    #[serde(default)]
+    pub ordertype: String,
+    /// Whether this order made or took liquidity, if known.
+    #[serde(default)]
+    pub maker: Option<bool>,
+}
+
+/// A (partial) sale of a lot.
+///
+/// A single sell produces one disposal for every lot it consumes. A buy that
+/// closes a short lot produces a disposal at the time of the buy, which is its
+/// acquisition, while the proceeds are those of the sell that opened the short
+/// lot.
+#[derive(Serialize, Debug, Clone)]
+pub struct Disposal {
+    pub amount: f64,
+    pub acquisition_time: Option<f64>,
+    pub acquisition_ordertxid: Option<String>,
+    pub acquisition_cost: f64,
+    pub disposal_time: f64,
+    pub disposal_ordertxid: String,
+    /// The type of the order that disposed of the lot, e.g. limit or market.
+    pub disposal_ordertype: String,
+    /// Whether the order that disposed of the lot made or took liquidity, if
+    /// known.
+    pub disposal_maker: Option<bool>,
+    pub proceeds: f64,
+    pub gain: f64,
+    /// The category assigned by the selected tax rules, if any.
+    pub classification: Option<String>,
+    /// The wash sale of a disposal at a loss, if any.
+    pub wash_sale: Option<WashSale>,
+}
+
+/// The realized PnL of a single trade, a sell or a buy closing short lots.
+#[derive(Debug, Clone, Default)]
+pub struct TradePnL {
+    /// The cost of the (parts of) lots the trade disposed of.
+    pub cost_basis: f64,
+    pub realized_pnl: f64,
+    /// The realized PnL of all trades up to and including this one.
+    pub cumulative_realized_pnl: f64,
+}
+
+/// A sell that exceeded the holdings and opened a short lot.
+#[derive(Debug, Clone)]
+pub struct ShortSale {
+    pub time: f64,
+    pub ordertxid: String,
+    /// The amount the sell exceeded the holdings by.
+    pub amount: f64,
+}
+
+/// Holdings acquired before the first trade of the history.
+#[derive(Debug, Clone)]
+pub struct OpeningBalance {
+    pub amount: f64,
+    /// The price per unit in the quote currency the holdings were acquired at.
+    pub price: f64,
+    /// The date of the acquisition, by default the time of the first trade.
+    pub date: Option<NaiveDate>,
+}
+
+impl OpeningBalance {
+    /// Parses an opening balance of the form `AMOUNT@PRICE[,DATE]`, e.g.
+    /// `0.5@25000,2022-06-01`.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || {
+            format!(
+                "Invalid opening balance '{}', expected AMOUNT@PRICE[,DATE] (e.g., 0.5@25000,2022-06-01)!",
+                value
+            )
+        };
+        let (balance, date) = match value.split_once(',') {
+            Some((balance, date)) => (
+                balance,
+                Some(NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| invalid())?),
+            ),
+            None => (value, None),
+        };
+        let (amount, price) = balance.split_once('@').ok_or_else(invalid)?;
+        let (amount, price): (f64, f64) = (
+            amount.parse().map_err(|_| invalid())?,
+            price.parse().map_err(|_| invalid())?,
+        );
+        if amount <= 0f64 || price < 0f64 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            amount,
+            price,
+            date,
+        })
+    }
+
+    /// Returns the opening balance as trade of the side `opening`, which the
+    /// FIFO lot engine adds to the holdings at its cost.
+    ///
+    /// # Arguments
+    ///
+    /// * `pair` - The trading pair.
+    /// * `time` - The time used if no date is set, i.e. the time of the first
+    ///   trade.
+    /// * `precision` - The precision of the base and quote currency.
+    ///
+    pub fn trade(&self, pair: &str, time: f64, precision: &Precision) -> Trade {
+        Trade {
+            txid: "opening-balance".to_string(),
+            ordertxid: "opening-balance".to_string(),
+            pair: pair.to_string(),
+            time: self.date.map_or(time, |date| {
+                date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp() as f64
+            }),
+            side: "opening".to_string(),
+            price: self.price.to_string(),
+            fee: "0".to_string(),
+            vol: self.amount.to_string(),
+            cost: precision.quote(self.amount * self.price).to_string(),
+            ordertype: "opening".to_string(),
+            margin: String::new(),
+            misc: String::new(),
+            postxid: String::new(),
+            maker: None,
+            base_fee: None,
+            source: None,
+        }
+    }
+}
+
+impl std::fmt::Display for OpeningBalance {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}@{}", self.amount, self.price)?;
+        if let Some(date) = self.date {
+            write!(f, ",{}", date)?;
+        }
+        Ok(())
+    }
+}
+
+/// The result of the PnL computation.
+#[derive(Debug, Default)]
+pub struct PnL {
+    pub realized_pnl: f64,
+    pub unrealized_pnl: f64,
+    /// The price of the last trade, which the unrealized PnL is based on.
+    pub price: f64,
+    pub balance: f64,
+    pub total_buy_volume_base: f64,
+    pub total_sell_volume_base: f64,
+    pub total_buy_volume_quote: f64,
+    pub total_sell_volume_quote: f64,
+    pub total_cost_of_sold_assets: f64,
+    pub total_value_of_sold_assets: f64,
+    /// The disposals that make up the realized PnL.
+    pub disposals: Vec<Disposal>,
+    /// The lots that are still open after processing all trades.
+    pub open_lots: Vec<Lot>,
+    /// The sells that opened short lots.
+    pub short_sales: Vec<ShortSale>,
+    /// The realized PnL of the trades that disposed of lots, by their id.
+    pub trade_pnl: HashMap<String, TradePnL>,
+    /// The amount of the lots swept as dust and their cost.
+    pub dust_swept: f64,
+    pub dust_cost: f64,
+}
+
+/// Computes the FIFO PnL for a given set of trades.
+///
+/// # Arguments
+///
+/// * `trades` - The trades to compute the PnL for, sorted by time. Trades of
+///   the side `credit` open a lot at their cost, usually none, those of the
+///   side `opening` at their cost, or a short lot if their amount is
+///   negative. Trades of the side `lost` dispose of lots at no proceeds,
+///   those of the sides `withdrawal` and `deposit` leave them unchanged.
+/// * `year` - An optional year to filter the trades. If provided, only profits
+///   made within the specified year are considered.
+/// * `year_start` - The first day of the fiscal years.
+/// * `precision` - The precision of the base and quote currency. Every amount
+///   and value is rounded accordingly, so that no artifacts of floating point
+///   arithmetic (e.g. lots of 1e-18 BTC) enter the results.
+///
+/// # Returns
+///
+/// The realized and unrealized PnL, the balance, total buy/sell volumes for
+/// base and quote currencies, the total cost of sold assets and the total
+/// value received from selling them as well as the individual disposals and
+/// the remaining open lots.
+///
+/// This function processes the trades in a FIFO manner to compute the realized
+/// and unrealized PnL. The unrealized PnL is based on the price of the last
+/// trade.
+pub fn compute_fifo_pnl(
+    trades: &[Trade],
+    year: Option<u32>,
+    year_start: YearStart,
+    precision: &Precision,
+) -> PnL {
+    compute_pnl(
+        trades,
+        year,
+        year_start,
+        precision,
+        &LotSelection::default(),
+        None,
+        0f64,
+    )
+}
+
+/// Computes the PnL for a given set of trades like `compute_fifo_pnl`, but
+/// with the lots of the sells selected as configured, e.g. by the specific
+/// identification method.
+///
+/// # Arguments
+///
+/// * `trades` - The trades to compute the PnL for, sorted by time.
+/// * `year` - An optional year to filter the trades.
+/// * `year_start` - The first day of the fiscal years.
+/// * `precision` - The precision of the base and quote currency.
+/// * `selection` - How the lots of the sells are selected.
+/// * `wash_sale` - Whether to flag or defer the wash sales of the US tax
+///   rules, if at all.
+/// * `dust_threshold` - The amount below which the lots left by a sell are
+///   swept, i.e. disposed of at no proceeds. Zero disables the sweeping.
+///
+pub fn compute_pnl(
+    trades: &[Trade],
+    year: Option<u32>,
+    year_start: YearStart,
+    precision: &Precision,
+    selection: &LotSelection,
+    wash_sale: Option<WashSaleRule>,
+    dust_threshold: f64,
+) -> PnL {
+    let mut engine: LotEngine = LotEngine::new(
+        year,
+        year_start,
+        precision,
+        selection,
+        wash_sale.map(|rule| WashSales::new(rule, trades)),
+        dust_threshold,
+    );
+    for trade in trades {
+        engine.feed(trade);
+    }
+    engine.finish()
+}
+
+/// The lot engine fed with one trade after the other, so the trades of a
+/// long history can be streamed through it instead of being held in memory.
+/// Only the open lots are kept, the disposals and the realized PnL of the
+/// trades can be taken as they arise.
+pub struct LotEngine<'a> {
+    year: Option<u32>,
+    year_start: YearStart,
+    precision: Precision,
+    selection: &'a LotSelection,
+    wash_sales: Option<WashSales<'a>>,
+    dust_threshold: f64,
+    lots: VecDeque<Lot>,
+    pnl: PnL,
+    /// The price of the last trade.
+    price: f64,
+}
+
+impl<'a> LotEngine<'a> {
+    /// Creates a lot engine without any lots.
+    ///
+    /// # Arguments
+    ///
+    /// * `year` - An optional year to filter the trades.
+    /// * `year_start` - The first day of the fiscal years.
+    /// * `precision` - The precision of the base and quote currency.
+    /// * `selection` - How the lots of the sells are selected.
+    /// * `wash_sales` - The wash sales to flag or defer, if at all, which
+    ///   require all trades in advance.
+    /// * `dust_threshold` - The amount below which the lots left by a sell are
+    ///   swept. Zero disables the sweeping.
+    ///
+    pub fn new(
+        year: Option<u32>,
+        year_start: YearStart,
+        precision: &Precision,
+        selection: &'a LotSelection,
+        wash_sales: Option<WashSales<'a>>,
+        dust_threshold: f64,
+    ) -> Self {
+        Self {
+            year,
+            year_start,
+            precision: *precision,
+            selection,
+            wash_sales,
+            dust_threshold,
+            lots: VecDeque::new(),
+            pnl: PnL::default(),
+            price: 0f64,
+        }
+    }
+
+    /// Processes the next trade, which must not precede the trades before.
+    pub fn feed(&mut self, trade: &Trade) {
+        let in_year: bool = self.year_start.contains(self.year, trade.time);
+        let amount: f64 = trade.vol.parse().unwrap();
+        // Credits and opening balances are added to the holdings at their
+        // cost, which is none for rebates. Opening short lots carried forward
+        // from a previous run are restored as they were.
+        if trade.side == "opening" && amount < 0f64 {
+            self.lots.push_back(Lot {
+                amount,
+                cost: trade.cost.parse().unwrap(),
+                time: trade.time,
+                ordertxid: trade.ordertxid.clone(),
+                ordertype: trade.ordertype.clone(),
+                maker: trade.maker,
+            });
+            self.pnl.balance = self.precision.base(self.pnl.balance + amount);
+            return;
+        }
+        if trade.side == "credit" || trade.side == "opening" {
+            acquire(
+                &mut self.lots,
+                &mut self.pnl,
+                trade,
+                amount,
+                self.precision.quote(trade.cost.parse().unwrap()),
+                in_year,
+                &self.precision,
+            );
+            self.pnl.balance = self.precision.base(self.pnl.balance + amount);
+            return;
+        }
+        // Transfers between the own wallets and accounts keep the lots.
+        if trade.side == "withdrawal" || trade.side == "deposit" {
+            return;
+        }
+        let trade_price: f64 = trade.price.parse().unwrap();
+        // Lost amounts are disposed of at no proceeds, which says nothing about
+        // the price.
+        if trade.side != "lost" {
+            self.price = trade_price;
+        }
+        // A fee charged in the base currency reduces the amount received or
+        // increases the amount given, otherwise it is charged in the quote
+        // currency (or valued in it).
+        let (fee, base_fee): (f64, f64) = match trade.base_fee {
+            Some(base_fee) => (0f64, base_fee),
+            None => (trade.fee.parse().unwrap(), 0f64),
+        };
+
+        if trade.side == "buy" {
+            let total_cost: f64 = self.precision.quote((amount * trade_price) + fee);
+            let received: f64 = self.precision.base(amount - base_fee);
+            // The losses of wash sales are deferred into the cost of the
+            // replacement lots.
+            let deferred_loss: f64 = self
+                .wash_sales
+                .as_mut()
+                .map_or(0f64, |wash_sales| wash_sales.deferred_loss(trade));
+            acquire(
+                &mut self.lots,
+                &mut self.pnl,
+                trade,
+                received,
+                self.precision.quote(total_cost + deferred_loss),
+                in_year,
+                &self.precision,
+            );
+            self.pnl.balance = self.precision.base(self.pnl.balance + received);
+            self.pnl.total_buy_volume_base =
+                self.precision.base(self.pnl.total_buy_volume_base + amount);
+            self.pnl.total_buy_volume_quote = self
+                .precision
+                .quote(self.pnl.total_buy_volume_quote + total_cost);
+        } else if trade.side == "sell" || trade.side == "lost" {
+            let sell_proceeds: f64 = self.precision.quote((amount * trade_price) - fee);
+            // The cost of a fee charged in the base currency reduces the gain.
+            let given: f64 = self.precision.base(amount + base_fee);
+            let mut cost_basis: f64 = 0f64;
+            let mut base_currency_to_sell: f64 = given;
+            let mut disposals: Vec<Disposal> = Vec::new();
+            let mut dispose = |lot: &Lot, lot_amount: f64, lot_cost: f64| {
+                let proceeds: f64 = self.precision.quote(sell_proceeds * (lot_amount / given));
+                disposals.push(Disposal {
+                    amount: lot_amount,
+                    acquisition_time: Some(lot.time),
+                    acquisition_ordertxid: Some(lot.ordertxid.clone()),
+                    acquisition_cost: lot_cost,
+                    disposal_time: trade.time,
+                    disposal_ordertxid: trade.ordertxid.clone(),
+                    disposal_ordertype: trade.ordertype.clone(),
+                    disposal_maker: trade.maker,
+                    proceeds,
+                    gain: self.precision.quote(proceeds - lot_cost),
+                    classification: None,
+                    wash_sale: None,
+                });
+            };
+
+            self.selection
+                .strategy
+                .prepare(&mut self.lots, &self.precision);
+            let selected: Vec<(usize, f64)> = self.selection.strategy.select(
+                &self.lots,
+                trade,
+                base_currency_to_sell,
+                &self.precision,
+            );
+            // Only the residuals of the lots the sell consumed are swept.
+            let mut consumed: Vec<bool> = vec![false; self.lots.len()];
+            for (index, taken) in selected {
+                consumed[index] = true;
+                let lot: &mut Lot = &mut self.lots[index];
+                if taken >= lot.amount {
+                    cost_basis = self.precision.quote(cost_basis + lot.cost);
+                    base_currency_to_sell = self.precision.base(base_currency_to_sell - lot.amount);
+                    dispose(lot, lot.amount, lot.cost);
+                    lot.amount = 0f64;
+                } else {
+                    let partial_cost: f64 = self.precision.quote((lot.cost / lot.amount) * taken);
+                    cost_basis = self.precision.quote(cost_basis + partial_cost);
+                    dispose(lot, taken, partial_cost);
+                    base_currency_to_sell = self.precision.base(base_currency_to_sell - taken);
+                    lot.amount = self.precision.base(lot.amount - taken);
+                    lot.cost = self.precision.quote(lot.cost - partial_cost);
+                }
+            }
+            // The lots disposed of entirely are closed.
+            let mut closed = consumed.into_iter();
+            let mut consumed: Vec<bool> = Vec::new();
+            self.lots.retain(|lot| {
+                let open: bool = lot.amount != 0f64;
+                let touched: bool = closed.next().unwrap_or(false);
+                if open {
+                    consumed.push(touched);
+                }
+                open
+            });
+            let deferred_loss: f64 = self
+                .wash_sales
+                .as_mut()
+                .filter(|_| trade.side == "sell")
+                .map_or(0f64, |wash_sales| {
+                    wash_sales.check(trade, &mut disposals, &mut self.lots, &self.precision)
+                });
+            // The dust left by the sell is disposed of along with it.
+            let mut swept: f64 = 0f64;
+            let mut consumed = consumed.into_iter();
+            self.lots.retain(|lot| {
+                let touched: bool = consumed.next().unwrap_or(false);
+                if !touched || lot.amount <= 0f64 || lot.amount >= self.dust_threshold {
+                    return true;
+                }
+                cost_basis = self.precision.quote(cost_basis + lot.cost);
+                swept = self.precision.base(swept + lot.amount);
+                self.pnl.dust_swept = self.precision.base(self.pnl.dust_swept + lot.amount);
+                self.pnl.dust_cost = self.precision.quote(self.pnl.dust_cost + lot.cost);
+                disposals.push(Disposal {
+                    amount: lot.amount,
+                    acquisition_time: Some(lot.time),
+                    acquisition_ordertxid: Some(lot.ordertxid.clone()),
+                    acquisition_cost: lot.cost,
+                    disposal_time: trade.time,
+                    disposal_ordertxid: trade.ordertxid.clone(),
+                    disposal_ordertype: trade.ordertype.clone(),
+                    disposal_maker: trade.maker,
+                    proceeds: 0f64,
+                    gain: -lot.cost,
+                    classification: None,
+                    wash_sale: None,
+                });
+                false
+            });
+            let disposed_proceeds: f64 = self.precision.quote(
+                disposals
+                    .iter()
+                    .map(|disposal| disposal.proceeds)
+                    .sum::<f64>(),
+            );
+            // The excess of a sell over the holdings opens a short lot, whose
+            // cost is the negative of the proceeds.
+            if base_currency_to_sell > 0f64 {
+                self.lots.push_back(Lot {
+                    amount: -base_currency_to_sell,
+                    cost: -self.precision.quote(sell_proceeds - disposed_proceeds),
+                    time: trade.time,
+                    ordertxid: trade.ordertxid.clone(),
+                    ordertype: trade.ordertype.clone(),
+                    maker: trade.maker,
+                });
+                self.pnl.short_sales.push(ShortSale {
+                    time: trade.time,
+                    ordertxid: trade.ordertxid.clone(),
+                    amount: base_currency_to_sell,
+                });
+            }
+
+            if in_year {
+                self.pnl.realized_pnl = self
+                    .precision
+                    .quote(self.pnl.realized_pnl + disposed_proceeds - cost_basis + deferred_loss);
+                if !disposals.is_empty() {
+                    self.pnl.trade_pnl.insert(
+                        trade.txid.clone(),
+                        TradePnL {
+                            cost_basis,
+                            realized_pnl: self
+                                .precision
+                                .quote(disposed_proceeds - cost_basis + deferred_loss),
+                            cumulative_realized_pnl: self.pnl.realized_pnl,
+                        },
+                    );
+                }
+                self.pnl.disposals.extend(disposals);
+            }
+            self.pnl.balance = self.precision.base(self.pnl.balance - given - swept);
+            self.pnl.total_sell_volume_base = self
+                .precision
+                .base(self.pnl.total_sell_volume_base + amount);
+            self.pnl.total_sell_volume_quote = self
+                .precision
+                .quote(self.pnl.total_sell_volume_quote + sell_proceeds);
+            self.pnl.total_cost_of_sold_assets = self
+                .precision
+                .quote(self.pnl.total_cost_of_sold_assets + cost_basis);
+            self.pnl.total_value_of_sold_assets = self
+                .precision
+                .quote(self.pnl.total_value_of_sold_assets + disposed_proceeds);
+        }
+    }
+
+    /// Takes the disposals recorded since they were taken last.
+    pub fn take_disposals(&mut self) -> Vec<Disposal> {
+        std::mem::take(&mut self.pnl.disposals)
+    }
+
+    /// Takes the realized PnL of a trade that disposed of lots.
+    pub fn take_trade_pnl(&mut self, txid: &str) -> Option<TradePnL> {
+        self.pnl.trade_pnl.remove(txid)
+    }
+
+    /// Returns the PnL after the trades fed, valuing the open lots at the
+    /// price of the last trade.
+    pub fn finish(self) -> PnL {
+        let Self {
+            precision,
+            lots,
+            mut pnl,
+            price,
+            ..
+        } = self;
+        pnl.unrealized_pnl = precision.quote(
+            lots.iter()
+                .map(|lot| (price - (lot.cost / lot.amount)) * lot.amount)
+                .sum(),
+        );
+        pnl.price = price;
+        pnl.open_lots = lots.into();
+        pnl
+    }
+}
+
+/// Adds an acquisition to the lots. It closes the short lots first, which
+/// realizes their PnL, and opens a lot with the remaining amount.
+///
+/// # Arguments
+///
+/// * `lots` - The open lots.
+/// * `pnl` - The PnL the closed short lots are added to.
+/// * `trade` - The trade of the acquisition.
+/// * `amount` - The amount acquired.
+/// * `cost` - The cost of the acquired amount.
+/// * `in_year` - Whether the trade is within the selected year.
+/// * `precision` - The precision of the base and quote currency.
+///
+fn acquire(
+    lots: &mut VecDeque<Lot>,
+    pnl: &mut PnL,
+    trade: &Trade,
+    amount: f64,
+    cost: f64,
+    in_year: bool,
+    precision: &Precision,
+) {
+    let mut remaining: f64 = amount;
+    let mut trade_pnl: Option<TradePnL> = None;
+    while remaining > 0f64 && lots.front().is_some_and(|lot| lot.amount < 0f64) {
+        let lot: Lot = lots.pop_front().unwrap();
+        let closed: f64 = remaining.min(-lot.amount);
+        let proceeds: f64 = precision.quote((lot.cost / lot.amount) * closed);
+        let acquisition_cost: f64 = precision.quote(cost * (closed / amount));
+        if closed < -lot.amount {
+            lots.push_front(Lot {
+                amount: precision.base(lot.amount + closed),
+                cost: precision.quote(lot.cost + proceeds),
+                ..lot.clone()
+            });
+        }
+        if in_year {
+            pnl.realized_pnl = precision.quote(pnl.realized_pnl + proceeds - acquisition_cost);
+            let closing: &mut TradePnL = trade_pnl.get_or_insert_with(TradePnL::default);
+            closing.cost_basis = precision.quote(closing.cost_basis + acquisition_cost);
+            closing.realized_pnl =
+                precision.quote(closing.realized_pnl + proceeds - acquisition_cost);
+            closing.cumulative_realized_pnl = pnl.realized_pnl;
+            pnl.disposals.push(Disposal {
+                amount: closed,
+                acquisition_time: Some(trade.time),
+                acquisition_ordertxid: Some(trade.ordertxid.clone()),
+                acquisition_cost,
+                disposal_time: trade.time,
+                disposal_ordertxid: lot.ordertxid,
+                disposal_ordertype: lot.ordertype,
+                disposal_maker: lot.maker,
+                proceeds,
+                gain: precision.quote(proceeds - acquisition_cost),
+                classification: None,
+                wash_sale: None,
+            });
+        }
+        pnl.total_cost_of_sold_assets =
+            precision.quote(pnl.total_cost_of_sold_assets + acquisition_cost);
+        pnl.total_value_of_sold_assets = precision.quote(pnl.total_value_of_sold_assets + proceeds);
+        remaining = precision.base(remaining - closed);
+    }
+    if let Some(trade_pnl) = trade_pnl {
+        pnl.trade_pnl.insert(trade.txid.clone(), trade_pnl);
+    }
+    if remaining > 0f64 {
+        lots.push_back(Lot {
+            amount: remaining,
+            cost: precision.quote(cost * (remaining / amount)),
+            time: trade.time,
+            ordertxid: trade.ordertxid.clone(),
+            ordertype: trade.ordertype.clone(),
+            maker: trade.maker,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cost_basis::{strategy, METHODS};
+
+    const PRECISION: Precision = Precision { base: 8, quote: 2 };
+
+    /// Returns a spot trade of XXBTZEUR.
+    fn trade(number: usize, side: &str, vol: f64, price: f64, fee: f64) -> Trade {
+        Trade {
+            txid: format!("T{:04}", number),
+            ordertxid: format!("O{:04}", number),
+            pair: "XXBTZEUR".to_string(),
+            time: 1704067200f64 + number as f64 * 3600f64,
+            side: side.to_string(),
+            price: format!("{:.2}", price),
+            fee: format!("{:.2}", fee),
+            vol: format!("{:.8}", vol),
+            cost: format!("{:.2}", vol * price),
+            ordertype: "limit".to_string(),
+            margin: String::new(),
+            misc: String::new(),
+            postxid: String::new(),
+            maker: None,
+            base_fee: None,
+            source: None,
+        }
+    }
+
+    /// Returns the PnL of the trades by a method of `METHODS`.
+    fn pnl(trades: &[Trade], method: &str) -> PnL {
+        compute_pnl(
+            trades,
+            None,
+            YearStart::default(),
+            &PRECISION,
+            &LotSelection {
+                strategy: strategy(method).unwrap(),
+            },
+            None,
+            0f64,
+        )
+    }
+
+    #[test]
+    fn methods_dispose_of_their_lots() {
+        // The lots cost 150, 101 (including the fee), 200 and 120 per unit.
+        let trades: Vec<Trade> = vec![
+            trade(0, "buy", 1f64, 150f64, 0f64),
+            trade(1, "buy", 1f64, 100f64, 1f64),
+            trade(2, "buy", 1f64, 200f64, 0f64),
+            trade(3, "buy", 1f64, 120f64, 0f64),
+            trade(4, "sell", 1f64, 300f64, 0f64),
+        ];
+        for (method, cost) in [
+            ("fifo", 150f64),
+            ("lifo", 120f64),
+            ("hifo", 200f64),
+            ("lofo", 101f64),
+            ("acb", 142.75),
+        ] {
+            let pnl: PnL = pnl(&trades, method);
+            assert_eq!(pnl.total_cost_of_sold_assets, cost, "{}", method);
+            assert_eq!(
+                pnl.realized_pnl,
+                PRECISION.quote(300f64 - cost),
+                "{}",
+                method
+            );
+            assert_eq!(pnl.balance, 3f64, "{}", method);
+            assert_eq!(pnl.disposals.len(), 1, "{}", method);
+        }
+    }
+
+    #[test]
+    fn fees_add_to_the_cost_and_reduce_the_proceeds() {
+        let trades: Vec<Trade> = vec![
+            trade(0, "buy", 1f64, 100f64, 2f64),
+            trade(1, "sell", 1f64, 110f64, 3f64),
+        ];
+        let pnl: PnL = pnl(&trades, "fifo");
+        assert_eq!(pnl.total_buy_volume_quote, 102f64);
+        assert_eq!(pnl.total_cost_of_sold_assets, 102f64);
+        assert_eq!(pnl.total_value_of_sold_assets, 107f64);
+        assert_eq!(pnl.realized_pnl, 5f64);
+        assert!(pnl.open_lots.is_empty());
+    }
+
+    #[test]
+    fn sell_spanning_lots_is_split_pro_rata() {
+        let trades: Vec<Trade> = vec![
+            trade(0, "buy", 0.5, 100f64, 0f64),
+            trade(1, "buy", 0.5, 200f64, 0f64),
+            trade(2, "sell", 0.75, 300f64, 0.45),
+        ];
+        let pnl: PnL = pnl(&trades, "fifo");
+        let disposals: Vec<(f64, f64, f64)> = pnl
+            .disposals
+            .iter()
+            .map(|disposal| {
+                (
+                    disposal.amount,
+                    disposal.acquisition_cost,
+                    disposal.proceeds,
+                )
+            })
+            .collect();
+        assert_eq!(disposals, vec![(0.5, 50f64, 149.7), (0.25, 50f64, 74.85)]);
+        assert_eq!(pnl.realized_pnl, 124.55);
+        assert_eq!(pnl.open_lots.len(), 1);
+        assert_eq!(pnl.open_lots[0].amount, 0.25);
+        assert_eq!(pnl.open_lots[0].cost, 50f64);
+    }
+
+    #[test]
+    fn split_fills_realize_the_same_pnl() {
+        let filled: Vec<Trade> = vec![
+            trade(0, "buy", 1f64, 100f64, 0.26),
+            trade(1, "buy", 1f64, 140f64, 0.36),
+            trade(2, "sell", 1.5, 180f64, 0.7),
+        ];
+        let split: Vec<Trade> = vec![
+            trade(0, "buy", 0.4, 100f64, 0.1),
+            trade(1, "buy", 0.6, 100f64, 0.16),
+            trade(2, "buy", 1f64, 140f64, 0.36),
+            trade(3, "sell", 0.5, 180f64, 0.2),
+            trade(4, "sell", 1f64, 180f64, 0.5),
+        ];
+        for method in METHODS {
+            let (filled, split): (PnL, PnL) = (pnl(&filled, method), pnl(&split, method));
+            assert_eq!(filled.realized_pnl, split.realized_pnl, "{}", method);
+            assert_eq!(filled.unrealized_pnl, split.unrealized_pnl, "{}", method);
+            assert_eq!(filled.balance, split.balance, "{}", method);
+        }
+    }
+
+    #[test]
+    fn sell_exceeding_the_holdings_opens_a_short_lot() {
+        let trades: Vec<Trade> = vec![
+            trade(0, "buy", 1f64, 100f64, 0f64),
+            trade(1, "sell", 1.5, 120f64, 0f64),
+        ];
+        let pnl: PnL = pnl(&trades, "fifo");
+        assert_eq!(pnl.realized_pnl, 20f64);
+        assert_eq!(pnl.balance, -0.5);
+        assert_eq!(pnl.open_lots.len(), 1);
+        assert_eq!(pnl.open_lots[0].amount, -0.5);
+        assert_eq!(pnl.open_lots[0].cost, -60f64);
+    }
+
+    #[test]
+    fn sell_only_sweeps_the_dust_of_its_lots() {
+        // The second lot is below the threshold, but not touched by the sell.
+        let trades: Vec<Trade> = vec![
+            trade(0, "buy", 1f64, 100f64, 0f64),
+            trade(1, "buy", 0.00005, 100f64, 0f64),
+            trade(2, "sell", 0.99995, 200f64, 0f64),
+        ];
+        let pnl: PnL = compute_pnl(
+            &trades,
+            None,
+            YearStart::default(),
+            &PRECISION,
+            &LotSelection {
+                strategy: strategy("fifo").unwrap(),
+            },
+            None,
+            0.0001,
+        );
+        assert_eq!(pnl.dust_swept, 0.00005);
+        assert_eq!(pnl.open_lots.len(), 1);
+        assert_eq!(pnl.open_lots[0].ordertxid, "O0001");
+        assert_eq!(pnl.open_lots[0].amount, 0.00005);
+    }
+}
@@ -0,0 +1,138 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module implements fiscal years that don't start on January 1, e.g. those
+running from April to March. A fiscal year is named after the calendar year it
+starts in, so with a start of 04-01 the fiscal year 2024 runs from 2024-04-01
+until 2025-03-31. The `--year` filter and the yearly breakdowns refer to the
+fiscal years.
+*/
+
+use chrono::{DateTime, Datelike, NaiveDate};
+use std::fmt;
+
+/// The first day of the fiscal years.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct YearStart {
+    month: u32,
+    day: u32,
+}
+
+impl Default for YearStart {
+    fn default() -> Self {
+        YearStart { month: 1, day: 1 }
+    }
+}
+
+impl fmt::Display for YearStart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02}-{:02}", self.month, self.day)
+    }
+}
+
+impl YearStart {
+    /// Parses the first day of the fiscal years given as `MM-DD`.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The month and day (e.g., 04-01).
+    ///
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || {
+            format!(
+                "Invalid fiscal year start '{}', expected MM-DD (e.g., 04-01)!",
+                value
+            )
+        };
+        let (month, day) = value.trim().split_once('-').ok_or_else(invalid)?;
+        let (month, day): (u32, u32) = (
+            month.parse().map_err(|_| invalid())?,
+            day.parse().map_err(|_| invalid())?,
+        );
+        // February 29 doesn't exist in every year, so a non-leap year is
+        // used for the validation.
+        NaiveDate::from_ymd_opt(2001, month, day).ok_or_else(invalid)?;
+        Ok(YearStart { month, day })
+    }
+
+    /// Returns whether the fiscal years are the calendar years.
+    pub fn is_calendar(&self) -> bool {
+        *self == YearStart::default()
+    }
+
+    /// Returns the fiscal year a day belongs to.
+    pub fn year_of_date(&self, date: NaiveDate) -> i32 {
+        if (date.month(), date.day()) < (self.month, self.day) {
+            date.year() - 1
+        } else {
+            date.year()
+        }
+    }
+
+    /// Returns the fiscal year a point in time (in seconds since the epoch,
+    /// UTC) belongs to.
+    pub fn year_of(&self, time: f64) -> i32 {
+        self.year_of_date(DateTime::from_timestamp_nanos((time * 1e9) as i64).date_naive())
+    }
+
+    /// Returns whether a point in time belongs to the selected fiscal year.
+    /// Every point in time matches if no year is selected.
+    pub fn contains(&self, year: Option<u32>, time: f64) -> bool {
+        year.is_none_or(|year| self.year_of(time) == year as i32)
+    }
+
+    /// Returns the name of a fiscal year, e.g. `2024` for calendar years and
+    /// `2024/25` otherwise.
+    pub fn label(&self, year: i32) -> String {
+        if self.is_calendar() {
+            year.to_string()
+        } else {
+            format!("{}/{:02}", year, (year + 1).rem_euclid(100))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn parses_the_year_start() {
+        assert_eq!(
+            YearStart::parse("04-01"),
+            Ok(YearStart { month: 4, day: 1 })
+        );
+        assert_eq!(YearStart::parse(" 4-6 ").unwrap().to_string(), "04-06");
+        assert!(YearStart::parse("01-01").unwrap().is_calendar());
+        for invalid in ["13-01", "02-30", "04", "April 1"] {
+            assert!(YearStart::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn names_the_year_after_its_start() {
+        let start: YearStart = YearStart::parse("04-01").unwrap();
+        assert_eq!(start.year_of_date(date(2024, 3, 31)), 2023);
+        assert_eq!(start.year_of_date(date(2024, 4, 1)), 2024);
+        assert_eq!(start.year_of_date(date(2025, 3, 31)), 2024);
+        assert_eq!(start.label(2024), "2024/25");
+        assert_eq!(start.label(1999), "1999/00");
+        assert_eq!(YearStart::default().label(2024), "2024");
+    }
+
+    #[test]
+    fn contains_the_times_of_the_year() {
+        let start: YearStart = YearStart::parse("07-01").unwrap();
+        // 2024-06-30 23:59:59 and 2024-07-01 00:00:00 UTC.
+        assert!(start.contains(Some(2023), 1719791999f64));
+        assert!(start.contains(Some(2024), 1719792000f64));
+        assert!(!start.contains(Some(2024), 1719791999f64));
+        assert!(start.contains(None, 1719791999f64));
+    }
+}
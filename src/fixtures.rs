@@ -0,0 +1,163 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module implements the fixtures of `--mock-dir`: canned responses of the
+Kraken API stored as files, which the client answers its requests from
+instead of the network, e.g. for integration tests, reproducible bug reports
+or demos without credentials. With `--record`, the responses of the API are
+captured into the directory instead.
+
+A response is stored under the endpoint and a digest of the parameters of the
+request (e.g., `0_private_TradesHistory-3f2a9c01d4b5e687.json`), so only
+requests with exactly the same parameters are answered from a fixture. The
+nonce and the signature are not part of the parameters, so the credentials
+never end up in the directory. The responses are stored along with the
+version of their layout, so fixtures recorded before Kraken changed its schema
+keep replaying (see the `schema` module).
+*/
+
+use crate::schema;
+use log::debug;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+
+/// Whether the responses are read from or written to the directory.
+#[derive(Clone, Debug)]
+pub enum Fixtures {
+    /// Answer the requests from the fixtures without using the network.
+    Replay(PathBuf),
+    /// Send the requests and store the responses as fixtures.
+    Record(PathBuf),
+}
+
+impl Fixtures {
+    /// Returns the path of the fixture of a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint of the request (e.g., /0/public/AssetPairs).
+    /// * `params` - The parameters of the request.
+    ///
+    fn path(&self, endpoint: &str, params: &[(&str, String)]) -> PathBuf {
+        let dir: &PathBuf = match self {
+            Fixtures::Replay(dir) | Fixtures::Record(dir) => dir,
+        };
+        let digest: String = Sha256::digest(serde_urlencoded::to_string(params).unwrap())
+            .iter()
+            .take(8)
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        dir.join(format!(
+            "{}-{}.json",
+            endpoint.trim_matches('/').replace('/', "_"),
+            digest
+        ))
+    }
+
+    /// Returns whether the requests are answered from the fixtures.
+    pub fn replays(&self) -> bool {
+        matches!(self, Fixtures::Replay(_))
+    }
+
+    /// Reads the fixture of a request.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint of the request.
+    /// * `params` - The parameters of the request.
+    ///
+    pub fn replay(&self, endpoint: &str, params: &[(&str, String)]) -> Result<String, String> {
+        let path: PathBuf = self.path(endpoint, params);
+        debug!("Replaying {} from {}", endpoint, path.display());
+        let stored: String = fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "No fixture of {} {:?} at {} ({}), record it via --record!",
+                endpoint,
+                params,
+                path.display(),
+                e
+            )
+        })?;
+        schema::unwrap(&stored).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Stores the response of a request as its fixture.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint of the request.
+    /// * `params` - The parameters of the request.
+    /// * `response` - The response.
+    ///
+    pub fn record(
+        &self,
+        endpoint: &str,
+        params: &[(&str, String)],
+        response: &str,
+    ) -> Result<(), String> {
+        let path: PathBuf = self.path(endpoint, params);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        debug!("Recording {} to {}", endpoint, path.display());
+        fs::write(&path, schema::wrap(response))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kraken::{fetch_account_trades, KrakenAPI, Trade};
+    use crate::rate_limit::RateLimiter;
+
+    /// Fetches the trades of all pairs from the fixtures in a directory.
+    async fn replay(dir: PathBuf) -> Vec<Trade> {
+        let api: KrakenAPI = KrakenAPI::new(
+            "key".to_string(),
+            "c2VjcmV0".to_string(),
+            RateLimiter::for_tier(""),
+        )
+        .with_fixtures(Some(Fixtures::Replay(dir)));
+        fetch_account_trades(&api, None, None).await
+    }
+
+    #[tokio::test]
+    async fn replays_unversioned_fixtures_of_older_layouts() {
+        let dir: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/legacy");
+        let trades: Vec<Trade> = replay(dir).await;
+        assert_eq!(trades.len(), 2);
+        // The fields Kraken added since are missing and defaulted.
+        assert_eq!(trades[0].txid, "TL0000-LEGACY");
+        assert_eq!(trades[0].margin, "");
+        assert_eq!(trades[0].postxid, "");
+        assert_eq!(trades[0].maker, None);
+        // Unknown fields are ignored.
+        assert_eq!(trades[1].txid, "TL0001-LEGACY");
+        assert_eq!(trades[1].vol, "0.05");
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_fixtures() {
+        let legacy: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/legacy/0_private_TradesHistory-236819620b237b0b.json");
+        let dir: PathBuf = std::env::temp_dir().join("kraken-pnl-fixtures-test");
+        let _ = fs::remove_dir_all(&dir);
+        Fixtures::Record(dir.clone())
+            .record(
+                "/0/private/TradesHistory",
+                &[("ofs", "0".to_string())],
+                &fs::read_to_string(legacy).unwrap(),
+            )
+            .unwrap();
+        let recorded: String =
+            fs::read_to_string(dir.join("0_private_TradesHistory-236819620b237b0b.json")).unwrap();
+        assert!(recorded.contains("\"schema\": 1"));
+        assert_eq!(replay(dir.clone()).await.len(), 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
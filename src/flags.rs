@@ -0,0 +1,148 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module groups the realized PnL by the flags of the orders that disposed of
+the lots, e.g. post-only orders, orders with a conditional close or trades
+closing a margin position, which shows how the execution styles contribute to
+the result.
+
+The order flags (`oflags`) and details (`misc`) are taken from the closed
+orders, the details of the trades (`misc`) from the trade history. A disposal
+counts towards every flag of its order, so the groups may overlap.
+*/
+
+use crate::fifo::Disposal;
+use crate::kraken::Trade;
+use crate::precision::Precision;
+use crate::report::print_table;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// The group of the disposals whose orders have no flags.
+const NO_FLAGS: &str = "none";
+
+/// The realized PnL of the disposals of a flag.
+#[derive(Debug, Default)]
+pub struct FlagTotals {
+    pub disposals: usize,
+    pub amount: f64,
+    pub proceeds: f64,
+    pub cost: f64,
+    pub gain: f64,
+}
+
+/// Returns the description of a flag.
+fn describe(flag: &str) -> &'static str {
+    match flag {
+        "post" => "post-only",
+        "fcib" => "fee in base currency",
+        "fciq" => "fee in quote currency",
+        "nompp" => "no market price protection",
+        "viqc" => "volume in quote currency",
+        "close" => "conditional close attached",
+        "stopped" => "triggered by the stop price",
+        "touched" => "triggered by the touch price",
+        "liquidated" => "position liquidation",
+        "partial" => "partially filled",
+        "closing" => "closes a margin position",
+        NO_FLAGS => "no flags",
+        _ => "",
+    }
+}
+
+/// Sums up the disposals by the flags of their orders.
+///
+/// # Arguments
+///
+/// * `disposals` - The per-lot disposals.
+/// * `trades` - The trades the PnL was computed from, whose details are added
+///   to the flags of their orders.
+/// * `order_flags` - The flags of the closed orders by the id of the order.
+/// * `precision` - The precision of the base and quote currency.
+///
+/// # Returns
+///
+/// The totals by flag, including `none` for orders without flags.
+///
+pub fn group_by_flags(
+    disposals: &[Disposal],
+    trades: &[Trade],
+    order_flags: &HashMap<String, Vec<String>>,
+    precision: &Precision,
+) -> BTreeMap<String, FlagTotals> {
+    let mut flags_by_order: HashMap<&str, BTreeSet<&str>> = HashMap::new();
+    for (ordertxid, flags) in order_flags {
+        flags_by_order
+            .entry(ordertxid)
+            .or_default()
+            .extend(flags.iter().map(String::as_str));
+    }
+    for trade in trades {
+        flags_by_order.entry(&trade.ordertxid).or_default().extend(
+            trade
+                .misc
+                .split(',')
+                .map(str::trim)
+                .filter(|flag| !flag.is_empty()),
+        );
+    }
+
+    let mut groups: BTreeMap<String, FlagTotals> = BTreeMap::new();
+    for disposal in disposals {
+        let flags: Vec<&str> = flags_by_order
+            .get(disposal.disposal_ordertxid.as_str())
+            .filter(|flags| !flags.is_empty())
+            .map(|flags| flags.iter().copied().collect())
+            .unwrap_or_else(|| vec![NO_FLAGS]);
+        for flag in flags {
+            let totals: &mut FlagTotals = groups.entry(flag.to_string()).or_default();
+            totals.disposals += 1;
+            totals.amount = precision.base(totals.amount + disposal.amount);
+            totals.proceeds = precision.quote(totals.proceeds + disposal.proceeds);
+            totals.cost = precision.quote(totals.cost + disposal.acquisition_cost);
+            totals.gain = precision.quote(totals.gain + disposal.gain);
+        }
+    }
+    groups
+}
+
+/// Prints the realized PnL by flag.
+///
+/// # Arguments
+///
+/// * `groups` - The totals by flag.
+/// * `quote` - The quote currency the gains are valued in.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn print_flag_summary(
+    groups: &BTreeMap<String, FlagTotals>,
+    quote: &str,
+    precision: &Precision,
+) {
+    println!("Realized PnL by order flags (a disposal counts towards every flag of its order):");
+    let header: Vec<String> = vec![
+        "Flag".to_string(),
+        "Description".to_string(),
+        "Disposals".to_string(),
+        "Amount".to_string(),
+        format!("Proceeds ({})", quote),
+        format!("Cost ({})", quote),
+        format!("Gain ({})", quote),
+    ];
+    let rows: Vec<Vec<String>> = groups
+        .iter()
+        .map(|(flag, totals)| {
+            vec![
+                flag.clone(),
+                describe(flag).to_string(),
+                totals.disposals.to_string(),
+                precision.fmt_base(totals.amount),
+                precision.fmt_quote(totals.proceeds),
+                precision.fmt_quote(totals.cost),
+                precision.fmt_quote(totals.gain),
+            ]
+        })
+        .collect();
+    print_table(&header, &rows, 2);
+}
@@ -0,0 +1,504 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module implements the `futures` subcommand, which computes the PnL of the
+derivatives traded on Kraken Futures (futures.kraken.com). They are traded
+with separate API keys via the Futures REST API, which signs its requests
+differently from the spot API, so the PnL is reported separately from spot.
+
+The fills are matched per contract in FIFO order, long and short positions
+alike. Linear contracts (e.g. PF_XBTUSD) realize their PnL in the quote
+currency, inverse contracts (e.g. PI_XBTUSD) in the base currency, as their
+size is denominated in USD. The funding payments and the fees are taken from
+the account log, which books them per contract.
+*/
+
+use crate::report::print_table;
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, SecondsFormat, Utc};
+use hmac::{Hmac, Mac};
+use log::{debug, info, warn};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The URL of the Futures REST API.
+pub const DEFAULT_FUTURES_URL: &str = "https://futures.kraken.com";
+
+/// The fills returned per request.
+const FILLS_PAGE_SIZE: usize = 100;
+
+/// The account log entries requested per request.
+const LOG_PAGE_SIZE: usize = 500;
+
+/// The attempts of a request exceeding the rate limit.
+const RETRIES: u32 = 3;
+
+/// A fill of an order.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Fill {
+    pub fill_id: String,
+    pub symbol: String,
+    pub side: String,
+    /// The size in contracts.
+    pub size: f64,
+    pub price: f64,
+    #[serde(rename = "fillTime")]
+    pub fill_time: String,
+}
+
+impl Fill {
+    /// Returns the time of the fill as UNIX timestamp.
+    pub fn time(&self) -> f64 {
+        iso_timestamp(&self.fill_time)
+    }
+}
+
+/// An entry of the account log, e.g. a trade, a funding payment or a
+/// transfer.
+#[derive(Deserialize, Debug, Clone)]
+pub struct AccountLogEntry {
+    pub id: u64,
+    /// The contract the entry is booked for, in lower case.
+    pub contract: Option<String>,
+    /// The fee charged.
+    pub fee: Option<f64>,
+    /// The funding received, negative if paid.
+    pub realized_funding: Option<f64>,
+}
+
+/// The PnL of a contract.
+#[derive(Debug, Clone, Default)]
+pub struct ContractPnL {
+    pub contract: String,
+    /// The currency the contract settles in.
+    pub currency: String,
+    pub fills: usize,
+    /// The traded size in contracts.
+    pub volume: f64,
+    pub realized_pnl: f64,
+    /// The funding received, negative if paid.
+    pub funding: f64,
+    pub fees: f64,
+    /// The open position in contracts, negative if short.
+    pub position: f64,
+}
+
+impl ContractPnL {
+    /// Returns the realized PnL including the funding and the fees.
+    pub fn net_pnl(&self) -> f64 {
+        self.realized_pnl + self.funding - self.fees
+    }
+}
+
+/// Parses a timestamp of the Futures API, e.g. 2024-01-02T03:04:05.678Z.
+fn iso_timestamp(value: &str) -> f64 {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.timestamp_millis() as f64 / 1000f64)
+        .unwrap_or_default()
+}
+
+/// Formats a UNIX timestamp as timestamp of the Futures API.
+fn iso_time(time: f64) -> String {
+    DateTime::<Utc>::from_timestamp_millis((time * 1000f64) as i64)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Returns whether a contract is inverse, i.e. its size is denominated in USD
+/// and it settles in the base currency.
+fn is_inverse(contract: &str) -> bool {
+    contract.starts_with("PI_") || contract.starts_with("FI_")
+}
+
+/// Returns the currency a contract settles in, e.g. XBT for PI_XBTUSD and
+/// USD for PF_XBTUSD.
+fn settlement_currency(contract: &str) -> String {
+    if !is_inverse(contract) {
+        return "USD".to_string();
+    }
+    let pair: &str = contract.split('_').nth(1).unwrap_or(contract);
+    pair.strip_suffix("USD").unwrap_or(pair).to_string()
+}
+
+/// A client of the Kraken Futures REST API.
+pub struct FuturesAPI {
+    api_key: String,
+    secret_key: String,
+    client: Client,
+    base_url: String,
+    /// The last nonce used by this client.
+    last_nonce: AtomicU64,
+}
+
+impl FuturesAPI {
+    /// Creates a client of the Futures API.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - The API key of Kraken Futures.
+    /// * `secret_key` - The secret of the API key.
+    /// * `base_url` - The URL of the API (e.g., https://futures.kraken.com).
+    /// * `client` - The HTTP client the requests are sent by.
+    ///
+    pub fn new(api_key: String, secret_key: String, base_url: &str, client: Client) -> Self {
+        Self {
+            api_key,
+            secret_key,
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            last_nonce: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns a nonce greater than all previous ones of this client.
+    fn next_nonce(&self) -> u64 {
+        let now: u64 = chrono::Utc::now().timestamp_millis() as u64;
+        self.last_nonce
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last + 1))
+            })
+            .map(|last| now.max(last + 1))
+            .unwrap()
+    }
+
+    /// Signs a request of the Futures API.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The path of the endpoint, which is signed without the
+    ///   `/derivatives` prefix.
+    /// * `data` - The URL-encoded parameters of the request.
+    /// * `nonce` - The nonce of the request.
+    ///
+    /// # Returns
+    ///
+    /// The signature, passed as `Authent` header.
+    ///
+    fn sign(&self, endpoint: &str, data: &str, nonce: &str) -> Result<String, String> {
+        let path: &str = endpoint.strip_prefix("/derivatives").unwrap_or(endpoint);
+        let key: Vec<u8> = general_purpose::STANDARD
+            .decode(&self.secret_key)
+            .map_err(|_| "The secret of the Futures API key is not valid base64!".to_string())?;
+        let mut mac = Hmac::<Sha512>::new_from_slice(&key).unwrap();
+        mac.update(&Sha256::digest(
+            format!("{}{}{}", data, nonce, path).as_bytes(),
+        ));
+        Ok(general_purpose::STANDARD.encode(mac.finalize().into_bytes()))
+    }
+
+    /// Sends a signed GET request to the Futures API.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The path of the endpoint (e.g., /derivatives/api/v3/fills).
+    /// * `params` - The parameters of the request.
+    ///
+    /// # Returns
+    ///
+    /// The response, or the error of the API.
+    ///
+    async fn get(&self, endpoint: &str, params: &[(&str, String)]) -> Result<Value, String> {
+        let data: String = serde_urlencoded::to_string(params).unwrap();
+        for attempt in 1..=RETRIES {
+            let nonce: String = self.next_nonce().to_string();
+            debug!("GET {} {}", endpoint, data);
+            let response: String = self
+                .client
+                .get(match data.is_empty() {
+                    true => format!("{}{}", self.base_url, endpoint),
+                    false => format!("{}{}?{}", self.base_url, endpoint, data),
+                })
+                .header("APIKey", &self.api_key)
+                .header("Nonce", &nonce)
+                .header("Authent", self.sign(endpoint, &data, &nonce)?)
+                .header("User-Agent", env!("CARGO_PKG_NAME"))
+                .send()
+                .await
+                .map_err(|e| format!("Failed to send the request to {}: {}", endpoint, e))?
+                .text()
+                .await
+                .map_err(|e| format!("Failed to read the response of {}: {}", endpoint, e))?;
+            let response: Value = serde_json::from_str(&response)
+                .map_err(|e| format!("Invalid response of {}: {}", endpoint, e))?;
+            let error: Option<&str> = response["error"].as_str().or_else(|| {
+                (response["result"].as_str() == Some("error")).then_some("unknown error")
+            });
+            match error {
+                None => return Ok(response),
+                Some("apiLimitExceeded") if attempt < RETRIES => {
+                    warn!("Rate limit of the Futures API exceeded, waiting 10 seconds...");
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                }
+                Some(error) => return Err(format!("{} failed: {}", endpoint, error)),
+            }
+        }
+        unreachable!()
+    }
+
+    /// Fetches the fills within a period, oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The start of the period as UNIX timestamp, if any.
+    /// * `end` - The end of the period as UNIX timestamp, if any.
+    ///
+    pub async fn fetch_fills(
+        &self,
+        start: Option<f64>,
+        end: Option<f64>,
+    ) -> Result<Vec<Fill>, String> {
+        let mut fills: Vec<Fill> = Vec::new();
+        let mut seen: HashSet<String> = HashSet::new();
+        // The fills are returned newest first, up to the last fill time.
+        let mut last_fill_time: Option<String> = end.map(iso_time);
+        loop {
+            let params: Vec<(&str, String)> = last_fill_time
+                .iter()
+                .map(|time| ("lastFillTime", time.clone()))
+                .collect();
+            let response: Value = self.get("/derivatives/api/v3/fills", &params).await?;
+            let page: Vec<Fill> = serde_json::from_value(response["fills"].clone())
+                .map_err(|e| format!("Invalid fills: {}", e))?;
+            let received: usize = page.len();
+            let oldest: Option<Fill> = page
+                .iter()
+                .min_by(|a, b| a.time().total_cmp(&b.time()))
+                .cloned();
+            let mut new: usize = 0;
+            for fill in page {
+                if seen.insert(fill.fill_id.clone()) {
+                    new += 1;
+                    if start.is_none_or(|start| fill.time() >= start)
+                        && end.is_none_or(|end| fill.time() <= end)
+                    {
+                        fills.push(fill);
+                    }
+                }
+            }
+            info!("Fetched {} fills of Kraken Futures", fills.len());
+            let Some(oldest) = oldest else {
+                break;
+            };
+            if received < FILLS_PAGE_SIZE
+                || new == 0
+                || start.is_some_and(|start| oldest.time() < start)
+            {
+                break;
+            }
+            last_fill_time = Some(oldest.fill_time);
+        }
+        fills.sort_by(|a, b| a.time().total_cmp(&b.time()));
+        Ok(fills)
+    }
+
+    /// Fetches the entries of the account log within a period, oldest first.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The start of the period as UNIX timestamp, if any.
+    /// * `end` - The end of the period as UNIX timestamp, if any.
+    ///
+    pub async fn fetch_account_log(
+        &self,
+        start: Option<f64>,
+        end: Option<f64>,
+    ) -> Result<Vec<AccountLogEntry>, String> {
+        let mut entries: Vec<AccountLogEntry> = Vec::new();
+        let mut from: Option<u64> = None;
+        loop {
+            let mut params: Vec<(&str, String)> = vec![
+                ("sort", "asc".to_string()),
+                ("count", LOG_PAGE_SIZE.to_string()),
+            ];
+            if let Some(start) = start {
+                params.push(("since", ((start * 1000f64) as i64).to_string()));
+            }
+            if let Some(end) = end {
+                params.push(("before", ((end * 1000f64) as i64).to_string()));
+            }
+            if let Some(from) = from {
+                params.push(("from", from.to_string()));
+            }
+            let response: Value = self.get("/api/history/v3/account-log", &params).await?;
+            let page: Vec<AccountLogEntry> = serde_json::from_value(response["logs"].clone())
+                .map_err(|e| format!("Invalid account log: {}", e))?;
+            let received: usize = page.len();
+            let last: Option<u64> = page.iter().map(|entry| entry.id).max();
+            entries.extend(
+                page.into_iter()
+                    .filter(|entry| from.is_none_or(|from| entry.id >= from)),
+            );
+            info!(
+                "Fetched {} entries of the account log of Kraken Futures",
+                entries.len()
+            );
+            match last {
+                Some(last) if received >= LOG_PAGE_SIZE => from = Some(last + 1),
+                _ => break,
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Computes the PnL of the contracts.
+///
+/// # Arguments
+///
+/// * `fills` - The fills up to the end of the period, sorted by time. The
+///   fills before the period open the positions closed within it.
+/// * `log` - The entries of the account log within the period.
+/// * `start` - The start of the period as UNIX timestamp, if any.
+///
+/// # Returns
+///
+/// The PnL of every contract that was traded or booked within the period,
+/// sorted by contract.
+///
+pub fn compute_futures_pnl(
+    fills: &[Fill],
+    log: &[AccountLogEntry],
+    start: Option<f64>,
+) -> Vec<ContractPnL> {
+    let mut contracts: BTreeMap<String, ContractPnL> = BTreeMap::new();
+    // The open lots of every contract as signed size and price.
+    let mut lots: BTreeMap<String, VecDeque<(f64, f64)>> = BTreeMap::new();
+    for fill in fills {
+        let in_period: bool = start.is_none_or(|start| fill.time() >= start);
+        let pnl: &mut ContractPnL = contract_pnl(&mut contracts, &fill.symbol);
+        let inverse: bool = is_inverse(&pnl.contract);
+        let queue: &mut VecDeque<(f64, f64)> = lots.entry(pnl.contract.clone()).or_default();
+        let signed: f64 = if fill.side == "sell" {
+            -fill.size
+        } else {
+            fill.size
+        };
+        // The fill closes the open lots of the opposite side first, and opens
+        // a lot with the remaining size.
+        let mut remaining: f64 = signed;
+        while remaining != 0f64 {
+            let Some((size, price)) = queue.front_mut() else {
+                break;
+            };
+            if size.signum() == remaining.signum() {
+                break;
+            }
+            let closed: f64 = remaining.abs().min(size.abs());
+            let direction: f64 = size.signum();
+            if in_period {
+                pnl.realized_pnl += direction
+                    * closed
+                    * match inverse {
+                        true => 1f64 / *price - 1f64 / fill.price,
+                        false => fill.price - *price,
+                    };
+            }
+            *size -= direction * closed;
+            remaining += direction * closed;
+            if size.abs() < 1e-12 {
+                queue.pop_front();
+            }
+        }
+        if remaining.abs() >= 1e-12 {
+            queue.push_back((remaining, fill.price));
+        }
+        pnl.position += signed;
+        if in_period {
+            pnl.fills += 1;
+            pnl.volume += fill.size;
+        }
+    }
+    for log_entry in log {
+        let Some(contract) = log_entry.contract.as_deref().filter(|c| !c.is_empty()) else {
+            continue;
+        };
+        let pnl: &mut ContractPnL = contract_pnl(&mut contracts, contract);
+        pnl.fees += log_entry.fee.unwrap_or_default();
+        pnl.funding += log_entry.realized_funding.unwrap_or_default();
+    }
+    // The contracts only traded before the period are left out.
+    contracts
+        .into_values()
+        .filter(|pnl| pnl.fills > 0 || pnl.fees != 0f64 || pnl.funding != 0f64)
+        .collect()
+}
+
+/// Returns the PnL of a contract, which is added if missing. The account log
+/// names the contracts in lower case, the fills in upper case.
+fn contract_pnl<'a>(
+    contracts: &'a mut BTreeMap<String, ContractPnL>,
+    contract: &str,
+) -> &'a mut ContractPnL {
+    let contract: String = contract.to_uppercase();
+    contracts
+        .entry(contract.clone())
+        .or_insert_with(|| ContractPnL {
+            currency: settlement_currency(&contract),
+            contract,
+            ..ContractPnL::default()
+        })
+}
+
+/// Formats a size in contracts without the artifacts of floating point
+/// arithmetic.
+fn fmt_size(size: f64) -> String {
+    // Adding zero turns a negative zero into zero.
+    format!("{}", (size * 1e8).round() / 1e8 + 0f64)
+}
+
+/// Prints the PnL of the contracts, with the totals per settlement currency.
+pub fn print_futures_pnl(contracts: &[ContractPnL]) {
+    let decimals = |currency: &str| if currency == "USD" { 4 } else { 8 };
+    let header: Vec<String> = [
+        "Contract",
+        "Currency",
+        "Fills",
+        "Volume",
+        "Realized PnL",
+        "Funding",
+        "Fees",
+        "Net PnL",
+        "Position",
+    ]
+    .map(String::from)
+    .to_vec();
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut totals: BTreeMap<String, ContractPnL> = BTreeMap::new();
+    for contract in contracts {
+        let d: usize = decimals(&contract.currency);
+        rows.push(vec![
+            contract.contract.clone(),
+            contract.currency.clone(),
+            contract.fills.to_string(),
+            fmt_size(contract.volume),
+            format!("{:.d$}", contract.realized_pnl),
+            format!("{:.d$}", contract.funding),
+            format!("{:.d$}", contract.fees),
+            format!("{:.d$}", contract.net_pnl()),
+            fmt_size(contract.position),
+        ]);
+        let total: &mut ContractPnL = totals.entry(contract.currency.clone()).or_default();
+        total.realized_pnl += contract.realized_pnl;
+        total.funding += contract.funding;
+        total.fees += contract.fees;
+    }
+    print_table(&header, &rows, 2);
+    for (currency, total) in totals {
+        let d: usize = decimals(&currency);
+        println!(
+            "Total ({}): realized PnL {:.d$}, funding {:.d$}, fees {:.d$}, net PnL {:.d$}",
+            currency,
+            total.realized_pnl,
+            total.funding,
+            total.fees,
+            total.net_pnl()
+        );
+    }
+}
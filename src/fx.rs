@@ -0,0 +1,503 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module converts values from the quote currency of a trading pair into a
+different reporting currency using daily exchange rates, either from Kraken's
+public OHLC data of the respective fiat pair (e.g. EURUSD) or from the euro
+foreign exchange reference rates of the European Central Bank (ECB).
+
+With a tax currency, the disposals themselves are converted: the cost of every
+lot at the rate of the day of its acquisition and the proceeds at the rate of
+the day of the disposal, as tax authorities expect.
+
+A realized gain in the reporting currency consists of two components: the
+gain of the asset itself, measured in the quote currency and converted at the
+rate of the disposal, and the gain or loss caused by the change of the
+exchange rate between acquisition and disposal of the lot.
+*/
+
+use crate::cache::Cache;
+use crate::fifo::{Disposal, PnL};
+use crate::kraken::KrakenAPI;
+use crate::precision::{try_fetch_asset_pair, AssetPair, Precision};
+use crate::prices::{cached_closes, fetch_daily_closes};
+use chrono::{DateTime, Days, NaiveDate};
+use std::collections::BTreeMap;
+
+/// The source of the daily exchange rates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FxSource {
+    /// The closing prices of Kraken's fiat pairs, available for the most
+    /// recent 720 days.
+    Kraken,
+    /// The reference rates of the ECB, available since 1999 for the
+    /// currencies quoted against the euro.
+    Ecb,
+}
+
+impl FxSource {
+    /// The values accepted by `--fx-source`.
+    pub const NAMES: [&'static str; 2] = ["kraken", "ecb"];
+
+    /// Returns the source for one of `NAMES`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "kraken" => Some(Self::Kraken),
+            "ecb" => Some(Self::Ecb),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the source, one of `NAMES`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Kraken => "kraken",
+            Self::Ecb => "ecb",
+        }
+    }
+}
+
+/// Daily exchange rates between two currencies.
+#[derive(Debug)]
+pub struct FxRates {
+    /// The name of the pair the rates are derived from.
+    pub pair: String,
+    /// The value of one unit of the quote currency in the reporting currency
+    /// by day.
+    rates: BTreeMap<NaiveDate, f64>,
+}
+
+impl FxRates {
+    /// Returns the exchange rate of the day of the given UNIX timestamp.
+    pub fn rate_at(&self, time: f64) -> Option<f64> {
+        let date: NaiveDate = DateTime::from_timestamp_nanos((time * 1e9) as i64).date_naive();
+        self.rates.get(&date).copied()
+    }
+
+    /// Returns the rate of the most recent day a rate is available for.
+    pub fn latest(&self) -> Option<f64> {
+        self.rates.values().next_back().copied()
+    }
+
+    /// Returns the first day a rate is available for.
+    pub fn first_date(&self) -> Option<NaiveDate> {
+        self.rates.keys().next().copied()
+    }
+
+    /// Returns the exchange rate of the day of the given UNIX timestamp, or an
+    /// error stating since when rates are available.
+    pub fn required_rate_at(&self, time: f64) -> Result<f64, String> {
+        self.rate_at(time).ok_or_else(|| {
+            format!(
+                "No {} exchange rate available for {} (available since {})!",
+                self.pair,
+                DateTime::from_timestamp_nanos((time * 1e9) as i64).date_naive(),
+                self.first_date()
+                    .map(|date| date.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+            )
+        })
+    }
+}
+
+/// Returns the first and the last day of the acquisitions and disposals, i.e.
+/// the days that need an exchange rate.
+pub fn disposal_period(disposals: &[Disposal]) -> (NaiveDate, NaiveDate) {
+    let times = disposals.iter().flat_map(|disposal| {
+        [disposal.acquisition_time, Some(disposal.disposal_time)]
+            .into_iter()
+            .flatten()
+            .filter_map(|time| DateTime::from_timestamp(time as i64, 0))
+    });
+    let today: NaiveDate = chrono::Utc::now().date_naive();
+    (
+        times.clone().min().map_or(today, |time| time.date_naive()),
+        times.max().map_or(today, |time| time.date_naive()),
+    )
+}
+
+/// Fetches the daily exchange rates to convert values of the quote currency of
+/// `asset_pair` into `reporting_currency`.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `cache` - The cache of the daily rates, if available.
+/// * `asset_pair` - The trading pair whose quote currency is converted.
+/// * `reporting_currency` - The currency code to convert into (e.g., EUR).
+/// * `period` - The first and the last day that need a rate. The rates are
+///   only requested if the cache lacks some of them.
+/// * `source` - The source of the rates.
+///
+/// Kraken only provides the most recent 720 daily candles, so older dates
+/// cannot be converted unless their rates were cached by an earlier run. The
+/// ECB publishes its rates on business days only, so the days in between take
+/// the rate of the previous business day.
+pub async fn fetch_fx_rates(
+    api: &KrakenAPI,
+    cache: Option<&Cache>,
+    asset_pair: &AssetPair,
+    reporting_currency: &str,
+    period: (NaiveDate, NaiveDate),
+    source: FxSource,
+) -> Result<FxRates, String> {
+    let quote: String = asset_pair.quote_code();
+    let (from, to): (NaiveDate, NaiveDate) = period;
+    if source == FxSource::Ecb {
+        // The rates state the units of a currency per euro, so currencies
+        // other than the euro are converted via their euro rates.
+        let euro_rates = |currency: String| async move {
+            if currency == "EUR" {
+                return Ok(None);
+            }
+            let fetch = fetch_ecb_rates(&currency, from, to);
+            cached_closes(cache, &format!("EUR{}", currency), "ecb", from, to, fetch)
+                .await
+                .map(Some)
+        };
+        let (quote_rates, reporting_rates) = (
+            euro_rates(quote.clone()).await?,
+            euro_rates(reporting_currency.to_string()).await?,
+        );
+        let days: Vec<NaiveDate> = match (&quote_rates, &reporting_rates) {
+            (Some(rates), _) | (None, Some(rates)) => rates.keys().copied().collect(),
+            (None, None) => from.iter_days().take_while(|day| *day <= to).collect(),
+        };
+        let rate = |rates: &Option<BTreeMap<NaiveDate, f64>>, day: &NaiveDate| match rates {
+            Some(rates) => rates.get(day).copied(),
+            None => Some(1f64),
+        };
+        let rates: BTreeMap<NaiveDate, f64> = days
+            .iter()
+            .filter_map(|day| {
+                Some((
+                    *day,
+                    rate(&reporting_rates, day)? / rate(&quote_rates, day)?,
+                ))
+            })
+            .collect();
+        return Ok(FxRates {
+            pair: format!("ECB {}/{}", quote, reporting_currency),
+            rates,
+        });
+    }
+
+    // A pair like EURUSD quotes the reporting currency in the quote currency,
+    // so its rates need to be inverted. USDEUR would be used as-is.
+    let (fx_pair, inverted): (AssetPair, bool) =
+        match try_fetch_asset_pair(api, &format!("{}{}", reporting_currency, quote)).await {
+            Some(pair) => (pair, true),
+            None => (
+                try_fetch_asset_pair(api, &format!("{}{}", quote, reporting_currency))
+                    .await
+                    .ok_or_else(|| {
+                        format!(
+                            "Kraken does not provide an exchange rate between {} and {}!",
+                            quote, reporting_currency
+                        )
+                    })?,
+                false,
+            ),
+        };
+
+    let fetch = fetch_daily_closes(api, &fx_pair.name);
+    let rates: BTreeMap<NaiveDate, f64> =
+        cached_closes(cache, &fx_pair.name, "kraken", from, to, fetch)
+            .await?
+            .into_iter()
+            .map(|(date, close)| (date, if inverted { 1f64 / close } else { close }))
+            .collect();
+
+    Ok(FxRates {
+        pair: fx_pair.name,
+        rates,
+    })
+}
+
+/// Fetches the most recent exchange rate to convert values of the quote
+/// currency of `asset_pair` into another currency, e.g. for the portfolio
+/// summary. The rate is one if the currencies are the same.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `cache` - The cache of the daily rates, if available.
+/// * `asset_pair` - The trading pair whose quote currency is converted.
+/// * `currency` - The currency code to convert into (e.g., EUR).
+/// * `source` - The source of the rates.
+///
+pub async fn fetch_latest_rate(
+    api: &KrakenAPI,
+    cache: Option<&Cache>,
+    asset_pair: &AssetPair,
+    currency: &str,
+    source: FxSource,
+) -> Result<f64, String> {
+    if asset_pair.quote_code() == currency {
+        return Ok(1f64);
+    }
+    // The ECB doesn't publish rates on weekends and holidays.
+    let today: NaiveDate = chrono::Utc::now().date_naive();
+    let from: NaiveDate = today.checked_sub_days(Days::new(7)).unwrap_or(today);
+    fetch_fx_rates(api, cache, asset_pair, currency, (from, today), source)
+        .await?
+        .latest()
+        .ok_or_else(|| {
+            format!(
+                "No recent exchange rate between {} and {} available!",
+                asset_pair.quote_code(),
+                currency
+            )
+        })
+}
+
+/// Fetches the ECB reference rates of a currency, i.e. its units per euro.
+///
+/// # Arguments
+///
+/// * `currency` - The currency code (e.g., USD).
+/// * `from` - The first day that needs a rate.
+/// * `to` - The last day that needs a rate.
+///
+/// # Returns
+///
+/// The rate of every day from `from` until `to` or today, where days without
+/// a published rate, e.g. weekends and holidays, take the rate of the
+/// previous business day.
+///
+async fn fetch_ecb_rates(
+    currency: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<BTreeMap<NaiveDate, f64>, String> {
+    // Holidays may last several days, so the rates start a week earlier.
+    let start: NaiveDate = from.checked_sub_days(Days::new(7)).unwrap_or(from);
+    let response = reqwest::Client::new()
+        .get(format!(
+            "https://data-api.ecb.europa.eu/service/data/EXR/D.{}.EUR.SP00.A",
+            currency
+        ))
+        .query(&[
+            ("format", "csvdata".to_string()),
+            ("startPeriod", start.to_string()),
+            ("endPeriod", to.to_string()),
+        ])
+        .header("User-Agent", env!("CARGO_PKG_NAME"))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach the ECB: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "The ECB responded with {} to the request of the {} rates",
+            response.status(),
+            currency
+        ));
+    }
+    let content: String = response
+        .text()
+        .await
+        .map_err(|e| format!("Failed to read response: {}", e))?;
+
+    let mut lines = content.lines();
+    let header: Vec<&str> = lines.next().unwrap_or_default().split(',').collect();
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|column| *column == name)
+            .ok_or_else(|| format!("The ECB rates lack the column {}!", name))
+    };
+    let (date_column, rate_column): (usize, usize) = (column("TIME_PERIOD")?, column("OBS_VALUE")?);
+    let mut published: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+    for line in lines.filter(|line| !line.trim().is_empty()) {
+        let fields: Vec<&str> = line.split(',').collect();
+        let date: Option<NaiveDate> = fields
+            .get(date_column)
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok());
+        let rate: Option<f64> = fields.get(rate_column).and_then(|rate| rate.parse().ok());
+        if let (Some(date), Some(rate)) = (date, rate) {
+            published.insert(date, rate);
+        }
+    }
+
+    let today: NaiveDate = chrono::Utc::now().date_naive();
+    let mut rates: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+    for day in from.iter_days().take_while(|day| *day <= to.min(today)) {
+        if let Some((_, rate)) = published.range(..=day).next_back() {
+            rates.insert(day, *rate);
+        }
+    }
+    Ok(rates)
+}
+
+/// Converts the disposals and the totals derived from them into another
+/// currency, e.g. the one taxes are filed in.
+///
+/// # Arguments
+///
+/// * `pnl` - The computed PnL, whose disposals, realized PnL, and cost and
+///   value of the sold assets are converted.
+/// * `rates` - The exchange rates into the other currency.
+/// * `precision` - The precision the converted values are rounded to.
+///
+/// The cost of every lot is converted at the rate of the day of its
+/// acquisition, the proceeds at the rate of the day of the disposal.
+pub fn convert_pnl(pnl: &mut PnL, rates: &FxRates, precision: &Precision) -> Result<(), String> {
+    for disposal in pnl.disposals.iter_mut() {
+        let disposal_rate: f64 = rates.required_rate_at(disposal.disposal_time)?;
+        let acquisition_rate: f64 = match disposal.acquisition_time {
+            Some(time) => rates.required_rate_at(time)?,
+            None => disposal_rate,
+        };
+        disposal.acquisition_cost = precision.quote(disposal.acquisition_cost * acquisition_rate);
+        disposal.proceeds = precision.quote(disposal.proceeds * disposal_rate);
+        disposal.gain = precision.quote(disposal.proceeds - disposal.acquisition_cost);
+        // A deferred loss of a wash sale was disallowed at the time of the
+        // disposal.
+        if let Some(wash_sale) = disposal.wash_sale.as_mut() {
+            wash_sale.disallowed_loss = precision.quote(wash_sale.disallowed_loss * disposal_rate);
+            if wash_sale.deferred {
+                disposal.gain = precision.quote(disposal.gain + wash_sale.disallowed_loss);
+            }
+        }
+    }
+    pnl.realized_pnl = precision.quote(pnl.disposals.iter().map(|disposal| disposal.gain).sum());
+    pnl.total_cost_of_sold_assets = precision.quote(
+        pnl.disposals
+            .iter()
+            .map(|disposal| disposal.acquisition_cost)
+            .sum(),
+    );
+    pnl.total_value_of_sold_assets =
+        precision.quote(pnl.disposals.iter().map(|disposal| disposal.proceeds).sum());
+    Ok(())
+}
+
+/// The realized PnL in the reporting currency, split into its components.
+#[derive(Debug, Default)]
+pub struct FxSplit {
+    pub asset_pnl: f64,
+    pub fx_pnl: f64,
+}
+
+impl FxSplit {
+    pub fn total(&self) -> f64 {
+        self.asset_pnl + self.fx_pnl
+    }
+}
+
+/// Splits the realized PnL of the disposals into asset and FX PnL in the
+/// reporting currency.
+///
+/// For a lot acquired at cost `C` with exchange rate `r_a` and sold for `P`
+/// with exchange rate `r_d`, the gain in the reporting currency is
+/// `P * r_d - C * r_a`, which consists of the asset PnL `(P - C) * r_d` and
+/// the FX PnL `C * (r_d - r_a)`.
+pub fn split_realized_pnl(disposals: &[Disposal], rates: &FxRates) -> Result<FxSplit, String> {
+    let mut split: FxSplit = FxSplit::default();
+    for disposal in disposals {
+        let disposal_rate: f64 = rates.required_rate_at(disposal.disposal_time)?;
+        // Disposals without acquisition have no cost, thus no FX component.
+        let acquisition_rate: f64 = match disposal.acquisition_time {
+            Some(time) => rates.required_rate_at(time)?,
+            None => disposal_rate,
+        };
+        split.asset_pnl += disposal.gain * disposal_rate;
+        split.fx_pnl += disposal.acquisition_cost * (disposal_rate - acquisition_rate);
+    }
+    Ok(split)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRECISION: Precision = Precision { base: 8, quote: 2 };
+
+    /// 2024-01-01 and 2024-06-01, 12:00 UTC.
+    const ACQUIRED: f64 = 1704110400f64;
+    const SOLD: f64 = 1717243200f64;
+
+    /// Returns the EURUSD rates of the days of `ACQUIRED` and `SOLD`.
+    fn rates() -> FxRates {
+        FxRates {
+            pair: "EURUSD".to_string(),
+            rates: BTreeMap::from([
+                (NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(), 1.1),
+                (NaiveDate::from_ymd_opt(2024, 6, 1).unwrap(), 1.2),
+            ]),
+        }
+    }
+
+    /// Returns the disposal of a lot bought for 100 and sold for 150.
+    fn disposal() -> Disposal {
+        Disposal {
+            amount: 1f64,
+            acquisition_time: Some(ACQUIRED),
+            acquisition_ordertxid: Some("O1".to_string()),
+            acquisition_cost: 100f64,
+            disposal_time: SOLD,
+            disposal_ordertxid: "O2".to_string(),
+            disposal_ordertype: "limit".to_string(),
+            disposal_maker: None,
+            proceeds: 150f64,
+            gain: 50f64,
+            classification: None,
+            wash_sale: None,
+        }
+    }
+
+    #[test]
+    fn converts_at_the_rates_of_acquisition_and_disposal() {
+        let mut pnl: PnL = PnL {
+            disposals: vec![disposal()],
+            ..Default::default()
+        };
+        convert_pnl(&mut pnl, &rates(), &PRECISION).unwrap();
+        assert_eq!(pnl.disposals[0].acquisition_cost, 110f64);
+        assert_eq!(pnl.disposals[0].proceeds, 180f64);
+        assert_eq!(pnl.realized_pnl, 70f64);
+        assert_eq!(pnl.total_cost_of_sold_assets, 110f64);
+        assert_eq!(pnl.total_value_of_sold_assets, 180f64);
+    }
+
+    #[test]
+    fn splits_the_gain_into_asset_and_fx_pnl() {
+        let split: FxSplit = split_realized_pnl(&[disposal()], &rates()).unwrap();
+        assert!((split.asset_pnl - 60f64).abs() < 1e-9);
+        assert!((split.fx_pnl - 10f64).abs() < 1e-9);
+        assert!((split.total() - 70f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn requires_the_rates_of_every_day() {
+        let rates: FxRates = rates();
+        assert_eq!(rates.rate_at(SOLD + 3600f64), Some(1.2));
+        assert_eq!(rates.latest(), Some(1.2));
+        let error: String = rates.required_rate_at(SOLD + 86400f64).unwrap_err();
+        assert_eq!(
+            error,
+            "No EURUSD exchange rate available for 2024-06-02 (available since 2024-01-01)!"
+        );
+        let mut pnl: PnL = PnL {
+            disposals: vec![Disposal {
+                disposal_time: SOLD + 86400f64,
+                ..disposal()
+            }],
+            ..Default::default()
+        };
+        assert!(convert_pnl(&mut pnl, &rates, &PRECISION).is_err());
+    }
+
+    #[test]
+    fn spans_the_acquisitions_and_disposals() {
+        assert_eq!(
+            disposal_period(&[disposal()]),
+            (
+                NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2024, 6, 1).unwrap()
+            )
+        );
+    }
+}
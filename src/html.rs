@@ -0,0 +1,266 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module writes a self-contained HTML report of a trading pair, meant to be
+handed to an accountant: the metadata of the run, the summary figures, the
+realized PnL by month, the equity chart, the per-lot disposals and the audit of
+the excluded trades. The chart is embedded as inline SVG, so the file has no
+external references and can be archived or sent as it is.
+*/
+
+use crate::chart::{escape, render_chart, Series};
+use crate::fifo::PnL;
+use crate::kraken::Trade;
+use crate::precision::{AssetPair, Precision};
+use crate::report::{format_time, monthly_breakdown, MonthlyTotals, ReportMetadata};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { border: 1px solid #ccc; padding: 4px 8px; }
+th { background: #f0f0f0; text-align: left; }
+td.number { text-align: right; font-family: monospace; }
+.negative { color: #b00; }
+dl { display: grid; grid-template-columns: max-content auto; gap: 2px 1em; }
+dt { font-weight: bold; }";
+
+/// Writes the HTML report.
+///
+/// # Arguments
+///
+/// * `file_path` - The path of the HTML file.
+/// * `metadata` - The parameters of the run.
+/// * `pnl` - The computed PnL.
+/// * `excluded` - The trades excluded from the computation, which are listed
+///   in the audit section.
+/// * `curve` - The series of the equity chart, omitted if there are no points.
+/// * `asset_pair` - The assets of the trading pair.
+/// * `precision` - The precision used to format amounts and values.
+///
+pub fn write_html_report(
+    file_path: &str,
+    metadata: &ReportMetadata,
+    pnl: &PnL,
+    excluded: &[Trade],
+    curve: &[Series],
+    asset_pair: &AssetPair,
+    precision: &Precision,
+) -> Result<(), String> {
+    let quote: &str = &asset_pair.quote;
+    let base: &str = &asset_pair.base;
+    let mut html: String = String::new();
+    let _ = writeln!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>PnL report {}</title>\n<style>\n{}\n</style>\n</head>\n<body>",
+        escape(&metadata.symbol),
+        STYLE
+    );
+    let _ = writeln!(html, "<h1>PnL report {}</h1>", escape(&metadata.symbol));
+
+    // The parameters of the run.
+    let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+    let parameters: Vec<(&str, String)> = vec![
+        (
+            "Generated by",
+            format!("{} {}", metadata.tool, metadata.version),
+        ),
+        ("Generated at", metadata.generated_at.clone()),
+        (
+            "Base / quote",
+            format!("{} / {}", metadata.base, metadata.quote),
+        ),
+        ("Start", optional(&metadata.start)),
+        ("End", optional(&metadata.end)),
+        (
+            "Year",
+            optional(&metadata.year.map(|year| year.to_string())),
+        ),
+        ("Fiscal year start", optional(&metadata.fiscal_year_start)),
+        ("Method", metadata.method.clone()),
+        ("Tax rules", optional(&metadata.tax_rules)),
+        ("Tax currency", optional(&metadata.tax_currency)),
+        ("FX source", optional(&metadata.fx_source)),
+        ("Price band", optional(&metadata.price_band)),
+        ("Opening balance", optional(&metadata.opening_balance)),
+        ("Orders", metadata.order_filter()),
+        ("Excluded trades", metadata.excluded_txids()),
+        ("Trades", metadata.trades.to_string()),
+        ("Data digest", format!("sha256:{}", metadata.data_digest)),
+    ];
+    html.push_str("<h2>Parameters</h2>\n<dl>\n");
+    for (key, value) in parameters {
+        let _ = writeln!(html, "<dt>{}</dt><dd>{}</dd>", key, escape(&value));
+    }
+    html.push_str("</dl>\n");
+
+    // The summary figures.
+    let figures: Vec<(String, f64, String)> = vec![
+        (
+            "Realized PnL".to_string(),
+            pnl.realized_pnl,
+            precision.fmt_quote(pnl.realized_pnl),
+        ),
+        (
+            "Unrealized PnL".to_string(),
+            pnl.unrealized_pnl,
+            precision.fmt_quote(pnl.unrealized_pnl),
+        ),
+        (
+            format!("Balance ({})", base),
+            pnl.balance,
+            precision.fmt_base(pnl.balance),
+        ),
+        (
+            format!("Total Buy Volume ({})", quote),
+            pnl.total_buy_volume_quote,
+            precision.fmt_quote(pnl.total_buy_volume_quote),
+        ),
+        (
+            format!("Total Sell Volume ({})", quote),
+            pnl.total_sell_volume_quote,
+            precision.fmt_quote(pnl.total_sell_volume_quote),
+        ),
+        (
+            "Total Cost of Sold Assets".to_string(),
+            pnl.total_cost_of_sold_assets,
+            precision.fmt_quote(pnl.total_cost_of_sold_assets),
+        ),
+        (
+            "Total Value of Sold Assets".to_string(),
+            pnl.total_value_of_sold_assets,
+            precision.fmt_quote(pnl.total_value_of_sold_assets),
+        ),
+    ];
+    let _ = writeln!(
+        html,
+        "<h2>Summary</h2>\n<p>Values are stated in {} unless noted otherwise.{}</p>\n<table>",
+        escape(quote),
+        metadata
+            .tax_currency
+            .as_ref()
+            .map(|currency| format!(
+                " The realized PnL, the cost and value of the sold assets, the monthly breakdown and the disposals are stated in {}, converted at the daily FX rates of the acquisitions and disposals.",
+                escape(currency)
+            ))
+            .unwrap_or_default()
+    );
+    for (label, value, formatted) in figures {
+        let _ = writeln!(
+            html,
+            "<tr><th>{}</th>{}</tr>",
+            escape(&label),
+            number(value, &formatted)
+        );
+    }
+    html.push_str("</table>\n");
+
+    // The realized PnL by month of the disposal.
+    let months: BTreeMap<String, MonthlyTotals> = monthly_breakdown(&pnl.disposals, precision);
+    html.push_str("<h2>Monthly breakdown</h2>\n");
+    if months.is_empty() {
+        html.push_str("<p>There are no disposals.</p>\n");
+    } else {
+        let _ = writeln!(
+            html,
+            "<table>\n<tr><th>Month</th><th>Disposals</th><th>Amount ({})</th><th>Proceeds</th><th>Cost</th><th>Gain</th></tr>",
+            escape(base)
+        );
+        for (month, totals) in &months {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td>{}{}{}{}{}</tr>",
+                month,
+                number(0f64, &totals.disposals.to_string()),
+                number(0f64, &precision.fmt_base(totals.amount)),
+                number(0f64, &precision.fmt_quote(totals.proceeds)),
+                number(0f64, &precision.fmt_quote(totals.cost)),
+                number(totals.gain, &precision.fmt_quote(totals.gain)),
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    if curve.iter().any(|series| !series.points.is_empty()) {
+        html.push_str("<h2>Equity curve</h2>\n");
+        html.push_str(&render_chart(
+            curve,
+            &format!("{} equity curve", metadata.symbol),
+        ));
+    }
+
+    // The per-lot disposals.
+    html.push_str("<h2>Disposals</h2>\n");
+    if pnl.disposals.is_empty() {
+        html.push_str("<p>There are no disposals.</p>\n");
+    } else {
+        let _ = writeln!(
+            html,
+            "<table>\n<tr><th>Acquired</th><th>Acquisition order</th><th>Disposed</th><th>Disposal order</th><th>Amount ({})</th><th>Cost</th><th>Proceeds</th><th>Gain</th><th>Classification</th></tr>",
+            escape(base)
+        );
+        for disposal in &pnl.disposals {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td>{}{}{}{}<td>{}</td></tr>",
+                disposal
+                    .acquisition_time
+                    .map(format_time)
+                    .unwrap_or_default(),
+                escape(
+                    disposal
+                        .acquisition_ordertxid
+                        .as_deref()
+                        .unwrap_or_default()
+                ),
+                format_time(disposal.disposal_time),
+                escape(&disposal.disposal_ordertxid),
+                number(0f64, &precision.fmt_base(disposal.amount)),
+                number(0f64, &precision.fmt_quote(disposal.acquisition_cost)),
+                number(0f64, &precision.fmt_quote(disposal.proceeds)),
+                number(disposal.gain, &precision.fmt_quote(disposal.gain)),
+                escape(disposal.classification.as_deref().unwrap_or_default()),
+            );
+        }
+        html.push_str("</table>\n");
+    }
+    // The trades left out of the computation, so they can be reviewed.
+    html.push_str("<h2>Audit</h2>\n");
+    if excluded.is_empty() {
+        html.push_str("<p>No trades were excluded.</p>\n");
+    } else {
+        let _ = writeln!(
+            html,
+            "<p>The following trades were excluded from the computation.</p>\n<table>\n<tr><th>Time</th><th>Trade</th><th>Order</th><th>Side</th><th>Price</th><th>Volume ({})</th><th>Cost</th></tr>",
+            escape(base)
+        );
+        for trade in excluded {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td>{}{}{}</tr>",
+                format_time(trade.time),
+                escape(&trade.txid),
+                escape(&trade.ordertxid),
+                escape(&trade.side),
+                number(0f64, &trade.price),
+                number(0f64, &trade.vol),
+                number(0f64, &trade.cost),
+            );
+        }
+        html.push_str("</table>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+    fs::write(file_path, html).map_err(|e| format!("Failed to write {}: {}", file_path, e))
+}
+
+/// Returns a right-aligned table cell, highlighting negative values.
+fn number(value: f64, formatted: &str) -> String {
+    if value < 0f64 {
+        format!("<td class=\"number negative\">{}</td>", formatted)
+    } else {
+        format!("<td class=\"number\">{}</td>", formatted)
+    }
+}
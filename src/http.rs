@@ -0,0 +1,32 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module holds the limits shared by the built-in HTTP endpoints, which
+are served via axum. The endpoints only answer GET requests with query
+parameters, so request bodies are rejected, and a request that isn't answered
+in time is aborted. The size of the request heads is limited by hyper.
+*/
+
+use axum::http::StatusCode;
+use axum::Router;
+use std::time::Duration;
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::timeout::TimeoutLayer;
+
+/// The largest request body accepted, none since the endpoints read none.
+const MAX_BODY: usize = 0;
+
+/// The time after which a request is aborted.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Returns the endpoints with the limits applied to every request.
+pub fn with_limits(router: Router) -> Router {
+    router
+        .layer(RequestBodyLimitLayer::new(MAX_BODY))
+        .layer(TimeoutLayer::with_status_code(
+            StatusCode::REQUEST_TIMEOUT,
+            TIMEOUT,
+        ))
+}
@@ -0,0 +1,503 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module imports the trades of other exchanges from their CSV exports
+(`--import EXCHANGE:PATH`), so the lots acquired elsewhere and moved to Kraken
+enter the same FIFO chain as the trades on Kraken. Every exchange implements
+`ExchangeImporter`, which normalizes the rows of its export into trades:
+
+- `binance`: The trade history of Binance Spot, either with the columns
+  `Date(UTC)`, `Pair`, `Side`, `Price`, `Executed`, `Amount` and `Fee`, whose
+  amounts end in their asset (e.g., `0.01000000BTC`), or with the columns
+  `Date(UTC)`, `Market`, `Type`, `Price`, `Amount`, `Total`, `Fee` and
+  `Fee Coin`.
+- `coinbase`: The transaction history of Coinbase with the columns
+  `Timestamp`, `Transaction Type`, `Asset`, `Quantity Transacted`,
+  `(Spot) Price Currency`, `(Spot) Price at Transaction`, `Subtotal` and
+  `Fees (and/or Spread)`, of which the buys and sells are imported. The
+  lines before the header are skipped.
+- `kraken`: The trades export of Kraken with the columns `txid`, `ordertxid`,
+  `pair`, `time`, `type`, `ordertype`, `price`, `cost`, `fee` and `vol`, e.g.
+  for the trades no longer returned by the API. Its trades keep their ids, so
+  they are recognized among the fetched trades (see `src/sources.rs`), and
+  its margin trades are left out.
+
+The trades are merged into the pair of the same base and quote currency
+(e.g., BTC and EUR for XXBTZEUR). Fees charged in the base currency reduce the
+amount received, those in other currencies (e.g., BNB) are not known in the
+quote currency and are left out with a warning.
+*/
+
+use crate::kraken::Trade;
+use crate::precision::{AssetPair, Precision};
+use chrono::{DateTime, NaiveDateTime};
+use log::warn;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The exchanges accepted by `--import`.
+pub const EXCHANGES: [&str; 3] = ["binance", "coinbase", "kraken"];
+
+/// A trade of another exchange.
+#[derive(Debug, Clone)]
+pub struct ImportedTrade {
+    /// The id of the trade, prefixed with the exchange unless it is one of
+    /// Kraken.
+    pub id: String,
+    /// The exchange, one of `EXCHANGES`.
+    pub source: &'static str,
+    /// The name of the trading pair on Kraken (e.g., XXBTZEUR), if the export
+    /// states it rather than the codes of the currencies.
+    pub pair: Option<String>,
+    /// The id of the order, if the export states it.
+    pub order_id: Option<String>,
+    pub time: f64,
+    /// The side, `buy` or `sell`.
+    pub side: &'static str,
+    /// The common codes of the base and quote currency (e.g., BTC and EUR).
+    pub base: String,
+    pub quote: String,
+    pub amount: f64,
+    pub price: f64,
+    /// The value in the quote currency, excluding the fee.
+    pub cost: f64,
+    /// The fee and its currency, if any.
+    pub fee: Option<(f64, String)>,
+}
+
+/// Returns whether an asset refers to one asset of a trading pair by its name
+/// (e.g., XXBT), its code (e.g., XBT) or its common code (e.g., BTC).
+///
+/// # Arguments
+///
+/// * `asset` - The asset as written in the export.
+/// * `name` - The name of the asset of the pair.
+/// * `code` - The common code of the asset of the pair.
+/// * `wsname` - The code of the asset of the pair in its WebSocket name.
+///
+fn same_asset(asset: &str, name: &str, code: &str, wsname: Option<&str>) -> bool {
+    let asset: String = asset.to_uppercase();
+    asset == name || asset == code || wsname == Some(asset.as_str())
+}
+
+impl ImportedTrade {
+    /// Returns whether the trade is of the base and quote currency of a
+    /// trading pair.
+    pub fn applies_to(&self, asset_pair: &AssetPair) -> bool {
+        if let Some(pair) = &self.pair {
+            return asset_pair.aliases().contains(pair);
+        }
+        let mut wsname = asset_pair.wsname.split('/');
+        same_asset(
+            &self.base,
+            &asset_pair.base,
+            &asset_pair.base_code(),
+            wsname.next(),
+        ) && same_asset(
+            &self.quote,
+            &asset_pair.quote,
+            &asset_pair.quote_code(),
+            wsname.next(),
+        )
+    }
+
+    /// Returns the fee in the quote currency, or its value if it is charged in
+    /// the base currency, and the fee charged in the base currency, if any.
+    pub fn fees(&self) -> (f64, Option<f64>) {
+        match &self.fee {
+            Some((fee, asset)) if asset.eq_ignore_ascii_case(&self.quote) => (*fee, None),
+            Some((fee, asset)) if asset.eq_ignore_ascii_case(&self.base) => {
+                (fee * self.price, Some(*fee))
+            }
+            Some((fee, asset)) => {
+                warn!(
+                    "Leaving out the fee of {} {} of the imported trade {}, which is not charged in {} or {}.",
+                    fee, asset, self.id, self.base, self.quote
+                );
+                (0f64, None)
+            }
+            None => (0f64, None),
+        }
+    }
+
+    /// Returns the imported trade as trade of a trading pair.
+    ///
+    /// # Arguments
+    ///
+    /// * `pair` - The trading pair.
+    /// * `precision` - The precision of the base and quote currency.
+    ///
+    pub fn trade(&self, pair: &str, precision: &Precision) -> Trade {
+        let (fee, base_fee): (f64, Option<f64>) = self.fees();
+        Trade {
+            txid: self.id.clone(),
+            ordertxid: self.order_id.clone().unwrap_or_else(|| self.id.clone()),
+            pair: pair.to_string(),
+            time: self.time,
+            side: self.side.to_string(),
+            price: self.price.to_string(),
+            fee: precision.quote(fee).to_string(),
+            vol: precision.base(self.amount).to_string(),
+            cost: precision.quote(self.cost).to_string(),
+            ordertype: "import".to_string(),
+            margin: String::new(),
+            misc: String::new(),
+            postxid: String::new(),
+            maker: None,
+            base_fee: base_fee.map(|fee| precision.base(fee)),
+            source: Some(self.source),
+        }
+    }
+}
+
+/// A row of a CSV export by the name of the column.
+pub type Row<'a> = HashMap<&'a str, &'a str>;
+
+/// An exchange whose CSV exports can be imported.
+pub trait ExchangeImporter {
+    /// Returns the name of the exchange, which prefixes the ids of the trades.
+    fn name(&self) -> &'static str;
+
+    /// Returns whether a line is the header of the export, since some exports
+    /// start with a preamble.
+    fn is_header(&self, columns: &[String]) -> bool;
+
+    /// Normalizes a row of the export.
+    ///
+    /// # Arguments
+    ///
+    /// * `row` - The values of the row by column.
+    /// * `number` - The number of the line, which identifies the trade along
+    ///   with its time if the export has no ids.
+    ///
+    /// # Returns
+    ///
+    /// The trade, or `None` if the row is no trade, e.g. a deposit.
+    ///
+    fn parse_row(&self, row: &Row, number: usize) -> Result<Option<ImportedTrade>, String>;
+}
+
+/// Splits a line of a CSV file into its values, which may be quoted.
+pub fn split_line(line: &str) -> Vec<String> {
+    let mut values: Vec<String> = vec![String::new()];
+    let mut quoted: bool = false;
+    let mut chars = line.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                values.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => values.push(String::new()),
+            _ => values.last_mut().unwrap().push(char),
+        }
+    }
+    values
+        .iter()
+        .map(|value| value.trim().to_string())
+        .collect()
+}
+
+/// Parses a number, ignoring currency symbols and thousands separators
+/// (e.g., `€1,234.50`).
+fn parse_number(value: &str) -> Option<f64> {
+    value
+        .chars()
+        .filter(|char| char.is_ascii_digit() || *char == '.' || *char == '-')
+        .collect::<String>()
+        .parse()
+        .ok()
+}
+
+/// Parses a time in UTC like `2024-01-31 12:00:00`, `2024-01-31T12:00:00Z`,
+/// `2024-01-31 12:00:00 UTC` or `2024-01-31 12:00:00.1234` as UNIX timestamp.
+fn parse_time(value: &str) -> Option<f64> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.timestamp_micros())
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(value.trim_end_matches(" UTC"), "%Y-%m-%d %H:%M:%S%.f")
+                .map(|time| time.and_utc().timestamp_micros())
+                .ok()
+        })
+        .map(|time| time as f64 / 1e6)
+}
+
+/// Splits an amount ending in its asset (e.g., `0.01000000BTC`).
+fn split_asset(value: &str) -> Option<(f64, String)> {
+    let index: usize = value.find(|char: char| char.is_ascii_alphabetic())?;
+    let (amount, asset) = value.split_at(index);
+    Some((parse_number(amount)?, asset.to_string()))
+}
+
+/// The quote currencies of Binance, to split the markets (e.g., BTCEUR).
+const BINANCE_QUOTES: [&str; 12] = [
+    "USDT", "USDC", "FDUSD", "BUSD", "TUSD", "EUR", "USD", "GBP", "TRY", "BTC", "ETH", "BNB",
+];
+
+/// The trade history of Binance Spot.
+pub struct Binance;
+
+impl ExchangeImporter for Binance {
+    fn name(&self) -> &'static str {
+        "binance"
+    }
+
+    fn is_header(&self, columns: &[String]) -> bool {
+        columns.iter().any(|column| column == "Date(UTC)")
+    }
+
+    fn parse_row(&self, row: &Row, number: usize) -> Result<Option<ImportedTrade>, String> {
+        let get = |column: &str| {
+            row.get(column)
+                .copied()
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| format!("lacks the {}", column))
+        };
+        let time: f64 = parse_time(get("Date(UTC)")?).ok_or("has an invalid date")?;
+        let price: f64 = parse_number(get("Price")?).ok_or("has an invalid price")?;
+        let (side, base, quote, amount, cost, fee) = if row.contains_key("Pair") {
+            // The amounts end in their asset.
+            let (amount, base) = split_asset(get("Executed")?).ok_or("has an invalid amount")?;
+            let (cost, quote) = split_asset(get("Amount")?).ok_or("has an invalid value")?;
+            (
+                get("Side")?,
+                base,
+                quote,
+                amount,
+                cost,
+                split_asset(get("Fee")?),
+            )
+        } else {
+            let market: &str = get("Market")?;
+            let quote: &str = BINANCE_QUOTES
+                .iter()
+                .find(|quote| market.len() > quote.len() && market.ends_with(*quote))
+                .ok_or_else(|| format!("has an unknown market {}", market))?;
+            (
+                get("Type")?,
+                market[..market.len() - quote.len()].to_string(),
+                quote.to_string(),
+                parse_number(get("Amount")?).ok_or("has an invalid amount")?,
+                parse_number(get("Total")?).ok_or("has an invalid value")?,
+                parse_number(get("Fee")?).zip(get("Fee Coin").ok().map(str::to_string)),
+            )
+        };
+        let side: &'static str = match side.to_uppercase().as_str() {
+            "BUY" => "buy",
+            "SELL" => "sell",
+            _ => return Err(format!("has an unknown side {}", side)),
+        };
+        Ok(Some(ImportedTrade {
+            id: format!("{}-{}-{}", self.name(), time, number),
+            source: self.name(),
+            pair: None,
+            order_id: None,
+            time,
+            side,
+            base,
+            quote,
+            amount,
+            price,
+            cost,
+            fee: fee.filter(|(fee, _)| *fee > 0f64),
+        }))
+    }
+}
+
+/// The transaction history of Coinbase.
+pub struct Coinbase;
+
+impl ExchangeImporter for Coinbase {
+    fn name(&self) -> &'static str {
+        "coinbase"
+    }
+
+    fn is_header(&self, columns: &[String]) -> bool {
+        ["Timestamp", "Transaction Type"]
+            .iter()
+            .all(|name| columns.iter().any(|column| column == name))
+    }
+
+    fn parse_row(&self, row: &Row, number: usize) -> Result<Option<ImportedTrade>, String> {
+        // The column names differ between the versions of the export.
+        let get = |columns: &[&str]| {
+            columns
+                .iter()
+                .find_map(|column| row.get(column).copied())
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| format!("lacks the {}", columns[0]))
+        };
+        let side: &'static str = match get(&["Transaction Type"])? {
+            "Buy" | "Advanced Trade Buy" => "buy",
+            "Sell" | "Advanced Trade Sell" => "sell",
+            _ => return Ok(None),
+        };
+        let time: f64 = parse_time(get(&["Timestamp"])?).ok_or("has an invalid timestamp")?;
+        let amount: f64 = parse_number(get(&["Quantity Transacted"])?)
+            .ok_or("has an invalid quantity")?
+            .abs();
+        let price: f64 = parse_number(get(&["Price at Transaction", "Spot Price at Transaction"])?)
+            .ok_or("has an invalid price")?;
+        let quote: String = get(&["Price Currency", "Spot Price Currency"])?.to_string();
+        let cost: f64 = match get(&["Subtotal"]) {
+            Ok(subtotal) => parse_number(subtotal)
+                .ok_or("has an invalid subtotal")?
+                .abs(),
+            Err(_) => amount * price,
+        };
+        let fee: Option<f64> = get(&["Fees and/or Spread", "Fees"])
+            .ok()
+            .and_then(parse_number)
+            .map(f64::abs);
+        Ok(Some(ImportedTrade {
+            id: match get(&["ID"]) {
+                Ok(id) => format!("{}-{}", self.name(), id),
+                Err(_) => format!("{}-{}-{}", self.name(), time, number),
+            },
+            source: self.name(),
+            pair: None,
+            order_id: None,
+            time,
+            side,
+            base: get(&["Asset"])?.to_string(),
+            quote: quote.clone(),
+            amount,
+            price,
+            cost,
+            fee: fee.filter(|fee| *fee > 0f64).map(|fee| (fee, quote)),
+        }))
+    }
+}
+
+/// The trades export of Kraken.
+pub struct Kraken;
+
+impl ExchangeImporter for Kraken {
+    fn name(&self) -> &'static str {
+        "kraken"
+    }
+
+    fn is_header(&self, columns: &[String]) -> bool {
+        ["txid", "ordertxid", "pair"]
+            .iter()
+            .all(|name| columns.iter().any(|column| column == name))
+    }
+
+    fn parse_row(&self, row: &Row, _number: usize) -> Result<Option<ImportedTrade>, String> {
+        let get = |column: &str| {
+            row.get(column)
+                .copied()
+                .filter(|value| !value.is_empty())
+                .ok_or_else(|| format!("lacks the {}", column))
+        };
+        // Margin trades don't change the holdings.
+        if get("margin").is_ok_and(|margin| margin.parse::<f64>().is_ok_and(|margin| margin > 0f64))
+        {
+            return Ok(None);
+        }
+        let side: &'static str = match get("type")? {
+            "buy" => "buy",
+            "sell" => "sell",
+            side => return Err(format!("has an unknown side {}", side)),
+        };
+        let number = |column: &str| {
+            get(column).and_then(|value| {
+                value
+                    .parse::<f64>()
+                    .map_err(|_| format!("has an invalid {}", column))
+            })
+        };
+        // Newer exports name the pairs by their currencies (e.g., BTC/EUR).
+        let pair: &str = get("pair")?;
+        let (base, quote, pair): (String, String, Option<String>) = match pair.split_once('/') {
+            Some((base, quote)) => (base.to_string(), quote.to_string(), None),
+            None => (String::new(), String::new(), Some(pair.to_string())),
+        };
+        let fee: f64 = number("fee")?;
+        Ok(Some(ImportedTrade {
+            id: get("txid")?.to_string(),
+            source: self.name(),
+            pair,
+            order_id: Some(get("ordertxid")?.to_string()),
+            time: parse_time(get("time")?).ok_or("has an invalid time")?,
+            side,
+            base,
+            // The fee is charged in the quote currency, which is only known by
+            // the pair in older exports.
+            quote: quote.clone(),
+            amount: number("vol")?,
+            price: number("price")?,
+            cost: number("cost")?,
+            fee: (fee > 0f64).then_some((fee, quote)),
+        }))
+    }
+}
+
+/// Returns the importer of one of `EXCHANGES`.
+pub fn importer(exchange: &str) -> Result<Box<dyn ExchangeImporter>, String> {
+    match exchange {
+        "binance" => Ok(Box::new(Binance)),
+        "coinbase" => Ok(Box::new(Coinbase)),
+        "kraken" => Ok(Box::new(Kraken)),
+        _ => Err(format!(
+            "Unknown exchange '{}', expected one of {}!",
+            exchange,
+            EXCHANGES.join(", ")
+        )),
+    }
+}
+
+/// Reads the trades of a CSV export of another exchange.
+///
+/// # Arguments
+///
+/// * `importer` - The importer of the exchange.
+/// * `path` - The CSV file.
+///
+/// # Returns
+///
+/// The trades sorted by time.
+///
+pub fn read_import(
+    importer: &dyn ExchangeImporter,
+    path: &Path,
+) -> Result<Vec<ImportedTrade>, String> {
+    let content: String = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim_start_matches('\u{feff}').trim()))
+        .filter(|(_, line)| !line.is_empty());
+    let header: Vec<String> = lines
+        .by_ref()
+        .map(|(_, line)| split_line(line))
+        .find(|columns| importer.is_header(columns))
+        .ok_or_else(|| {
+            format!(
+                "{} is no CSV export of {}, its header is missing!",
+                path.display(),
+                importer.name()
+            )
+        })?;
+
+    let mut trades: Vec<ImportedTrade> = Vec::new();
+    for (number, line) in lines {
+        let values: Vec<String> = split_line(line);
+        let row: Row = header
+            .iter()
+            .map(String::as_str)
+            .zip(values.iter().map(String::as_str))
+            .collect();
+        let trade: Option<ImportedTrade> = importer
+            .parse_row(&row, number)
+            .map_err(|e| format!("Line {} of {} {}: {}", number, path.display(), e, line))?;
+        trades.extend(trade);
+    }
+    trades.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    Ok(trades)
+}
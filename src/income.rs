@@ -0,0 +1,345 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module extracts the income from the ledger entries of an asset, i.e.
+staking and earn rewards, airdrops, dividends and credits such as rebates.
+Most tax returns require income to be declared separately from the capital
+gains and losses of disposals, so it is reported in a statement of its own,
+valued in the quote currency at the day it was received. As the value is
+taxed as income, it is also the cost basis of the amount received, which can
+be added to the holdings as lots of its own.
+
+Credits, i.e. fee rebates and referral rewards, can be treated as income or
+offset against the fees according to a policy.
+*/
+
+use crate::fiscal::YearStart;
+use crate::kraken::Trade;
+use crate::ledger::LedgerEntry;
+use crate::precision::Precision;
+use crate::prices::Prices;
+use crate::report::{print_table, CsvFormat};
+use log::warn;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+
+/// A ledger entry that is income.
+#[derive(Debug, Clone)]
+pub struct IncomeItem {
+    /// The id of the ledger entry.
+    pub id: String,
+    /// The reference id of the ledger entry.
+    pub refid: String,
+    pub time: f64,
+    /// The kind of income, e.g. staking or airdrop.
+    pub kind: String,
+    /// The amount received, net of fees.
+    pub amount: f64,
+    /// The price at the day of receipt, if known.
+    pub price: Option<f64>,
+    /// The value in the quote currency at the day of receipt, if a price is
+    /// known.
+    pub value: Option<f64>,
+}
+
+/// The treatment of credits, i.e. fee rebates and referral rewards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RebatePolicy {
+    /// Credits are income.
+    Income,
+    /// Credits are added to the holdings at no cost, which lowers the cost
+    /// basis like a refund of the fees paid.
+    Fees,
+    /// Credits are neither income nor part of the holdings.
+    Ignore,
+}
+
+impl RebatePolicy {
+    /// The values accepted by `--rebates`.
+    pub const NAMES: [&'static str; 3] = ["income", "fees", "ignore"];
+
+    /// Returns the policy for one of `NAMES`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "income" => Some(Self::Income),
+            "fees" => Some(Self::Fees),
+            "ignore" => Some(Self::Ignore),
+            _ => None,
+        }
+    }
+}
+
+/// Returns whether a ledger entry receives an amount.
+fn is_received(entry: &LedgerEntry) -> bool {
+    entry
+        .amount
+        .parse::<f64>()
+        .is_ok_and(|amount| amount > 0f64)
+}
+
+/// Returns the kind of income of a ledger entry, or `None` if it isn't income.
+///
+/// Entries of type `earn` are only income if they are rewards, allocations
+/// to and deallocations from earn strategies are transfers. Credits are only
+/// income if the rebate policy says so.
+pub fn income_kind(entry: &LedgerEntry, rebates: RebatePolicy) -> Option<&str> {
+    let received: bool = is_received(entry);
+    match entry.entry_type.as_str() {
+        "staking" | "reward" | "dividend" | "airdrop" if received => Some(&entry.entry_type),
+        "credit" if received && rebates == RebatePolicy::Income => Some("credit"),
+        "earn" if received && entry.subtype == "reward" => Some("earn"),
+        _ => None,
+    }
+}
+
+/// Converts the credits of an asset into trades of the side `credit`, which
+/// the FIFO lot engine adds to the holdings at no cost.
+///
+/// # Arguments
+///
+/// * `entries` - The ledger entries of the base asset of the trading pair.
+/// * `pair` - The trading pair.
+///
+/// # Returns
+///
+/// A trade for every credit, referring to the ledger entry by its `txid` so
+/// the balance cross-check considers the credit explained.
+///
+pub fn credit_trades(entries: &[LedgerEntry], pair: &str) -> Vec<Trade> {
+    entries
+        .iter()
+        .filter(|entry| entry.entry_type == "credit" && is_received(entry))
+        .map(|entry| {
+            let amount: f64 =
+                entry.amount.parse::<f64>().unwrap() - entry.fee.parse::<f64>().unwrap_or(0f64);
+            Trade {
+                txid: entry.refid.clone(),
+                ordertxid: entry.id.clone(),
+                pair: pair.to_string(),
+                time: entry.time,
+                side: "credit".to_string(),
+                price: "0".to_string(),
+                fee: "0".to_string(),
+                vol: amount.to_string(),
+                cost: "0".to_string(),
+                ordertype: "credit".to_string(),
+                margin: String::new(),
+                misc: String::new(),
+                postxid: String::new(),
+                maker: None,
+                base_fee: None,
+                source: None,
+            }
+        })
+        .collect()
+}
+
+/// Converts the income into trades of the side `credit`, which the FIFO lot
+/// engine adds to the holdings at their value at the day of receipt.
+///
+/// # Arguments
+///
+/// * `items` - The income of the base asset of the trading pair.
+/// * `pair` - The trading pair.
+///
+/// # Returns
+///
+/// A trade for every item, referring to the ledger entry by its `txid` so
+/// the balance cross-check considers the income explained. Items without a
+/// price are added at no cost.
+///
+pub fn income_trades(items: &[IncomeItem], pair: &str) -> Vec<Trade> {
+    items
+        .iter()
+        .map(|item| {
+            if item.value.is_none() {
+                warn!(
+                    "No price is known for the {} income {} of {}, adding it at no cost.",
+                    item.kind, item.id, pair
+                );
+            }
+            Trade {
+                txid: item.refid.clone(),
+                ordertxid: item.id.clone(),
+                pair: pair.to_string(),
+                time: item.time,
+                side: "credit".to_string(),
+                price: item.price.unwrap_or_default().to_string(),
+                fee: "0".to_string(),
+                vol: item.amount.to_string(),
+                cost: item.value.unwrap_or_default().to_string(),
+                ordertype: item.kind.clone(),
+                margin: String::new(),
+                misc: String::new(),
+                postxid: String::new(),
+                maker: None,
+                base_fee: None,
+                source: None,
+            }
+        })
+        .collect()
+}
+
+/// Extracts the income from the ledger entries of an asset.
+///
+/// # Arguments
+///
+/// * `entries` - The ledger entries of the asset.
+/// * `rebates` - The treatment of credits.
+/// * `prices` - The daily prices the income is valued at.
+/// * `precision` - The precision of the base and quote currency.
+///
+/// # Returns
+///
+/// The income sorted by time.
+///
+pub fn income_items(
+    entries: &[LedgerEntry],
+    rebates: RebatePolicy,
+    prices: &Prices,
+    precision: &Precision,
+) -> Vec<IncomeItem> {
+    let mut items: Vec<IncomeItem> = entries
+        .iter()
+        .filter_map(|entry| {
+            let kind: &str = income_kind(entry, rebates)?;
+            let amount: f64 = precision.base(
+                entry.amount.parse::<f64>().unwrap() - entry.fee.parse::<f64>().unwrap_or(0f64),
+            );
+            let price: Option<f64> = prices.price_at_time(entry.time).map(|(price, _)| price);
+            Some(IncomeItem {
+                id: entry.id.clone(),
+                refid: entry.refid.clone(),
+                time: entry.time,
+                kind: kind.to_string(),
+                amount,
+                price,
+                value: price.map(|price| precision.quote(amount * price)),
+            })
+        })
+        .collect();
+    items.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    items
+}
+
+/// Prints the income per year and kind.
+///
+/// # Arguments
+///
+/// * `items` - The income.
+/// * `year_start` - The first day of the fiscal years.
+/// * `asset` - The name of the base asset.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn print_income_statement(
+    items: &[IncomeItem],
+    year_start: YearStart,
+    asset: &str,
+    precision: &Precision,
+) {
+    // The amount, the value and whether a price was missing by year and kind.
+    let mut totals: BTreeMap<(i32, &str), (f64, f64, bool)> = BTreeMap::new();
+    for item in items {
+        let total = totals
+            .entry((year_start.year_of(item.time), &item.kind))
+            .or_default();
+        total.0 = precision.base(total.0 + item.amount);
+        total.1 = precision.quote(total.1 + item.value.unwrap_or_default());
+        total.2 |= item.value.is_none();
+    }
+
+    let header: Vec<String> = vec![
+        "Year".to_string(),
+        "Kind".to_string(),
+        format!("Received ({})", asset),
+        "Value".to_string(),
+    ];
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut year_total: Option<(i32, f64)> = None;
+    let push_year_total = |rows: &mut Vec<Vec<String>>, total: Option<(i32, f64)>| {
+        if let Some((year, value)) = total {
+            rows.push(vec![
+                year_start.label(year),
+                "total".to_string(),
+                String::new(),
+                precision.fmt_quote(value),
+            ]);
+        }
+    };
+    for ((year, kind), (amount, value, incomplete)) in &totals {
+        if year_total.is_some_and(|(total_year, _)| total_year != *year) {
+            push_year_total(&mut rows, year_total.take());
+        }
+        let (_, total) = year_total.get_or_insert((*year, 0f64));
+        *total = precision.quote(*total + value);
+        rows.push(vec![
+            year_start.label(*year),
+            kind.to_string(),
+            precision.fmt_base(*amount),
+            format!(
+                "{}{}",
+                precision.fmt_quote(*value),
+                if *incomplete { "*" } else { "" }
+            ),
+        ]);
+    }
+    push_year_total(&mut rows, year_total);
+
+    print_table(&header, &rows, 2);
+    if rows.is_empty() {
+        println!("No income.");
+    } else if totals.values().any(|(_, _, incomplete)| *incomplete) {
+        println!("* Incomplete, no price is known for some of the days of receipt.");
+    }
+}
+
+/// Writes every income item with its price and value to a CSV file.
+///
+/// # Arguments
+///
+/// * `items` - The income.
+/// * `file_path` - The path of the file.
+/// * `precision` - The precision used to format amounts and values.
+/// * `format` - The delimiter, decimal separator and date format of the CSV
+///   file.
+///
+pub fn write_income_report(
+    items: &[IncomeItem],
+    file_path: &str,
+    precision: &Precision,
+    format: &CsvFormat,
+) -> Result<(), String> {
+    let mut file: File =
+        File::create(file_path).map_err(|e| format!("Failed to create {}: {}", file_path, e))?;
+    let mut lines: Vec<String> = vec![format.row(&[
+        "date",
+        "ledger_id",
+        "refid",
+        "kind",
+        "amount",
+        "price",
+        "value",
+    ])];
+    for item in items {
+        lines.push(
+            format.row(&[
+                format.time(item.time),
+                item.id.clone(),
+                item.refid.clone(),
+                item.kind.clone(),
+                format.number(&precision.fmt_base(item.amount)),
+                item.price
+                    .map(|price| format.number(&precision.fmt_quote(price)))
+                    .unwrap_or_default(),
+                item.value
+                    .map(|value| format.number(&precision.fmt_quote(value)))
+                    .unwrap_or_default(),
+            ]),
+        );
+    }
+    writeln!(file, "{}", lines.join("\n"))
+        .map_err(|e| format!("Failed to write {}: {}", file_path, e))
+}
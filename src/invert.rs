@@ -0,0 +1,52 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module inverts the trades of a trading pair for users whose bookkeeping
+currency is the crypto asset, e.g. to treat EUR as the asset and BTC as the
+currency it is priced in. Buying BTC with EUR becomes a sale of EUR for BTC and
+vice versa, so the FIFO lot engine computes the PnL of the EUR lots in BTC
+without knowing about the inversion.
+*/
+
+use crate::kraken::Trade;
+
+/// Inverts the trades of a trading pair.
+///
+/// # Arguments
+///
+/// * `trades` - The trades sorted by time.
+///
+/// # Returns
+///
+/// The trades with swapped sides, the cost as volume, the volume as cost and
+/// the reciprocal price. Fees charged in the quote currency are charged in the
+/// base currency of the inverted view and vice versa. Credits of the base asset
+/// don't involve the quote currency and are omitted.
+///
+pub fn invert_trades(trades: &[Trade]) -> Vec<Trade> {
+    trades
+        .iter()
+        .filter(|trade| trade.side == "buy" || trade.side == "sell")
+        .map(|trade| {
+            let price: f64 = trade.price.parse().unwrap();
+            let (fee, base_fee): (f64, Option<f64>) = match trade.base_fee {
+                Some(base_fee) => (base_fee, None),
+                None => {
+                    let fee: f64 = trade.fee.parse().unwrap();
+                    (fee / price, Some(fee))
+                }
+            };
+            Trade {
+                side: if trade.side == "buy" { "sell" } else { "buy" }.to_string(),
+                price: (1f64 / price).to_string(),
+                fee: fee.to_string(),
+                vol: trade.cost.clone(),
+                cost: trade.vol.clone(),
+                base_fee,
+                ..trade.clone()
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,281 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module generates the closing journal entries of a fiscal year for the
+bookkeeping of entities, from the lots that are open at its end, i.e. those
+that `--save-lots` carries forward:
+
+- The realized PnL of the year is transferred to the retained earnings.
+- With `--revaluation-reserve`, every open lot is revalued at the closing
+  price and the difference to its cost is booked to the revaluation reserve,
+  as under the revaluation model of IAS 38. Otherwise the lots are carried at
+  cost.
+- Every open lot is closed against the closing balance account at the end of
+  the year and opened against the opening balance account on the next day.
+
+Every entry consists of a debit and a credit line of the same amount, so the
+journal balances. Short lots have a negative carrying amount and are thus
+booked the other way round.
+*/
+
+use crate::fifo::Lot;
+use crate::precision::Precision;
+use chrono::{Days, NaiveDate};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// The accounts the entries are booked to, besides the holdings of the base
+/// currency.
+const REALIZED_PNL: &str = "Realized PnL";
+const RETAINED_EARNINGS: &str = "Retained earnings";
+const REVALUATION_RESERVE: &str = "Revaluation reserve";
+const CLOSING_BALANCE: &str = "Closing balance";
+const OPENING_BALANCE: &str = "Opening balance";
+
+/// A line of the journal, which debits or credits an account.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalLine {
+    pub date: NaiveDate,
+    /// The number of the entry the line belongs to.
+    pub entry: usize,
+    pub account: String,
+    pub debit: f64,
+    pub credit: f64,
+    pub description: String,
+}
+
+/// The journal being built, numbering its entries.
+struct Journal {
+    lines: Vec<JournalLine>,
+    precision: Precision,
+}
+
+impl Journal {
+    /// Books an amount from the credited to the debited account, or the other
+    /// way round if it is negative. Amounts that round to zero are skipped.
+    fn book(&mut self, date: NaiveDate, debit: &str, credit: &str, amount: f64, description: &str) {
+        let amount: f64 = self.precision.quote(amount);
+        if amount == 0f64 {
+            return;
+        }
+        let (debit, credit): (&str, &str) = match amount > 0f64 {
+            true => (debit, credit),
+            false => (credit, debit),
+        };
+        let entry: usize = self.lines.len() / 2 + 1;
+        for (account, debit, credit) in [(debit, amount.abs(), 0f64), (credit, 0f64, amount.abs())]
+        {
+            self.lines.push(JournalLine {
+                date,
+                entry,
+                account: account.to_string(),
+                debit,
+                credit,
+                description: description.to_string(),
+            });
+        }
+    }
+}
+
+/// Generates the closing entries of a fiscal year.
+///
+/// # Arguments
+///
+/// * `lots` - The lots open at the end of the year.
+/// * `realized_pnl` - The realized PnL of the year.
+/// * `price` - The closing price the lots are revalued at, or `None` to carry
+///   them at cost.
+/// * `date` - The last day of the year.
+/// * `base` - The currency code of the base currency (e.g., BTC).
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn closing_entries(
+    lots: &[Lot],
+    realized_pnl: f64,
+    price: Option<f64>,
+    date: NaiveDate,
+    base: &str,
+    precision: &Precision,
+) -> Vec<JournalLine> {
+    let mut journal: Journal = Journal {
+        lines: Vec::new(),
+        precision: *precision,
+    };
+    let holdings: String = format!("Holdings {}", base);
+    let next_day: NaiveDate = date + Days::new(1);
+    journal.book(
+        date,
+        REALIZED_PNL,
+        RETAINED_EARNINGS,
+        realized_pnl,
+        "Transfer of the realized PnL to equity",
+    );
+    for lot in lots {
+        let description: String = format!(
+            "Lot of {} {} acquired by {}",
+            precision.fmt_base(lot.amount),
+            base,
+            lot.ordertxid
+        );
+        let mut carrying: f64 = lot.cost;
+        if let Some(price) = price {
+            let revaluation: f64 = precision.quote(lot.amount * price - lot.cost);
+            journal.book(
+                date,
+                &holdings,
+                REVALUATION_RESERVE,
+                revaluation,
+                &format!(
+                    "Revaluation at {}: {}",
+                    precision.fmt_quote(price),
+                    description
+                ),
+            );
+            carrying += revaluation;
+        }
+        journal.book(
+            date,
+            CLOSING_BALANCE,
+            &holdings,
+            carrying,
+            &format!("Closing: {}", description),
+        );
+        journal.book(
+            next_day,
+            &holdings,
+            OPENING_BALANCE,
+            carrying,
+            &format!("Opening: {}", description),
+        );
+    }
+    journal.lines
+}
+
+/// Writes the journal to a CSV file.
+///
+/// # Arguments
+///
+/// * `lines` - The lines of the journal.
+/// * `file_path` - The path of the CSV file.
+/// * `currency` - The currency the amounts are stated in.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn write_journal(
+    lines: &[JournalLine],
+    file_path: &str,
+    currency: &str,
+    precision: &Precision,
+) -> Result<(), String> {
+    let file: File =
+        File::create(file_path).map_err(|e| format!("Failed to create {}: {}", file_path, e))?;
+    let mut writer: BufWriter<File> = BufWriter::new(file);
+    let amount = |value: f64| match value == 0f64 {
+        true => String::new(),
+        false => precision.fmt_quote(value),
+    };
+    let mut write = || -> std::io::Result<()> {
+        writeln!(
+            writer,
+            "date,entry,account,debit,credit,currency,description"
+        )?;
+        for line in lines {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},\"{}\"",
+                line.date,
+                line.entry,
+                line.account,
+                amount(line.debit),
+                amount(line.credit),
+                currency,
+                line.description.replace('"', "\"\"")
+            )?;
+        }
+        writer.flush()
+    };
+    write().map_err(|e| format!("Failed to write {}: {}", file_path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRECISION: Precision = Precision { base: 8, quote: 2 };
+
+    /// Returns an open lot.
+    fn lot(amount: f64, cost: f64, ordertxid: &str) -> Lot {
+        Lot {
+            amount,
+            cost,
+            time: 1704067200f64,
+            ordertxid: ordertxid.to_string(),
+            ordertype: "limit".to_string(),
+            maker: None,
+        }
+    }
+
+    /// Returns the balances of the accounts, debits minus credits.
+    fn balances(lines: &[JournalLine]) -> Vec<(String, f64)> {
+        let mut balances: Vec<(String, f64)> = Vec::new();
+        for line in lines {
+            match balances
+                .iter_mut()
+                .find(|(account, _)| *account == line.account)
+            {
+                Some((_, balance)) => *balance += line.debit - line.credit,
+                None => balances.push((line.account.clone(), line.debit - line.credit)),
+            }
+        }
+        balances
+    }
+
+    #[test]
+    fn closes_and_opens_every_lot_at_cost() {
+        let date: NaiveDate = NaiveDate::from_ymd_opt(2024, 12, 31).unwrap();
+        let lots: Vec<Lot> = vec![lot(0.5, 20000f64, "O1"), lot(-0.1, -5000f64, "O2")];
+        let lines: Vec<JournalLine> =
+            closing_entries(&lots, 1234.56, None, date, "BTC", &PRECISION);
+        // The PnL transfer and a closing and an opening entry per lot.
+        assert_eq!(lines.len(), 10);
+        assert_eq!(lines[0].account, REALIZED_PNL);
+        assert_eq!(lines[0].debit, 1234.56);
+        assert_eq!(lines[1].account, RETAINED_EARNINGS);
+        assert_eq!(lines[1].credit, 1234.56);
+        assert_eq!(lines[2].account, CLOSING_BALANCE);
+        assert_eq!(lines[2].debit, 20000f64);
+        assert_eq!(lines[4].date, date + Days::new(1));
+        assert_eq!(lines[4].account, "Holdings BTC");
+        assert_eq!(lines[4].debit, 20000f64);
+        // The short lot is booked the other way round.
+        assert_eq!(lines[6].account, "Holdings BTC");
+        assert_eq!(lines[6].debit, 5000f64);
+        // Every entry balances, and the holdings are carried forward.
+        for entry in lines.chunks(2) {
+            assert_eq!(entry[0].debit, entry[1].credit);
+        }
+        let holdings: f64 = balances(&lines)
+            .into_iter()
+            .find(|(account, _)| account == "Holdings BTC")
+            .unwrap()
+            .1;
+        assert_eq!(holdings, 0f64);
+    }
+
+    #[test]
+    fn revalues_the_lots_into_the_reserve() {
+        let date: NaiveDate = NaiveDate::from_ymd_opt(2024, 12, 31).unwrap();
+        let lots: Vec<Lot> = vec![lot(0.5, 20000f64, "O1")];
+        let lines: Vec<JournalLine> =
+            closing_entries(&lots, 0f64, Some(50000f64), date, "BTC", &PRECISION);
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0].account, "Holdings BTC");
+        assert_eq!(lines[0].debit, 5000f64);
+        assert_eq!(lines[1].account, REVALUATION_RESERVE);
+        assert_eq!(lines[1].credit, 5000f64);
+        // The lot is closed and opened at its revalued carrying amount.
+        assert_eq!(lines[2].debit, 25000f64);
+        assert_eq!(lines[5].credit, 25000f64);
+    }
+}
@@ -0,0 +1,338 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module fetches the ledger entries of an asset and cross-checks the
+balance reconstructed from the trades against the balances reported in the
+ledger. Every trade serves as a checkpoint: as long as the difference between
+both balances stays constant, the trades explain every movement of the asset.
+The first checkpoint where the difference changes is where data is missing,
+e.g. due to deposits, withdrawals, trades of other pairs or trades that were
+not fetched.
+*/
+
+use crate::kraken::{fetch_all_pages, parse_page, KrakenAPI, Trade};
+use crate::precision::Precision;
+use crate::report::format_time;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// An entry of the ledger, which defaults missing fields and ignores unknown
+/// ones like the trades (see the `schema` module).
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LedgerEntry {
+    #[serde(skip)]
+    pub id: String,
+    pub refid: String,
+    pub time: f64,
+    #[serde(rename = "type")]
+    pub entry_type: String,
+    /// The subtype, e.g. `reward` or `allocation` for entries of type `earn`.
+    pub subtype: String,
+    pub amount: String,
+    pub fee: String,
+    pub balance: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct LedgersResult {
+    ledger: HashMap<String, LedgerEntry>,
+    count: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct LedgersResponse {
+    error: Vec<String>,
+    result: Option<LedgersResult>,
+}
+
+/// Fetches the ledger entries of an asset from the Kraken API.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `asset` - The asset to fetch the ledger entries for (e.g., XXBT).
+/// * `start` - An optional start date for filtering the entries.
+/// * `end` - An optional end date for filtering the entries.
+///
+/// # Returns
+///
+/// The ledger entries sorted by time.
+///
+pub async fn fetch_ledgers(
+    api: &KrakenAPI,
+    asset: &str,
+    start: Option<f64>,
+    end: Option<f64>,
+) -> Vec<LedgerEntry> {
+    let mut params = vec![("asset", asset.to_string())];
+    if let Some(start) = start {
+        params.push(("start", start.to_string()));
+    }
+    if let Some(end) = end {
+        params.push(("end", end.to_string()));
+    }
+
+    info!("Fetching ledger entries...");
+    try_fetch_ledgers(api, &params)
+        .await
+        .unwrap_or_else(|error| {
+            error!("Error fetching ledger entries: {:?}", error);
+            std::process::exit(1);
+        })
+}
+
+/// Fetches the ledger entries matching the request parameters (e.g., `asset`
+/// and `type`) from the Kraken API.
+///
+/// # Returns
+///
+/// The ledger entries sorted by time, or the errors reported by Kraken.
+///
+pub async fn try_fetch_ledgers(
+    api: &KrakenAPI,
+    params: &[(&str, String)],
+) -> Result<Vec<LedgerEntry>, Vec<String>> {
+    let entries: HashMap<String, LedgerEntry> = fetch_all_pages(
+        api,
+        "/0/private/Ledgers",
+        params,
+        |response| {
+            let ledgers_response: LedgersResponse = parse_page(response)?;
+            ledgers_response
+                .result
+                .map(|result| (result.ledger, result.count as usize))
+                .ok_or(ledgers_response.error)
+        },
+        |entry| entry.time,
+        Some,
+    )
+    .await?;
+
+    let mut entries: Vec<LedgerEntry> = entries
+        .into_iter()
+        .map(|(id, entry)| LedgerEntry { id, ..entry })
+        .collect();
+    entries.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    Ok(entries)
+}
+
+/// Sets the fee charged in the base currency of every trade whose ledger entry
+/// of the base asset reports a fee.
+///
+/// Kraken states the fee of a trade in the quote currency, even if it was
+/// charged in the base currency (e.g. by the order flag `fcib`) or in a third
+/// currency. Only the ledger tells where it was charged. Fees charged in a
+/// third currency are still accounted with their value in the quote currency.
+///
+/// # Arguments
+///
+/// * `trades` - The trades.
+/// * `entries` - The ledger entries of the base asset.
+///
+/// # Returns
+///
+/// The number of trades with a fee in the base currency.
+///
+pub fn apply_base_fees(trades: &mut [Trade], entries: &[LedgerEntry]) -> usize {
+    let fee_by_refid: HashMap<&str, f64> = entries
+        .iter()
+        .filter(|entry| entry.entry_type == "trade")
+        .filter_map(|entry| {
+            let fee: f64 = entry.fee.parse().ok()?;
+            (fee > 0f64).then_some((entry.refid.as_str(), fee))
+        })
+        .collect();
+    let mut count: usize = 0usize;
+    for trade in trades {
+        trade.base_fee = fee_by_refid.get(trade.txid.as_str()).copied();
+        count += trade.base_fee.is_some() as usize;
+    }
+    count
+}
+
+/// The balances at a checkpoint, i.e. after a trade.
+#[derive(Debug)]
+pub struct Checkpoint<'a> {
+    pub trade: &'a Trade,
+    pub trade_balance: f64,
+    pub ledger_balance: f64,
+    /// Whether there is a ledger entry that refers to the trade.
+    pub has_ledger_entry: bool,
+}
+
+impl Checkpoint<'_> {
+    /// The difference between the ledger and the reconstructed balance.
+    pub fn offset(&self) -> f64 {
+        self.ledger_balance - self.trade_balance
+    }
+}
+
+/// The first checkpoint where the reconstructed balance diverges from the
+/// ledger, including the ledger entries that are not explained by the trades.
+#[derive(Debug)]
+pub struct Divergence<'a> {
+    pub checkpoint: usize,
+    pub unexplained_entries: Vec<&'a LedgerEntry>,
+}
+
+/// The result of the balance cross-check.
+#[derive(Debug)]
+pub struct BalanceCheck<'a> {
+    pub checkpoints: Vec<Checkpoint<'a>>,
+    pub divergence: Option<Divergence<'a>>,
+}
+
+/// Reconstructs the running balance from the trades and compares it with the
+/// ledger balance after every trade. An opening balance is part of the
+/// reconstructed balance but no checkpoint, since the ledger has no entry for it.
+///
+/// # Arguments
+///
+/// * `trades` - The trades sorted by time.
+/// * `entries` - The ledger entries of the base asset sorted by time.
+/// * `precision` - The precision of the base asset.
+///
+pub fn cross_check_balance<'a>(
+    trades: &'a [Trade],
+    entries: &'a [LedgerEntry],
+    precision: &Precision,
+) -> BalanceCheck<'a> {
+    let trade_txids: HashSet<&str> = trades.iter().map(|trade| trade.txid.as_str()).collect();
+    let entry_by_refid: HashMap<&str, usize> = entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| (entry.refid.as_str(), index))
+        .collect();
+
+    let mut checkpoints: Vec<Checkpoint> = Vec::new();
+    let mut divergence: Option<Divergence> = None;
+    let mut trade_balance: f64 = 0f64;
+    let mut previous_entry: Option<usize> = None;
+
+    for trade in trades {
+        let amount: f64 = trade.vol.parse().unwrap();
+        let base_fee: f64 = trade.base_fee.unwrap_or(0f64);
+        trade_balance = precision.base(
+            if matches!(
+                trade.side.as_str(),
+                "buy" | "credit" | "opening" | "deposit"
+            ) {
+                trade_balance + amount - base_fee
+            } else {
+                trade_balance - amount - base_fee
+            },
+        );
+        // Opening balances precede the history of the ledger.
+        if trade.side == "opening" {
+            continue;
+        }
+
+        // Use the entry of the trade itself or the last entry before it.
+        let ledger_entry: Option<usize> = entry_by_refid
+            .get(trade.txid.as_str())
+            .copied()
+            .or_else(|| entries.iter().rposition(|entry| entry.time <= trade.time));
+        let ledger_balance: f64 = ledger_entry
+            .map(|index| entries[index].balance.parse().unwrap())
+            .unwrap_or(0f64);
+
+        let checkpoint = Checkpoint {
+            trade,
+            trade_balance,
+            ledger_balance,
+            has_ledger_entry: entry_by_refid.contains_key(trade.txid.as_str()),
+        };
+
+        if divergence.is_none() {
+            let diverged: bool = !checkpoint.has_ledger_entry
+                || checkpoints.last().is_some_and(|last: &Checkpoint| {
+                    precision.base(checkpoint.offset() - last.offset()) != 0f64
+                });
+            if diverged {
+                let from: usize = previous_entry.map(|index| index + 1).unwrap_or(0);
+                let to: usize = ledger_entry.map(|index| index + 1).unwrap_or(0);
+                divergence = Some(Divergence {
+                    checkpoint: checkpoints.len(),
+                    unexplained_entries: entries[from..to.max(from)]
+                        .iter()
+                        .filter(|entry| !trade_txids.contains(entry.refid.as_str()))
+                        .collect(),
+                });
+            }
+        }
+
+        previous_entry = ledger_entry.or(previous_entry);
+        checkpoints.push(checkpoint);
+    }
+
+    BalanceCheck {
+        checkpoints,
+        divergence,
+    }
+}
+
+/// Prints the result of the balance cross-check.
+pub fn print_balance_check(check: &BalanceCheck, asset: &str, precision: &Precision) {
+    println!("Balance cross-check against the ledger ({}):", asset);
+    println!(
+        "{:<20} {:>20} {:>20} {:>20}",
+        "Checkpoint", "Trades", "Ledger", "Difference"
+    );
+    // Print the last checkpoint of every month to keep the output short.
+    for (index, checkpoint) in check.checkpoints.iter().enumerate() {
+        let month = |checkpoint: &Checkpoint| format_time(checkpoint.trade.time)[..7].to_string();
+        let is_last_of_month: bool = check
+            .checkpoints
+            .get(index + 1)
+            .is_none_or(|next| month(next) != month(checkpoint));
+        if is_last_of_month {
+            println!(
+                "{:<20} {:>20} {:>20} {:>20}",
+                format_time(checkpoint.trade.time),
+                precision.fmt_base(checkpoint.trade_balance),
+                precision.fmt_base(checkpoint.ledger_balance),
+                precision.fmt_base(checkpoint.offset()),
+            );
+        }
+    }
+
+    match &check.divergence {
+        None => println!(
+            "No divergence found across {} checkpoints.",
+            check.checkpoints.len()
+        ),
+        Some(divergence) => {
+            let checkpoint: &Checkpoint = &check.checkpoints[divergence.checkpoint];
+            println!(
+                "First divergence at {} (trade {} of order {}):",
+                format_time(checkpoint.trade.time),
+                checkpoint.trade.txid,
+                checkpoint.trade.ordertxid,
+            );
+            if !checkpoint.has_ledger_entry {
+                println!("  The trade has no ledger entry.");
+            }
+            if divergence.unexplained_entries.is_empty() {
+                println!("  No ledger entries explain the difference.");
+            } else {
+                println!("  Ledger entries not explained by the trades:");
+                for entry in &divergence.unexplained_entries {
+                    println!(
+                        "  {} {:<12} {} (ref: {}) amount: {} fee: {} balance: {}",
+                        format_time(entry.time),
+                        entry.entry_type,
+                        entry.id,
+                        entry.refid,
+                        entry.amount,
+                        entry.fee,
+                        entry.balance,
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,135 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This library holds the modules of the kraken-pnl-calculator, whose binary is
+built on top of it.
+*/
+
+//! The lot engine computing the PnL of a trading pair is built around the
+//! `CostBasisStrategy` trait, so other rules of selecting the lots a sell
+//! disposes of, e.g. of other jurisdictions, can be implemented without
+//! patching the crate:
+//!
+//! ```
+//! use kraken_pnl_calculator::{CostBasisStrategy, Lot, LotEngine, LotSelection, PnL};
+//! use kraken_pnl_calculator::{Precision, Trade, YearStart};
+//! use std::collections::VecDeque;
+//! use std::sync::Arc;
+//!
+//! /// The newest lots first, like LIFO.
+//! #[derive(Debug)]
+//! struct Newest;
+//!
+//! impl CostBasisStrategy for Newest {
+//!     fn name(&self) -> String {
+//!         "Newest".to_string()
+//!     }
+//!
+//!     fn select(
+//!         &self,
+//!         lots: &VecDeque<Lot>,
+//!         _trade: &Trade,
+//!         amount: f64,
+//!         _precision: &Precision,
+//!     ) -> Vec<(usize, f64)> {
+//!         let mut selected: Vec<(usize, f64)> = Vec::new();
+//!         let mut remaining: f64 = amount;
+//!         for (index, lot) in lots.iter().enumerate().rev() {
+//!             if remaining <= 0f64 {
+//!                 break;
+//!             }
+//!             if lot.amount > 0f64 {
+//!                 selected.push((index, lot.amount.min(remaining)));
+//!                 remaining -= lot.amount.min(remaining);
+//!             }
+//!         }
+//!         selected
+//!     }
+//! }
+//!
+//! /// Returns the PnL of the trades of a pair, sorted by time.
+//! fn pnl(trades: &[Trade], precision: &Precision) -> PnL {
+//!     let selection: LotSelection = LotSelection {
+//!         strategy: Arc::new(Newest),
+//!     };
+//!     let mut engine: LotEngine =
+//!         LotEngine::new(None, YearStart::default(), precision, &selection, None, 0f64);
+//!     for trade in trades {
+//!         engine.feed(trade);
+//!     }
+//!     engine.finish()
+//! }
+//! ```
+//!
+//! The strategies of `--method` are returned by `strategy`.
+
+pub mod adjustments;
+pub mod cache;
+pub mod chart;
+pub mod checkpoint;
+pub mod cli;
+pub mod config;
+pub mod cost_basis;
+pub mod credentials;
+pub mod cross;
+pub mod execution;
+pub mod export;
+pub mod fees;
+pub mod fifo;
+pub mod fiscal;
+pub mod fixtures;
+pub mod flags;
+pub mod futures;
+pub mod fx;
+pub mod html;
+mod http;
+pub mod imports;
+pub mod income;
+pub mod invert;
+pub mod journal;
+pub mod kraken;
+pub mod ledger;
+pub mod logging;
+pub mod lots;
+pub mod margin;
+pub mod metrics;
+pub mod normalized;
+pub mod notify;
+pub mod otp;
+pub mod performance;
+pub mod precision;
+pub mod preflight;
+pub mod prices;
+pub mod rate_limit;
+pub mod reconcile;
+pub mod report;
+pub mod returns;
+pub mod risk;
+pub mod runs;
+pub mod schema;
+pub mod selection;
+pub mod server;
+pub mod simulate;
+pub mod slippage;
+pub mod sources;
+pub mod spec_id;
+pub mod state;
+pub mod stats;
+pub mod strategies;
+pub mod stream;
+pub mod tax;
+pub mod transfers;
+pub mod tui;
+pub mod update;
+pub mod verify;
+pub mod wash_sale;
+pub mod watch;
+pub mod xlsx;
+
+pub use cost_basis::{strategy, CostBasisStrategy, LotSelection, METHODS};
+pub use fifo::{Lot, LotEngine, PnL};
+pub use fiscal::YearStart;
+pub use kraken::Trade;
+pub use precision::Precision;
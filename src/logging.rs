@@ -0,0 +1,76 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module contains the logger, which writes the messages of this crate to
+stderr, so the results printed to stdout can be piped. The verbosity is
+controlled by `-v` (debug: requests, pagination offsets and rate-limiter
+waits), `-vv` (trace) and `--quiet` (errors only).
+
+Messages are written through the progress bars, so they don't tear them. The
+credentials, the nonce and the signature of a request are never logged.
+*/
+
+use indicatif::{MultiProgress, ProgressDrawTarget};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::sync::OnceLock;
+
+/// The progress bars of the running paginated fetches.
+static PROGRESS: OnceLock<MultiProgress> = OnceLock::new();
+
+/// Returns the progress bars, which are hidden with `--quiet`.
+pub fn progress() -> &'static MultiProgress {
+    PROGRESS.get_or_init(|| {
+        let progress = MultiProgress::new();
+        if log::max_level() < LevelFilter::Info {
+            progress.set_draw_target(ProgressDrawTarget::hidden());
+        }
+        progress
+    })
+}
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        // The messages of the dependencies (e.g. reqwest) are not of interest.
+        metadata.level() <= log::max_level()
+            && metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line: String = match record.level() {
+            Level::Error | Level::Info => record.args().to_string(),
+            Level::Warn => format!("Warning: {}", record.args()),
+            Level::Debug => format!("[debug] {}", record.args()),
+            Level::Trace => format!("[trace] {}", record.args()),
+        };
+        progress().suspend(|| eprintln!("{}", line));
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: Logger = Logger;
+
+/// Initializes the logger.
+///
+/// # Arguments
+///
+/// * `verbosity` - The number of times `-v` was passed.
+/// * `quiet` - Whether only errors are logged.
+///
+pub fn init(verbosity: u8, quiet: bool) {
+    let level: LevelFilter = match (quiet, verbosity) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    log::set_logger(&LOGGER).expect("The logger is already initialized!");
+    log::set_max_level(level);
+}
@@ -0,0 +1,119 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module carries the open lots forward from one run to the next. At the end
+of a year, the lots that are still open are saved to a JSON file, which seeds
+the lots of the next year's run, so the FIFO chain stays consistent without
+fetching the complete history every time.
+
+The file records the time of the last trade that went into the lots, so the
+trades up to that time aren't processed a second time when loading it.
+*/
+
+use crate::fifo::Lot;
+use crate::kraken::Trade;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+/// The content of a lots file.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LotsFile {
+    pub tool: String,
+    pub version: String,
+    pub generated_at: String,
+    pub symbol: String,
+    /// Whether base and quote currency are swapped.
+    pub inverted: bool,
+    /// The time of the last trade that went into the lots as UNIX timestamp.
+    pub until: Option<f64>,
+    pub lots: Vec<Lot>,
+}
+
+/// Saves the open lots to a JSON file.
+///
+/// # Arguments
+///
+/// * `lots` - The open lots.
+/// * `file_path` - The path of the JSON file.
+/// * `symbol` - The trading pair.
+/// * `inverted` - Whether base and quote currency are swapped.
+/// * `until` - The time of the last trade that went into the lots.
+///
+pub fn save_lots(
+    lots: &[Lot],
+    file_path: &str,
+    symbol: &str,
+    inverted: bool,
+    until: Option<f64>,
+) -> Result<(), String> {
+    let content = LotsFile {
+        tool: env!("CARGO_PKG_NAME").to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        generated_at: chrono::Utc::now().to_rfc3339(),
+        symbol: symbol.to_string(),
+        inverted,
+        until,
+        lots: lots.to_vec(),
+    };
+    let file: File =
+        File::create(file_path).map_err(|e| format!("Failed to create {}: {}", file_path, e))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &content)
+        .map_err(|e| format!("Failed to write {}: {}", file_path, e))
+}
+
+/// Loads the lots saved by [`save_lots`].
+///
+/// # Arguments
+///
+/// * `file_path` - The path of the JSON file.
+/// * `symbol` - The trading pair the lots must belong to.
+/// * `inverted` - Whether base and quote currency are swapped, which must
+///   match the run that saved the lots.
+///
+pub fn load_lots(file_path: &str, symbol: &str, inverted: bool) -> Result<LotsFile, String> {
+    let file: File =
+        File::open(file_path).map_err(|e| format!("Failed to open {}: {}", file_path, e))?;
+    let content: LotsFile = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| format!("Invalid {}: {}", file_path, e))?;
+    if content.symbol != symbol {
+        return Err(format!(
+            "{} contains the lots of {}, not of {}!",
+            file_path, content.symbol, symbol
+        ));
+    }
+    if content.inverted != inverted {
+        return Err(format!(
+            "{} was saved {} --invert, so it must be loaded {} it!",
+            file_path,
+            if content.inverted { "with" } else { "without" },
+            if content.inverted { "with" } else { "without" },
+        ));
+    }
+    Ok(content)
+}
+
+/// Returns a lot as trade of the side `opening`, which the FIFO lot engine
+/// restores as lot, keeping its time, order and cost.
+pub fn lot_trade(lot: &Lot, pair: &str) -> Trade {
+    Trade {
+        txid: format!("lot-{}", lot.ordertxid),
+        ordertxid: lot.ordertxid.clone(),
+        pair: pair.to_string(),
+        time: lot.time,
+        side: "opening".to_string(),
+        price: (lot.cost / lot.amount).to_string(),
+        fee: "0".to_string(),
+        vol: lot.amount.to_string(),
+        cost: lot.cost.to_string(),
+        ordertype: "opening".to_string(),
+        margin: String::new(),
+        misc: String::new(),
+        postxid: String::new(),
+        maker: None,
+        base_fee: None,
+        source: None,
+    }
+}
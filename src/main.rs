@@ -6,7 +6,9 @@ GitHub: https://github.com/btschwertfeger
 This program computes the FIFO PnL for a given trading pair on the Kraken
 exchange. It fetches the trades and closed orders from the Kraken API and
 computes the FIFO PnL based on the trades. The program requires the following
-environment variables to be set:
+environment variables to be set, either directly or via a .env file (their
+names can be changed in the configuration file), unless the credentials are
+stored in the OS keyring via `login` and `--keyring` is passed:
 
 - KRAKEN_API_KEY
 - KRAKEN_SECRET_KEY
@@ -18,548 +20,2872 @@ $ export KRAKEN_SECRET_KEY=mysecret
 $ cargo run -- --symbol XXBTZEUR --userref 1734531952 --tier pro --year 2024 --start 2024-01-01 --end 2024-12-31
 */
 
-use base64::{engine::general_purpose, Engine as _};
-use chrono::{DateTime, Datelike, NaiveDate};
-use clap::{Arg, Command};
-use hmac::{Hmac, Mac};
-use reqwest::blocking::Client;
-use serde::Deserialize;
-use sha2::{Digest, Sha256, Sha512};
-use std::collections::VecDeque;
+use chrono::{DateTime, NaiveDate};
+use clap::ArgMatches;
+use kraken_pnl_calculator::{
+    cache, chart, checkpoint, cli, config, cost_basis, credentials, cross, execution, export, fees,
+    fifo, fiscal, fixtures, flags, futures, fx, html, income, invert, journal, kraken, ledger,
+    logging, lots, margin, metrics, normalized, notify, otp, performance, precision, preflight,
+    prices, rate_limit, reconcile, report, returns, risk, runs, selection, server, simulate,
+    slippage, sources, state, stats, strategies, stream, tax, transfers, tui, update, verify,
+    watch, xlsx,
+};
+use log::{error, info, warn};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::env;
-use std::fs::File;
-use std::io::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use cache::{
+    default_cache_path, list_runs, print_query_result, query, write_query_result, Cache, NonceStore,
+};
+use chart::{equity_curve, write_chart, Series};
+use checkpoint::{checkpoint_path, Checkpoint};
+use cli::{build_cli, Mode, Options};
+use config::{default_config_path, init_config, load_config, Config};
+use cost_basis::print_comparison;
+use credentials::{load_dotenv, login, logout, read_credential};
+use cross::{cross_pair_trades, Scope};
+use execution::{group_by_execution, print_execution_summary};
+use export::write_export;
+use fees::{print_fee_summary, summarize_fees};
+use fifo::{compute_pnl, Disposal, LotEngine, OpeningBalance, PnL, TradePnL};
+use fiscal::YearStart;
+use fixtures::Fixtures;
+use flags::{group_by_flags, print_flag_summary};
+use futures::{compute_futures_pnl, print_futures_pnl, ContractPnL, FuturesAPI};
+use fx::{
+    convert_pnl, disposal_period, fetch_fx_rates, fetch_latest_rate, split_realized_pnl, FxRates,
+    FxSplit,
+};
+use html::write_html_report;
+use income::{
+    credit_trades, income_items, income_kind, income_trades, print_income_statement,
+    write_income_report, IncomeItem, RebatePolicy,
+};
+use invert::invert_trades;
+use journal::{closing_entries, write_journal, JournalLine};
+use kraken::{
+    fetch_account_trades, fetch_closed_orders, fetch_order_flags, fetch_trades, fetch_trades_into,
+    http_client, stop_paginating_on_ctrl_c, ClosedOrder, KrakenAPI, Trade, PAGE_SIZE,
+};
+use ledger::{
+    apply_base_fees, cross_check_balance, fetch_ledgers, print_balance_check, LedgerEntry,
+};
+use lots::{load_lots, lot_trade, save_lots};
+use margin::{compute_margin_pnl, fetch_rollovers, is_margin_trade, print_margin_pnl, MarginPnL};
+use metrics::{serve_metrics, Metrics};
+use normalized::{convert_exchange, convert_kraken, write_document, Document};
+use notify::Thresholds;
+use otp::Otp;
+use performance::{compute_performance, print_performance};
+use precision::{
+    fetch_asset_pair, fetch_pair_aliases, fetch_precision, AssetPair, PairAliases, Precision,
+};
+use preflight::{confirm, estimate, preflight, print_checks, print_estimate, Estimate};
+use prices::{fetch_prices, Prices};
+use rate_limit::{RateLimiter, Usage};
+use reconcile::{fetch_balances, print_reconciliation, reconcile, Reconciliation};
+use report::{
+    aggregate_fills, compute_data_digest, format_time, print_portfolio_summary,
+    print_yearly_summary, write_disposals, write_trades_to_csv, write_valuations, ConvertedPnL,
+    DataDigest, DisposalsCsv, PortfolioEntry, ReportMetadata, TradesCsv,
+};
+use returns::{compute_returns, daily_valuations, DailyValuation};
+use risk::{compute_exposure, fetch_ticker_price, print_exposure, Exposure};
+use runs::{lock_account, Run, RunLock};
+use selection::{expand_symbols, is_pattern, print_traded_pairs, summarize_traded_pairs};
+use server::serve;
+use simulate::{print_simulation, simulate_sale, Simulation};
+use slippage::{compute_slippage, print_slippage};
+use sources::{merge_sources, print_data_quality, DataQuality};
+use state::{export_state, import_state};
+use stats::{
+    api_usage, ledger_velocity, print_stats, project_fetch, trade_velocity, Projection, Velocity,
+};
+use strategies::{group_by_strategy, print_strategies};
+use stream::{subscribe_own_trades, OwnTrades};
+use tax::{
+    age_open_lots, classify_disposals, print_form_8949_items, print_lot_aging, print_open_lots,
+    TaxRules,
+};
+use transfers::{is_transfer, transfer_trades};
+use tui::show_dashboard;
+use verify::verify;
+use watch::{merge_ledger_entries, merge_trades, start_iteration, OVERLAP};
+use xlsx::write_xlsx;
 
 // =============================================================================
-// The following structs are used to fetch historical trades from the Kraken
-// API.
-
-#[derive(Deserialize, Debug)]
-#[allow(dead_code)]
-struct Trade {
-    ordertxid: String,
-    pair: String,
-    time: f64,
-    #[serde(rename = "type")]
-    side: String,
-    price: String,
-    fee: String,
-    vol: String,
-    cost: String,
-    ordertype: String,
+
+#[tokio::main]
+async fn main() {
+    let matches = build_cli().get_matches();
+    logging::init(matches.get_count("verbose"), matches.get_flag("quiet"));
+    load_dotenv();
+
+    let config_path: PathBuf = matches
+        .get_one::<PathBuf>("config")
+        .cloned()
+        .unwrap_or_else(default_config_path);
+    match matches.subcommand() {
+        Some(("config", sub_matches)) => {
+            if let Some(("init", init_matches)) = sub_matches.subcommand() {
+                if let Err(e) = init_config(&config_path, init_matches.get_flag("force")) {
+                    error!("Error creating the configuration file: {}", e);
+                    std::process::exit(1);
+                }
+                println!("Created {}", config_path.display());
+            }
+            return;
+        }
+        Some(("self-update", sub_matches)) => {
+            if let Err(e) = update::self_update(sub_matches.get_flag("check")).await {
+                error!("Error during self-update: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        _ => {}
+    }
+
+    let config: Config =
+        load_config(&config_path, matches.contains_id("config")).unwrap_or_else(|e| {
+            error!("Error loading the configuration file: {}", e);
+            std::process::exit(1);
+        });
+    let credential_names: [&str; 2] = [
+        &config.credentials.api_key_env,
+        &config.credentials.secret_key_env,
+    ];
+    match matches.subcommand() {
+        Some(("login", _)) => {
+            if let Err(e) = login(&credential_names) {
+                error!("Error during login: {}", e);
+                std::process::exit(1);
+            }
+            println!("Stored the credentials in the keyring.");
+            return;
+        }
+        Some(("logout", _)) => {
+            if let Err(e) = logout(&credential_names) {
+                error!("Error during logout: {}", e);
+                std::process::exit(1);
+            }
+            println!("Removed the credentials from the keyring.");
+            return;
+        }
+        _ => {}
+    }
+    let cache_path: PathBuf = config.cache.clone().unwrap_or_else(default_cache_path);
+    if let Some(("query", sub_matches)) = matches.subcommand() {
+        let result = query(&cache_path, sub_matches.get_one::<String>("sql").unwrap())
+            .unwrap_or_else(|e| {
+                error!("Error executing the query: {}", e);
+                std::process::exit(1);
+            });
+        match sub_matches.get_one::<String>("output") {
+            Some(output) => write_query_result(&result, output),
+            None => print_query_result(&result),
+        }
+        return;
+    }
+    if let Some(("runs", sub_matches)) = matches.subcommand() {
+        if let Some(("list", list_matches)) = sub_matches.subcommand() {
+            let result = list_runs(
+                &cache_path,
+                *list_matches.get_one::<usize>("limit").unwrap(),
+            )
+            .unwrap_or_else(|e| {
+                error!("Error listing the runs: {}", e);
+                std::process::exit(1);
+            });
+            print_query_result(&result);
+        }
+        return;
+    }
+    if let Some(("stats", sub_matches)) = matches.subcommand() {
+        let cache: Cache = open_cache_or_exit(&cache_path);
+        let now: f64 = chrono::Utc::now().timestamp() as f64;
+        let loaded = cache.pairs().and_then(|pairs| {
+            let mut velocities: Vec<Velocity> = Vec::new();
+            for pair in pairs {
+                velocities.extend(trade_velocity(
+                    &pair,
+                    &cache.load_trades(&pair, None, None)?,
+                    now,
+                ));
+            }
+            let mut ledger: Vec<(usize, f64)> = Vec::new();
+            for asset in cache.ledger_assets()? {
+                ledger.push(ledger_velocity(
+                    &cache.load_ledger_entries(&asset, None, None)?,
+                    now,
+                ));
+            }
+            Ok((velocities, ledger, cache.load_runs()?))
+        });
+        let (velocities, ledger, runs) = loaded.unwrap_or_else(|e| {
+            error!("Error reading the cache: {}", e);
+            std::process::exit(1);
+        });
+        let days: u32 = *sub_matches.get_one::<u32>("days").unwrap();
+        let mut projections: Vec<Projection> = vec![project_fetch(&velocities, &ledger, 0)];
+        if days > 0 {
+            projections.push(project_fetch(&velocities, &ledger, days));
+        }
+        print_stats(
+            &velocities,
+            &api_usage(&runs),
+            &projections,
+            config.tier.as_deref().unwrap_or("starter"),
+        );
+        return;
+    }
+
+    if let Some(("serve", sub_matches)) = matches.subcommand() {
+        let cache: Cache = open_cache_or_exit(&cache_path);
+        let year_start: YearStart = year_start_or_exit(&config);
+        let api: KrakenAPI = public_api(sub_matches);
+        let address: &String = sub_matches.get_one("addr").unwrap();
+        if let Err(e) = serve(api, cache, address, year_start).await {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(("verify", sub_matches)) = matches.subcommand() {
+        let fixtures: Vec<PathBuf> = sub_matches
+            .get_many::<PathBuf>("fixtures")
+            .map(|paths| paths.cloned().collect())
+            .unwrap_or_default();
+        match verify(
+            &fixtures,
+            sub_matches.get_flag("bless"),
+            *sub_matches.get_one::<usize>("random").unwrap(),
+            *sub_matches.get_one::<u64>("seed").unwrap(),
+        ) {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(("tui", sub_matches)) = matches.subcommand() {
+        let cache: Cache = open_cache_or_exit(&cache_path);
+        let year_start: YearStart = year_start_or_exit(&config);
+        let api: KrakenAPI = public_api(sub_matches);
+        if let Err(e) = show_dashboard(&api, &cache, year_start).await {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(("convert", sub_matches)) = matches.subcommand() {
+        let (source, convert_matches) = sub_matches.subcommand().unwrap();
+        let inputs: Vec<PathBuf> = convert_matches
+            .get_many::<PathBuf>("inputs")
+            .map(|inputs| inputs.cloned().collect())
+            .unwrap_or_default();
+        let output: &PathBuf = convert_matches.get_one("output").unwrap();
+        let document: Result<Document, String> = if source == "kraken" {
+            let api: KrakenAPI = public_api(convert_matches);
+            let cache: Option<Cache> = inputs.is_empty().then(|| open_cache_or_exit(&cache_path));
+            convert_kraken(&api, cache.as_ref(), &inputs).await
+        } else {
+            convert_exchange(source, &inputs)
+        };
+        match document.and_then(|document| {
+            write_document(&document, output).map(|_| {
+                format!(
+                    "Converted {} trades and {} ledger events to {}",
+                    document.trades.len(),
+                    document.ledger.len(),
+                    output.display()
+                )
+            })
+        }) {
+            Ok(message) => println!("{}", message),
+            Err(e) => {
+                error!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(("state", sub_matches)) = matches.subcommand() {
+        let mut cache: Cache = open_cache_or_exit(&cache_path);
+        let result = match sub_matches.subcommand() {
+            Some(("export", export_matches)) => {
+                let path: &PathBuf = export_matches.get_one("path").unwrap();
+                let api: KrakenAPI = public_api(export_matches);
+                export_state(&api, &cache, path)
+                    .await
+                    .map(|(trades, entries)| {
+                        format!(
+                            "Exported {} trades and {} ledger entries to {}",
+                            trades,
+                            entries,
+                            path.display()
+                        )
+                    })
+            }
+            Some(("import", import_matches)) => {
+                let path: &PathBuf = import_matches.get_one("path").unwrap();
+                import_state(&mut cache, path).map(|(trades, entries)| {
+                    format!(
+                        "Imported {} trades and {} ledger entries from {}",
+                        trades,
+                        entries,
+                        path.display()
+                    )
+                })
+            }
+            _ => unreachable!(),
+        };
+        match result {
+            Ok(message) => println!("{}", message),
+            Err(e) => {
+                error!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(("futures", sub_matches)) = matches.subcommand() {
+        let credential = |name: &str| {
+            read_credential(name, config.credentials.keyring)
+                .and_then(|value| {
+                    value.ok_or_else(|| format!("The environment variable '{}' must be set!", name))
+                })
+                .unwrap_or_else(|e| {
+                    error!("{}", e);
+                    std::process::exit(1);
+                })
+        };
+        let (proxy, insecure) = cli::transport(sub_matches);
+        let client = http_client(proxy.as_deref(), insecure).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        let api: FuturesAPI = FuturesAPI::new(
+            credential(&config.credentials.futures_api_key_env),
+            credential(&config.credentials.futures_secret_key_env),
+            sub_matches.get_one::<String>("futures-url").unwrap(),
+            client,
+        );
+        let (start, end) = cli::period(sub_matches);
+        // The fills before the period open the positions closed within it.
+        let fetched = match api.fetch_fills(None, end).await {
+            Ok(fills) => api
+                .fetch_account_log(start, end)
+                .await
+                .map(|log| (fills, log)),
+            Err(e) => Err(e),
+        };
+        let (fills, log) = fetched.unwrap_or_else(|e| {
+            error!("Error fetching the data of Kraken Futures: {}", e);
+            std::process::exit(1);
+        });
+        let selected: Vec<String> = sub_matches
+            .get_many::<String>("contract")
+            .map(|contracts| contracts.map(|contract| contract.to_uppercase()).collect())
+            .unwrap_or_default();
+        let contracts: Vec<ContractPnL> = compute_futures_pnl(&fills, &log, start)
+            .into_iter()
+            .filter(|pnl| selected.is_empty() || selected.contains(&pnl.contract))
+            .collect();
+        if contracts.is_empty() {
+            warn!("No fills or bookings of contracts found within the selected period.");
+            return;
+        }
+        print_futures_pnl(&contracts);
+        return;
+    }
+
+    let mut started_at = chrono::Utc::now();
+    let mut started = std::time::Instant::now();
+    let (mode, matches): (Mode, &ArgMatches) = match matches.subcommand() {
+        Some(("fetch", sub_matches)) => (Mode::Fetch, sub_matches),
+        Some(("pnl", sub_matches)) => (Mode::Pnl, sub_matches),
+        Some(("export", sub_matches)) => (Mode::Export, sub_matches),
+        Some(("report", sub_matches)) => (Mode::Report, sub_matches),
+        Some(("simulate", sub_matches)) => (Mode::Simulate, sub_matches),
+        Some(("pairs", sub_matches)) => (Mode::Pairs, sub_matches),
+        _ => (Mode::All, &matches),
+    };
+    let symbols: Vec<String> = cli::symbols(matches, &config);
+    if symbols.is_empty() && mode != Mode::Pairs {
+        error!(
+            "No trading pair passed via --symbol or configured in {}!",
+            config_path.display()
+        );
+        std::process::exit(1);
+    }
+    // Patterns may select several pairs, whose output paths must differ.
+    let several: bool = symbols.len() > 1 || symbols.iter().any(|symbol| is_pattern(symbol));
+    if several && mode == Mode::Simulate {
+        error!("simulate requires a single trading pair passed via --symbol!");
+        std::process::exit(1);
+    }
+    let options: Options = Options::new(matches, mode, &config, several);
+    if options.from_cache && options.input.is_some() {
+        error!("--from-cache can't be combined with --input!");
+        std::process::exit(1);
+    }
+    if options.check && options.offline() {
+        error!(
+            "--check can't be combined with --from-cache or --input, since the API is not used!"
+        );
+        std::process::exit(1);
+    }
+    if options.estimate && options.offline() {
+        error!(
+            "--estimate can't be combined with --from-cache or --input, since nothing is fetched!"
+        );
+        std::process::exit(1);
+    }
+    if options.reconcile && options.offline() {
+        error!("--reconcile can't be combined with --from-cache or --input, since the API is not used!");
+        std::process::exit(1);
+    }
+    if options.offline() && options.order_filter.needs_closed_orders() {
+        error!(
+            "--userref, --cl-ord-id and --cl-ord-id-prefix can't be combined with --from-cache or --input, since closed orders are only fetched from the API!"
+        );
+        std::process::exit(1);
+    }
+    if options.stream && options.offline() {
+        error!("--stream can't be combined with --from-cache or --input, since the trades are streamed from the API!");
+        std::process::exit(1);
+    }
+    if options.stream && (options.order_filter.needs_closed_orders() || options.needs_ledger()) {
+        error!(
+            "--stream can't be combined with --userref, --cl-ord-id, --cl-ord-id-prefix or the options reading the ledger, since only the trades are streamed!"
+        );
+        std::process::exit(1);
+    }
+    if options.low_memory && (options.order_filter.needs_closed_orders() || options.needs_ledger())
+    {
+        error!(
+            "--low-memory can't be combined with --userref, --cl-ord-id, --cl-ord-id-prefix or the options reading the ledger, since only the trades are streamed!"
+        );
+        std::process::exit(1);
+    }
+    if options.metrics_addr.is_some() && options.watch.is_none() && !options.stream {
+        error!("--metrics-addr requires --watch or --stream, since the metrics are served while the tool keeps running!");
+        std::process::exit(1);
+    }
+    let fixtures: Option<Fixtures> = cli::fixtures(matches);
+    let replays: bool = fixtures.as_ref().is_some_and(Fixtures::replays);
+    if options.stream && fixtures.is_some() {
+        error!(
+            "--stream can't be combined with --mock-dir, since the WebSocket API is not replayed!"
+        );
+        std::process::exit(1);
+    }
+    if options.offline() && options.group_by_flags {
+        error!("--group-by-flags can't be combined with --from-cache or --input, since closed orders are only fetched from the API!");
+        std::process::exit(1);
+    }
+    if options.input.is_some()
+        && (options.group_by.is_some() || options.order_details || options.slippage)
+    {
+        error!("--group-by, --order-details and --slippage can't be combined with --input, since the closed orders are only fetched from the API or the cache!");
+        std::process::exit(1);
+    }
+    // The order prices are stated in the quote currency of their own pair,
+    // unlike the trades of other pairs and the inverted trades.
+    if (options.slippage || options.order_details)
+        && (options.invert || options.cross_pairs || options.by_asset)
+    {
+        error!("--slippage and --order-details can't be combined with --invert, --cross-pairs or --by-asset, since the order prices are stated in the quote currency of their own pair!");
+        std::process::exit(1);
+    }
+    // The traded pairs matching a pattern or --quote are looked up in the
+    // account, while the document states its pairs by their own names.
+    if options.input.is_some()
+        && (options.quote.is_some() || symbols.iter().any(|symbol| is_pattern(symbol)))
+    {
+        error!("--input requires the trading pairs passed by name via --symbol!");
+        std::process::exit(1);
+    }
+
+    // The credentials are not needed when the private data is read from the
+    // cache, a normalized document or the fixtures.
+    let credential = |name: &str| {
+        let value: Option<String> = read_credential(name, options.keyring).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        value.unwrap_or_else(|| {
+            if !options.offline() && !replays {
+                if options.keyring {
+                    error!(
+                        "The environment variable '{}' must be set or stored via `login`!",
+                        name
+                    );
+                } else {
+                    error!("The environment variable '{}' must be set!", name);
+                }
+                std::process::exit(1);
+            }
+            String::new()
+        })
+    };
+    let api_key: String = credential(&config.credentials.api_key_env);
+    let secret_key: String = credential(&config.credentials.secret_key_env);
+    // The second factor is taken from --otp, the static password, or
+    // generated from the TOTP secret, in this order.
+    let optional_credential = |name: &str| {
+        read_credential(name, options.keyring).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        })
+    };
+    let otp: Option<Otp> =
+        match cli::otp(matches).or_else(|| optional_credential(&config.credentials.otp_env)) {
+            Some(password) => Some(Otp::Static(password)),
+            None => optional_credential(&config.credentials.totp_secret_env).map(|secret| {
+                Otp::totp(&secret).unwrap_or_else(|e| {
+                    error!("{}", e);
+                    std::process::exit(1);
+                })
+            }),
+        };
+    let api: KrakenAPI = kraken_api(
+        matches,
+        api_key,
+        secret_key,
+        RateLimiter::for_tier(&options.tier),
+    )
+    .with_otp(otp)
+    .with_nonce_window(cli::nonce_window(matches));
+    // The nonces are persisted in the cache, so they keep increasing across
+    // runs using the same API key.
+    let nonces: Option<NonceStore> = if options.offline() || replays {
+        None
+    } else {
+        NonceStore::open(&cache_path, &api.account())
+            .inspect_err(|e| warn!("Not persisting the nonces: {}", e))
+            .ok()
+    };
+    let api: KrakenAPI = api.with_nonce_store(nonces);
+
+    if options.check {
+        let checks = preflight(
+            &api,
+            &options.tier,
+            options.order_filter.needs_closed_orders(),
+            options.needs_ledger(),
+            options.start,
+            options.end,
+        )
+        .await;
+        print_checks(&checks);
+        if checks.iter().any(|check| check.result.is_err()) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    // The estimate is confirmed before the account is locked, so waiting for
+    // the answer doesn't block another run.
+    if options.estimate {
+        let estimate: Estimate = estimate(
+            &api,
+            symbols.len(),
+            options.quote.is_some() || symbols.iter().any(|symbol| is_pattern(symbol)),
+            options.order_filter.needs_closed_orders(),
+            options.needs_ledger(),
+            options.start,
+            options.end,
+        )
+        .await
+        .unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        print_estimate(&estimate, &options.tier);
+        if !confirm("Proceed with the fetch?") {
+            info!("Not fetching.");
+            return;
+        }
+    }
+
+    // Concurrent runs against the same account would share its API counter.
+    let account: String = if options.offline() {
+        String::new()
+    } else {
+        api.account()
+    };
+    let _lock: Option<RunLock> = if account.is_empty() || options.force {
+        None
+    } else {
+        Some(lock_account(&cache_path, &account).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        }))
+    };
+    // The pages are checkpointed while fetching, so an interrupted fetch is
+    // resumed by the next run. This happens after locking the account, so a
+    // concurrent run doesn't replace the checkpoint of the running one.
+    if (options.resume || options.restart) && (options.offline() || replays) {
+        error!(
+            "--{} can't be combined with --from-cache, --input or --mock-dir, since nothing is fetched!",
+            if options.restart { "restart" } else { "resume" }
+        );
+        std::process::exit(1);
+    }
+    if options.resume {
+        warn!("Interrupted fetches are resumed by default, --resume is no longer needed.");
+    }
+    let checkpoint: Option<Arc<Checkpoint>> = if account.is_empty() || replays || options.offline()
+    {
+        None
+    } else {
+        Checkpoint::open(&checkpoint_path(&cache_path, &account), options.restart)
+            .inspect_err(|e| warn!("Not checkpointing the fetch: {}", e))
+            .ok()
+            .map(Arc::new)
+    };
+    // The resumed fetch continues from the counter of the interrupted one,
+    // which Kraken still holds until it decayed.
+    if let Some((value, age)) = checkpoint
+        .as_ref()
+        .and_then(|checkpoint| checkpoint.counter())
+    {
+        api.rate_limiter().restore(value, age).await;
+        info!(
+            "Continuing with the API counter of the interrupted fetch, {:.1} points {:.0}s ago.",
+            value,
+            age.as_secs_f64()
+        );
+    }
+    if checkpoint.is_some() {
+        stop_paginating_on_ctrl_c();
+    }
+    let api: KrakenAPI = api.with_checkpoint(checkpoint.clone());
+
+    // The cache is optional, a run must not fail because of it unless it
+    // reads from the cache or only fills it.
+    let mut cache: Option<Cache> =
+        match options.from_cache || mode == Mode::Fetch || options.low_memory {
+            true => Some(open_cache_or_exit(&cache_path)),
+            false => Cache::open(&cache_path)
+                .inspect_err(|e| warn!("Not caching the fetched data: {}", e))
+                .ok(),
+        };
+
+    if mode == Mode::Pairs {
+        let trades: Vec<Trade> = match cache.as_ref().filter(|_| options.from_cache) {
+            Some(cache) => cache
+                .pairs()
+                .and_then(|pairs| {
+                    let mut trades: Vec<Trade> = Vec::new();
+                    for pair in pairs {
+                        trades.extend(cache.load_trades(&pair, options.start, options.end)?);
+                    }
+                    Ok(trades)
+                })
+                .unwrap_or_else(|e| {
+                    error!("Error reading the cache: {}", e);
+                    std::process::exit(1);
+                }),
+            None => {
+                info!("Fetching the trades of all pairs...");
+                fetch_account_trades(&api, options.start, options.end).await
+            }
+        };
+        if let Some(checkpoint) = &checkpoint {
+            checkpoint.finish();
+        }
+        let aliases: PairAliases = fetch_pair_aliases(&api).await.unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        print_traded_pairs(&summarize_traded_pairs(&trades, &aliases));
+        return;
+    }
+
+    // The trade history is fetched once per iteration and shared by the
+    // pairs, so their data is as recent as the start of the iteration.
+    let mut loaded_at: f64 = chrono::Utc::now().timestamp() as f64;
+    let symbols: Vec<String> = expand_symbols(
+        &api,
+        cache.as_ref().filter(|_| options.from_cache),
+        &symbols,
+        options.quote.as_deref(),
+        options.start,
+        options.end,
+    )
+    .await
+    .unwrap_or_else(|e| {
+        error!("Error selecting the trading pairs: {}", e);
+        std::process::exit(1);
+    });
+    if symbols.is_empty() {
+        error!("No traded pair matches the selection!");
+        std::process::exit(1);
+    }
+    if options.low_memory && symbols.len() > 1 {
+        error!("--low-memory streams the trades of a single trading pair, select one!");
+        std::process::exit(1);
+    }
+    // Per asset, the first pair of a base currency accounts for all of its
+    // pairs, so the others would count its trades twice.
+    let symbols: Vec<String> = if options.by_asset && symbols.len() > 1 {
+        let aliases: PairAliases = fetch_pair_aliases(&api).await.unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        let mut accounted: HashMap<String, String> = HashMap::new();
+        symbols
+            .into_iter()
+            .filter(|symbol| {
+                let Some(base) = aliases.get(symbol).map(|pair| pair.base_code()) else {
+                    return true;
+                };
+                match accounted.get(&base) {
+                    Some(first) => {
+                        info!("The trades of {} are accounted within {}.", symbol, first);
+                        false
+                    }
+                    None => {
+                        accounted.insert(base, symbol.clone());
+                        true
+                    }
+                }
+            })
+            .collect()
+    } else {
+        symbols
+    };
+
+    // In the watch mode, the data of the previous iteration is kept, so only
+    // the new trades are fetched. The stream adds its trades to it.
+    let mut fetched: HashMap<String, PairData> = HashMap::new();
+    let mut usage_before: Usage = Usage::default();
+    let refresh: Option<String> = match options.watch {
+        Some(interval) => Some(format!("every {}s", interval.as_secs())),
+        None => options.stream.then(|| "on every fill".to_string()),
+    };
+    // The subscription starts before the trades are fetched, so no fill is
+    // missed in between.
+    let ws_url: String = cli::ws_url(matches);
+    let subscribe = || async {
+        subscribe_own_trades(&api, &ws_url, &symbols)
+            .await
+            .unwrap_or_else(|e| {
+                error!("{}", e);
+                std::process::exit(1);
+            })
+    };
+    let mut stream: Option<OwnTrades> = if options.stream {
+        Some(subscribe().await)
+    } else {
+        None
+    };
+    let metrics: Option<Metrics> = match &options.metrics_addr {
+        Some(address) => Some(serve_metrics(address).await.unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        })),
+        None => None,
+    };
+    // The thresholds are only checked while the tool keeps running.
+    let mut thresholds: Option<Thresholds> = match refresh {
+        Some(_) => Thresholds::new(&config.notify).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        }),
+        None => None,
+    };
+    // The trades are fetched via REST in the first iteration and whenever the
+    // stream reconnected, since fills may have been missed.
+    let mut fetch: bool = true;
+    loop {
+        if let Some(refresh) = &refresh {
+            start_iteration(started_at, refresh);
+        }
+        // The data is fetched pair by pair to stay within the rate limits,
+        // while the PnL of the pairs is computed in parallel.
+        let mut pairs: Vec<PairData> = Vec::new();
+        let mut outputs: Vec<String> = Vec::new();
+        // With --low-memory, the trades are streamed through the lot engine
+        // instead of being loaded.
+        for symbol in symbols.iter().filter(|_| options.low_memory) {
+            let cache: &mut Cache = cache.as_mut().unwrap();
+            outputs.extend(export_low_memory(&api, symbol, &options, cache).await);
+        }
+        for symbol in symbols.iter().filter(|_| !options.low_memory) {
+            let data: PairData = match fetched.remove(symbol) {
+                Some(previous) if !fetch => previous,
+                previous => {
+                    load_pair(&api, symbol, &options, cache.as_mut(), previous, loaded_at).await
+                }
+            };
+            if refresh.is_some() {
+                fetched.insert(symbol.clone(), data.clone());
+            }
+            if mode != Mode::Fetch {
+                pairs.push(data);
+            }
+        }
+        // All pairs are fetched and cached, so a later run starts over.
+        if let Some(checkpoint) = &checkpoint {
+            checkpoint.finish();
+        }
+        let mut portfolio: Vec<PortfolioEntry> = Vec::new();
+        for prepared in prepare_pairs(pairs, &options) {
+            outputs.extend(run(&api, prepared, &options, cache.as_mut(), &mut portfolio).await);
+        }
+        if let Some(metrics) = &metrics {
+            metrics.update(&portfolio);
+        }
+        if let Some(thresholds) = thresholds.as_mut() {
+            thresholds.notify(&portfolio).await;
+        }
+        if portfolio.len() > 1 && (options.mode.prints_pnl() || options.mode == Mode::Report) {
+            println!("{}", "*".repeat(80));
+            print_portfolio_summary(&portfolio);
+            println!("{}", "*".repeat(80));
+        }
+
+        // A simulation leaves no trace, not even in the recorded runs.
+        if let Some(cache) = cache.as_ref().filter(|_| mode != Mode::Simulate) {
+            let usage: Usage = api.usage().await;
+            let iteration: Usage = usage.since(&usage_before);
+            usage_before = usage;
+            let run = Run {
+                started_at: started_at.to_rfc3339(),
+                account: account.clone(),
+                arguments: env::args().skip(1).collect::<Vec<String>>().join(" "),
+                symbols: symbols.clone(),
+                duration: started.elapsed().as_secs_f64(),
+                requests: iteration.requests,
+                api_points: iteration.points,
+                rate_limit_wait: iteration.waited.as_secs_f64(),
+                rate_limited: iteration.rejected,
+                outputs,
+            };
+            if let Err(e) = cache.record_run(&run) {
+                warn!("Failed to record the run: {}", e);
+            }
+        }
+
+        // The next iteration fetches the trades since this one.
+        api.forget_trade_histories().await;
+        loaded_at = chrono::Utc::now().timestamp() as f64;
+        match (stream.as_mut(), options.watch) {
+            (Some(own_trades), _) => match own_trades.next_trades().await {
+                Ok(trades) => {
+                    info!("Received {} trades from the stream.", trades.len());
+                    if let Some(cache) = cache.as_mut() {
+                        if let Err(e) = cache.store_trades(&trades) {
+                            warn!("Failed to cache the streamed trades: {}", e);
+                        }
+                    }
+                    for trade in trades {
+                        if let Some(data) = fetched.get_mut(&trade.pair) {
+                            data.loaded_at = chrono::Utc::now().timestamp() as f64;
+                            merge_trades(&mut data.trades, vec![trade]);
+                        }
+                    }
+                    fetch = false;
+                }
+                Err(e) => {
+                    warn!("{} Reconnecting...", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    *own_trades = subscribe().await;
+                    fetch = true;
+                }
+            },
+            (None, Some(interval)) => {
+                tokio::time::sleep(interval).await;
+                fetch = true;
+            }
+            (None, None) => break,
+        }
+        started_at = chrono::Utc::now();
+        started = std::time::Instant::now();
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct TradesResult {
-    trades: std::collections::HashMap<String, Trade>,
-    count: u32,
+/// The fetched or cached data of a trading pair.
+#[derive(Clone)]
+struct PairData {
+    symbol: String,
+    asset_pair: AssetPair,
+    precision: Precision,
+    trades: Vec<Trade>,
+    ledger_entries: Option<Vec<LedgerEntry>>,
+    /// The income from the ledger valued at the daily prices, if requested.
+    income: Vec<IncomeItem>,
+    /// The transfers from the ledger as trades, if they are considered.
+    transfers: Vec<Trade>,
+    /// The legs of the trades of the other pairs, if they are merged.
+    cross_trades: Vec<Trade>,
+    rollovers: Vec<LedgerEntry>,
+    /// The time the data was fetched or loaded at.
+    loaded_at: f64,
 }
 
-#[derive(Deserialize, Debug)]
-struct TradesResponse {
-    error: Vec<String>,
-    result: Option<TradesResult>,
+/// The trades of a trading pair prepared for the reports, and their PnL.
+struct PreparedPair {
+    symbol: String,
+    asset_pair: AssetPair,
+    precision: Precision,
+    trades: Vec<Trade>,
+    excluded_trades: Vec<Trade>,
+    /// The trades within the price band, if one is set.
+    listed_trades: Option<Vec<Trade>>,
+    /// The trades outside of the price band, if they are excluded from the
+    /// PnL.
+    pnl_trades: Option<Vec<Trade>>,
+    ledger_entries: Option<Vec<LedgerEntry>>,
+    income: Vec<IncomeItem>,
+    margin_pnl: Option<(MarginPnL, Precision, String)>,
+    loaded_until: Option<f64>,
+    /// The time the data was fetched or loaded at.
+    loaded_at: f64,
+    pnl: PnL,
+    /// The merge decisions of the imported trades, if any were imported.
+    data_quality: Option<DataQuality>,
 }
 
-// =============================================================================
-// The following structs are used to fetch closed orders from the Kraken API.
+/// Opens the cache, or exits if it can't be opened.
+///
+/// # Arguments
+///
+/// * `cache_path` - The path of the cache.
+///
+fn open_cache_or_exit(cache_path: &Path) -> Cache {
+    Cache::open(cache_path).unwrap_or_else(|e| {
+        error!("Error opening the cache: {}", e);
+        std::process::exit(1);
+    })
+}
 
-#[derive(Deserialize, Debug)]
-struct Order {}
+/// Returns the first day of the fiscal years of the configuration, or exits
+/// if it is invalid.
+///
+/// # Arguments
+///
+/// * `config` - The configuration.
+///
+fn year_start_or_exit(config: &Config) -> YearStart {
+    config
+        .fiscal_year_start
+        .as_deref()
+        .map(YearStart::parse)
+        .transpose()
+        .unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        })
+        .unwrap_or_default()
+}
+
+/// Creates a Kraken API client without credentials, for the subcommands that
+/// only use public endpoints.
+///
+/// # Arguments
+///
+/// * `matches` - The arguments of the (sub)command.
+///
+fn public_api(matches: &ArgMatches) -> KrakenAPI {
+    kraken_api(
+        matches,
+        String::new(),
+        String::new(),
+        RateLimiter::for_tier(""),
+    )
+}
+
+/// Creates the Kraken API client configured by the global arguments, i.e.
+/// the base URL, the proxy and the fixtures.
+///
+/// # Arguments
+///
+/// * `matches` - The arguments of the (sub)command.
+/// * `api_key` - The API key, empty if only public endpoints are used.
+/// * `secret_key` - The secret key of the API key.
+/// * `rate_limiter` - The rate limiter of the API tier.
+///
+fn kraken_api(
+    matches: &ArgMatches,
+    api_key: String,
+    secret_key: String,
+    rate_limiter: RateLimiter,
+) -> KrakenAPI {
+    let (proxy, insecure): (Option<String>, bool) = cli::transport(matches);
+    KrakenAPI::new(api_key, secret_key, rate_limiter)
+        .with_base_url(cli::api_url(matches))
+        .with_transport(proxy.as_deref(), insecure)
+        .unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        })
+        .with_fixtures(cli::fixtures(matches))
+}
+
+/// Returns the trades and ledger entries of a trading pair as normalized
+/// document.
+///
+/// # Arguments
+///
+/// * `asset_pair` - The trading pair.
+/// * `trades` - The trades of the pair.
+/// * `entries` - The ledger entries of the base currency.
+/// * `rollovers` - The ledger entries of the quote currency.
+///
+fn normalize(
+    asset_pair: &AssetPair,
+    trades: &[Trade],
+    entries: &[LedgerEntry],
+    rollovers: &[LedgerEntry],
+) -> Document {
+    let ledger = entries
+        .iter()
+        .map(|entry| (asset_pair.base.as_str(), entry))
+        .chain(
+            rollovers
+                .iter()
+                .map(|entry| (asset_pair.quote.as_str(), entry)),
+        );
+    Document::from_kraken(trades, ledger, |_| Some(asset_pair))
+}
+
+/// Fetches the trades of a trading pair, or loads them from the cache. The
+/// fetched data is stored in the cache. Whatever the source, the data is
+/// taken from the normalized schema.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `symbol` - The trading pair.
+/// * `options` - The options of the run.
+/// * `cache` - The cache of the fetched data, if available.
+/// * `previous` - The data of the previous iteration of the watch mode, which
+///   only the new trades and ledger entries are added to.
+/// * `loaded_at` - The time the iteration started at, before the trade history
+///   shared by the pairs was fetched.
+///
+async fn load_pair(
+    api: &KrakenAPI,
+    symbol: &str,
+    options: &Options,
+    mut cache: Option<&mut Cache>,
+    previous: Option<PairData>,
+    loaded_at: f64,
+) -> PairData {
+    let Options { end, .. } = *options;
+    // =========================================================================
+    // Fetch the trades
+    let asset_pair: AssetPair = match &previous {
+        Some(previous) => previous.asset_pair.clone(),
+        None => fetch_asset_pair(api, symbol).await,
+    };
+    // The cache is reloaded as a whole, since other runs may have changed it.
+    let mut previous: Option<PairData> = previous.filter(|_| !options.offline());
+    // The legs of the other pairs are fetched since the previous iteration as
+    // well, so they are merged like the trades.
+    let mut cross_trades: Vec<Trade> = previous
+        .as_mut()
+        .map(|previous| std::mem::take(&mut previous.cross_trades))
+        .unwrap_or_default();
+    let start: Option<f64> = match &previous {
+        Some(previous) => Some(
+            options
+                .start
+                .unwrap_or_default()
+                .max(previous.loaded_at - OVERLAP),
+        ),
+        None => options.start,
+    };
+    // The fetched and cached data is normalized like the documents passed via
+    // --input, so the accounting consumes the same schema whatever the source.
+    let (precision, normalized): (Precision, Option<Document>) = if options.input.is_some() {
+        (fetch_precision(api, &asset_pair).await, None)
+    } else if options.from_cache {
+        let cache: &Cache = cache.as_deref().unwrap();
+        let document: Document = cache
+            .load_trades(symbol, start, end)
+            .and_then(|trades| {
+                let entries: Vec<LedgerEntry> = if options.needs_ledger() {
+                    cache.load_ledger_entries(&asset_pair.base, start, end)?
+                } else {
+                    Vec::new()
+                };
+                let rollovers: Vec<LedgerEntry> = if trades.iter().any(is_margin_trade) {
+                    cache.load_ledger_entries(&asset_pair.quote, start, end)?
+                } else {
+                    Vec::new()
+                };
+                Ok(normalize(&asset_pair, &trades, &entries, &rollovers))
+            })
+            .unwrap_or_else(|e| {
+                error!("Error reading the cache: {}", e);
+                std::process::exit(1);
+            });
+        let precision: Precision = fetch_precision(api, &asset_pair).await;
+        (precision, Some(document))
+    } else {
+        let (precision, trades, ledger_entries) = tokio::join!(
+            async {
+                match &previous {
+                    Some(previous) => previous.precision,
+                    None => fetch_precision(api, &asset_pair).await,
+                }
+            },
+            fetch_trades(api, &asset_pair, &options.order_filter, start, end),
+            async {
+                if options.needs_ledger() {
+                    Some(fetch_ledgers(api, &asset_pair.base, start, end).await)
+                } else {
+                    None
+                }
+            },
+        );
+        let rollovers: Vec<LedgerEntry> = if trades
+            .iter()
+            .chain(previous.iter().flat_map(|previous| &previous.trades))
+            .any(is_margin_trade)
+        {
+            info!("Fetching rollover fees...");
+            fetch_rollovers(api, &asset_pair.quote, start, end)
+                .await
+                .unwrap_or_else(|error| {
+                    warn!(
+                        "Failed to fetch the rollover fees, the margin PnL doesn't include them: {:?}",
+                        error
+                    );
+                    Vec::new()
+                })
+        } else {
+            Vec::new()
+        };
+        if let Some(cache) = cache.as_deref_mut() {
+            let result = cache
+                .store_trades(&trades)
+                .and_then(|_| match &ledger_entries {
+                    Some(entries) => cache.store_ledger_entries(&asset_pair.base, entries),
+                    None => Ok(()),
+                })
+                .and_then(|_| cache.store_ledger_entries(&asset_pair.quote, &rollovers));
+            if let Err(e) = result {
+                warn!("Failed to cache the fetched data: {}", e);
+            }
+        }
+        let (trades, ledger_entries, rollovers) = match previous {
+            Some(mut previous) => {
+                info!(
+                    "Fetched {} new trades of {} since the previous iteration.",
+                    trades
+                        .iter()
+                        .filter(|trade| previous.trades.iter().all(|t| t.txid != trade.txid))
+                        .count(),
+                    symbol
+                );
+                merge_trades(&mut previous.trades, trades);
+                if let (Some(entries), Some(fetched)) =
+                    (previous.ledger_entries.as_mut(), ledger_entries)
+                {
+                    merge_ledger_entries(entries, fetched);
+                }
+                merge_ledger_entries(&mut previous.rollovers, rollovers);
+                (previous.trades, previous.ledger_entries, previous.rollovers)
+            }
+            None => (trades, ledger_entries, rollovers),
+        };
+        let entries: &[LedgerEntry] = ledger_entries.as_deref().unwrap_or_default();
+        (
+            precision,
+            Some(normalize(&asset_pair, &trades, entries, &rollovers)),
+        )
+    };
+    // The fetched and cached data lies within the period already, which the
+    // watch mode narrows to the data since the previous iteration.
+    let (document, from, to): (&Document, Option<f64>, Option<f64>) = match &normalized {
+        Some(document) => (document, None, None),
+        None => (options.input.as_ref().unwrap(), start, end),
+    };
+    let trades: Vec<Trade> = document.trades_of(&asset_pair, from, to);
+    let ledger_entries: Option<Vec<LedgerEntry>> = options
+        .needs_ledger()
+        .then(|| document.ledger_of(&asset_pair.base, from, to));
+    let rollovers: Vec<LedgerEntry> = if trades.iter().any(is_margin_trade) {
+        document.ledger_of(&asset_pair.quote, from, to)
+    } else {
+        Vec::new()
+    };
+    // The income is valued at the daily prices of the days of receipt, which
+    // is also the cost of the lots it adds to the holdings, and the transfers
+    // at those of the days they were made.
+    let with_income: bool = options.income || options.income_lots;
+    let (income, transfers): (Vec<IncomeItem>, Vec<Trade>) = match &ledger_entries {
+        Some(entries) if options.mode != Mode::Fetch => {
+            let times = entries
+                .iter()
+                .filter(|entry| {
+                    (with_income && income_kind(entry, options.rebates).is_some())
+                        || (options.transfers.is_some() && is_transfer(entry))
+                })
+                .filter_map(|entry| DateTime::from_timestamp(entry.time as i64, 0));
+            let prices: Prices = match (times.clone().min(), times.max()) {
+                (Some(from), Some(to)) => {
+                    fetch_prices(
+                        api,
+                        cache.as_deref(),
+                        &asset_pair,
+                        &options.price_sources,
+                        &options.coingecko_ids,
+                        from.date_naive(),
+                        to.date_naive(),
+                    )
+                    .await
+                }
+                _ => Prices::default(),
+            };
+            (
+                match with_income {
+                    true => income_items(entries, options.rebates, &prices, &precision),
+                    false => Vec::new(),
+                },
+                options
+                    .transfers
+                    .map(|policy| transfer_trades(entries, symbol, policy, &prices, &precision))
+                    .unwrap_or_default(),
+            )
+        }
+        _ => (Vec::new(), Vec::new()),
+    };
+    // The trades of the other pairs are turned into legs of this pair, valued
+    // at the daily prices of the days they were made.
+    let scope = Scope {
+        quoted: options.cross_pairs,
+        crypto: options.cross_pairs || options.by_asset,
+        fiat: options.by_asset,
+    };
+    if scope.any() && options.mode != Mode::Fetch {
+        let others: Vec<Trade> = if let Some(document) = &options.input {
+            document.other_trades(&asset_pair, start, end)
+        } else {
+            let others: Vec<Trade> = if options.from_cache {
+                let cache: &Cache = cache.as_deref().unwrap();
+                cache
+                    .pairs()
+                    .and_then(|pairs| {
+                        let mut others: Vec<Trade> = Vec::new();
+                        for pair in pairs.iter().filter(|pair| *pair != symbol) {
+                            others.extend(cache.load_trades(pair, start, end)?);
+                        }
+                        Ok(others)
+                    })
+                    .unwrap_or_else(|e| {
+                        error!("Error reading the cache: {}", e);
+                        std::process::exit(1);
+                    })
+            } else {
+                info!("Fetching the trades of all pairs...");
+                fetch_account_trades(api, start, end).await
+            };
+            Document::from_kraken(&others, [], |_| None).other_trades(&asset_pair, None, None)
+        };
+        let aliases: PairAliases = fetch_pair_aliases(api).await.unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        let legs: Vec<Trade> = cross_pair_trades(
+            api,
+            cache.as_deref(),
+            &asset_pair,
+            &others,
+            &aliases,
+            scope,
+            &options.price_sources,
+            &options.coingecko_ids,
+            options.fx_source,
+            &precision,
+        )
+        .await
+        .unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        merge_trades(&mut cross_trades, legs);
+    }
+    if options.mode == Mode::Fetch {
+        println!(
+            "Stored {} trades{} of {} in the cache.",
+            trades.len(),
+            ledger_entries
+                .as_ref()
+                .map(|entries| format!(" and {} ledger entries", entries.len()))
+                .unwrap_or_default(),
+            symbol
+        );
+    }
+    PairData {
+        symbol: symbol.to_string(),
+        asset_pair,
+        precision,
+        trades,
+        ledger_entries,
+        income,
+        transfers,
+        cross_trades,
+        rollovers,
+        loaded_at,
+    }
+}
+
+/// Prepares the trades of a trading pair for the reports and computes their
+/// PnL, which involves no requests.
+fn prepare_pair(data: PairData, options: &Options) -> PreparedPair {
+    let Options {
+        year,
+        year_start,
+        start,
+        ..
+    } = *options;
+    let PairData {
+        symbol,
+        asset_pair,
+        precision,
+        trades,
+        ledger_entries,
+        income,
+        transfers,
+        cross_trades,
+        rollovers,
+        loaded_at,
+    } = data;
+    let symbol: &str = &symbol;
+    // The order ids, order types and the side are matched here, which also
+    // covers the cached trades.
+    let trades: Vec<Trade> = trades
+        .into_iter()
+        .filter(|trade| options.order_filter.matches_trade(trade))
+        .collect();
+    // The excluded trades are dropped before anything is computed from the
+    // trades, but stay in the cache.
+    let (excluded_trades, trades): (Vec<Trade>, Vec<Trade>) = trades
+        .into_iter()
+        .partition(|trade| options.excluded_txids.contains(&trade.txid));
+    if !excluded_trades.is_empty() {
+        info!(
+            "Excluding {} trades of {}: {}.",
+            excluded_trades.len(),
+            symbol,
+            excluded_trades
+                .iter()
+                .map(|trade| trade.txid.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        );
+    }
+    // With several pairs, every txid only matches the trades of one of them.
+    if !options.several {
+        for txid in &options.excluded_txids {
+            if !excluded_trades.iter().any(|trade| &trade.txid == txid) {
+                warn!("No trade of {} matches the excluded txid {}.", symbol, txid);
+            }
+        }
+    }
+
+    // Margin trades don't change the holdings, so they are computed separately.
+    let (margin_trades, mut trades): (Vec<Trade>, Vec<Trade>) =
+        trades.into_iter().partition(is_margin_trade);
+    let margin_pnl: Option<(MarginPnL, Precision, String)> =
+        (!margin_trades.is_empty()).then(|| {
+            (
+                compute_margin_pnl(&margin_trades, &rollovers, year, year_start, &precision),
+                precision,
+                asset_pair.base.clone(),
+            )
+        });
+    let imports: Vec<Trade> = options
+        .imports
+        .iter()
+        .filter(|import| import.applies_to(&asset_pair))
+        .map(|import| import.trade(symbol, &precision))
+        .collect();
+    if !options.several && imports.len() < options.imports.len() {
+        warn!(
+            "{} imported trades are not of {}/{} and don't apply to {}.",
+            options.imports.len() - imports.len(),
+            asset_pair.base_code(),
+            asset_pair.quote_code(),
+            symbol
+        );
+    }
+    // The imports are merged before any other trades are added, so only the
+    // trades of the API and of the exports are compared.
+    let data_quality: Option<DataQuality> = (!imports.is_empty()).then(|| {
+        info!(
+            "Merging {} trades imported from CSV exports into the trades.",
+            imports.len()
+        );
+        let (merged, quality): (Vec<Trade>, DataQuality) = merge_sources(
+            std::mem::take(&mut trades),
+            imports,
+            options.prefer_source,
+            &precision,
+        );
+        if !quality.decisions.is_empty() {
+            warn!(
+                "Dropped {} trades of {} that overlap those of another source, see the data-quality report.",
+                quality.decisions.len(),
+                symbol
+            );
+        }
+        trades = merged;
+        quality
+    });
+    if let Some(entries) = &ledger_entries {
+        let count: usize = apply_base_fees(&mut trades, entries);
+        if count > 0 {
+            info!(
+                "Accounting the fees of {} trades in {} as charged in the base currency.",
+                count, symbol
+            );
+        }
+        if options.rebates == RebatePolicy::Fees {
+            let credits: Vec<Trade> = credit_trades(entries, symbol);
+            if !credits.is_empty() {
+                info!(
+                    "Adding {} credits of {} to the holdings at no cost.",
+                    credits.len(),
+                    asset_pair.base
+                );
+                trades.extend(credits);
+                trades.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+            }
+        }
+    }
+    if options.income_lots && !income.is_empty() {
+        info!(
+            "Adding {} income items of {} to the holdings at their value.",
+            income.len(),
+            asset_pair.base
+        );
+        trades.extend(income_trades(&income, symbol));
+        trades.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    }
+    let adjustments: Vec<Trade> = options
+        .adjustments
+        .iter()
+        .filter(|adjustment| adjustment.applies_to(&asset_pair))
+        .map(|adjustment| adjustment.trade(symbol, &precision))
+        .collect();
+    // With several pairs, every adjustment only applies to some of them.
+    if !options.several {
+        for adjustment in &options.adjustments {
+            if !adjustment.applies_to(&asset_pair) {
+                warn!(
+                    "The adjustment {} of {} doesn't apply to {}.",
+                    adjustment.id, adjustment.asset, symbol
+                );
+            }
+        }
+    }
+    if !adjustments.is_empty() {
+        info!(
+            "Merging {} manual adjustments of {} into the trades.",
+            adjustments.len(),
+            asset_pair.base
+        );
+        trades.extend(adjustments);
+        trades.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    }
+    if !transfers.is_empty() {
+        info!(
+            "Merging {} transfers of {} into the trades.",
+            transfers.len(),
+            asset_pair.base
+        );
+        trades.extend(transfers);
+        trades.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    }
+    if !cross_trades.is_empty() {
+        info!(
+            "Merging {} legs of trades of other pairs of {} into the trades.",
+            cross_trades.len(),
+            asset_pair.base
+        );
+        trades.extend(cross_trades);
+        trades.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    }
+    let (asset_pair, precision, mut trades) = if options.invert {
+        (
+            asset_pair.inverted(),
+            precision.inverted(),
+            invert_trades(&trades),
+        )
+    } else {
+        (asset_pair, precision, trades)
+    };
+    // The lots carried forward from a previous run replace the trades that
+    // went into them.
+    let loaded_until: Option<f64> = options.load_lots.as_ref().and_then(|path| {
+        let file_path: String = options.output_path(path, symbol);
+        let loaded = load_lots(&file_path, symbol, options.invert).unwrap_or_else(|e| {
+            error!("Error loading the lots: {}", e);
+            std::process::exit(1);
+        });
+        let count: usize = trades.len();
+        trades.retain(|trade| loaded.until.is_none_or(|until| trade.time > until));
+        info!(
+            "Loaded {} lots of {} from {}, skipping {} trades that went into them.",
+            loaded.lots.len(),
+            symbol,
+            file_path,
+            count - trades.len()
+        );
+        trades.splice(0..0, loaded.lots.iter().map(|lot| lot_trade(lot, symbol)));
+        trades.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+        loaded.until
+    });
+    // The opening balance precedes the trades, unless it is dated later.
+    if let Some(opening_balance) = &options.opening_balance {
+        let time: f64 = trades
+            .first()
+            .map(|trade| trade.time)
+            .or(start)
+            .unwrap_or_else(|| chrono::Utc::now().timestamp() as f64);
+        trades.insert(0, opening_balance.trade(symbol, time, &precision));
+        trades.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    }
 
-#[derive(Deserialize, Debug)]
-struct OrdersResult {
-    closed: std::collections::HashMap<String, Order>,
-    count: u32,
-}
+    // The trades within the price band are listed and, if requested, excluded
+    // from the PnL. The valuations and the balance check still consider all
+    // trades, since the excluded ones were executed nevertheless.
+    let in_price_band = |trade: &Trade| options.in_price_band(trade.price.parse().unwrap());
+    let listed_trades: Option<Vec<Trade>> = options.price_band().map(|_| {
+        trades
+            .iter()
+            .filter(|t| in_price_band(t))
+            .cloned()
+            .collect()
+    });
+    let pnl_trades: Option<Vec<Trade>> = options.exclude_price_band.then(|| {
+        trades
+            .iter()
+            .filter(|t| !in_price_band(t))
+            .cloned()
+            .collect()
+    });
 
-#[derive(Deserialize, Debug)]
-struct OrdersResponse {
-    error: Vec<String>,
-    result: Option<OrdersResult>,
+    // =========================================================================
+    // Compute FIFO PnL, or that of the specific identification
+    let pnl: PnL = compute_pnl(
+        pnl_trades.as_deref().unwrap_or(&trades),
+        year,
+        year_start,
+        &precision,
+        &options.lot_selection,
+        options.wash_sale,
+        options.dust_threshold,
+    );
+    PreparedPair {
+        symbol: symbol.to_string(),
+        asset_pair,
+        precision,
+        trades,
+        excluded_trades,
+        listed_trades,
+        pnl_trades,
+        ledger_entries,
+        income,
+        margin_pnl,
+        loaded_until,
+        loaded_at,
+        pnl,
+        data_quality,
+    }
 }
 
-// =============================================================================
-
-/// A Kraken API client.
-struct KrakenAPI {
-    api_key: String,
-    secret_key: String,
-    client: Client,
-    base_url: String,
+/// Prepares the trading pairs in parallel, each thread handling a contiguous
+/// chunk of them. The results keep the order of the pairs, so the output
+/// doesn't depend on the number of threads.
+///
+/// # Arguments
+///
+/// * `pairs` - The data of the trading pairs.
+/// * `options` - The options of the run.
+///
+fn prepare_pairs(pairs: Vec<PairData>, options: &Options) -> Vec<PreparedPair> {
+    let threads: usize = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(pairs.len());
+    if threads <= 1 {
+        return pairs
+            .into_iter()
+            .map(|data| prepare_pair(data, options))
+            .collect();
+    }
+    let chunk_size: usize = pairs.len().div_ceil(threads);
+    let mut chunks: Vec<Vec<PairData>> = Vec::new();
+    let mut pairs = pairs.into_iter().peekable();
+    while pairs.peek().is_some() {
+        chunks.push(pairs.by_ref().take(chunk_size).collect());
+    }
+    thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .into_iter()
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .into_iter()
+                        .map(|data| prepare_pair(data, options))
+                        .collect::<Vec<PreparedPair>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Failed to prepare the trading pairs!"))
+            .collect()
+    })
 }
-impl KrakenAPI {
-    /// Creates a new Kraken API client.
-    fn new(api_key: String, secret_key: String) -> Self {
-        Self {
-            api_key,
-            secret_key,
-            client: Client::new(),
-            base_url: "https://api.kraken.com".to_string(),
-        }
-    }
-
-    /// Computes the Kraken signature for a given request.
-    ///
-    /// # Arguments
-    ///
-    /// * `url_path` - The URL path of the API endpoint.
-    /// * `data` - The request data to be signed.
-    /// * `nonce` - A unique nonce value for the request.
-    ///
-    /// # Returns
-    ///
-    /// A string representing the computed Kraken signature.
-    ///
-    /// # Example
-    ///
-    /// ```
-    /// let signature = api.get_kraken_signature("/0/private/Balance", "nonce=123456", "123456");
-    /// ```
-    /// The signature as a string.
-    ///
-    fn get_kraken_signature(&self, url_path: &str, data: &str, nonce: &str) -> String {
-        let key = general_purpose::STANDARD.decode(&self.secret_key).unwrap();
-        let mut mac = Hmac::<Sha512>::new_from_slice(&key).unwrap();
-        mac.update(url_path.as_bytes());
-        mac.update(&Sha256::digest(format!("{}{}", nonce, data).as_bytes()));
-        general_purpose::STANDARD.encode(mac.finalize().into_bytes())
-    }
-
-    /// Sends a POST request to the Kraken API.
-    ///
-    /// # Returns
-    ///
-    /// The response as a string.
-    ///
-    fn request(&self, endpoint: &str, params: Vec<(&str, String)>) -> String {
-        let nonce = format!(
-            "{}",
-            (chrono::Utc::now().timestamp_nanos_opt().unwrap() / 10)
-        );
-        let mut params = params.clone();
-        params.push(("nonce", nonce.clone()));
-        let encoded_params = serde_urlencoded::to_string(&params).unwrap();
-        let response = self
-            .client
-            .post(format!("{}{}", self.base_url, endpoint))
-            .header(
-                "Content-Type",
-                "application/x-www-form-urlencoded; charset=utf-8",
-            )
-            .header("API-Key", &self.api_key)
-            .header(
-                "API-Sign",
-                self.get_kraken_signature(endpoint, &encoded_params, &nonce),
-            )
-            .form(&params)
-            .send()
-            .expect("Failed to send POST request!");
 
-        if response.status().is_success() {
-            response.text().expect("Failed to read response text!")
-        } else {
-            eprintln!("Error during request: {}", response.status());
-            "".to_string()
+/// Returns the closed orders of the period, fetched and stored in the cache,
+/// or read from it with `--from-cache`.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `options` - The options of the run.
+/// * `cache` - The cache of the fetched data, if available.
+///
+async fn closed_orders(
+    api: &KrakenAPI,
+    options: &Options,
+    cache: Option<&Cache>,
+) -> HashMap<String, ClosedOrder> {
+    if options.from_cache {
+        let orders: HashMap<String, ClosedOrder> = cache
+            .map(|cache| cache.load_orders().unwrap_or_default())
+            .unwrap_or_default();
+        if orders.is_empty() {
+            warn!("No closed orders are cached, run without --from-cache once to fetch them.");
         }
+        return orders;
+    }
+    let orders: HashMap<String, ClosedOrder> =
+        fetch_closed_orders(api, options.start, options.end).await;
+    if let Some(Err(e)) = cache.map(|cache| cache.store_orders(&orders)) {
+        warn!("Failed to cache the closed orders: {}", e);
     }
+    orders
 }
 
-// =============================================================================
-
-/// Fetches the trades and closed orders from the Kraken API.
+/// Writes the requested reports of a trading pair and prints its PnL.
 ///
 /// # Arguments
 ///
 /// * `api` - The Kraken API client.
-/// * `delay` - The time to wait between requests, depending on the API tier.
-/// * `symbol` - The trading pair symbol (e.g., XXBTZEUR).
-/// * `userref` - An optional user reference id to filter trades.
-/// * `start` - An optional start date for filtering trades.
-/// * `end` - An optional end date for filtering trades.
+/// * `prepared` - The prepared trades and the PnL of the pair.
+/// * `options` - The options of the run.
+/// * `cache` - The cache of the fetched data, if available.
+/// * `portfolio` - The PnL of the pairs, which the PnL of this pair is added
+///   to.
 ///
 /// # Returns
 ///
-/// A vector of trades that match the given criteria.
+/// The paths of the files written.
 ///
-/// This function fetches trades and closed orders from the Kraken API based on
-/// the provided criteria. It handles pagination and rate limiting based on the
-/// API tier. If a user reference is provided, it also fetches closed orders to
-/// match trades with the given user reference. The trades are sorted by time
-/// before being returned. All trades that match the given criteria.
-///
-fn fetch_trades(
-    api: KrakenAPI,
-    delay: u64,
-    symbol: &String,
-    userref: Option<i32>,
-    start: Option<f64>,
-    end: Option<f64>,
-) -> Vec<Trade> {
-    let mut params = vec![];
+async fn run(
+    api: &KrakenAPI,
+    prepared: PreparedPair,
+    options: &Options,
+    cache: Option<&mut Cache>,
+    portfolio: &mut Vec<PortfolioEntry>,
+) -> Vec<String> {
+    let Options {
+        year,
+        year_start,
+        start,
+        end,
+        balance_check,
+        tax_rules,
+        returns_period,
+        ..
+    } = *options;
+    let PreparedPair {
+        symbol,
+        asset_pair,
+        precision,
+        trades,
+        excluded_trades,
+        listed_trades,
+        pnl_trades,
+        ledger_entries,
+        income,
+        margin_pnl,
+        loaded_until,
+        loaded_at,
+        mut pnl,
+        data_quality,
+    } = prepared;
+    let symbol: &str = &symbol;
+    let listed_trades: &[Trade] = listed_trades.as_deref().unwrap_or(&trades);
+    let pnl_trades: &[Trade] = pnl_trades.as_deref().unwrap_or(&trades);
 
-    if let Some(userref) = userref {
-        params.push(("userref", userref.to_string()));
-    }
-    if let Some(start) = start {
-        params.push(("start", start.to_string()));
+    if let Some(amount) = options.sell {
+        let simulation: Simulation = simulate_sale(
+            pnl_trades,
+            symbol,
+            amount,
+            options.sell_price.unwrap_or(pnl.price),
+            &precision,
+            &options.lot_selection,
+            options.wash_sale,
+            options.dust_threshold,
+            tax_rules,
+        );
+        print_simulation(&simulation, &asset_pair.base, &precision);
+        print_open_lots(
+            &simulation.open_lots,
+            simulation.price,
+            DateTime::from_timestamp(simulation.time as i64, 0)
+                .unwrap()
+                .date_naive(),
+            tax_rules,
+            &asset_pair.base,
+            &precision,
+        );
+        return Vec::new();
     }
-    if let Some(end) = end {
-        params.push(("end", end.to_string()));
+
+    // Converting into the quote currency is a no-op.
+    let tax_currency: Option<String> = options
+        .tax_currency
+        .clone()
+        .filter(|currency| *currency != asset_pair.quote_code());
+    if let (Some(currency), None) = (&options.tax_currency, &tax_currency) {
+        info!(
+            "{} is the quote currency of {}, the disposals are not converted.",
+            currency, symbol
+        );
     }
+    let metadata: ReportMetadata = report_metadata(
+        symbol,
+        &asset_pair,
+        options,
+        tax_currency.clone(),
+        excluded_trades
+            .iter()
+            .map(|trade| trade.txid.clone())
+            .collect(),
+        pnl_trades.len(),
+        compute_data_digest(pnl_trades),
+    );
 
-    let mut relevant_trades: Vec<Trade> = Vec::new();
-    let mut offset: usize = 0usize;
+    // The fills of an order are merged for the listing only.
+    let aggregated: Option<(Vec<Trade>, HashMap<String, TradePnL>)> = options
+        .aggregate_fills
+        .then(|| aggregate_fills(listed_trades, &pnl.trade_pnl, &precision));
+    let (listed_trades, trade_pnl): (&[Trade], &HashMap<String, TradePnL>) = match &aggregated {
+        Some((aggregated, trade_pnl)) => {
+            info!(
+                "Listing the {} trades of {} as {} with the fills of every order merged.",
+                listed_trades.len(),
+                symbol,
+                aggregated.len()
+            );
+            (aggregated, trade_pnl)
+        }
+        None => (listed_trades, &pnl.trade_pnl),
+    };
 
-    println!("Fetching trades...");
-    loop {
-        let mut paginated_params: Vec<(&str, String)> = params.clone();
-        paginated_params.push(("ofs", offset.to_string()));
+    // The closed orders enrich the trades and tell the strategies apart.
+    let orders: Option<HashMap<String, ClosedOrder>> =
+        if options.order_details || options.slippage || options.group_by.is_some() {
+            Some(closed_orders(api, options, cache.as_deref()).await)
+        } else {
+            None
+        };
 
-        let response: String = api.request("/0/private/TradesHistory", paginated_params.clone());
-        let trades_response: TradesResponse =
-            serde_json::from_str(&response).expect("Failed to parse response!");
+    let mut outputs: Vec<String> = Vec::new();
+    if let Some(trades_csv) = &options.trades_csv {
+        let file_path: String = trades_csv_path(options, trades_csv, symbol);
+        write_trades_to_csv(
+            listed_trades,
+            trade_pnl,
+            orders.as_ref().filter(|_| options.order_details),
+            &precision,
+            &file_path,
+            &metadata,
+            &options.csv_format,
+        );
+        if file_path != "-" {
+            outputs.push(file_path);
+        }
+    }
 
-        if let Some(result) = trades_response.result {
-            let trades: Vec<Trade> = result
-                .trades
-                .into_iter()
-                .filter(|(_, trade)| trade.pair == *symbol)
-                .map(|(_, trade)| trade)
-                .collect();
-            relevant_trades.extend(trades);
+    if options.mode.prints_pnl() {
+        println!("{}", "*".repeat(80));
+        for trade in listed_trades.iter() {
+            let attribution: String = match trade_pnl.get(&trade.txid) {
+                Some(trade_pnl) => format!(
+                    " | cost basis {}, realized PnL {}, cumulative {} {}",
+                    precision.fmt_quote(trade_pnl.cost_basis),
+                    precision.fmt_quote(trade_pnl.realized_pnl),
+                    precision.fmt_quote(trade_pnl.cumulative_realized_pnl),
+                    asset_pair.quote_code()
+                ),
+                None => String::new(),
+            };
+            println!("{:?} {}{}", trade, format_time(trade.time), attribution);
+        }
+        println!("{}", "*".repeat(80));
+    }
 
-            if result.count as usize <= offset + 50 {
-                break;
+    // =========================================================================
+    // Report the FIFO PnL
+    let mut warnings: Vec<String> = Vec::from_iter(report_short_sales(
+        &pnl,
+        options,
+        symbol,
+        &asset_pair,
+        &precision,
+    ));
+    if let Some(path) = &options.save_lots {
+        let file_path: String = options.output_path(path, symbol);
+        let until: Option<f64> = pnl_trades
+            .last()
+            .map(|trade| trade.time)
+            .into_iter()
+            .chain(loaded_until)
+            .reduce(f64::max);
+        if let Err(e) = save_lots(&pnl.open_lots, &file_path, symbol, options.invert, until) {
+            error!("Error saving the lots: {}", e);
+            std::process::exit(1);
+        }
+        outputs.push(file_path);
+    }
+    // The lots open at the end of the valuation date are valued at its
+    // closing price instead of the price of the last trade.
+    let valuation: Option<(NaiveDate, String)> = match options.valuation_date {
+        Some(date) => {
+            let prices: Prices = fetch_prices(
+                api,
+                cache.as_deref(),
+                &asset_pair,
+                &options.price_sources,
+                &options.coingecko_ids,
+                date,
+                date,
+            )
+            .await;
+            let Some((close, source)) = prices.price_at(&date) else {
+                error!(
+                    "No closing price of {} is known for {}, check the price sources!",
+                    symbol, date
+                );
+                std::process::exit(1);
+            };
+            let until: f64 = (date + chrono::Days::new(1))
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+                .timestamp() as f64;
+            let count: usize = pnl_trades.partition_point(|trade| trade.time < until);
+            if count < pnl_trades.len() {
+                pnl.open_lots = compute_pnl(
+                    &pnl_trades[..count],
+                    year,
+                    year_start,
+                    &precision,
+                    &options.lot_selection,
+                    options.wash_sale,
+                    options.dust_threshold,
+                )
+                .open_lots;
+            }
+            pnl.price = close;
+            pnl.unrealized_pnl = precision.quote(
+                pnl.open_lots
+                    .iter()
+                    .map(|lot| lot.amount * close - lot.cost)
+                    .sum(),
+            );
+            Some((date, source.to_string()))
+        }
+        None => None,
+    };
+    if let Some(path) = &options.closing_entries {
+        let file_path: String = options.output_path(path, symbol);
+        // The lots are closed at the end of the valuation date, or the end
+        // date or the day of the last trade that went into them.
+        let date: NaiveDate = match &valuation {
+            Some((date, _)) => *date,
+            None => end
+                .or_else(|| {
+                    pnl_trades
+                        .last()
+                        .map(|trade| trade.time)
+                        .into_iter()
+                        .chain(loaded_until)
+                        .reduce(f64::max)
+                })
+                .and_then(|time| DateTime::from_timestamp(time as i64, 0))
+                .map(|time| time.date_naive())
+                .unwrap_or_else(|| chrono::Utc::now().date_naive()),
+        };
+        let lines: Vec<JournalLine> = closing_entries(
+            &pnl.open_lots,
+            pnl.realized_pnl,
+            options.revaluation_reserve.then_some(pnl.price),
+            date,
+            &asset_pair.base_code(),
+            &precision,
+        );
+        if let Err(e) = write_journal(&lines, &file_path, &asset_pair.quote_code(), &precision) {
+            error!("Error writing the closing entries: {}", e);
+            std::process::exit(1);
+        }
+        outputs.push(file_path);
+    }
+    // The disposals are stated in the currency taxes are filed in from now on.
+    let disposal_pair: AssetPair = match &tax_currency {
+        Some(currency) => {
+            let rates: FxRates = fetch_fx_rates(
+                api,
+                cache.as_deref(),
+                &asset_pair,
+                currency,
+                disposal_period(&pnl.disposals),
+                options.fx_source,
+            )
+            .await
+            .unwrap_or_else(|e| {
+                error!("Error converting into {}: {}", currency, e);
+                std::process::exit(1);
+            });
+            if let Err(e) = convert_pnl(&mut pnl, &rates, &precision) {
+                error!("Error converting into {}: {}", currency, e);
+                std::process::exit(1);
             }
-            std::thread::sleep(std::time::Duration::from_secs(delay));
+            info!(
+                "Converted {} disposals into {} at the {} rates.",
+                pnl.disposals.len(),
+                currency,
+                options.fx_source.name()
+            );
+            AssetPair {
+                quote: currency.clone(),
+                ..asset_pair.clone()
+            }
+        }
+        None => asset_pair.clone(),
+    };
+    let fees: f64 = summarize_fees(pnl_trades, year, year_start, &precision)
+        .total
+        .fees;
+    // The pairs are only consolidated if there are several of them.
+    let converted: Option<ConvertedPnL> = match &options.portfolio_currency {
+        Some(currency) if options.several => {
+            let realized_rate: Result<f64, String> = fetch_latest_rate(
+                api,
+                cache.as_deref(),
+                &disposal_pair,
+                currency,
+                options.fx_source,
+            )
+            .await;
+            let rate: Result<f64, String> = fetch_latest_rate(
+                api,
+                cache.as_deref(),
+                &asset_pair,
+                currency,
+                options.fx_source,
+            )
+            .await;
+            match (realized_rate, rate) {
+                (Ok(realized_rate), Ok(rate)) => Some(ConvertedPnL {
+                    currency: currency.clone(),
+                    realized_pnl: precision.quote(pnl.realized_pnl * realized_rate),
+                    unrealized_pnl: precision.quote(pnl.unrealized_pnl * rate),
+                    fees: precision.quote(fees * rate),
+                    value: precision.quote(pnl.balance * pnl.price * rate),
+                }),
+                (Err(e), _) | (_, Err(e)) => {
+                    let warning: String =
+                        format!("Not consolidating {} in {}: {}", symbol, currency, e);
+                    warn!("{}", warning);
+                    warnings.push(warning);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    let entry: PortfolioEntry = PortfolioEntry {
+        symbol: symbol.to_string(),
+        realized_pnl: pnl.realized_pnl,
+        realized_currency: tax_currency
+            .clone()
+            .unwrap_or_else(|| asset_pair.quote_code()),
+        unrealized_pnl: pnl.unrealized_pnl,
+        unrealized_currency: asset_pair.quote_code(),
+        balance: pnl.balance,
+        base_currency: asset_pair.base_code(),
+        fees,
+        converted,
+        loaded_at,
+        precision,
+        warnings,
+    };
+    if let Some(notifier) = &options.notifier {
+        notifier.notify(&entry).await;
+    }
+    portfolio.push(entry);
+    let pnl_by_category: Vec<(&str, f64)> = tax_rules
+        .map(|rules| classify_disposals(rules, &mut pnl.disposals))
+        .unwrap_or_default();
+
+    if let Some(lot_report) = &options.lot_report {
+        let file_path: String = options.output_path(lot_report, symbol);
+        write_disposals(
+            &pnl.disposals,
+            &file_path,
+            &metadata,
+            &precision,
+            &options.csv_format,
+        );
+        outputs.push(file_path);
+    }
+    for format in &options.export_formats {
+        let file_path: PathBuf =
+            Path::new(&options.exports_dir).join(options.output_path(format.file_name(), symbol));
+        let file_path: String = file_path.to_string_lossy().into_owned();
+        write_export(
+            &pnl.disposals,
+            *format,
+            &file_path,
+            &disposal_pair,
+            &precision,
+        );
+        outputs.push(file_path);
+    }
+    if let Some(chart) = &options.chart {
+        let (pnl_curve, balance): (Series, Series) = equity_curve(
+            &pnl.disposals,
+            pnl_trades,
+            year,
+            year_start,
+            &disposal_pair,
+            &precision,
+        );
+        if pnl_curve.points.is_empty() && balance.points.is_empty() {
+            warn!("There are no trades of {} to chart.", symbol);
         } else {
-            eprintln!("Error fetching trades: {:?}", trades_response.error);
+            let file_path: String = options.output_path(chart, symbol);
+            let title: String = format!("{} equity curve", symbol);
+            if let Err(e) = write_chart(&[pnl_curve, balance], &title, &file_path) {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+            outputs.push(file_path);
+        }
+    }
+    if let Some(html_report) = &options.html_report {
+        let file_path: String = options.output_path(html_report, symbol);
+        let (pnl_curve, balance): (Series, Series) = equity_curve(
+            &pnl.disposals,
+            pnl_trades,
+            year,
+            year_start,
+            &disposal_pair,
+            &precision,
+        );
+        if let Err(e) = write_html_report(
+            &file_path,
+            &metadata,
+            &pnl,
+            &excluded_trades,
+            &[pnl_curve, balance],
+            &asset_pair,
+            &precision,
+        ) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        outputs.push(file_path);
+    }
+
+    if let Some(xlsx) = &options.xlsx {
+        let file_path: String = options.output_path(xlsx, symbol);
+        if let Err(e) = write_xlsx(
+            &file_path,
+            &metadata,
+            &pnl,
+            pnl_trades,
+            &asset_pair,
+            &precision,
+        ) {
+            error!("{}", e);
             std::process::exit(1);
         }
+        outputs.push(file_path);
+    }
 
-        offset += 50;
+    if let Some(income_report) = &options.income_report {
+        let file_path: String = options.output_path(income_report, symbol);
+        if let Err(e) = write_income_report(&income, &file_path, &precision, &options.csv_format) {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        outputs.push(file_path);
     }
 
     // =========================================================================
-    let mut trades: Vec<Trade> = if userref.is_some() {
-        // When the userref is passed, we need to query the closed orders as
-        // well since only those can be matched up with trades based on the user
-        // reference number.
-        println!("Fetching closed orders...");
+    if options.mode == Mode::Report {
+        if options.income {
+            println!("Capital gains and losses:");
+        }
+        print_yearly_summary(
+            &pnl.disposals,
+            tax_rules,
+            year_start,
+            &asset_pair.base,
+            &precision,
+        );
+        println!("{}", "*".repeat(80));
+        if options.income {
+            println!(
+                "Income (valued at the daily price in {}):",
+                asset_pair.quote
+            );
+            print_income_statement(&income, year_start, &asset_pair.base, &precision);
+            println!("{}", "*".repeat(80));
+        }
+        return outputs;
+    }
+    if options.mode.prints_pnl() {
+        if tax_rules == Some(TaxRules::Us) {
+            print_form_8949_items(&pnl.disposals, &asset_pair.base, &precision);
+            println!("{}", "*".repeat(80));
+        }
+        // Label the values that were converted into the tax currency.
+        let in_tax_currency: String = tax_currency
+            .as_ref()
+            .map(|currency| format!(" in {}", currency))
+            .unwrap_or_default();
+        println!(
+            "Realized PnL{}: {}",
+            in_tax_currency,
+            precision.fmt_quote(pnl.realized_pnl)
+        );
+        for (category, category_pnl) in &pnl_by_category {
+            println!(
+                "Realized PnL{} ({}): {}",
+                in_tax_currency,
+                category,
+                precision.fmt_quote(*category_pnl)
+            );
+        }
+        if let Some(reporting_currency) = &options.reporting_currency {
+            let split: FxSplit = fetch_fx_rates(
+                api,
+                cache.as_deref(),
+                &asset_pair,
+                reporting_currency,
+                disposal_period(&pnl.disposals),
+                options.fx_source,
+            )
+            .await
+            .and_then(|rates| split_realized_pnl(&pnl.disposals, &rates))
+            .unwrap_or_else(|e| {
+                error!("Error converting into {}: {}", reporting_currency, e);
+                std::process::exit(1);
+            });
+            println!(
+                "Realized PnL in {}: {}",
+                reporting_currency,
+                precision.fmt_quote(split.total())
+            );
+            println!(
+                "  Asset PnL in {}: {}",
+                reporting_currency,
+                precision.fmt_quote(split.asset_pnl)
+            );
+            println!(
+                "  FX PnL in {}: {}",
+                reporting_currency,
+                precision.fmt_quote(split.fx_pnl)
+            );
+        }
+        let on: NaiveDate = match &valuation {
+            Some((date, source)) => {
+                println!(
+                    "Unrealized PnL as of {} (closing price {} from {}): {}",
+                    date,
+                    pnl.price,
+                    source,
+                    precision.fmt_quote(pnl.unrealized_pnl)
+                );
+                *date
+            }
+            None => {
+                println!(
+                    "Unrealized PnL: {}",
+                    precision.fmt_quote(pnl.unrealized_pnl)
+                );
+                end.and_then(|end| DateTime::from_timestamp(end as i64, 0))
+                    .map(|end| end.date_naive())
+                    .unwrap_or_else(|| chrono::Utc::now().date_naive())
+                    .min(chrono::Utc::now().date_naive())
+            }
+        };
+        if let Some(days) = options.lot_aging {
+            let aging = age_open_lots(&pnl.open_lots, pnl.price, on, days, &precision);
+            print_lot_aging(&aging, tax_rules, &asset_pair.base, &precision);
+        }
+        if options.open_lots {
+            print_open_lots(
+                &pnl.open_lots,
+                pnl.price,
+                on,
+                tax_rules,
+                &asset_pair.base,
+                &precision,
+            );
+        }
+        println!("Balance: {}", precision.fmt_base(pnl.balance));
+        if pnl.dust_swept > 0f64 {
+            println!(
+                "Dust Swept (Base): {} at a cost of {}",
+                precision.fmt_base(pnl.dust_swept),
+                precision.fmt_quote(pnl.dust_cost)
+            );
+        }
+        println!(
+            "Total Buy Volume (Base): {}",
+            precision.fmt_base(pnl.total_buy_volume_base)
+        );
+        println!(
+            "Total Sell Volume (Base): {}",
+            precision.fmt_base(pnl.total_sell_volume_base)
+        );
+        println!(
+            "Total Buy Volume (Quote): {}",
+            precision.fmt_quote(pnl.total_buy_volume_quote)
+        );
+        println!(
+            "Total Sell Volume (Quote): {}",
+            precision.fmt_quote(pnl.total_sell_volume_quote)
+        );
+        println!(
+            "Total Cost of Sold Assets{}: {}",
+            in_tax_currency,
+            precision.fmt_quote(pnl.total_cost_of_sold_assets)
+        );
+        println!(
+            "Total Value of Sold Assets{}: {}",
+            in_tax_currency,
+            precision.fmt_quote(pnl.total_value_of_sold_assets)
+        );
+        println!("{}", "*".repeat(80));
+        if let Some(quality) = &data_quality {
+            print_data_quality(quality, symbol);
+            println!("{}", "*".repeat(80));
+        }
+        if !options.compared_methods.is_empty() {
+            // The method of the run comes first, for reference.
+            let method: String = options.lot_selection.method();
+            let results: Vec<(String, PnL)> = [&options.lot_selection]
+                .into_iter()
+                .chain(
+                    options
+                        .compared_methods
+                        .iter()
+                        .filter(|selection| selection.method() != method),
+                )
+                .map(|selection| {
+                    (
+                        selection.method(),
+                        compute_pnl(
+                            pnl_trades,
+                            year,
+                            year_start,
+                            &precision,
+                            selection,
+                            options.wash_sale,
+                            options.dust_threshold,
+                        ),
+                    )
+                })
+                .collect();
+            print_comparison(&results, &precision);
+            println!("{}", "*".repeat(80));
+        }
+        if options.fee_summary {
+            print_fee_summary(
+                &summarize_fees(pnl_trades, year, year_start, &precision),
+                &asset_pair,
+                &precision,
+                ledger_entries.is_some(),
+            );
+            println!("{}", "*".repeat(80));
+        }
+        if options.performance {
+            print_performance(
+                &compute_performance(&pnl.disposals, &precision),
+                &disposal_pair.quote,
+                &precision,
+            );
+            println!("{}", "*".repeat(80));
+        }
+        if let (true, Some(orders)) = (options.slippage, &orders) {
+            print_slippage(
+                &compute_slippage(pnl_trades, orders, year, year_start, &precision),
+                &asset_pair.quote,
+                &precision,
+            );
+            println!("{}", "*".repeat(80));
+        }
+        if options.group_by_flags {
+            let order_flags: HashMap<String, Vec<String>> =
+                fetch_order_flags(api, start, end).await;
+            print_flag_summary(
+                &group_by_flags(&pnl.disposals, pnl_trades, &order_flags, &precision),
+                &disposal_pair.quote,
+                &precision,
+            );
+            println!("{}", "*".repeat(80));
+        }
+        if options.by_execution {
+            print_execution_summary(
+                &group_by_execution(&pnl.disposals, pnl_trades, year, year_start, &precision),
+                &asset_pair.quote,
+                &disposal_pair.quote,
+                &precision,
+            );
+            println!("{}", "*".repeat(80));
+        }
+        if let (Some(key), Some(orders)) = (&options.group_by, &orders) {
+            // The open lots are those at the end of the valuation date.
+            let until: Option<f64> = options.valuation_date.map(|date| {
+                (date + chrono::Days::new(1))
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap()
+                    .and_utc()
+                    .timestamp() as f64
+            });
+            print_strategies(
+                &group_by_strategy(
+                    pnl_trades,
+                    &key.strategies(orders),
+                    pnl.price,
+                    until,
+                    year,
+                    year_start,
+                    &precision,
+                    &options.lot_selection,
+                    options.wash_sale,
+                    options.dust_threshold,
+                ),
+                key,
+                &asset_pair.base,
+                &asset_pair.quote,
+                &precision,
+            );
+            println!("{}", "*".repeat(80));
+        }
+        if let Some(limits) = &options.exposure {
+            match fetch_ticker_price(api, symbol).await {
+                Ok(price) => {
+                    let price: f64 = if options.invert { 1f64 / price } else { price };
+                    let exposure: Exposure =
+                        compute_exposure(symbol, &pnl.open_lots, price, limits, &precision);
+                    print_exposure(&exposure, limits.portfolio_value, &asset_pair, &precision);
+                }
+                Err(e) => warn!("Skipping the exposure check: {}", e),
+            }
+            println!("{}", "*".repeat(80));
+        }
+        if let Some((margin_pnl, precision, base)) = &margin_pnl {
+            print_margin_pnl(margin_pnl, base, precision);
+            println!("{}", "*".repeat(80));
+        }
+    }
 
-        let mut closed_order_txids: Vec<String> = Vec::new();
-        offset = 0usize;
+    // =========================================================================
+    // Value the position daily and compute the return metrics
+    let series: Option<Vec<DailyValuation>> =
+        if returns_period.is_some() || options.valuations.is_some() {
+            let to: NaiveDate = chrono::Utc::now().date_naive();
+            let from: NaiveDate = trades
+                .first()
+                .and_then(|trade| DateTime::from_timestamp(trade.time as i64, 0))
+                .map(|time| time.date_naive())
+                .unwrap_or(to);
+            let prices: Prices = fetch_prices(
+                api,
+                cache.as_deref(),
+                &asset_pair,
+                &options.price_sources,
+                &options.coingecko_ids,
+                from,
+                to,
+            )
+            .await;
+            let series: Vec<DailyValuation> = daily_valuations(&trades, &prices, to)
+                .unwrap_or_else(|e| {
+                    error!("Error valuing the position: {}", e);
+                    std::process::exit(1);
+                });
+            if let Some(valuations) = &options.valuations {
+                let file_path: String = options.output_path(valuations, symbol);
+                write_valuations(
+                    &series,
+                    &file_path,
+                    &metadata,
+                    &precision,
+                    &options.csv_format,
+                );
+                outputs.push(file_path);
+            }
+            Some(series)
+        } else {
+            None
+        };
+    if let (Some((from, to)), Some(series)) = (returns_period, &series) {
+        match compute_returns(series, from, to) {
+            Some(returns) => {
+                println!(
+                    "Time-weighted return ({} to {}): {:.2}%",
+                    returns.from,
+                    returns.to,
+                    returns.time_weighted * 100f64
+                );
+                match returns.money_weighted {
+                    Some(irr) => println!(
+                        "Money-weighted return (IRR, annualized): {:.2}%",
+                        irr * 100f64
+                    ),
+                    None => println!("Money-weighted return (IRR, annualized): -"),
+                }
+            }
+            None => println!("No valuations within the selected period."),
+        }
+        println!("{}", "*".repeat(80));
+    }
 
-        loop {
-            let mut paginated_params: Vec<(&str, String)> = params.clone();
-            paginated_params.push(("ofs", offset.to_string()));
+    // =========================================================================
+    // Cross-check the balance against the ledger
+    if let (true, Some(entries)) = (balance_check, &ledger_entries) {
+        let check = cross_check_balance(&trades, entries, &precision);
+        print_balance_check(&check, &asset_pair.base, &precision);
+        println!("{}", "*".repeat(80));
+    }
 
-            let response: String = api.request("/0/private/ClosedOrders", paginated_params.clone());
-            let orders_response: OrdersResponse =
-                serde_json::from_str(&response).expect("Failed to parse response!");
+    // =========================================================================
+    // Reconcile the balance with the balance of the account
+    if options.reconcile {
+        match fetch_balances(api).await {
+            Ok(balances) => {
+                let reconciliation: Reconciliation = reconcile(
+                    pnl.balance,
+                    &balances,
+                    &asset_pair,
+                    pnl_trades,
+                    ledger_entries.as_deref(),
+                    &precision,
+                );
+                print_reconciliation(&reconciliation, &asset_pair.base, end.is_some(), &precision);
+                println!("{}", "*".repeat(80));
+            }
+            Err(e) => warn!("Skipping the reconciliation of {}: {}", symbol, e),
+        }
+    }
+    // =========================================================================
+    outputs
+}
 
-            if let Some(result) = orders_response.result {
-                let orders: Vec<String> = result.closed.into_keys().collect();
-                closed_order_txids.extend(orders);
+/// Returns the parameters of the run stated in the reports of a trading pair.
+///
+/// # Arguments
+///
+/// * `symbol` - The trading pair.
+/// * `asset_pair` - The trading pair as listed by Kraken.
+/// * `options` - The options of the run.
+/// * `tax_currency` - The currency the disposals are converted into, if any.
+/// * `excluded_txids` - The ids of the trades excluded from the PnL.
+/// * `trades` - The number of trades the PnL is computed from.
+/// * `data_digest` - The digest of these trades.
+///
+fn report_metadata(
+    symbol: &str,
+    asset_pair: &AssetPair,
+    options: &Options,
+    tax_currency: Option<String>,
+    excluded_txids: Vec<String>,
+    trades: usize,
+    data_digest: String,
+) -> ReportMetadata {
+    ReportMetadata {
+        tool: env!("CARGO_PKG_NAME").to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        generated_at: chrono::Utc::now().to_rfc3339(),
+        symbol: symbol.to_string(),
+        base: asset_pair.base.clone(),
+        quote: asset_pair.quote.clone(),
+        start: options.start_date.clone(),
+        end: options.end_date.clone(),
+        year: options.year,
+        fiscal_year_start: (!options.year_start.is_calendar())
+            .then(|| options.year_start.to_string()),
+        userrefs: options.order_filter.userrefs.clone(),
+        ordertxids: options.order_filter.ordertxids.clone(),
+        cl_ord_ids: options.order_filter.cl_ord_ids.clone(),
+        cl_ord_id_prefixes: options.order_filter.cl_ord_id_prefixes.clone(),
+        ordertypes: options.order_filter.ordertypes.clone(),
+        side: options.order_filter.side.clone(),
+        tier: options.tier.clone(),
+        method: options.lot_selection.method(),
+        tax_rules: options.tax_rules.map(|rules| rules.name().to_string()),
+        wash_sales: options.wash_sale.map(|rule| rule.name().to_string()),
+        fx_source: tax_currency
+            .as_ref()
+            .map(|_| options.fx_source.name().to_string()),
+        tax_currency,
+        price_band: options.price_band(),
+        inverted: options.invert,
+        aggregated_fills: options.aggregate_fills,
+        opening_balance: options
+            .opening_balance
+            .as_ref()
+            .map(|balance| balance.to_string()),
+        excluded_txids,
+        trades,
+        data_digest,
+    }
+}
 
-                if result.count as usize <= closed_order_txids.len() {
-                    break;
-                }
-                std::thread::sleep(std::time::Duration::from_secs(delay));
-            } else {
-                eprintln!("Error fetching closed orders: {:?}", orders_response.error);
-                std::process::exit(1);
-            }
+/// Returns the path of the trades CSV of a trading pair, after making sure
+/// it can be written.
+///
+/// # Arguments
+///
+/// * `options` - The options of the run.
+/// * `trades_csv` - The path passed via --csv, `-` for stdout.
+/// * `symbol` - The trading pair.
+///
+fn trades_csv_path(options: &Options, trades_csv: &str, symbol: &str) -> String {
+    if trades_csv == "-" {
+        return trades_csv.to_string();
+    }
+    let file_path: String = options.output_path(trades_csv, symbol);
+    if !options.force && Path::new(&file_path).exists() {
+        error!(
+            "{} already exists, pass --force to overwrite it!",
+            file_path
+        );
+        std::process::exit(1);
+    }
+    if let Some(parent) = Path::new(&file_path).parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            error!("Failed to create {}: {}", parent.display(), e);
+            std::process::exit(1);
+        }
+    }
+    file_path
+}
 
-            offset += 50;
+/// Warns about the sells that exceeded the holdings, or fails with --strict.
+///
+/// # Arguments
+///
+/// * `pnl` - The PnL of the trading pair.
+/// * `options` - The options of the run.
+/// * `symbol` - The trading pair.
+/// * `asset_pair` - The trading pair as listed by Kraken.
+/// * `precision` - The precision of the base and quote currency.
+///
+/// # Returns
+///
+/// The warning, if any sell exceeded the holdings.
+///
+fn report_short_sales(
+    pnl: &PnL,
+    options: &Options,
+    symbol: &str,
+    asset_pair: &AssetPair,
+    precision: &Precision,
+) -> Option<String> {
+    if let Some(short_sale) = pnl.short_sales.first() {
+        if options.strict {
+            error!(
+                "Sold {} {} with no matching acquisition at {} (order {}), the history may be incomplete! Pass --opening-balance AMOUNT@PRICE[,DATE] to seed the holdings.",
+                precision.fmt_base(short_sale.amount),
+                asset_pair.base,
+                format_time(short_sale.time),
+                short_sale.ordertxid
+            );
+            std::process::exit(1);
         }
+        let warning: String = format!(
+            "{} sells of {} exceed the holdings by a total of {} {} and opened short lots.",
+            pnl.short_sales.len(),
+            symbol,
+            precision.fmt_base(pnl.short_sales.iter().map(|sale| sale.amount).sum()),
+            asset_pair.base
+        );
+        warn!(
+            "{} Pass --strict to fail instead, or --opening-balance to seed the holdings.",
+            warning
+        );
+        return Some(warning);
+    }
+    None
+}
 
-        relevant_trades
-            .into_iter()
-            .filter(|trade| closed_order_txids.contains(&trade.ordertxid))
-            .collect()
-    } else {
-        relevant_trades
-    };
-    trades.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
-    trades
+/// The cached trades of a trading pair left out of the streamed PnL.
+#[derive(Default)]
+struct Skipped {
+    /// The ids of the trades excluded via --exclude-txid or --exclude-file.
+    excluded_txids: Vec<String>,
+    /// The number of margin trades, whose PnL is not computed.
+    margin_trades: usize,
 }
 
-/// Computes the FIFO PnL for a given set of trades.
+/// Passes the cached trades of a trading pair that go into the PnL to `f` in
+/// the order of time, with the opening balance sorted in like in
+/// `prepare_pair`.
 ///
 /// # Arguments
 ///
-/// * `trades` - A vector of trades to compute the PnL for.
-/// * `year` - An optional year to filter the trades. If provided, only profits
-///   made within the specified year are considered.
+/// * `cache` - The cache the trades are read from.
+/// * `symbol` - The trading pair.
+/// * `options` - The options of the run.
+/// * `precision` - The precision of the base and quote currency.
+/// * `f` - The function the trades are passed to.
 ///
 /// # Returns
 ///
-/// A tuple containing the realized PnL, unrealized PnL, balance, total buy/sell volumes for base and quote currencies,
-/// total cost of sold assets, and total value received from selling them.
+/// The trades that were left out.
 ///
-/// This function processes the trades in a FIFO manner to compute the realized
-/// and unrealized PnL. It also calculates the total volume of bought and sold assets for both base and quote currencies,
-/// as well as the total cost of sold assets and the total value received from selling them.
-fn compute_fifo_pnl(
-    trades: Vec<Trade>,
-    year: Option<u32>,
-) -> (f64, f64, f64, f64, f64, f64, f64, f64, f64) {
-    let mut fifo_queue: VecDeque<(f64, f64)> = VecDeque::new();
-    let mut realized_pnl: f64 = 0f64;
-    let mut balance: f64 = 0f64;
-    let mut price: f64 = 0f64;
-    let mut total_buy_volume_base: f64 = 0f64;
-    let mut total_sell_volume_base: f64 = 0f64;
-    let mut total_buy_volume_quote: f64 = 0f64;
-    let mut total_sell_volume_quote: f64 = 0f64;
-    let mut total_cost_of_sold_assets: f64 = 0f64;
-    let mut total_value_of_sold_assets: f64 = 0f64;
-
-    for trade in trades {
-        let trade_year: i32 = DateTime::from_timestamp_nanos((trade.time * 1e9) as i64).year();
-        let side: String = trade.side;
-        let amount: f64 = trade.vol.parse().unwrap();
-        price = trade.price.parse().unwrap();
-        let fee: f64 = trade.fee.parse().unwrap();
-
-        if side == "buy" {
-            let total_cost: f64 = (amount * price) + fee;
-            fifo_queue.push_back((amount, total_cost));
-            balance += amount;
-            total_buy_volume_base += amount;
-            total_buy_volume_quote += total_cost;
-        } else if side == "sell" {
-            let sell_proceeds: f64 = (amount * price) - fee;
-            let mut cost_basis: f64 = 0f64;
-            let mut base_currency_to_sell: f64 = amount;
-
-            while base_currency_to_sell > 0f64 && !fifo_queue.is_empty() {
-                let (fifo_amount, fifo_cost) = fifo_queue.pop_front().unwrap();
-                if fifo_amount <= base_currency_to_sell {
-                    cost_basis += fifo_cost;
-                    base_currency_to_sell -= fifo_amount;
-                } else {
-                    let partial_cost: f64 = (fifo_cost / fifo_amount) * base_currency_to_sell;
-                    cost_basis += partial_cost;
-                    fifo_queue.push_front((
-                        fifo_amount - base_currency_to_sell,
-                        fifo_cost - partial_cost,
-                    ));
-                    base_currency_to_sell = 0f64;
-                }
+fn for_each_pnl_trade(
+    cache: &Cache,
+    symbol: &str,
+    options: &Options,
+    precision: &Precision,
+    mut f: impl FnMut(&Trade),
+) -> Skipped {
+    let mut skipped: Skipped = Skipped::default();
+    let mut opening_balance: Option<&OpeningBalance> = options.opening_balance.as_ref();
+    cache
+        .for_each_trade(symbol, options.start, options.end, |trade| {
+            if !options.order_filter.matches_trade(&trade) {
+                return;
             }
-
-            let pnl: f64 = sell_proceeds - cost_basis;
-            if let Some(year) = year {
-                if trade_year == year as i32 {
-                    realized_pnl += pnl;
+            if options.excluded_txids.contains(&trade.txid) {
+                skipped.excluded_txids.push(trade.txid);
+                return;
+            }
+            if is_margin_trade(&trade) {
+                skipped.margin_trades += 1;
+                return;
+            }
+            // Without a date, the opening balance precedes the first trade.
+            if let Some(balance) = opening_balance {
+                let opening: Trade = balance.trade(symbol, trade.time, precision);
+                if opening.time <= trade.time {
+                    f(&opening);
+                    opening_balance = None;
                 }
-            } else {
-                realized_pnl += pnl;
             }
-            balance -= amount;
-            total_sell_volume_base += amount;
-            total_sell_volume_quote += sell_proceeds;
-            total_cost_of_sold_assets += cost_basis;
-            total_value_of_sold_assets += sell_proceeds;
-        }
+            f(&trade);
+        })
+        .unwrap_or_else(|e| {
+            error!("Error reading the cache: {}", e);
+            std::process::exit(1);
+        });
+    if let Some(balance) = opening_balance {
+        let time: f64 = options
+            .start
+            .unwrap_or_else(|| chrono::Utc::now().timestamp() as f64);
+        f(&balance.trade(symbol, time, precision));
     }
+    skipped
+}
 
-    let unrealized_pnl: f64 = fifo_queue
-        .iter()
-        .map(|(lot_amount, lot_cost)| (price - (lot_cost / lot_amount)) * lot_amount)
-        .sum();
-
-    (
-        realized_pnl,
-        unrealized_pnl,
-        balance,
-        total_buy_volume_base,
-        total_sell_volume_base,
-        total_buy_volume_quote,
-        total_sell_volume_quote,
-        total_cost_of_sold_assets,
-        total_value_of_sold_assets,
-    )
+/// Stores the fetched trades in the cache and clears them.
+fn store_fetched_trades(cache: &mut Cache, trades: &mut Vec<Trade>) {
+    if let Err(e) = cache.store_trades(trades) {
+        error!("Failed to cache the fetched trades: {}", e);
+        std::process::exit(1);
+    }
+    trades.clear();
 }
 
-/// Writes the trades to a CSV file.
+/// Writes the trades CSV and the lot report of a trading pair by streaming
+/// its trades from the cache through the lot engine, so only its open lots
+/// are held in memory, regardless of the length of the history. Unless
+/// --from-cache is passed, the trades are fetched into the cache first, page
+/// by page.
 ///
 /// # Arguments
 ///
-/// * `trades` - A reference to a vector of trades to be written to the CSV
-///   file.
-/// * `file_path` - The path of the CSV file to write the trades to.
-///
-/// This function writes the trades to a CSV file with the specified file path.
-/// The CSV file includes a header row and each trade is written as a row in the
-/// CSV file. The time field is converted to a human-readable format before
-/// being written to the file.
-fn write_trades_to_csv(trades: &Vec<Trade>, file_path: &str) {
-    let mut file: File = File::create(file_path).expect("Could not create file");
-    writeln!(
-        file,
-        "time,pair,side,price,fee,vol,cost,ordertype,ordertxid"
-    )
-    .expect("Failed to write header to CSV!");
-
-    for trade in trades {
-        let time_str = DateTime::from_timestamp_nanos((trade.time * 1e9) as i64)
-            .format("%Y-%m-%d %H:%M:%S")
-            .to_string();
-        writeln!(
-            file,
-            "{},{},{},{},{},{},{},{},{}",
-            time_str,
-            trade.pair,
-            trade.side,
-            trade.price,
-            trade.fee,
-            trade.vol,
-            trade.cost,
-            trade.ordertype,
-            trade.ordertxid,
-        )
-        .expect("Failed to write trades to CSV!");
+/// * `api` - The Kraken API client.
+/// * `symbol` - The trading pair.
+/// * `options` - The options of the run.
+/// * `cache` - The cache the trades are stored in and read from.
+///
+/// # Returns
+///
+/// The paths of the files written.
+///
+async fn export_low_memory(
+    api: &KrakenAPI,
+    symbol: &str,
+    options: &Options,
+    cache: &mut Cache,
+) -> Vec<String> {
+    let asset_pair: AssetPair = fetch_asset_pair(api, symbol).await;
+    let precision: Precision = fetch_precision(api, &asset_pair).await;
+    if !options.from_cache {
+        // The trades arrive newest first, and the cache sorts them by time
+        // when they are read.
+        let fetched: RefCell<(&mut Cache, Vec<Trade>)> = RefCell::new((cache, Vec::new()));
+        let count: usize =
+            fetch_trades_into(api, &asset_pair, options.start, options.end, |trade| {
+                let mut fetched = fetched.borrow_mut();
+                let (cache, trades) = &mut *fetched;
+                trades.push(trade);
+                if trades.len() >= PAGE_SIZE {
+                    store_fetched_trades(cache, trades);
+                }
+            })
+            .await;
+        let (cache, mut trades) = fetched.into_inner();
+        store_fetched_trades(cache, &mut trades);
+        info!("Stored {} trades of {} in the cache.", count, symbol);
     }
-}
-
-// =============================================================================
+    let cache: &Cache = cache;
 
-fn main() {
-    let matches = Command::new("FIFO PnL Calculator")
-        .version("0.1.0")
-        .author("Benjamin Thomas Schwertfeger")
-        .about("Compute FIFO PnL for Kraken trades")
-        .arg(
-            Arg::new("symbol")
-                .long("symbol")
-                .value_name("SYMBOL")
-                .help("Trading pair symbol (e.g., XXBTZEUR)")
-                .required(true)
-                .value_parser(clap::value_parser!(String)),
-        )
-        .arg(
-            Arg::new("start")
-                .long("start")
-                .value_name("START")
-                .help("Start date for filtering trades (e.g., 2023-01-01)")
-                .value_parser(clap::value_parser!(String)),
-        )
-        .arg(
-            Arg::new("end")
-                .long("end")
-                .value_name("END")
-                .help("End date for filtering trades (e.g., 2023-12-31)")
-                .value_parser(clap::value_parser!(String)),
-        )
-        .arg(
-            Arg::new("userref")
-                .long("userref")
-                .value_name("USERREF")
-                .help("A user reference id to filter trades")
-                .value_parser(clap::value_parser!(i32)),
-        )
-        .arg(
-            Arg::new("csv")
-                .long("csv")
-                .help("Generate a CSV file listing the trades")
-                .action(clap::ArgAction::SetTrue),
-        )
-        .arg(
-            Arg::new("year")
-                .long("year")
-                .value_name("YEAR")
-                .help("Only consider profits made within a specific year")
-                .value_parser(clap::value_parser!(u32)),
-        )
-        .arg(
-            Arg::new("tier")
-                .long("tier")
-                .value_name("TIER")
-                .help("API tier (starter, intermediate, or pro)")
-                .required(true)
-                .value_parser(clap::value_parser!(String)),
-        )
-        .get_matches();
-
-    let symbol: &String = matches.get_one::<String>("symbol").unwrap();
-    let year: Option<u32> = matches.get_one::<u32>("year").copied();
-    let start: Option<f64> = matches.get_one::<String>("start").map(|s| {
-        NaiveDate::parse_from_str(s, "%Y-%m-%d")
-            .unwrap()
-            .and_hms_opt(0, 0, 0)
-            .unwrap()
-            .and_utc()
-            .timestamp() as f64
+    // The metadata on top of the files states the digest of all trades, so
+    // they are read twice.
+    let mut digest: DataDigest = DataDigest::default();
+    let mut count: usize = 0;
+    let skipped: Skipped = for_each_pnl_trade(cache, symbol, options, &precision, |trade| {
+        digest.update(trade);
+        count += 1;
     });
-    let end: Option<f64> = matches.get_one::<String>("end").map(|s| {
-        NaiveDate::parse_from_str(s, "%Y-%m-%d")
-            .unwrap()
-            .and_hms_opt(23, 59, 59)
-            .unwrap()
-            .and_utc()
-            .timestamp() as f64
-    });
-    let userref: Option<i32> = matches.get_one::<i32>("userref").copied();
-    let csv = matches.get_flag("csv");
-    let api_key: String =
-        env::var("KRAKEN_API_KEY").expect("The environment variable 'KRAKEN_API_KEY' must be set!");
-    let secret_key: String = env::var("KRAKEN_SECRET_KEY")
-        .expect("The environment variable 'KRAKEN_SECRET_KEY' must be set!");
-
-    let api = KrakenAPI::new(api_key, secret_key);
-    let delay: u64 = match matches.get_one::<String>("tier").unwrap().as_str() {
-        "starter" => 7, // It takes 7 seconds to recover 2 API points with 0.33 points per second.
-        "intermediate" => 4, // It takes 4 seconds to recover 2 API points with 0.5 points per second.
-        "pro" => 2,          // It takes 2 seconds to recover 2 API points with 1 point per second.
-        _ => 7,              // Default to starter tier.
-    };
-
-    // =========================================================================
-    // Fetch trades and compute FIFO PnL
-    let trades = fetch_trades(api, delay, symbol, userref, start, end);
-
-    if csv {
-        write_trades_to_csv(&trades, "trades.csv");
+    if !skipped.excluded_txids.is_empty() {
+        info!(
+            "Excluding {} trades of {}: {}.",
+            skipped.excluded_txids.len(),
+            symbol,
+            skipped.excluded_txids.join(", ")
+        );
     }
-
-    println!("{}", "*".repeat(80));
-    for trade in &trades {
-        println!(
-            "{:?} {}",
-            trade,
-            DateTime::from_timestamp_nanos((trade.time * 1e9) as i64).format("%Y-%m-%d %H:%M:%S")
+    for txid in &options.excluded_txids {
+        if !skipped.excluded_txids.contains(txid) {
+            warn!("No trade of {} matches the excluded txid {}.", symbol, txid);
+        }
+    }
+    if skipped.margin_trades > 0 {
+        warn!(
+            "Leaving out the {} margin trades of {}, whose PnL --low-memory doesn't compute.",
+            skipped.margin_trades, symbol
         );
     }
+    let metadata: ReportMetadata = report_metadata(
+        symbol,
+        &asset_pair,
+        options,
+        None,
+        skipped.excluded_txids,
+        count,
+        digest.finish(),
+    );
 
-    // =========================================================================
-    // Compute FIFO PnL
-    println!("{}", "*".repeat(80));
-    let (
-        realized_pnl,
-        unrealized_pnl,
-        balance,
-        total_buy_volume_base,
-        total_sell_volume_base,
-        total_buy_volume_quote,
-        total_sell_volume_quote,
-        total_cost_of_sold_assets,
-        total_value_of_sold_assets,
-    ) = compute_fifo_pnl(trades, year);
-
-    // =========================================================================
-    println!("Realized PnL: {}", realized_pnl);
-    println!("Unrealized PnL: {}", unrealized_pnl);
-    println!("Balance: {}", balance);
-    println!("Total Buy Volume (Base): {}", total_buy_volume_base);
-    println!("Total Sell Volume (Base): {}", total_sell_volume_base);
-    println!("Total Buy Volume (Quote): {}", total_buy_volume_quote);
-    println!("Total Sell Volume (Quote): {}", total_sell_volume_quote);
-    println!("Total Cost of Sold Assets: {}", total_cost_of_sold_assets);
-    println!("Total Value of Sold Assets: {}", total_value_of_sold_assets);
-    println!("{}", "*".repeat(80));
-    // =========================================================================
+    // The orders are far fewer than their fills, so they are kept in memory.
+    let orders: Option<HashMap<String, ClosedOrder>> = if options.order_details {
+        Some(closed_orders(api, options, Some(cache)).await)
+    } else {
+        None
+    };
+    let mut outputs: Vec<String> = Vec::new();
+    let mut trades_csv: Option<TradesCsv> = options.trades_csv.as_ref().map(|trades_csv| {
+        let file_path: String = trades_csv_path(options, trades_csv, symbol);
+        let csv: TradesCsv = TradesCsv::create(
+            &file_path,
+            &metadata,
+            &options.csv_format,
+            &precision,
+            orders.as_ref(),
+        );
+        if file_path != "-" {
+            outputs.push(file_path);
+        }
+        csv
+    });
+    let mut disposals_csv: Option<DisposalsCsv> = options.lot_report.as_ref().map(|lot_report| {
+        let file_path: String = options.output_path(lot_report, symbol);
+        let csv: DisposalsCsv =
+            DisposalsCsv::create(&file_path, &metadata, &options.csv_format, &precision);
+        outputs.push(file_path);
+        csv
+    });
+    let mut engine: LotEngine = LotEngine::new(
+        options.year,
+        options.year_start,
+        &precision,
+        &options.lot_selection,
+        None,
+        options.dust_threshold,
+    );
+    let mut until: Option<f64> = None;
+    for_each_pnl_trade(cache, symbol, options, &precision, |trade| {
+        engine.feed(trade);
+        until = Some(trade.time);
+        let trade_pnl: Option<TradePnL> = engine.take_trade_pnl(&trade.txid);
+        if let Some(csv) = trades_csv.as_mut() {
+            csv.write(trade, trade_pnl.as_ref());
+        }
+        let mut disposals: Vec<Disposal> = engine.take_disposals();
+        if let Some(rules) = options.tax_rules {
+            classify_disposals(rules, &mut disposals);
+        }
+        if let Some(csv) = disposals_csv.as_mut() {
+            for disposal in &disposals {
+                csv.write(disposal);
+            }
+        }
+    });
+    // The files are complete before --strict may fail the run.
+    drop((trades_csv, disposals_csv));
+    let pnl: PnL = engine.finish();
+    info!(
+        "Streamed {} trades of {} through the lot engine, {} lots are open.",
+        count,
+        symbol,
+        pnl.open_lots.len()
+    );
+    report_short_sales(&pnl, options, symbol, &asset_pair, &precision);
+    if let Some(path) = &options.save_lots {
+        let file_path: String = options.output_path(path, symbol);
+        if let Err(e) = save_lots(&pnl.open_lots, &file_path, symbol, false, until) {
+            error!("Error saving the lots: {}", e);
+            std::process::exit(1);
+        }
+        outputs.push(file_path);
+    }
+    outputs
 }
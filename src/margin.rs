@@ -0,0 +1,283 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module computes the PnL of margin trades. Margin trades don't change the
+spot holdings, so they are kept away from the FIFO lot engine: a trade opening
+a position is tracked until the trades closing it, which refer to it by their
+`postxid`, are executed. Every close realizes the price difference of the
+closed amount, net of the proportional opening and closing fees.
+
+The rollover fees charged while positions are open are booked in the ledger of
+the quote currency and reduce the realized margin PnL of the year they were
+charged.
+*/
+
+use crate::fiscal::YearStart;
+use crate::kraken::{KrakenAPI, Trade};
+use crate::ledger::{try_fetch_ledgers, LedgerEntry};
+use crate::precision::Precision;
+use crate::report::format_time;
+use log::warn;
+use std::collections::HashSet;
+
+/// Returns whether a trade closes a margin position.
+fn is_closing(trade: &Trade) -> bool {
+    trade
+        .misc
+        .split(',')
+        .any(|detail| detail.trim() == "closing")
+}
+
+/// Returns whether a trade opens or closes a margin position.
+pub fn is_margin_trade(trade: &Trade) -> bool {
+    trade
+        .margin
+        .parse::<f64>()
+        .is_ok_and(|margin| margin != 0f64)
+        || is_closing(trade)
+}
+
+/// An open margin position.
+#[derive(Debug, Clone)]
+pub struct Position {
+    /// The id of the opening trade.
+    pub txid: String,
+    /// `buy` for long and `sell` for short positions.
+    pub side: String,
+    pub time: f64,
+    pub price: f64,
+    /// The amount of the base currency that is still open.
+    pub amount: f64,
+    /// The opening fee of the open amount.
+    pub fee: f64,
+}
+
+/// A (partial) close of a margin position.
+#[derive(Debug, Clone)]
+pub struct Close {
+    pub position: String,
+    pub side: String,
+    pub amount: f64,
+    pub open_time: f64,
+    pub close_time: f64,
+    pub open_price: f64,
+    pub close_price: f64,
+    /// The opening and closing fees of the closed amount.
+    pub fees: f64,
+    /// The price difference of the closed amount net of the fees.
+    pub pnl: f64,
+}
+
+/// The result of the margin PnL computation.
+#[derive(Debug, Default)]
+pub struct MarginPnL {
+    pub closes: Vec<Close>,
+    pub open_positions: Vec<Position>,
+    pub rollover_fees: f64,
+    /// The PnL of the closes net of the rollover fees.
+    pub realized_pnl: f64,
+}
+
+/// Fetches the rollover fees charged in the quote currency.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `quote` - The quote asset of the trading pair (e.g., ZEUR).
+/// * `start` - An optional start date for filtering the entries.
+/// * `end` - An optional end date for filtering the entries.
+///
+/// # Returns
+///
+/// The ledger entries of type `rollover`, or the errors reported by Kraken.
+///
+pub async fn fetch_rollovers(
+    api: &KrakenAPI,
+    quote: &str,
+    start: Option<f64>,
+    end: Option<f64>,
+) -> Result<Vec<LedgerEntry>, Vec<String>> {
+    let mut params = vec![
+        ("asset", quote.to_string()),
+        ("type", "rollover".to_string()),
+    ];
+    if let Some(start) = start {
+        params.push(("start", start.to_string()));
+    }
+    if let Some(end) = end {
+        params.push(("end", end.to_string()));
+    }
+    try_fetch_ledgers(api, &params).await
+}
+
+/// Computes the PnL of the margin trades of a trading pair.
+///
+/// # Arguments
+///
+/// * `trades` - The margin trades sorted by time.
+/// * `rollovers` - The rollover entries of the ledger of the quote currency.
+///   Only those referring to the positions of `trades` are considered.
+/// * `year` - An optional year to filter the closes and rollover fees by.
+/// * `year_start` - The first day of the fiscal years.
+/// * `precision` - The precision of the base and quote currency.
+///
+/// # Returns
+///
+/// The closes, the positions that are still open and the realized PnL.
+///
+pub fn compute_margin_pnl(
+    trades: &[Trade],
+    rollovers: &[LedgerEntry],
+    year: Option<u32>,
+    year_start: YearStart,
+    precision: &Precision,
+) -> MarginPnL {
+    let in_year = |time: f64| year_start.contains(year, time);
+    let mut pnl: MarginPnL = MarginPnL::default();
+    let mut positions: Vec<Position> = Vec::new();
+
+    for trade in trades {
+        let amount: f64 = trade.vol.parse().unwrap();
+        let price: f64 = trade.price.parse().unwrap();
+        let fee: f64 = trade.fee.parse().unwrap();
+        if !is_closing(trade) {
+            positions.push(Position {
+                txid: trade.txid.clone(),
+                side: trade.side.clone(),
+                time: trade.time,
+                price,
+                amount,
+                fee,
+            });
+            continue;
+        }
+
+        // Close the referenced position, or the oldest positions of the
+        // opposite side if the reference is unknown.
+        let mut to_close: f64 = amount;
+        while to_close > 0f64 {
+            let Some(index) = positions
+                .iter()
+                .position(|position| position.txid == trade.postxid)
+                .or_else(|| {
+                    positions
+                        .iter()
+                        .position(|position| position.side != trade.side)
+                })
+            else {
+                warn!(
+                    "Trade {} closes {} {} more than the open margin positions, ignoring the remainder.",
+                    trade.txid,
+                    precision.fmt_base(to_close),
+                    trade.pair
+                );
+                break;
+            };
+            let position: &mut Position = &mut positions[index];
+            let closed: f64 = to_close.min(position.amount);
+            let opening_fee: f64 = precision.quote(position.fee * closed / position.amount);
+            let fees: f64 = precision.quote(opening_fee + fee * closed / amount);
+            let direction: f64 = if position.side == "buy" { 1f64 } else { -1f64 };
+            let close = Close {
+                position: position.txid.clone(),
+                side: position.side.clone(),
+                amount: closed,
+                open_time: position.time,
+                close_time: trade.time,
+                open_price: position.price,
+                close_price: price,
+                fees,
+                pnl: precision.quote(direction * (price - position.price) * closed - fees),
+            };
+            position.amount = precision.base(position.amount - closed);
+            position.fee = precision.quote(position.fee - opening_fee);
+            if position.amount <= 0f64 {
+                positions.remove(index);
+            }
+            to_close = precision.base(to_close - closed);
+            if in_year(trade.time) {
+                pnl.realized_pnl = precision.quote(pnl.realized_pnl + close.pnl);
+                pnl.closes.push(close);
+            }
+        }
+    }
+
+    let references: HashSet<&str> = trades
+        .iter()
+        .flat_map(|trade| [trade.txid.as_str(), trade.ordertxid.as_str()])
+        .collect();
+    for entry in rollovers {
+        if entry.entry_type != "rollover"
+            || !references.contains(entry.refid.as_str())
+            || !in_year(entry.time)
+        {
+            continue;
+        }
+        let fee: f64 = entry.fee.parse::<f64>().unwrap_or(0f64)
+            - entry.amount.parse::<f64>().unwrap_or(0f64).min(0f64);
+        pnl.rollover_fees = precision.quote(pnl.rollover_fees + fee);
+    }
+    pnl.realized_pnl = precision.quote(pnl.realized_pnl - pnl.rollover_fees);
+    pnl.open_positions = positions;
+    pnl
+}
+
+/// Prints the margin PnL.
+///
+/// # Arguments
+///
+/// * `pnl` - The margin PnL.
+/// * `asset` - The name of the base asset.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn print_margin_pnl(pnl: &MarginPnL, asset: &str, precision: &Precision) {
+    let sum = |values: &mut dyn Iterator<Item = f64>| values.fold(0f64, |sum, value| sum + value);
+    println!("Margin PnL:");
+    for close in &pnl.closes {
+        println!(
+            "{} closed {} {} of the {} position {} (opened {} at {}) at {}, fees: {}, PnL: {}",
+            format_time(close.close_time),
+            precision.fmt_base(close.amount),
+            asset,
+            if close.side == "buy" { "long" } else { "short" },
+            close.position,
+            format_time(close.open_time),
+            close.open_price,
+            close.close_price,
+            precision.fmt_quote(close.fees),
+            precision.fmt_quote(close.pnl)
+        );
+    }
+    println!(
+        "Closed: {} {} in {} closes",
+        precision.fmt_base(sum(&mut pnl.closes.iter().map(|close| close.amount))),
+        asset,
+        pnl.closes.len()
+    );
+    println!(
+        "PnL of the closes: {} (after fees of {})",
+        precision.fmt_quote(sum(&mut pnl.closes.iter().map(|close| close.pnl))),
+        precision.fmt_quote(sum(&mut pnl.closes.iter().map(|close| close.fees)))
+    );
+    println!("Rollover fees: {}", precision.fmt_quote(pnl.rollover_fees));
+    println!(
+        "Realized Margin PnL: {}",
+        precision.fmt_quote(pnl.realized_pnl)
+    );
+    for (label, side) in [("long", "buy"), ("short", "sell")] {
+        let open: Vec<&Position> = pnl
+            .open_positions
+            .iter()
+            .filter(|position| position.side == side)
+            .collect();
+        println!(
+            "Open {} positions: {} ({} {})",
+            label,
+            open.len(),
+            precision.fmt_base(sum(&mut open.iter().map(|position| position.amount))),
+            asset
+        );
+    }
+}
@@ -0,0 +1,146 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module exposes the PnL of the watch and stream modes as Prometheus
+metrics. A small HTTP server answers `GET /metrics` with the text exposition
+format, which is rendered anew after every iteration, so scrapes never wait
+for a computation.
+*/
+
+use crate::http::with_limits;
+use crate::report::PortfolioEntry;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use log::{debug, info};
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+
+/// The metrics served to the scrapers.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    /// The rendered metrics of the last iteration.
+    text: Arc<Mutex<String>>,
+}
+
+impl Metrics {
+    /// Replaces the served metrics by those of an iteration.
+    pub fn update(&self, entries: &[PortfolioEntry]) {
+        *self.text.lock().unwrap() = render(entries);
+    }
+}
+
+/// A gauge as its name, its description, and its value and currency (if any)
+/// taken from the PnL of a trading pair.
+type Gauge = (
+    &'static str,
+    &'static str,
+    fn(&PortfolioEntry) -> (f64, &str),
+);
+
+/// The gauges exported per trading pair.
+const GAUGES: [Gauge; 5] = [
+    ("realized_pnl", "The realized FIFO PnL", |entry| {
+        (entry.realized_pnl, &entry.realized_currency)
+    }),
+    (
+        "unrealized_pnl",
+        "The unrealized PnL of the open lots",
+        |entry| (entry.unrealized_pnl, &entry.unrealized_currency),
+    ),
+    ("balance", "The holdings in the base currency", |entry| {
+        (entry.balance, &entry.base_currency)
+    }),
+    (
+        "fees_total",
+        "The fees paid in the quote currency",
+        |entry| (entry.fees, &entry.unrealized_currency),
+    ),
+    (
+        "last_fetch_timestamp_seconds",
+        "The time the trades were fetched at",
+        |entry| (entry.loaded_at, ""),
+    ),
+];
+
+/// Renders the metrics of the trading pairs in the Prometheus text format.
+///
+/// # Arguments
+///
+/// * `entries` - The PnL of the trading pairs.
+///
+fn render(entries: &[PortfolioEntry]) -> String {
+    let mut text: String = String::new();
+    for (name, help, metric) in GAUGES {
+        writeln!(text, "# HELP kraken_pnl_{} {}.", name, help).unwrap();
+        writeln!(text, "# TYPE kraken_pnl_{} gauge", name).unwrap();
+        for entry in entries {
+            let (value, currency) = metric(entry);
+            let currency: String = if currency.is_empty() {
+                String::new()
+            } else {
+                format!(",currency=\"{}\"", currency)
+            };
+            writeln!(
+                text,
+                "kraken_pnl_{}{{pair=\"{}\"{}}} {}",
+                name, entry.symbol, currency, value
+            )
+            .unwrap();
+        }
+    }
+    text
+}
+
+/// Starts the HTTP server of the metrics.
+///
+/// # Arguments
+///
+/// * `address` - The address to listen on (e.g., 127.0.0.1:9184).
+///
+/// # Returns
+///
+/// The metrics, which are empty until the first update.
+///
+pub async fn serve_metrics(address: &str) -> Result<Metrics, String> {
+    let listener: TcpListener = TcpListener::bind(address)
+        .await
+        .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+    info!("Serving the metrics at http://{}/metrics", address);
+    let metrics: Metrics = Metrics::default();
+    let router: Router = with_limits(
+        Router::new()
+            .route("/metrics", get(respond))
+            .fallback(not_found)
+            .with_state(metrics.clone()),
+    );
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, router).await {
+            debug!("Failed to serve the metrics: {}", e);
+        }
+    });
+    Ok(metrics)
+}
+
+/// The content type of the Prometheus text format.
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Answers a request for the metrics.
+async fn respond(State(metrics): State<Metrics>) -> impl IntoResponse {
+    let text: String = metrics.text.lock().unwrap().clone();
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], text)
+}
+
+/// Answers a request for any other path.
+async fn not_found() -> impl IntoResponse {
+    (
+        StatusCode::NOT_FOUND,
+        [(header::CONTENT_TYPE, CONTENT_TYPE)],
+        "Not found, the metrics are served at /metrics\n",
+    )
+}
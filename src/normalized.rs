@@ -0,0 +1,838 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module defines the normalized schema of trades and ledger events, a
+versioned intermediate format that decouples fetching the data from the
+accounting. `convert` translates the data of Kraken (the local cache, state
+files or recorded TradesHistory responses) and the CSV exports of other
+exchanges into it, and `--input` computes the PnL from it instead of fetching
+the data, so any source that writes the schema can be accounted for.
+
+A document is a JSON file:
+
+    {"schema": "kraken-pnl-calculator/normalized", "version": 1,
+     "trades": [...], "ledger": [...]}
+
+or a CSV file whose first line states the schema and its version
+(`# kraken-pnl-calculator/normalized 1`), followed by the header
+`CSV_COLUMNS` and one line per trade or ledger event, as told by its
+`record` column. Amounts are decimal strings, so they keep their precision,
+and times are UNIX timestamps in seconds.
+
+Readers accept every version up to `SCHEMA_VERSION`. Adding optional fields
+keeps the version, other changes increase it.
+*/
+
+use crate::cache::Cache;
+use crate::imports::{importer, read_import, split_line, ImportedTrade, EXCHANGES};
+use crate::kraken::{KrakenAPI, Trade};
+use crate::ledger::LedgerEntry;
+use crate::precision::{fetch_pair_aliases, AssetPair, PairAliases};
+use crate::schema::{upgrade, version_of};
+use crate::state::State;
+use crate::verify::load_fixture;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The name of the schema.
+pub const SCHEMA: &str = "kraken-pnl-calculator/normalized";
+
+/// The version of the schema written by this version of the tool.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The columns of a CSV document. Trades leave the columns of the ledger
+/// events empty and vice versa.
+pub const CSV_COLUMNS: [&str; 24] = [
+    "record",
+    "source",
+    "id",
+    "order_id",
+    "refid",
+    "time",
+    "pair",
+    "base",
+    "quote",
+    "asset",
+    "side",
+    "type",
+    "subtype",
+    "amount",
+    "price",
+    "cost",
+    "fee",
+    "base_fee",
+    "balance",
+    "order_type",
+    "margin",
+    "misc",
+    "position_id",
+    "liquidity",
+];
+
+/// A trade.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TradeEvent {
+    /// The exchange the trade was made at, e.g. `kraken` or `binance`.
+    pub source: String,
+    pub id: String,
+    /// The id of the order, which is the id of the trade if unknown.
+    pub order_id: String,
+    /// The name of the trading pair at the exchange, e.g. XXBTZEUR.
+    pub pair: String,
+    /// The common codes of the base and quote currency (e.g., BTC and EUR),
+    /// empty if unknown.
+    #[serde(default)]
+    pub base: String,
+    #[serde(default)]
+    pub quote: String,
+    pub time: f64,
+    /// `buy` or `sell`.
+    pub side: String,
+    /// The traded amount of the base currency.
+    pub amount: String,
+    pub price: String,
+    /// The value in the quote currency, excluding the fee.
+    pub cost: String,
+    /// The fee in the quote currency, or its value if charged in the base
+    /// currency.
+    pub fee: String,
+    /// The fee charged in the base currency, empty if none.
+    #[serde(default)]
+    pub base_fee: String,
+    #[serde(default)]
+    pub order_type: String,
+    /// The initial margin of a margin trade, empty or zero for spot trades.
+    #[serde(default)]
+    pub margin: String,
+    /// A comma-separated list of details, e.g. `closing`.
+    #[serde(default)]
+    pub misc: String,
+    /// The id of the position a closing trade closes.
+    #[serde(default)]
+    pub position_id: String,
+    /// `maker` or `taker`, empty if unknown.
+    #[serde(default)]
+    pub liquidity: String,
+}
+
+/// A ledger event, i.e. a change of the balance of an asset.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LedgerEvent {
+    pub source: String,
+    pub id: String,
+    /// The id of the trade or transfer the event belongs to.
+    pub refid: String,
+    pub time: f64,
+    /// The asset as named by the exchange, e.g. XXBT.
+    pub asset: String,
+    /// The type, e.g. `trade`, `deposit` or `staking`, and its subtype.
+    #[serde(rename = "type")]
+    pub event_type: String,
+    #[serde(default)]
+    pub subtype: String,
+    pub amount: String,
+    pub fee: String,
+    #[serde(default)]
+    pub balance: String,
+}
+
+/// A normalized document.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Document {
+    pub schema: String,
+    pub version: u32,
+    pub trades: Vec<TradeEvent>,
+    #[serde(default)]
+    pub ledger: Vec<LedgerEvent>,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Document {
+            schema: SCHEMA.to_string(),
+            version: SCHEMA_VERSION,
+            trades: Vec::new(),
+            ledger: Vec::new(),
+        }
+    }
+}
+
+impl TradeEvent {
+    /// Returns a trade of Kraken as event.
+    ///
+    /// # Arguments
+    ///
+    /// * `trade` - The trade.
+    /// * `asset_pair` - The trading pair, if known, which states the codes of
+    ///   the base and quote currency.
+    ///
+    pub fn from_trade(trade: &Trade, asset_pair: Option<&AssetPair>) -> Self {
+        TradeEvent {
+            source: trade.source.unwrap_or("kraken").to_string(),
+            id: trade.txid.clone(),
+            order_id: trade.ordertxid.clone(),
+            pair: trade.pair.clone(),
+            base: asset_pair.map(AssetPair::base_code).unwrap_or_default(),
+            quote: asset_pair.map(AssetPair::quote_code).unwrap_or_default(),
+            time: trade.time,
+            side: trade.side.clone(),
+            amount: trade.vol.clone(),
+            price: trade.price.clone(),
+            cost: trade.cost.clone(),
+            fee: trade.fee.clone(),
+            base_fee: trade
+                .base_fee
+                .map(|fee| fee.to_string())
+                .unwrap_or_default(),
+            order_type: trade.ordertype.clone(),
+            margin: trade.margin.clone(),
+            misc: trade.misc.clone(),
+            position_id: trade.postxid.clone(),
+            liquidity: match trade.maker {
+                Some(true) => "maker",
+                Some(false) => "taker",
+                None => "",
+            }
+            .to_string(),
+        }
+    }
+
+    /// Returns a trade imported from another exchange as event.
+    ///
+    /// # Arguments
+    ///
+    /// * `trade` - The imported trade.
+    /// * `exchange` - The name of the exchange.
+    ///
+    pub fn from_import(trade: &ImportedTrade, exchange: &str) -> Self {
+        let (fee, base_fee): (f64, Option<f64>) = trade.fees();
+        // The values are parsed from decimals, products of them are rounded to
+        // drop the artifacts of the floating point arithmetic.
+        let decimal = |value: f64| {
+            let value: String = format!("{:.12}", value);
+            value
+                .trim_end_matches('0')
+                .trim_end_matches('.')
+                .to_string()
+        };
+        TradeEvent {
+            source: exchange.to_string(),
+            id: trade.id.clone(),
+            order_id: trade.order_id.clone().unwrap_or_else(|| trade.id.clone()),
+            pair: trade
+                .pair
+                .clone()
+                .unwrap_or_else(|| format!("{}{}", trade.base, trade.quote)),
+            base: trade.base.to_uppercase(),
+            quote: trade.quote.to_uppercase(),
+            time: trade.time,
+            side: trade.side.to_string(),
+            amount: decimal(trade.amount),
+            price: decimal(trade.price),
+            cost: decimal(trade.cost),
+            fee: decimal(fee),
+            base_fee: base_fee.map(decimal).unwrap_or_default(),
+            order_type: "import".to_string(),
+            margin: String::new(),
+            misc: String::new(),
+            position_id: String::new(),
+            liquidity: String::new(),
+        }
+    }
+
+    /// Returns whether the event is a trade of a trading pair, by the name of
+    /// the pair or by the codes of its base and quote currency.
+    pub fn applies_to(&self, asset_pair: &AssetPair) -> bool {
+        self.pair == asset_pair.name
+            || self.pair == asset_pair.altname
+            || (!self.base.is_empty()
+                && self.base == asset_pair.base_code()
+                && self.quote == asset_pair.quote_code())
+    }
+
+    /// Returns the event as trade of a trading pair.
+    pub fn trade(&self, pair: &str) -> Trade {
+        Trade {
+            txid: self.id.clone(),
+            ordertxid: self.order_id.clone(),
+            pair: pair.to_string(),
+            time: self.time,
+            side: self.side.clone(),
+            price: self.price.clone(),
+            fee: self.fee.clone(),
+            vol: self.amount.clone(),
+            cost: self.cost.clone(),
+            ordertype: self.order_type.clone(),
+            margin: self.margin.clone(),
+            misc: self.misc.clone(),
+            postxid: self.position_id.clone(),
+            maker: match self.liquidity.as_str() {
+                "maker" => Some(true),
+                "taker" => Some(false),
+                _ => None,
+            },
+            base_fee: self.base_fee.parse().ok(),
+            // The trades of Kraken are indistinguishable from those of its
+            // exports.
+            source: EXCHANGES
+                .iter()
+                .copied()
+                .find(|exchange| *exchange == self.source && *exchange != "kraken"),
+        }
+    }
+}
+
+impl LedgerEvent {
+    /// Returns a ledger entry of an asset of Kraken as event.
+    pub fn from_entry(asset: &str, entry: &LedgerEntry) -> Self {
+        LedgerEvent {
+            source: "kraken".to_string(),
+            id: entry.id.clone(),
+            refid: entry.refid.clone(),
+            time: entry.time,
+            asset: asset.to_string(),
+            event_type: entry.entry_type.clone(),
+            subtype: entry.subtype.clone(),
+            amount: entry.amount.clone(),
+            fee: entry.fee.clone(),
+            balance: entry.balance.clone(),
+        }
+    }
+
+    /// Returns the event as ledger entry.
+    pub fn entry(&self) -> LedgerEntry {
+        LedgerEntry {
+            id: self.id.clone(),
+            refid: self.refid.clone(),
+            time: self.time,
+            entry_type: self.event_type.clone(),
+            subtype: self.subtype.clone(),
+            amount: self.amount.clone(),
+            fee: self.fee.clone(),
+            balance: self.balance.clone(),
+        }
+    }
+}
+
+/// Returns whether a time lies within the optional bounds.
+fn within(time: f64, start: Option<f64>, end: Option<f64>) -> bool {
+    start.is_none_or(|start| time >= start) && end.is_none_or(|end| time <= end)
+}
+
+impl Document {
+    /// Returns trades and ledger entries of Kraken as document, sorted by
+    /// time the way `convert` writes them.
+    ///
+    /// # Arguments
+    ///
+    /// * `trades` - The trades.
+    /// * `ledger` - The ledger entries with the name of their asset (e.g.,
+    ///   XXBT).
+    /// * `pair_of` - Returns the trading pair of a trade, if known, which
+    ///   states the codes of the base and quote currency.
+    ///
+    pub fn from_kraken<'a, 'b>(
+        trades: &[Trade],
+        ledger: impl IntoIterator<Item = (&'b str, &'b LedgerEntry)>,
+        pair_of: impl Fn(&Trade) -> Option<&'a AssetPair>,
+    ) -> Self {
+        let mut document = Document {
+            trades: trades
+                .iter()
+                .map(|trade| TradeEvent::from_trade(trade, pair_of(trade)))
+                .collect(),
+            ledger: ledger
+                .into_iter()
+                .map(|(asset, entry)| LedgerEvent::from_entry(asset, entry))
+                .collect(),
+            ..Document::default()
+        };
+        document.trades.sort_by(|a, b| a.time.total_cmp(&b.time));
+        document.ledger.sort_by(|a, b| a.time.total_cmp(&b.time));
+        document
+    }
+
+    /// Returns the trades of a trading pair, sorted by time.
+    ///
+    /// # Arguments
+    ///
+    /// * `asset_pair` - The trading pair.
+    /// * `start` - The time of the first trade, if any.
+    /// * `end` - The time of the last trade, if any.
+    ///
+    pub fn trades_of(
+        &self,
+        asset_pair: &AssetPair,
+        start: Option<f64>,
+        end: Option<f64>,
+    ) -> Vec<Trade> {
+        let mut trades: Vec<Trade> = self
+            .trades
+            .iter()
+            .filter(|event| event.applies_to(asset_pair) && within(event.time, start, end))
+            .map(|event| event.trade(&asset_pair.name))
+            .collect();
+        trades.sort_by(|a, b| a.time.total_cmp(&b.time));
+        trades
+    }
+
+    /// Returns the trades of the other trading pairs, by their own pair.
+    ///
+    /// # Arguments
+    ///
+    /// * `asset_pair` - The trading pair to leave out.
+    /// * `start` - The start of the period, if any.
+    /// * `end` - The end of the period, if any.
+    ///
+    pub fn other_trades(
+        &self,
+        asset_pair: &AssetPair,
+        start: Option<f64>,
+        end: Option<f64>,
+    ) -> Vec<Trade> {
+        self.trades
+            .iter()
+            .filter(|event| !event.applies_to(asset_pair) && within(event.time, start, end))
+            .map(|event| event.trade(&event.pair))
+            .collect()
+    }
+
+    /// Returns the ledger entries of an asset, by its name (e.g., XXBT), sorted
+    /// by time.
+    pub fn ledger_of(&self, asset: &str, start: Option<f64>, end: Option<f64>) -> Vec<LedgerEntry> {
+        let mut entries: Vec<LedgerEntry> = self
+            .ledger
+            .iter()
+            .filter(|event| event.asset == asset && within(event.time, start, end))
+            .map(LedgerEvent::entry)
+            .collect();
+        entries.sort_by(|a, b| a.time.total_cmp(&b.time));
+        entries
+    }
+}
+
+/// Quotes a value of a CSV file if needed.
+fn csv_value(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes a document, as CSV if the path ends in `.csv`, otherwise as JSON.
+pub fn write_document(document: &Document, path: &Path) -> Result<(), String> {
+    let content: String = if path.extension().is_some_and(|extension| extension == "csv") {
+        let mut lines: Vec<String> = vec![
+            format!("# {} {}", document.schema, document.version),
+            CSV_COLUMNS.join(","),
+        ];
+        for event in &document.trades {
+            let values: [&str; 24] = [
+                "trade",
+                &event.source,
+                &event.id,
+                &event.order_id,
+                "",
+                &event.time.to_string(),
+                &event.pair,
+                &event.base,
+                &event.quote,
+                "",
+                &event.side,
+                "",
+                "",
+                &event.amount,
+                &event.price,
+                &event.cost,
+                &event.fee,
+                &event.base_fee,
+                "",
+                &event.order_type,
+                &event.margin,
+                &event.misc,
+                &event.position_id,
+                &event.liquidity,
+            ];
+            lines.push(values.map(csv_value).join(","));
+        }
+        for event in &document.ledger {
+            let values: [&str; 24] = [
+                "ledger",
+                &event.source,
+                &event.id,
+                "",
+                &event.refid,
+                &event.time.to_string(),
+                "",
+                "",
+                "",
+                &event.asset,
+                "",
+                &event.event_type,
+                &event.subtype,
+                &event.amount,
+                "",
+                "",
+                &event.fee,
+                "",
+                &event.balance,
+                "",
+                "",
+                "",
+                "",
+                "",
+            ];
+            lines.push(values.map(csv_value).join(","));
+        }
+        lines.join("\n") + "\n"
+    } else {
+        serde_json::to_string_pretty(document)
+            .map_err(|e| format!("Failed to serialize the document: {}", e))?
+    };
+    fs::write(path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Checks that a document is of the schema and of a version this tool reads.
+fn check_version(schema: &str, version: u32, path: &Path) -> Result<(), String> {
+    if schema != SCHEMA {
+        return Err(format!(
+            "{} is of the schema '{}', expected '{}'!",
+            path.display(),
+            schema,
+            SCHEMA
+        ));
+    }
+    if version == 0 || version > SCHEMA_VERSION {
+        return Err(format!(
+            "{} is of version {} of the schema, this version of the tool reads up to version {}!",
+            path.display(),
+            version,
+            SCHEMA_VERSION
+        ));
+    }
+    Ok(())
+}
+
+/// Reads a CSV document.
+fn read_csv(content: &str, path: &Path) -> Result<Document, String> {
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty());
+    let (schema, version): (&str, u32) = lines
+        .next()
+        .and_then(|(_, line)| line.strip_prefix('#')?.trim().split_once(' '))
+        .and_then(|(schema, version)| Some((schema, version.trim().parse().ok()?)))
+        .ok_or_else(|| {
+            format!(
+                "{} lacks the line stating the schema, e.g. '# {} {}'!",
+                path.display(),
+                SCHEMA,
+                SCHEMA_VERSION
+            )
+        })?;
+    check_version(schema, version, path)?;
+    let header: Vec<String> = lines
+        .next()
+        .map(|(_, line)| split_line(line))
+        .ok_or_else(|| format!("{} lacks the header!", path.display()))?;
+
+    let mut document = Document {
+        version,
+        ..Document::default()
+    };
+    for (number, line) in lines {
+        let values: Vec<String> = split_line(line);
+        let row: HashMap<&str, &str> = header
+            .iter()
+            .map(String::as_str)
+            .zip(values.iter().map(String::as_str))
+            .collect();
+        let invalid = || format!("Invalid line {} in {}: {}", number, path.display(), line);
+        let get = |column: &str| {
+            row.get(column)
+                .map(|value| value.to_string())
+                .unwrap_or_default()
+        };
+        let time: f64 = get("time").parse().map_err(|_| invalid())?;
+        match get("record").as_str() {
+            "trade" => document.trades.push(TradeEvent {
+                source: get("source"),
+                id: get("id"),
+                order_id: get("order_id"),
+                pair: get("pair"),
+                base: get("base"),
+                quote: get("quote"),
+                time,
+                side: get("side"),
+                amount: get("amount"),
+                price: get("price"),
+                cost: get("cost"),
+                fee: get("fee"),
+                base_fee: get("base_fee"),
+                order_type: get("order_type"),
+                margin: get("margin"),
+                misc: get("misc"),
+                position_id: get("position_id"),
+                liquidity: get("liquidity"),
+            }),
+            "ledger" => document.ledger.push(LedgerEvent {
+                source: get("source"),
+                id: get("id"),
+                refid: get("refid"),
+                time,
+                asset: get("asset"),
+                event_type: get("type"),
+                subtype: get("subtype"),
+                amount: get("amount"),
+                fee: get("fee"),
+                balance: get("balance"),
+            }),
+            _ => {
+                return Err(format!(
+                    "{} (the record must be trade or ledger)",
+                    invalid()
+                ))
+            }
+        }
+    }
+    Ok(document)
+}
+
+/// Reads a document, as CSV if the path ends in `.csv`, otherwise as JSON.
+pub fn read_document(path: &Path) -> Result<Document, String> {
+    let content: String = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let document: Document = if path.extension().is_some_and(|extension| extension == "csv") {
+        read_csv(&content, path)?
+    } else {
+        let document: Document = serde_json::from_str(&content)
+            .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+        check_version(&document.schema, document.version, path)?;
+        document
+    };
+    for event in &document.trades {
+        if !matches!(event.side.as_str(), "buy" | "sell") {
+            return Err(format!(
+                "The trade {} in {} has the side '{}', expected buy or sell!",
+                event.id,
+                path.display(),
+                event.side
+            ));
+        }
+    }
+    Ok(document)
+}
+
+/// Converts the data of Kraken into a document.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client, used to look up the currency codes of
+///   the trading pairs. No credentials are required.
+/// * `cache` - The cache, which is converted if no inputs are passed.
+/// * `inputs` - The state files written by `state export` or recorded
+///   TradesHistory responses.
+///
+pub async fn convert_kraken(
+    api: &KrakenAPI,
+    cache: Option<&Cache>,
+    inputs: &[PathBuf],
+) -> Result<Document, String> {
+    let mut trades: Vec<Trade> = Vec::new();
+    let mut ledger: Vec<(String, LedgerEntry)> = Vec::new();
+    match cache {
+        Some(cache) => {
+            for pair in cache.pairs().map_err(|e| e.to_string())? {
+                trades.extend(
+                    cache
+                        .load_trades(&pair, None, None)
+                        .map_err(|e| e.to_string())?,
+                );
+            }
+            for asset in cache.ledger_assets().map_err(|e| e.to_string())? {
+                for entry in cache
+                    .load_ledger_entries(&asset, None, None)
+                    .map_err(|e| e.to_string())?
+                {
+                    ledger.push((asset.clone(), entry));
+                }
+            }
+        }
+        None => {
+            for path in inputs {
+                // The ledger is only part of state files.
+                let stored: String = fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                let content: String = upgrade(&stored, version_of(&stored))
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                if let Ok(state) = serde_json::from_str::<State>(&content) {
+                    for (asset, entries) in state.ledger {
+                        for record in entries {
+                            let entry = LedgerEntry {
+                                id: record.id,
+                                ..record.entry
+                            };
+                            ledger.push((asset.clone(), entry));
+                        }
+                    }
+                }
+                trades.extend(load_fixture(path)?.into_values().flatten());
+            }
+        }
+    }
+
+    // The currency codes let other tools match the pairs without knowing
+    // the names of Kraken.
+    let aliases: PairAliases = fetch_pair_aliases(api).await.unwrap_or_else(|e| {
+        warn!("Not stating the currency codes of the pairs: {}", e);
+        PairAliases::default()
+    });
+    Ok(Document::from_kraken(
+        &trades,
+        ledger.iter().map(|(asset, entry)| (asset.as_str(), entry)),
+        |trade| aliases.get(&trade.pair),
+    ))
+}
+
+/// Converts the CSV exports of another exchange into a document.
+///
+/// # Arguments
+///
+/// * `exchange` - The exchange, one of `imports::EXCHANGES`.
+/// * `inputs` - The CSV exports.
+///
+pub fn convert_exchange(exchange: &str, inputs: &[PathBuf]) -> Result<Document, String> {
+    let importer = importer(exchange)?;
+    let mut document: Document = Document::default();
+    for path in inputs {
+        document.trades.extend(
+            read_import(importer.as_ref(), path)?
+                .iter()
+                .map(|trade| TradeEvent::from_import(trade, exchange)),
+        );
+    }
+    document.trades.sort_by(|a, b| a.time.total_cmp(&b.time));
+    Ok(document)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a trade of Kraken.
+    fn trade() -> Trade {
+        Trade {
+            txid: "TQ4DAF-5DRI7-3TTV2Y".to_string(),
+            ordertxid: "OQCLML-BW3P3-BUCMWZ".to_string(),
+            pair: "XXBTZEUR".to_string(),
+            time: 1704345880.1234,
+            side: "buy".to_string(),
+            price: "40000.0".to_string(),
+            fee: "1.6".to_string(),
+            vol: "0.025".to_string(),
+            cost: "1000.0".to_string(),
+            ordertype: "limit".to_string(),
+            margin: "0.0".to_string(),
+            misc: "".to_string(),
+            maker: Some(true),
+            ..Default::default()
+        }
+    }
+
+    /// Returns a document of a trade and a ledger entry.
+    fn document() -> Document {
+        let entry: LedgerEntry = LedgerEntry {
+            id: "L4UESK-KG3EQ-UFO4T5".to_string(),
+            refid: "TQ4DAF-5DRI7-3TTV2Y".to_string(),
+            time: 1704345880.1234,
+            entry_type: "trade".to_string(),
+            subtype: "".to_string(),
+            amount: "-1001.6".to_string(),
+            fee: "1.6".to_string(),
+            balance: "0.0".to_string(),
+        };
+        Document {
+            trades: vec![TradeEvent::from_trade(&trade(), None)],
+            ledger: vec![LedgerEvent::from_entry("ZEUR", &entry)],
+            ..Document::default()
+        }
+    }
+
+    /// Writes a document and reads it again.
+    fn write_and_read(document: &Document, name: &str) -> Document {
+        let path: PathBuf = std::env::temp_dir().join(name);
+        write_document(document, &path).unwrap();
+        let read: Document = read_document(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        read
+    }
+
+    #[test]
+    fn trades_keep_their_fields() {
+        let event: TradeEvent = TradeEvent::from_trade(&trade(), None);
+        assert_eq!(event.source, "kraken");
+        assert_eq!(event.liquidity, "maker");
+        let converted: Trade = event.trade("XXBTZEUR");
+        assert_eq!(converted.txid, trade().txid);
+        assert_eq!(converted.vol, trade().vol);
+        assert_eq!(converted.maker, Some(true));
+        assert_eq!(converted.source, None);
+    }
+
+    #[test]
+    fn documents_round_trip_as_json_and_csv() {
+        for name in [
+            "kraken-pnl-normalized-test.json",
+            "kraken-pnl-normalized-test.csv",
+        ] {
+            let read: Document = write_and_read(&document(), name);
+            assert_eq!(read.version, SCHEMA_VERSION, "{}", name);
+            assert_eq!(read.trades.len(), 1, "{}", name);
+            assert_eq!(read.trades[0].time, 1704345880.1234, "{}", name);
+            assert_eq!(read.trades[0].amount, "0.025", "{}", name);
+            assert_eq!(read.ledger.len(), 1, "{}", name);
+            assert_eq!(read.ledger[0].entry().amount, "-1001.6", "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_newer_versions_and_other_schemas() {
+        let path: PathBuf = std::env::temp_dir().join("kraken-pnl-normalized-version-test.json");
+        for (schema, version) in [(SCHEMA, SCHEMA_VERSION + 1), ("other", SCHEMA_VERSION)] {
+            let document: Document = Document {
+                schema: schema.to_string(),
+                version,
+                ..document()
+            };
+            write_document(&document, &path).unwrap();
+            assert!(read_document(&path).is_err(), "{} {}", schema, version);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn applies_to_the_pair_by_name_or_currencies() {
+        let asset_pair: AssetPair = AssetPair {
+            name: "XXBTZEUR".to_string(),
+            base: "XXBT".to_string(),
+            quote: "ZEUR".to_string(),
+            altname: "XBTEUR".to_string(),
+            wsname: "XBT/EUR".to_string(),
+        };
+        let mut event: TradeEvent = TradeEvent::from_trade(&trade(), None);
+        assert!(event.applies_to(&asset_pair));
+        event.pair = "BTCEUR".to_string();
+        assert!(!event.applies_to(&asset_pair));
+        event.base = "BTC".to_string();
+        event.quote = "EUR".to_string();
+        assert!(event.applies_to(&asset_pair));
+    }
+}
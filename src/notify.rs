@@ -0,0 +1,528 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module sends the PnL of the trading pairs of a run as notifications via
+`--notify`, and alerts in the watch and stream modes when the realized PnL of
+a trading pair rises above a threshold or its unrealized PnL falls below a
+stop level. The notifications are posted to a webhook, sent via a Telegram
+bot, or sent via email, as configured in the `[notify]` section of the
+configuration file, and the alerts are always logged.
+
+A threshold alerts once when it is crossed, including the first iteration if
+it is crossed already, and again only after the PnL went back in between, so a
+PnL hovering around it does not alert on every iteration.
+
+The text of the notifications can be customized by a template, for every
+channel or for each of them, whose placeholders like `{symbol}` are replaced
+by the fields of the notification (see `FIELDS`). Braces are escaped by doubling
+them. The templates are checked when the notifier is created, so an unknown
+placeholder fails the start of the run instead of its end.
+*/
+
+use crate::config::{EmailConfig, NotifyConfig};
+use crate::report::PortfolioEntry;
+use chrono::Utc;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::transport::smtp::response::Response;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+
+/// The channels a template can be configured for.
+const CHANNELS: [&str; 3] = ["webhook", "telegram", "email"];
+
+/// The placeholders of the templates.
+const FIELDS: [&str; 15] = [
+    "text",
+    "symbol",
+    "metric",
+    "value",
+    "threshold",
+    "currency",
+    "realized_pnl",
+    "realized_currency",
+    "unrealized_pnl",
+    "unrealized_currency",
+    "balance",
+    "base_currency",
+    "fees",
+    "warnings",
+    "time",
+];
+
+/// A crossed threshold of a trading pair.
+struct Alert<'a> {
+    metric: &'static str,
+    value: f64,
+    threshold: f64,
+    currency: &'a str,
+}
+
+/// A notification about a trading pair.
+struct Notification<'a> {
+    entry: &'a PortfolioEntry,
+    /// The crossed threshold, if the notification is an alert.
+    alert: Option<Alert<'a>>,
+    /// The default text of the notification.
+    text: String,
+}
+
+impl Notification<'_> {
+    /// Returns the value of a placeholder of the templates.
+    fn field(&self, name: &str) -> String {
+        let entry: &PortfolioEntry = self.entry;
+        match (name, &self.alert) {
+            ("text", _) => self.text.clone(),
+            ("symbol", _) => entry.symbol.clone(),
+            ("metric", Some(alert)) => alert.metric.to_string(),
+            ("value", Some(alert)) => entry.precision.fmt_quote(alert.value),
+            ("threshold", Some(alert)) => alert.threshold.to_string(),
+            ("currency", Some(alert)) => alert.currency.to_string(),
+            // The run summaries have no threshold.
+            ("metric" | "value" | "threshold" | "currency", None) => String::new(),
+            ("realized_pnl", _) => entry.precision.fmt_quote(entry.realized_pnl),
+            ("realized_currency", _) => entry.realized_currency.clone(),
+            ("unrealized_pnl", _) => entry.precision.fmt_quote(entry.unrealized_pnl),
+            ("unrealized_currency", _) => entry.unrealized_currency.clone(),
+            ("balance", _) => entry.precision.fmt_base(entry.balance),
+            ("base_currency", _) => entry.base_currency.clone(),
+            ("fees", _) => entry.precision.fmt_quote(entry.fees),
+            ("warnings", _) => entry.warnings.join("; "),
+            ("time", _) => Utc::now().to_rfc3339(),
+            _ => unreachable!("The templates are checked when they are parsed."),
+        }
+    }
+}
+
+/// A part of a template.
+#[derive(Debug)]
+enum Part {
+    Text(String),
+    Field(String),
+}
+
+/// The text of a notification with placeholders.
+#[derive(Debug)]
+struct Template(Vec<Part>);
+
+impl Template {
+    /// Parses a template and checks its placeholders.
+    fn parse(template: &str) -> Result<Self, String> {
+        let mut parts: Vec<Part> = Vec::new();
+        let mut text: String = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name: String = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => {
+                                return Err(format!(
+                                    "Unclosed placeholder {{{} in the notification template!",
+                                    name
+                                ))
+                            }
+                        }
+                    }
+                    if !FIELDS.contains(&name.as_str()) {
+                        return Err(format!(
+                            "Unknown placeholder {{{}}} in the notification template, expected one of {}!",
+                            name,
+                            FIELDS.join(", ")
+                        ));
+                    }
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                    parts.push(Part::Field(name));
+                }
+                '}' => {
+                    return Err(
+                        "Unmatched } in the notification template, write }} instead!".to_string(),
+                    )
+                }
+                c => text.push(c),
+            }
+        }
+        parts.push(Part::Text(text));
+        Ok(Self(parts))
+    }
+
+    /// Returns the text of a notification.
+    fn render(&self, notification: &Notification) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Field(name) => notification.field(name),
+            })
+            .collect()
+    }
+}
+
+/// Sends the notifications via the configured channels.
+pub struct Notifier {
+    webhook_url: Option<String>,
+    telegram: Option<(String, String)>,
+    email: Option<(EmailConfig, Option<String>)>,
+    /// The templates by channel, falling back to the one of every channel and
+    /// then to the default text.
+    templates: HashMap<&'static str, Template>,
+    client: reqwest::Client,
+}
+
+impl Notifier {
+    /// Creates the notifier of the configured channels.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The `[notify]` section of the configuration file.
+    ///
+    pub fn new(config: &NotifyConfig) -> Result<Self, String> {
+        if config.webhook_url.is_none()
+            && config.telegram_chat_id.is_none()
+            && config.email.is_none()
+        {
+            return Err(
+                "--notify requires a webhook_url, telegram_chat_id or email in the [notify] section of the configuration file!"
+                    .to_string(),
+            );
+        }
+        Self::channels(config)
+    }
+
+    /// Creates the notifier of the configured channels, which may be none.
+    fn channels(config: &NotifyConfig) -> Result<Self, String> {
+        let telegram: Option<(String, String)> = match &config.telegram_chat_id {
+            Some(chat_id) => {
+                let token: String = std::env::var(&config.telegram_token_env).map_err(|_| {
+                    format!(
+                        "The Telegram bot token must be set via {}!",
+                        config.telegram_token_env
+                    )
+                })?;
+                Some((token, chat_id.clone()))
+            }
+            None => None,
+        };
+        let email: Option<(EmailConfig, Option<String>)> = match &config.email {
+            Some(email) => {
+                if email.server.is_empty() || email.from.is_empty() || email.to.is_empty() {
+                    return Err(
+                        "The email notifications require a server, from and to!".to_string()
+                    );
+                }
+                if email.username.is_some() && !email.tls && !email.starttls {
+                    return Err(
+                        "Refusing to send the SMTP password over a plain connection, enable tls or starttls!"
+                            .to_string(),
+                    );
+                }
+                let password: Option<String> = match &email.username {
+                    Some(_) => Some(std::env::var(&email.password_env).map_err(|_| {
+                        format!("The SMTP password must be set via {}!", email.password_env)
+                    })?),
+                    None => None,
+                };
+                Some((email.clone(), password))
+            }
+            None => None,
+        };
+        if let Some(channel) = config
+            .templates
+            .keys()
+            .find(|channel| !CHANNELS.contains(&channel.as_str()))
+        {
+            return Err(format!(
+                "Unknown channel {} of a notification template, expected one of {}!",
+                channel,
+                CHANNELS.join(", ")
+            ));
+        }
+        let mut templates: HashMap<&'static str, Template> = HashMap::new();
+        for channel in CHANNELS {
+            if let Some(template) = config.templates.get(channel).or(config.template.as_ref()) {
+                templates.insert(channel, Template::parse(template)?);
+            }
+        }
+        Ok(Self {
+            webhook_url: config.webhook_url.clone(),
+            telegram,
+            email,
+            templates,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Sends the PnL of a trading pair. Failing channels are logged, but
+    /// don't fail the run.
+    ///
+    /// # Arguments
+    ///
+    /// * `entry` - The PnL of the trading pair.
+    ///
+    pub async fn notify(&self, entry: &PortfolioEntry) {
+        let notification: Notification = Notification {
+            entry,
+            alert: None,
+            text: format!(
+                "{}: the realized PnL is {} {}, the unrealized PnL {} {} at a balance of {} {}",
+                entry.symbol,
+                entry.precision.fmt_quote(entry.realized_pnl),
+                entry.realized_currency,
+                entry.precision.fmt_quote(entry.unrealized_pnl),
+                entry.unrealized_currency,
+                entry.precision.fmt_base(entry.balance),
+                entry.base_currency
+            ),
+        };
+        info!("{}", notification.text);
+        if let Err(e) = self.send(&notification).await {
+            warn!("Failed to send the notification: {}", e);
+        }
+    }
+
+    /// Returns the text of a notification for a channel.
+    fn text(&self, channel: &str, notification: &Notification) -> String {
+        match self.templates.get(channel) {
+            Some(template) => template.render(notification),
+            None => notification.text.clone(),
+        }
+    }
+
+    /// Sends a notification via every configured channel.
+    async fn send(&self, notification: &Notification<'_>) -> Result<(), String> {
+        let mut errors: Vec<String> = Vec::new();
+        if let Some(url) = &self.webhook_url {
+            let entry: &PortfolioEntry = notification.entry;
+            let mut payload: Value = json!({
+                "text": self.text("webhook", notification),
+                "symbol": entry.symbol,
+                "realized_pnl": entry.realized_pnl,
+                "unrealized_pnl": entry.unrealized_pnl,
+                "balance": entry.balance,
+                "warnings": entry.warnings,
+            });
+            if let Some(alert) = &notification.alert {
+                payload["metric"] = json!(alert.metric);
+                payload["value"] = json!(alert.value);
+                payload["threshold"] = json!(alert.threshold);
+            }
+            if let Err(e) = self.post(url, &payload).await {
+                errors.push(format!("webhook: {}", e));
+            }
+        }
+        if let Some((token, chat_id)) = &self.telegram {
+            let url: String = format!("https://api.telegram.org/bot{}/sendMessage", token);
+            let payload: Value =
+                json!({"chat_id": chat_id, "text": self.text("telegram", notification)});
+            if let Err(e) = self.post(&url, &payload).await {
+                // The URL contains the token, which must not end up in the log.
+                errors.push(format!("Telegram: {}", e.replace(token.as_str(), "***")));
+            }
+        }
+        if let Some((email, password)) = &self.email {
+            let text: String = self.text("email", notification);
+            if let Err(e) = send_email(email, password.as_deref(), &text).await {
+                errors.push(format!("email: {}", e));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join(", "))
+        }
+    }
+
+    /// Posts a JSON payload.
+    async fn post(&self, url: &str, payload: &Value) -> Result<(), String> {
+        let response = self
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header("User-Agent", env!("CARGO_PKG_NAME"))
+            .body(payload.to_string())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("responded with {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+/// Sends the alerts of the thresholds in the watch and stream modes.
+pub struct Thresholds {
+    realized_above: Option<f64>,
+    unrealized_below: Option<f64>,
+    notifier: Notifier,
+    /// The trading pairs and metrics whose threshold is crossed.
+    breached: HashSet<(String, &'static str)>,
+}
+
+impl Thresholds {
+    /// Creates the alerts of the configured thresholds, which are only logged
+    /// if no channel is configured.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The `[notify]` section of the configuration file.
+    ///
+    /// # Returns
+    ///
+    /// The alerts, or `None` if no threshold is configured.
+    ///
+    pub fn new(config: &NotifyConfig) -> Result<Option<Self>, String> {
+        if config.realized_above.is_none() && config.unrealized_below.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            realized_above: config.realized_above,
+            unrealized_below: config.unrealized_below,
+            notifier: Notifier::channels(config)?,
+            breached: HashSet::new(),
+        }))
+    }
+
+    /// Checks the thresholds against the PnL of an iteration and sends the
+    /// alerts of those that were crossed since the previous one. Failing
+    /// channels are logged, but don't stop the watch mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `entries` - The PnL of the trading pairs.
+    ///
+    pub async fn notify(&mut self, entries: &[PortfolioEntry]) {
+        let mut notifications: Vec<Notification> = Vec::new();
+        for entry in entries {
+            if let Some(threshold) = self.realized_above {
+                let breached: bool = entry.realized_pnl > threshold;
+                if self.crossed(&entry.symbol, "realized_pnl", breached) {
+                    notifications.push(Notification {
+                        entry,
+                        alert: Some(Alert {
+                            metric: "realized_pnl",
+                            value: entry.realized_pnl,
+                            threshold,
+                            currency: &entry.realized_currency,
+                        }),
+                        text: format!(
+                            "{}: the realized PnL of {} {} rose above {} {}",
+                            entry.symbol,
+                            entry.precision.fmt_quote(entry.realized_pnl),
+                            entry.realized_currency,
+                            threshold,
+                            entry.realized_currency
+                        ),
+                    });
+                }
+            }
+            if let Some(threshold) = self.unrealized_below {
+                let breached: bool = entry.unrealized_pnl < threshold;
+                if self.crossed(&entry.symbol, "unrealized_pnl", breached) {
+                    notifications.push(Notification {
+                        entry,
+                        alert: Some(Alert {
+                            metric: "unrealized_pnl",
+                            value: entry.unrealized_pnl,
+                            threshold,
+                            currency: &entry.unrealized_currency,
+                        }),
+                        text: format!(
+                            "{}: the unrealized PnL of {} {} fell below the stop level of {} {}",
+                            entry.symbol,
+                            entry.precision.fmt_quote(entry.unrealized_pnl),
+                            entry.unrealized_currency,
+                            threshold,
+                            entry.unrealized_currency
+                        ),
+                    });
+                }
+            }
+        }
+        for notification in notifications {
+            warn!("{}", notification.text);
+            if let Err(e) = self.notifier.send(&notification).await {
+                warn!("Failed to send the notification: {}", e);
+            }
+        }
+    }
+
+    /// Records the state of a threshold and returns whether it was crossed
+    /// since the previous iteration.
+    fn crossed(&mut self, symbol: &str, metric: &'static str, breached: bool) -> bool {
+        let key: (String, &'static str) = (symbol.to_string(), metric);
+        if breached {
+            self.breached.insert(key)
+        } else {
+            self.breached.remove(&key);
+            false
+        }
+    }
+}
+
+/// Sends a notification via email.
+///
+/// # Arguments
+///
+/// * `email` - The SMTP server and the addresses.
+/// * `password` - The password of the user, if the server requires a login.
+/// * `text` - The notification, whose first line is also the subject.
+///
+async fn send_email(email: &EmailConfig, password: Option<&str>, text: &str) -> Result<(), String> {
+    let mailbox = |address: &str| {
+        address
+            .parse::<Mailbox>()
+            .map_err(|e| format!("Invalid address '{}': {}", address, e))
+    };
+    let mut builder = Message::builder()
+        .from(mailbox(&email.from)?)
+        .subject(text.lines().next().unwrap_or_default())
+        .header(ContentType::TEXT_PLAIN);
+    for to in &email.to {
+        builder = builder.to(mailbox(to)?);
+    }
+    let message: Message = builder
+        .body(text.to_string())
+        .map_err(|e| format!("Failed to build the email: {}", e))?;
+
+    let parameters = || {
+        TlsParameters::new(email.server.clone()).map_err(|e| format!("Failed to set up TLS: {}", e))
+    };
+    let tls: Tls = if email.starttls {
+        Tls::Required(parameters()?)
+    } else if email.tls {
+        Tls::Wrapper(parameters()?)
+    } else {
+        Tls::None
+    };
+    let mut transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&email.server)
+        .port(email.port)
+        .tls(tls);
+    if let (Some(username), Some(password)) = (&email.username, password) {
+        transport = transport.credentials(Credentials::new(username.clone(), password.to_string()));
+    }
+    let response: Response = transport
+        .build()
+        .send(message)
+        .await
+        .map_err(|e| e.to_string())?;
+    debug!("SMTP <- {:?}", response.message().collect::<Vec<&str>>());
+    Ok(())
+}
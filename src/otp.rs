@@ -0,0 +1,139 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module provides the second factor of API keys with two-factor
+authentication enabled, which Kraken expects as the `otp` field of every
+private request. It is either a static password, or a time-based one-time
+password (TOTP, RFC 6238) generated from the secret shown when the second
+factor was set up, so it never has to be typed in during long runs.
+*/
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+/// The seconds a time-based one-time password is valid for.
+const TOTP_STEP: u64 = 30;
+
+/// The second factor of an API key.
+#[derive(Clone)]
+pub enum Otp {
+    /// A static password, or a code entered by the user.
+    Static(String),
+    /// The decoded secret of a time-based one-time password.
+    Totp(Vec<u8>),
+}
+
+impl Otp {
+    /// Creates the second factor of a TOTP secret.
+    ///
+    /// # Arguments
+    ///
+    /// * `secret` - The secret in base32, as shown when setting up the second
+    ///   factor (e.g., JBSWY3DPEHPK3PXP). Spaces and padding are ignored.
+    ///
+    pub fn totp(secret: &str) -> Result<Self, String> {
+        let secret: Vec<u8> = decode_base32(secret)
+            .filter(|secret| !secret.is_empty())
+            .ok_or_else(|| "The TOTP secret is not valid base32!".to_string())?;
+        Ok(Otp::Totp(secret))
+    }
+
+    /// Returns the password of a request sent now.
+    pub fn code(&self) -> String {
+        match self {
+            Otp::Static(password) => password.clone(),
+            Otp::Totp(secret) => totp_code(secret, chrono::Utc::now().timestamp() as u64),
+        }
+    }
+}
+
+/// Decodes base32 (RFC 4648) case-insensitively.
+fn decode_base32(value: &str) -> Option<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut buffer: u64 = 0;
+    let mut bits: u32 = 0;
+    for c in value.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let digit: u64 = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u64 - 'A' as u64,
+            c @ '2'..='7' => c as u64 - '2' as u64 + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | digit;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+/// Computes the time-based one-time password of six digits.
+///
+/// # Arguments
+///
+/// * `secret` - The decoded secret.
+/// * `time` - The UNIX timestamp of the request.
+///
+fn totp_code(secret: &[u8], time: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).unwrap();
+    mac.update(&(time / TOTP_STEP).to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    // The dynamic truncation of RFC 4226.
+    let offset: usize = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value: u32 = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    format!("{:06}", value % 1_000_000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The secret of the test vectors of RFC 6238 for HMAC-SHA1.
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn matches_the_test_vectors_of_rfc_6238() {
+        // The RFC lists eight digits, of which the last six are the code.
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ] {
+            assert_eq!(totp_code(SECRET, time), code, "{}", time);
+        }
+    }
+
+    #[test]
+    fn decodes_base32() {
+        assert_eq!(
+            decode_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").as_deref(),
+            Some(SECRET)
+        );
+        // Case, spaces and padding don't matter.
+        assert_eq!(
+            decode_base32("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").as_deref(),
+            Some(SECRET)
+        );
+        assert_eq!(decode_base32("MZXW6===").as_deref(), Some(&b"foo"[..]));
+        assert_eq!(decode_base32("MZXW6YQ1"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_secrets() {
+        assert!(Otp::totp("GEZDGNBVGY3TQOJQ").is_ok());
+        assert!(Otp::totp("not base32!").is_err());
+        assert!(Otp::totp("").is_err());
+    }
+}
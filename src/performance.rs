@@ -0,0 +1,153 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module computes trading performance statistics from the per-lot disposals,
+e.g. the win rate and the profit factor. Every disposal, i.e. the (partial)
+sale of a lot, counts as a closed position, while a round trip is a sell order
+that closed one or more lots.
+*/
+
+use crate::fifo::Disposal;
+use crate::precision::Precision;
+use std::collections::HashSet;
+
+/// The performance statistics of the disposals of a trading pair.
+#[derive(Debug, Default)]
+pub struct Performance {
+    pub disposals: usize,
+    pub winning: usize,
+    pub losing: usize,
+    /// The sum of the gains of the winning disposals.
+    pub gross_profit: f64,
+    /// The sum of the (negative) gains of the losing disposals.
+    pub gross_loss: f64,
+    pub largest_gain: f64,
+    pub largest_loss: f64,
+    /// The average time between acquisition and disposal in days.
+    pub average_holding_days: f64,
+    /// The number of sell orders that closed one or more lots.
+    pub round_trips: usize,
+}
+
+impl Performance {
+    /// Returns the share of winning disposals as a percentage.
+    pub fn win_rate(&self) -> f64 {
+        if self.disposals == 0 {
+            0f64
+        } else {
+            self.winning as f64 / self.disposals as f64 * 100f64
+        }
+    }
+
+    /// Returns the average gain of the winning disposals.
+    pub fn average_gain(&self) -> f64 {
+        if self.winning == 0 {
+            0f64
+        } else {
+            self.gross_profit / self.winning as f64
+        }
+    }
+
+    /// Returns the average loss of the losing disposals.
+    pub fn average_loss(&self) -> f64 {
+        if self.losing == 0 {
+            0f64
+        } else {
+            self.gross_loss / self.losing as f64
+        }
+    }
+
+    /// Returns the gross profit divided by the absolute gross loss, or `None`
+    /// if nothing was lost.
+    pub fn profit_factor(&self) -> Option<f64> {
+        (self.gross_loss != 0f64).then(|| self.gross_profit / self.gross_loss.abs())
+    }
+}
+
+/// Computes the performance statistics of the disposals.
+///
+/// # Arguments
+///
+/// * `disposals` - The per-lot disposals.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn compute_performance(disposals: &[Disposal], precision: &Precision) -> Performance {
+    let mut performance: Performance = Performance {
+        disposals: disposals.len(),
+        ..Default::default()
+    };
+    let mut holding_days: Vec<f64> = Vec::new();
+    let mut orders: HashSet<&str> = HashSet::new();
+    for disposal in disposals {
+        if disposal.gain > 0f64 {
+            performance.winning += 1;
+            performance.gross_profit = precision.quote(performance.gross_profit + disposal.gain);
+            performance.largest_gain = performance.largest_gain.max(disposal.gain);
+        } else if disposal.gain < 0f64 {
+            performance.losing += 1;
+            performance.gross_loss = precision.quote(performance.gross_loss + disposal.gain);
+            performance.largest_loss = performance.largest_loss.min(disposal.gain);
+        }
+        // Short lots are disposed of before they are acquired.
+        if let Some(acquisition_time) = disposal.acquisition_time {
+            holding_days.push((disposal.disposal_time - acquisition_time).abs() / 86400f64);
+        }
+        orders.insert(&disposal.disposal_ordertxid);
+    }
+    if !holding_days.is_empty() {
+        performance.average_holding_days =
+            holding_days.iter().sum::<f64>() / holding_days.len() as f64;
+    }
+    performance.round_trips = orders.len();
+    performance
+}
+
+/// Prints the performance statistics.
+///
+/// # Arguments
+///
+/// * `performance` - The performance statistics.
+/// * `quote` - The quote currency the gains are valued in.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn print_performance(performance: &Performance, quote: &str, precision: &Precision) {
+    println!("Trading performance:");
+    println!(
+        "  Disposals: {} ({} winning, {} losing) in {} round trips",
+        performance.disposals, performance.winning, performance.losing, performance.round_trips
+    );
+    println!("  Win rate: {:.2}%", performance.win_rate());
+    println!(
+        "  Average gain per winning disposal: {} {}",
+        precision.fmt_quote(performance.average_gain()),
+        quote
+    );
+    println!(
+        "  Average loss per losing disposal: {} {}",
+        precision.fmt_quote(performance.average_loss()),
+        quote
+    );
+    println!(
+        "  Largest gain: {} {}",
+        precision.fmt_quote(performance.largest_gain),
+        quote
+    );
+    println!(
+        "  Largest loss: {} {}",
+        precision.fmt_quote(performance.largest_loss),
+        quote
+    );
+    println!(
+        "  Average holding period: {:.1} days",
+        performance.average_holding_days
+    );
+    println!(
+        "  Profit factor: {}",
+        performance
+            .profit_factor()
+            .map(|factor| format!("{:.2}", factor))
+            .unwrap_or_else(|| "n/a (no losses)".to_string())
+    );
+}
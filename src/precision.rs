@@ -0,0 +1,326 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module provides the native precision of the assets of a trading pair, as
+published by Kraken via the `/0/public/AssetPairs` and `/0/public/Assets`
+endpoints. Amounts of the base currency and values in the quote currency are
+rounded to the precision of the respective asset, both in the lot arithmetic
+and in the reports.
+
+Kraken refers to a pair by different names depending on the API, e.g.
+XXBTZEUR, XBTEUR or XBT/EUR, which users may also know as BTC/EUR. All of them
+are mapped to the name of the pair via the listing of all pairs.
+*/
+
+use crate::kraken::KrakenAPI;
+use log::error;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// The base and quote asset of a trading pair.
+#[derive(Deserialize, Debug, Clone)]
+pub struct AssetPair {
+    /// The name of the pair, e.g. XXBTZEUR.
+    #[serde(skip)]
+    pub name: String,
+    pub base: String,
+    pub quote: String,
+    /// The alternative name of the pair (e.g., XBTEUR).
+    #[serde(default)]
+    pub altname: String,
+    /// The name of the pair used by the WebSocket API (e.g., XBT/EUR).
+    #[serde(default)]
+    pub wsname: String,
+}
+
+impl AssetPair {
+    /// Returns the common currency code of the base asset (e.g., BTC).
+    pub fn base_code(&self) -> String {
+        self.currency_code(0, &self.base)
+    }
+
+    /// Returns the common currency code of the quote asset (e.g., EUR).
+    pub fn quote_code(&self) -> String {
+        self.currency_code(1, &self.quote)
+    }
+
+    /// Returns the pair with swapped base and quote asset, which keeps the
+    /// name of the traded pair.
+    pub fn inverted(&self) -> Self {
+        let wsname: String = match self.wsname.split_once('/') {
+            Some((base, quote)) => format!("{}/{}", quote, base),
+            None => String::new(),
+        };
+        Self {
+            name: self.name.clone(),
+            base: self.quote.clone(),
+            quote: self.base.clone(),
+            altname: self.altname.clone(),
+            wsname,
+        }
+    }
+
+    /// Returns the names the pair is known by: its name, the alternative and
+    /// the WebSocket name, and the latter with common currency codes, with
+    /// and without the slash (e.g., BTC/EUR and BTCEUR).
+    pub fn aliases(&self) -> Vec<String> {
+        let common: String = format!("{}/{}", self.base_code(), self.quote_code());
+        let mut aliases: Vec<String> = vec![
+            self.name.clone(),
+            self.altname.clone(),
+            self.wsname.clone(),
+            common.replace('/', ""),
+            common,
+        ];
+        aliases.retain(|alias| !alias.is_empty());
+        aliases.dedup();
+        aliases
+    }
+
+    fn currency_code(&self, index: usize, fallback: &str) -> String {
+        match self.wsname.split('/').nth(index) {
+            Some("XBT") => "BTC".to_string(),
+            Some("XDG") => "DOGE".to_string(),
+            Some(code) => code.to_string(),
+            None => fallback.to_string(),
+        }
+    }
+}
+
+/// The trading pairs listed by Kraken and the names they are known by.
+#[derive(Debug, Default)]
+pub struct PairAliases {
+    pairs: HashMap<String, AssetPair>,
+    /// The name of the pair by its upper-case alias.
+    names: HashMap<String, String>,
+}
+
+impl PairAliases {
+    /// Returns the name of the pair a symbol refers to (e.g., XXBTZEUR for
+    /// BTC/EUR), if any. The symbol is matched regardless of its case.
+    pub fn canonical(&self, symbol: &str) -> Option<&str> {
+        self.names.get(&symbol.to_uppercase()).map(String::as_str)
+    }
+
+    /// Returns the pair a symbol refers to, if any.
+    pub fn get(&self, symbol: &str) -> Option<&AssetPair> {
+        self.canonical(symbol).and_then(|name| self.pairs.get(name))
+    }
+}
+
+/// Fetches all trading pairs and maps the names they are known by to their
+/// name.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+///
+pub async fn fetch_pair_aliases(api: &KrakenAPI) -> Result<PairAliases, String> {
+    let response: PublicResponse<AssetPair> =
+        serde_json::from_str(&api.public_request("/0/public/AssetPairs", vec![]).await)
+            .map_err(|e| format!("Failed to parse response: {}", e))?;
+    let pairs: HashMap<String, AssetPair> = response
+        .result
+        .ok_or_else(|| format!("Error fetching the trading pairs: {:?}", response.error))?
+        .into_iter()
+        .map(|(name, pair)| (name.clone(), AssetPair { name, ..pair }))
+        .collect();
+    let mut names: HashMap<String, String> = HashMap::new();
+    // The names of the pairs take precedence over the aliases of others.
+    for pair in pairs.values() {
+        for alias in pair.aliases().into_iter().skip(1) {
+            names
+                .entry(alias.to_uppercase())
+                .or_insert_with(|| pair.name.clone());
+        }
+    }
+    for name in pairs.keys() {
+        names.insert(name.to_uppercase(), name.clone());
+    }
+    Ok(PairAliases { pairs, names })
+}
+
+#[derive(Deserialize, Debug)]
+struct AssetInfo {
+    decimals: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct PublicResponse<T> {
+    error: Vec<String>,
+    result: Option<HashMap<String, T>>,
+}
+
+/// The number of decimals of the base and quote currency of a trading pair.
+#[derive(Debug, Clone, Copy)]
+pub struct Precision {
+    pub base: u32,
+    pub quote: u32,
+}
+
+impl Precision {
+    /// Rounds an amount of the base currency.
+    pub fn base(&self, value: f64) -> f64 {
+        round(value, self.base)
+    }
+
+    /// Rounds a value in the quote currency.
+    pub fn quote(&self, value: f64) -> f64 {
+        round(value, self.quote)
+    }
+
+    /// Formats an amount of the base currency.
+    pub fn fmt_base(&self, value: f64) -> String {
+        format!("{:.*}", self.base as usize, value)
+    }
+
+    /// Formats a value in the quote currency.
+    pub fn fmt_quote(&self, value: f64) -> String {
+        format!("{:.*}", self.quote as usize, value)
+    }
+
+    /// Returns the precision with swapped base and quote currency.
+    pub fn inverted(&self) -> Self {
+        Self {
+            base: self.quote,
+            quote: self.base,
+        }
+    }
+}
+
+fn round(value: f64, decimals: u32) -> f64 {
+    let factor: f64 = 10f64.powi(decimals as i32);
+    let rounded: f64 = (value * factor).round() / factor;
+    // Avoids reporting "-0" for values that are rounded to zero.
+    if rounded == 0f64 {
+        0f64
+    } else {
+        rounded
+    }
+}
+
+/// Parses a response of a public endpoint.
+fn parse_response<T: for<'de> Deserialize<'de>>(response: &str) -> HashMap<String, T> {
+    let response: PublicResponse<T> =
+        serde_json::from_str(response).expect("Failed to parse response!");
+    match response.result {
+        Some(result) if response.error.is_empty() => result,
+        _ => {
+            error!("Error fetching asset information: {:?}", response.error);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Looks up the base and quote asset of a trading pair.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `symbol` - The trading pair symbol (e.g., XXBTZEUR).
+///
+pub async fn fetch_asset_pair(api: &KrakenAPI, symbol: &str) -> AssetPair {
+    try_fetch_asset_pair(api, symbol).await.unwrap_or_else(|| {
+        error!("Unknown trading pair: {}", symbol);
+        std::process::exit(1);
+    })
+}
+
+/// Looks up the base and quote asset of a trading pair, returning `None` if
+/// Kraken does not know the pair.
+pub async fn try_fetch_asset_pair(api: &KrakenAPI, symbol: &str) -> Option<AssetPair> {
+    let response: PublicResponse<AssetPair> = serde_json::from_str(
+        &api.public_request("/0/public/AssetPairs", vec![("pair", symbol.to_string())])
+            .await,
+    )
+    .expect("Failed to parse response!");
+    response
+        .result?
+        .into_iter()
+        .next()
+        .map(|(name, pair)| AssetPair { name, ..pair })
+}
+
+/// Looks up the precision of the base and quote currency of a trading pair.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `pair` - The assets of the trading pair.
+///
+pub async fn fetch_precision(api: &KrakenAPI, pair: &AssetPair) -> Precision {
+    let assets: HashMap<String, AssetInfo> = parse_response(
+        &api.public_request(
+            "/0/public/Assets",
+            vec![("asset", format!("{},{}", pair.base, pair.quote))],
+        )
+        .await,
+    );
+    let decimals = |asset: &String| {
+        assets
+            .get(asset)
+            .map(|info| info.decimals)
+            .unwrap_or_else(|| {
+                error!("Unknown asset: {}", asset);
+                std::process::exit(1);
+            })
+    };
+
+    Precision {
+        base: decimals(&pair.base),
+        quote: decimals(&pair.quote),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRECISION: Precision = Precision { base: 8, quote: 2 };
+
+    fn asset_pair() -> AssetPair {
+        AssetPair {
+            name: "XXBTZEUR".to_string(),
+            base: "XXBT".to_string(),
+            quote: "ZEUR".to_string(),
+            altname: "XBTEUR".to_string(),
+            wsname: "XBT/EUR".to_string(),
+        }
+    }
+
+    #[test]
+    fn rounds_to_the_decimals_of_the_currencies() {
+        assert_eq!(PRECISION.quote(0.125), 0.13);
+        assert_eq!(PRECISION.quote(0.1 + 0.2), 0.3);
+        assert_eq!(PRECISION.base(1.000000004), 1f64);
+        assert_eq!(PRECISION.fmt_quote(1.5), "1.50");
+        assert_eq!(PRECISION.fmt_base(0.1), "0.10000000");
+        // Negative values rounded to zero aren't reported as -0.
+        assert_eq!(PRECISION.fmt_quote(PRECISION.quote(-0.001)), "0.00");
+    }
+
+    #[test]
+    fn inverts_the_precision_and_the_pair() {
+        let inverted: Precision = PRECISION.inverted();
+        assert_eq!((inverted.base, inverted.quote), (2, 8));
+        let pair: AssetPair = asset_pair().inverted();
+        assert_eq!(pair.name, "XXBTZEUR");
+        assert_eq!((pair.base.as_str(), pair.quote.as_str()), ("ZEUR", "XXBT"));
+        assert_eq!(pair.wsname, "EUR/XBT");
+        assert_eq!(pair.base_code(), "EUR");
+        assert_eq!(pair.quote_code(), "BTC");
+    }
+
+    #[test]
+    fn lists_the_aliases_of_a_pair() {
+        let pair: AssetPair = asset_pair();
+        assert_eq!(pair.base_code(), "BTC");
+        assert_eq!(pair.quote_code(), "EUR");
+        assert_eq!(
+            pair.aliases(),
+            vec!["XXBTZEUR", "XBTEUR", "XBT/EUR", "BTCEUR", "BTC/EUR"]
+        );
+    }
+}
@@ -0,0 +1,427 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module contains the preflight check of `--check`, which validates the
+credentials and the permissions of the API key with a few cheap requests and
+estimates the requests of the actual fetch, so problems surface before a long
+fetch is started instead of in the middle of it. `--estimate` only counts the
+entries to fetch and asks for confirmation before the fetch starts.
+*/
+
+use crate::kraken::{KrakenAPI, PAGE_SIZE};
+use crate::rate_limit::RateLimiter;
+use serde_json::Value;
+use std::io::{BufRead, Write};
+use std::time::Duration;
+
+/// The API tiers, from the slowest to the fastest.
+const TIERS: [&str; 3] = ["starter", "intermediate", "pro"];
+
+/// The outcome of a single check.
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    /// A description of the result, or the reason the check failed.
+    pub result: Result<String, String>,
+}
+
+/// Sends a request to a private endpoint and returns its result, translating
+/// the errors into the likely cause.
+async fn check_request(
+    api: &KrakenAPI,
+    endpoint: &str,
+    params: Vec<(&str, String)>,
+    permission: &str,
+) -> Result<Value, String> {
+    let response: String = api.request(endpoint, params).await;
+    let response: Value = serde_json::from_str(&response)
+        .map_err(|e| format!("Failed to parse the response: {}", e))?;
+    let errors: Vec<&str> = response["error"]
+        .as_array()
+        .map(|errors| errors.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    match errors.first() {
+        None => Ok(response["result"].clone()),
+        Some(&"EAPI:Invalid key") => Err(
+            "The API key is invalid, or it has two-factor authentication enabled and the one-time password is missing or wrong"
+                .to_string(),
+        ),
+        Some(&"EAPI:Invalid signature") => {
+            Err("The signature is invalid, check the secret key".to_string())
+        }
+        Some(&"EAPI:Invalid nonce") => Err(
+            "The nonce is invalid, the API key may be used by another application with a higher nonce; use a separate key or configure a nonce window (--nonce-window)"
+                .to_string(),
+        ),
+        Some(&"EGeneral:Permission denied") => Err(format!(
+            "The API key lacks the \"{}\" permission",
+            permission
+        )),
+        Some(_) => Err(errors.join(", ")),
+    }
+}
+
+/// Returns the number of entries of a paginated endpoint within a period.
+async fn check_count(
+    api: &KrakenAPI,
+    endpoint: &str,
+    params: &[(&str, String)],
+    permission: &str,
+) -> Result<usize, String> {
+    let result: Value = check_request(api, endpoint, params.to_vec(), permission).await?;
+    result["count"]
+        .as_u64()
+        .map(|count| count as usize)
+        .ok_or_else(|| "The response doesn't contain a count".to_string())
+}
+
+/// Validates the credentials and permissions and estimates the requests of the
+/// fetch.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `tier` - The configured API tier.
+/// * `closed_orders` - Whether the closed orders are fetched as well, which
+///   the user reference and client order ids require.
+/// * `ledger` - Whether the ledger entries are fetched as well.
+/// * `start` - An optional start date for filtering the trades.
+/// * `end` - An optional end date for filtering the trades.
+///
+/// # Returns
+///
+/// The outcome of every check. Checks depending on a failed one are skipped.
+///
+pub async fn preflight(
+    api: &KrakenAPI,
+    tier: &str,
+    closed_orders: bool,
+    ledger: bool,
+    start: Option<f64>,
+    end: Option<f64>,
+) -> Vec<Check> {
+    let mut checks: Vec<Check> = Vec::new();
+    let mut check = |name: &str, result: Result<String, String>| {
+        let ok: bool = result.is_ok();
+        checks.push(Check {
+            name: name.to_string(),
+            result,
+        });
+        ok
+    };
+
+    if !check(
+        "Secret key",
+        if api.has_valid_secret() {
+            Ok("valid base64".to_string())
+        } else {
+            Err("The secret key is not valid base64".to_string())
+        },
+    ) {
+        return checks;
+    }
+    let balance = check_request(api, "/0/private/Balance", vec![], "Query Funds")
+        .await
+        .map(|result| {
+            format!(
+                "signature accepted, {} assets with a balance",
+                result.as_object().map_or(0, |assets| assets.len())
+            )
+        });
+    if !check("Signature and Query Funds", balance) {
+        return checks;
+    }
+
+    let mut params: Vec<(&str, String)> = Vec::new();
+    if let Some(start) = start {
+        params.push(("start", start.to_string()));
+    }
+    if let Some(end) = end {
+        params.push(("end", end.to_string()));
+    }
+    // The entries to fetch and the cost of a page by endpoint.
+    let mut requests: Vec<(usize, f64)> = Vec::new();
+    let mut count = async |endpoint: &'static str, name: &'static str, permission: &str| {
+        let result = check_count(api, endpoint, &params, permission).await;
+        if let Ok(count) = result {
+            requests.push((count, RateLimiter::cost(endpoint)));
+        }
+        result.map(|count| format!("{} {} in the selected period", count, name))
+    };
+    let trades = count(
+        "/0/private/TradesHistory",
+        "trades",
+        "Query Closed Orders & Trades",
+    )
+    .await;
+    let orders = if closed_orders {
+        Some(
+            count(
+                "/0/private/ClosedOrders",
+                "closed orders",
+                "Query Closed Orders & Trades",
+            )
+            .await,
+        )
+    } else {
+        None
+    };
+    let ledger_entries = if ledger {
+        Some(
+            count(
+                "/0/private/Ledgers",
+                "ledger entries",
+                "Query Ledger Entries",
+            )
+            .await,
+        )
+    } else {
+        None
+    };
+    let mut ok: bool = check("Query Closed Orders & Trades (trades)", trades);
+    if let Some(orders) = orders {
+        ok &= check("Query Closed Orders & Trades (closed orders)", orders);
+    }
+    if let Some(ledger_entries) = ledger_entries {
+        ok &= check("Query Ledger Entries", ledger_entries);
+    }
+    if !ok {
+        return checks;
+    }
+
+    // Kraken doesn't report the tier, so the budget is based on the configured
+    // one. The counter is assumed to be empty when the fetch starts.
+    let limiter: &RateLimiter = api.rate_limiter();
+    let pages: usize = requests
+        .iter()
+        .map(|(count, _)| count.div_ceil(PAGE_SIZE).max(1))
+        .sum();
+    let points: f64 = requests
+        .iter()
+        .map(|(count, cost)| count.div_ceil(PAGE_SIZE).max(1) as f64 * cost)
+        .sum();
+    let seconds: f64 = budget_seconds(points, limiter);
+    check(
+        "Rate-limit budget",
+        Ok(format!(
+            "tier {} (configured, not reported by Kraken): counter of {} points decaying by {}/s; the fetch takes {} requests ({} points), about {:.0} seconds",
+            tier,
+            limiter.max_counter(),
+            limiter.decay_rate(),
+            pages,
+            points,
+            seconds
+        )),
+    );
+    checks
+}
+
+/// Returns the seconds it takes to spend a number of points of the API counter,
+/// which is assumed to be empty at first.
+fn budget_seconds(points: f64, limiter: &RateLimiter) -> f64 {
+    (points - limiter.max_counter()).max(0f64) / limiter.decay_rate()
+}
+
+/// The entries of a paginated endpoint to fetch.
+#[derive(Debug)]
+pub struct Entries {
+    pub name: &'static str,
+    pub count: usize,
+    /// The number of times the entries are fetched, e.g. once per pair.
+    pub passes: usize,
+    /// The cost of a page.
+    pub cost: f64,
+}
+
+impl Entries {
+    /// Returns the number of pages of all passes.
+    pub fn pages(&self) -> usize {
+        self.count.div_ceil(PAGE_SIZE).max(1) * self.passes
+    }
+}
+
+/// The requests of a fetch, estimated from the number of entries to fetch.
+#[derive(Debug)]
+pub struct Estimate {
+    pub entries: Vec<Entries>,
+    /// Whether the pairs are discovered from the trade history, which isn't
+    /// counted per pair.
+    pub discovery: bool,
+}
+
+impl Estimate {
+    /// Returns the number of requests of the fetch.
+    pub fn requests(&self) -> usize {
+        self.entries.iter().map(Entries::pages).sum()
+    }
+
+    /// Returns the points the fetch adds to the API counter.
+    pub fn points(&self) -> f64 {
+        self.entries
+            .iter()
+            .map(|entries| entries.pages() as f64 * entries.cost)
+            .sum()
+    }
+
+    /// Returns the expected duration of the fetch with the rate limit of a
+    /// tier, waiting for the counter to decay.
+    pub fn duration(&self, limiter: &RateLimiter) -> Duration {
+        Duration::from_secs_f64(budget_seconds(self.points(), limiter))
+    }
+}
+
+/// Estimates the requests of a fetch by counting the entries to fetch, which
+/// takes one request per endpoint.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `pairs` - The number of selected pairs, whose closed orders and ledger
+///   entries are fetched once per pair, while their trades are fetched
+///   together.
+/// * `discovery` - Whether the pairs are discovered from the trade history,
+///   i.e. selected by a pattern or --quote.
+/// * `closed_orders` - Whether the closed orders are fetched as well.
+/// * `ledger` - Whether the ledger entries are fetched as well.
+/// * `start` - An optional start date for filtering the trades.
+/// * `end` - An optional end date for filtering the trades.
+///
+/// # Returns
+///
+/// The estimate, or an error if the entries can't be counted.
+///
+pub async fn estimate(
+    api: &KrakenAPI,
+    pairs: usize,
+    discovery: bool,
+    closed_orders: bool,
+    ledger: bool,
+    start: Option<f64>,
+    end: Option<f64>,
+) -> Result<Estimate, String> {
+    let mut params: Vec<(&str, String)> = Vec::new();
+    if let Some(start) = start {
+        params.push(("start", start.to_string()));
+    }
+    if let Some(end) = end {
+        params.push(("end", end.to_string()));
+    }
+    let endpoints: [(&'static str, &'static str, &str, bool); 3] = [
+        (
+            "/0/private/TradesHistory",
+            "trades",
+            "Query Closed Orders & Trades",
+            true,
+        ),
+        (
+            "/0/private/ClosedOrders",
+            "closed orders",
+            "Query Closed Orders & Trades",
+            closed_orders,
+        ),
+        (
+            "/0/private/Ledgers",
+            "ledger entries",
+            "Query Ledger Entries",
+            ledger,
+        ),
+    ];
+    let mut entries: Vec<Entries> = Vec::new();
+    for (endpoint, name, permission, fetched) in endpoints {
+        if !fetched {
+            continue;
+        }
+        let count: usize = check_count(api, endpoint, &params, permission)
+            .await
+            .map_err(|e| format!("Failed to count the {}: {}", name, e))?;
+        // The trade history of all pairs is fetched once and shared by them,
+        // including the discovery of the pairs.
+        let passes: usize = if name == "trades" { 1 } else { pairs };
+        entries.push(Entries {
+            name,
+            count,
+            passes,
+            cost: RateLimiter::cost(endpoint),
+        });
+    }
+    Ok(Estimate { entries, discovery })
+}
+
+/// Formats a duration as hours, minutes and seconds.
+fn format_duration(duration: Duration) -> String {
+    let seconds: u64 = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60, seconds % 60) {
+        (0, 0, seconds) => format!("{}s", seconds),
+        (0, minutes, seconds) => format!("{}m {}s", minutes, seconds),
+        (hours, minutes, _) => format!("{}h {}m", hours, minutes),
+    }
+}
+
+/// Prints the estimate of a fetch.
+///
+/// # Arguments
+///
+/// * `estimate` - The estimate of the fetch.
+/// * `tier` - The configured API tier.
+///
+pub fn print_estimate(estimate: &Estimate, tier: &str) {
+    for entries in &estimate.entries {
+        println!(
+            "{} {} in the selected period: {} pages{}",
+            entries.count,
+            entries.name,
+            entries.pages(),
+            if entries.passes > 1 {
+                format!(", fetching them {} times", entries.passes)
+            } else {
+                String::new()
+            }
+        );
+    }
+    if estimate.discovery {
+        println!("Every pattern counts as one pair, the pairs it selects add a pass over the closed orders and ledger entries each.");
+    }
+    println!(
+        "The fetch takes {} requests and adds {} points to the API counter.",
+        estimate.requests(),
+        estimate.points()
+    );
+    for name in TIERS {
+        let limiter: RateLimiter = RateLimiter::for_tier(name);
+        println!(
+            "  {:<12} (counter of {} points decaying by {}/s): about {}{}",
+            name,
+            limiter.max_counter(),
+            limiter.decay_rate(),
+            format_duration(estimate.duration(&limiter)),
+            if name == tier { " [configured]" } else { "" }
+        );
+    }
+}
+
+/// Asks a question on the terminal and returns whether it was answered with
+/// yes. No answer, e.g. without a terminal, counts as no.
+pub fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    let _ = std::io::stdout().flush();
+    let mut answer: String = String::new();
+    match std::io::stdin().lock().read_line(&mut answer) {
+        Ok(0) | Err(_) => {
+            println!();
+            false
+        }
+        Ok(_) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+    }
+}
+
+/// Prints the outcome of the checks.
+pub fn print_checks(checks: &[Check]) {
+    for check in checks {
+        match &check.result {
+            Ok(result) => println!("[ok]     {}: {}", check.name, result),
+            Err(reason) => println!("[failed] {}: {}", check.name, reason),
+        }
+    }
+}
@@ -0,0 +1,544 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module fetches historical daily prices of a trading pair. Kraken's public
+OHLC endpoint only provides the most recent 720 days and lacks data for
+delisted pairs, so the prices can be taken from a chain of sources: every day
+is priced by the first source of the chain that provides a price for it.
+
+The following sources are available:
+
+- `kraken`: The daily candles of Kraken's public OHLC endpoint.
+- `kraken-trades`: The last trade of every day from Kraken's public Trades
+  endpoint, which covers the full history of a pair and so backfills the days
+  older than those of the OHLC endpoint. Every day is a request of its own.
+- `coingecko`: The daily prices of the public CoinGecko API.
+- `csv:PATH`: A manually maintained CSV file with the columns `date`
+  (YYYY-MM-DD) and `price`, and optionally `pair` to store the prices of
+  several trading pairs in one file.
+
+The prices of the remote sources are stored in the cache, so a day is only
+requested as long as it is not cached. The price of the current day is never
+cached, since its candle is still open. If a source fails, the cached prices
+are used.
+*/
+
+use crate::cache::Cache;
+use crate::kraken::KrakenAPI;
+use crate::precision::AssetPair;
+use chrono::{DateTime, NaiveDate};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::path::PathBuf;
+
+#[derive(Deserialize, Debug)]
+struct OHLCResponse {
+    error: Vec<String>,
+    result: Option<HashMap<String, Value>>,
+}
+
+/// Fetches the daily closing prices of a trading pair.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `pair` - The name of the trading pair (e.g., XXBTZEUR).
+///
+/// # Returns
+///
+/// The closing price by day. Kraken only provides the most recent 720 daily
+/// candles, so older dates are not included.
+///
+pub async fn fetch_daily_closes(
+    api: &KrakenAPI,
+    pair: &str,
+) -> Result<BTreeMap<NaiveDate, f64>, String> {
+    let response: OHLCResponse = serde_json::from_str(
+        &api.public_request(
+            "/0/public/OHLC",
+            vec![("pair", pair.to_string()), ("interval", "1440".to_string())],
+        )
+        .await,
+    )
+    .map_err(|e| format!("Failed to parse response: {}", e))?;
+    let candles: Vec<Value> = response
+        .result
+        .and_then(|mut result| result.remove(pair))
+        .and_then(|candles| candles.as_array().cloned())
+        .ok_or_else(|| format!("Error fetching prices of {}: {:?}", pair, response.error))?;
+
+    // Every candle is [time, open, high, low, close, vwap, volume, count].
+    let mut closes: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+    for candle in candles {
+        let time: i64 = candle[0].as_i64().unwrap_or_default();
+        let close: f64 = candle[4]
+            .as_str()
+            .and_then(|close| close.parse().ok())
+            .ok_or_else(|| "Invalid OHLC data!".to_string())?;
+        let date: NaiveDate = DateTime::from_timestamp(time, 0).unwrap().date_naive();
+        closes.insert(date, close);
+    }
+    Ok(closes)
+}
+
+#[derive(Deserialize, Debug)]
+struct TradesResponse {
+    error: Vec<String>,
+    result: Option<HashMap<String, Value>>,
+}
+
+/// Fetches the public trades of a trading pair since a time.
+///
+/// # Returns
+///
+/// The price and time of every trade, oldest first, up to 1000 trades.
+///
+async fn fetch_public_trades(
+    api: &KrakenAPI,
+    pair: &str,
+    since: i64,
+) -> Result<Vec<(f64, f64)>, String> {
+    let response: TradesResponse = serde_json::from_str(
+        &api.public_request(
+            "/0/public/Trades",
+            vec![("pair", pair.to_string()), ("since", since.to_string())],
+        )
+        .await,
+    )
+    .map_err(|e| format!("Failed to parse response: {}", e))?;
+    let trades: Vec<Value> = response
+        .result
+        .and_then(|mut result| result.remove(pair))
+        .and_then(|trades| trades.as_array().cloned())
+        .ok_or_else(|| format!("Error fetching trades of {}: {:?}", pair, response.error))?;
+
+    // Every trade is [price, volume, time, side, type, misc, trade_id].
+    trades
+        .iter()
+        .map(|trade| {
+            trade[0]
+                .as_str()
+                .and_then(|price| price.parse().ok())
+                .zip(trade[2].as_f64())
+                .ok_or_else(|| "Invalid trade data!".to_string())
+        })
+        .collect()
+}
+
+/// Fetches the closing prices of some days of a trading pair from its public
+/// trades, i.e. the price of the last trade of every day.
+///
+/// The trades of the last hour of a day are requested first, and those of the
+/// whole day if none was made within it. Days without trades are skipped.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `pair` - The name of the trading pair (e.g., XXBTZEUR).
+/// * `days` - The days to fetch the prices of.
+///
+pub async fn fetch_trade_closes(
+    api: &KrakenAPI,
+    pair: &str,
+    days: Vec<NaiveDate>,
+) -> Result<BTreeMap<NaiveDate, f64>, String> {
+    if !days.is_empty() {
+        info!(
+            "Backfilling the prices of {} days of {} from the public trades...",
+            days.len(),
+            pair
+        );
+    }
+    let mut closes: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+    let mut requests: usize = 0;
+    for date in days {
+        let end: i64 = (date + chrono::Days::new(1))
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp();
+        for since in [end - 3600, end - 86400] {
+            // Kraken allows about one public request per second.
+            if requests > 0 {
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            }
+            requests += 1;
+            let trades: Vec<(f64, f64)> = match fetch_public_trades(api, pair, since).await {
+                Ok(trades) => trades,
+                // The prices fetched so far are kept.
+                Err(e) if !closes.is_empty() => {
+                    warn!("{}, stopping the backfill of {}.", e, pair);
+                    return Ok(closes);
+                }
+                Err(e) => return Err(e),
+            };
+            if let Some((price, _)) = trades.iter().rev().find(|(_, time)| *time < end as f64) {
+                closes.insert(date, *price);
+                break;
+            }
+        }
+    }
+    Ok(closes)
+}
+
+/// Returns the daily closing prices of a trading pair from a remote source,
+/// requesting them only if the cache lacks some of the days.
+///
+/// # Arguments
+///
+/// * `cache` - The cache of the prices, if available.
+/// * `pair` - The name of the trading pair (e.g., XXBTZEUR).
+/// * `source` - The name of the source the prices are cached under.
+/// * `from` - The first day that needs a price.
+/// * `to` - The last day that needs a price.
+/// * `fetch` - Requests the prices from the source, which is only awaited if
+///   the cache is incomplete.
+///
+/// # Returns
+///
+/// The cached prices merged with the fetched ones, or the cached prices only
+/// if requesting the source fails.
+///
+pub async fn cached_closes(
+    cache: Option<&Cache>,
+    pair: &str,
+    source: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+    fetch: impl Future<Output = Result<BTreeMap<NaiveDate, f64>, String>>,
+) -> Result<BTreeMap<NaiveDate, f64>, String> {
+    let mut closes: BTreeMap<NaiveDate, f64> = match cache {
+        Some(cache) => cache
+            .load_prices(pair, source, from, to)
+            .unwrap_or_else(|e| {
+                warn!("Failed to read the cached prices of {}: {}", pair, e);
+                BTreeMap::new()
+            }),
+        None => BTreeMap::new(),
+    };
+    let complete: bool = from
+        .iter_days()
+        .take_while(|date| *date <= to)
+        .all(|date| closes.contains_key(&date));
+    if complete {
+        debug!("Using the cached {} prices of {}", source, pair);
+        return Ok(closes);
+    }
+    match fetch.await {
+        Ok(fetched) => {
+            if let Some(cache) = cache {
+                let today: NaiveDate = chrono::Utc::now().date_naive();
+                let closed: BTreeMap<NaiveDate, f64> = fetched
+                    .range(..today)
+                    .map(|(date, close)| (*date, *close))
+                    .collect();
+                if let Err(e) = cache.store_prices(pair, source, &closed) {
+                    warn!("Failed to cache the prices of {}: {}", pair, e);
+                }
+            }
+            closes.extend(fetched);
+            Ok(closes)
+        }
+        Err(e) if !closes.is_empty() => {
+            warn!(
+                "{}, using the {} cached {} prices of {}.",
+                e,
+                closes.len(),
+                source,
+                pair
+            );
+            Ok(closes)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// A source of historical prices.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PriceSource {
+    Kraken,
+    KrakenTrades,
+    CoinGecko,
+    Csv(PathBuf),
+}
+
+impl PriceSource {
+    /// Parses a source like `kraken`, `coingecko` or `csv:prices.csv`.
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "kraken" => Ok(PriceSource::Kraken),
+            "kraken-trades" => Ok(PriceSource::KrakenTrades),
+            "coingecko" => Ok(PriceSource::CoinGecko),
+            _ => match name.strip_prefix("csv:") {
+                Some(path) if !path.is_empty() => Ok(PriceSource::Csv(PathBuf::from(path))),
+                _ => Err(format!(
+                    "Unknown price source '{}', expected kraken, kraken-trades, coingecko, or csv:PATH!",
+                    name
+                )),
+            },
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            PriceSource::Kraken => "kraken".to_string(),
+            PriceSource::KrakenTrades => "kraken-trades".to_string(),
+            PriceSource::CoinGecko => "coingecko".to_string(),
+            PriceSource::Csv(path) => format!("csv:{}", path.display()),
+        }
+    }
+
+    /// Fetches the daily prices of a trading pair from this source, using the
+    /// cache for the remote sources.
+    async fn fetch(
+        &self,
+        api: &KrakenAPI,
+        cache: Option<&Cache>,
+        asset_pair: &AssetPair,
+        coingecko_ids: &HashMap<String, String>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<BTreeMap<NaiveDate, f64>, String> {
+        let name: String = self.name();
+        match self {
+            PriceSource::Kraken => {
+                let fetch = fetch_daily_closes(api, &asset_pair.name);
+                cached_closes(cache, &asset_pair.name, &name, from, to, fetch).await
+            }
+            PriceSource::KrakenTrades => {
+                // Every day is a request, so only the uncached ones are made.
+                let cached: BTreeMap<NaiveDate, f64> = cache
+                    .and_then(|cache| cache.load_prices(&asset_pair.name, &name, from, to).ok())
+                    .unwrap_or_default();
+                let today: NaiveDate = chrono::Utc::now().date_naive();
+                let days: Vec<NaiveDate> = from
+                    .iter_days()
+                    .take_while(|date| *date <= to && *date < today)
+                    .filter(|date| !cached.contains_key(date))
+                    .collect();
+                let fetch = fetch_trade_closes(api, &asset_pair.name, days);
+                cached_closes(cache, &asset_pair.name, &name, from, to, fetch).await
+            }
+            PriceSource::CoinGecko => {
+                let fetch = fetch_coingecko_prices(asset_pair, coingecko_ids, from, to);
+                cached_closes(cache, &asset_pair.name, &name, from, to, fetch).await
+            }
+            PriceSource::Csv(path) => read_csv_prices(path, asset_pair),
+        }
+    }
+}
+
+/// Fetches the daily prices of a trading pair from the public CoinGecko API.
+///
+/// The coin is identified by the CoinGecko id of the base currency, which is
+/// derived from the currency code for common coins and can be configured for
+/// all others.
+async fn fetch_coingecko_prices(
+    asset_pair: &AssetPair,
+    coingecko_ids: &HashMap<String, String>,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<BTreeMap<NaiveDate, f64>, String> {
+    let base: String = asset_pair.base_code();
+    let id: String = match coingecko_ids.get(&base) {
+        Some(id) => id.clone(),
+        None => match base.as_str() {
+            "BTC" => "bitcoin",
+            "ETH" => "ethereum",
+            "SOL" => "solana",
+            "XRP" => "ripple",
+            "ADA" => "cardano",
+            "DOT" => "polkadot",
+            "DOGE" => "dogecoin",
+            "LTC" => "litecoin",
+            "XMR" => "monero",
+            "LINK" => "chainlink",
+            "XLM" => "stellar",
+            "ATOM" => "cosmos",
+            "USDT" => "tether",
+            "USDC" => "usd-coin",
+            _ => {
+                return Err(format!(
+                    "No CoinGecko id known for {}, please configure it!",
+                    base
+                ))
+            }
+        }
+        .to_string(),
+    };
+    let timestamp = |date: NaiveDate| date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+
+    #[derive(Deserialize)]
+    struct MarketChart {
+        prices: Vec<(f64, f64)>,
+    }
+    let response = reqwest::Client::new()
+        .get(format!(
+            "https://api.coingecko.com/api/v3/coins/{}/market_chart/range",
+            id
+        ))
+        .query(&[
+            ("vs_currency", asset_pair.quote_code().to_lowercase()),
+            ("from", timestamp(from).to_string()),
+            ("to", (timestamp(to) + 86400).to_string()),
+        ])
+        .header("User-Agent", env!("CARGO_PKG_NAME"))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach CoinGecko: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("CoinGecko responded with {}", response.status()));
+    }
+    let chart: MarketChart = serde_json::from_str(
+        &response
+            .text()
+            .await
+            .map_err(|e| format!("Failed to read response: {}", e))?,
+    )
+    .map_err(|e| format!("Failed to parse response: {}", e))?;
+
+    // The last price of a day serves as its closing price.
+    let mut closes: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+    for (time, price) in chart.prices {
+        let date: NaiveDate = DateTime::from_timestamp_millis(time as i64)
+            .ok_or_else(|| "Invalid CoinGecko data!".to_string())?
+            .date_naive();
+        closes.insert(date, price);
+    }
+    Ok(closes)
+}
+
+/// Reads the daily prices of a trading pair from a CSV file.
+fn read_csv_prices(
+    path: &PathBuf,
+    asset_pair: &AssetPair,
+) -> Result<BTreeMap<NaiveDate, f64>, String> {
+    let content: String = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut lines = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or_else(|| format!("{} is empty!", path.display()))?
+        .split(',')
+        .map(str::trim)
+        .collect();
+    let column = |name: &str| header.iter().position(|column| *column == name);
+    let (date_column, price_column) = column("date")
+        .zip(column("price"))
+        .ok_or_else(|| format!("{} lacks the columns date and price!", path.display()))?;
+    let pair_column: Option<usize> = column("pair");
+
+    let mut closes: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+    for line in lines {
+        let values: Vec<&str> = line.split(',').map(str::trim).collect();
+        if let Some(pair_column) = pair_column {
+            if values.get(pair_column) != Some(&asset_pair.name.as_str()) {
+                continue;
+            }
+        }
+        let invalid = || format!("Invalid line in {}: {}", path.display(), line);
+        let date: NaiveDate = values
+            .get(date_column)
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+            .ok_or_else(invalid)?;
+        let price: f64 = values
+            .get(price_column)
+            .and_then(|price| price.parse().ok())
+            .ok_or_else(invalid)?;
+        closes.insert(date, price);
+    }
+    Ok(closes)
+}
+
+/// The daily prices of a trading pair together with the source of each price.
+#[derive(Debug, Default)]
+pub struct Prices {
+    closes: BTreeMap<NaiveDate, (f64, usize)>,
+    sources: Vec<String>,
+}
+
+impl Prices {
+    /// Returns the price of a day and the name of the source it was taken
+    /// from.
+    pub fn price_at(&self, date: &NaiveDate) -> Option<(f64, &str)> {
+        self.closes
+            .get(date)
+            .map(|(price, source)| (*price, self.sources[*source].as_str()))
+    }
+
+    /// Returns the price of the UTC day of a UNIX timestamp and the name of
+    /// the source it was taken from.
+    pub fn price_at_time(&self, time: f64) -> Option<(f64, &str)> {
+        self.price_at(&DateTime::from_timestamp_nanos((time * 1e9) as i64).date_naive())
+    }
+
+    /// Returns the names of the sources that were queried.
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+}
+
+/// Fetches the daily prices of a trading pair from a chain of sources.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `cache` - The cache of the prices of the remote sources, if available.
+/// * `asset_pair` - The trading pair.
+/// * `chain` - The sources in the order of preference.
+/// * `coingecko_ids` - The CoinGecko ids by currency code (e.g., BTC).
+/// * `from` - The first day that needs a price.
+/// * `to` - The last day that needs a price.
+///
+/// # Returns
+///
+/// The prices of the days between `from` and `to`. A source is only queried
+/// for the period of the days the previous sources lack prices for. Sources
+/// that fail are skipped with a warning.
+///
+pub async fn fetch_prices(
+    api: &KrakenAPI,
+    cache: Option<&Cache>,
+    asset_pair: &AssetPair,
+    chain: &[PriceSource],
+    coingecko_ids: &HashMap<String, String>,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Prices {
+    let mut prices: Prices = Prices::default();
+    for source in chain {
+        let mut missing = from
+            .iter_days()
+            .take_while(|date| *date <= to)
+            .filter(|date| !prices.closes.contains_key(date));
+        let Some(first) = missing.next() else {
+            break;
+        };
+        let last: NaiveDate = missing.last().unwrap_or(first);
+
+        let closes: BTreeMap<NaiveDate, f64> = match source
+            .fetch(api, cache, asset_pair, coingecko_ids, first, last)
+            .await
+        {
+            Ok(closes) => closes,
+            Err(e) => {
+                warn!("Skipping price source {}: {}", source.name(), e);
+                continue;
+            }
+        };
+        let index: usize = prices.sources.len();
+        prices.sources.push(source.name());
+        for (date, close) in closes.range(from..=to) {
+            prices.closes.entry(*date).or_insert((*close, index));
+        }
+    }
+    prices
+}
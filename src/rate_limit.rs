@@ -0,0 +1,251 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module models Kraken's API counter to rate limit private requests.
+
+Every private request increases the counter of the API key by a cost that
+depends on the endpoint, while the counter decays at a constant rate that
+depends on the account tier. Requests are rejected once the counter would
+exceed the maximum of the tier. Tracking the counter as a token bucket allows
+bursting until the maximum is reached and only waiting as long as necessary
+afterwards.
+
+See https://docs.kraken.com/api/docs/guides/spot-rest-ratelimits
+*/
+
+use log::debug;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// The state of the API counter at a point in time.
+#[derive(Debug)]
+struct Counter {
+    value: f64,
+    updated: Instant,
+    usage: Usage,
+}
+
+/// The requests sent through a rate limiter so far.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Usage {
+    pub requests: usize,
+    /// The sum of the costs of all requests.
+    pub points: f64,
+    /// The time spent waiting for the counter to decay.
+    pub waited: Duration,
+    /// The number of requests rejected by Kraken because of the rate limit.
+    pub rejected: usize,
+}
+
+impl Usage {
+    /// Returns the requests sent since an earlier usage was taken.
+    pub fn since(&self, earlier: &Usage) -> Usage {
+        Usage {
+            requests: self.requests - earlier.requests,
+            points: self.points - earlier.points,
+            waited: self.waited.saturating_sub(earlier.waited),
+            rejected: self.rejected - earlier.rejected,
+        }
+    }
+}
+
+/// A token bucket modelling Kraken's API counter.
+#[derive(Debug)]
+pub struct RateLimiter {
+    max_counter: f64,
+    /// The decay of the counter in points per second.
+    decay_rate: f64,
+    counter: Mutex<Counter>,
+}
+
+impl RateLimiter {
+    /// Creates a new rate limiter, assuming the counter is initially zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_counter` - The maximum value of the counter.
+    /// * `decay_rate` - The decay of the counter in points per second.
+    ///
+    pub fn new(max_counter: f64, decay_rate: f64) -> Self {
+        Self {
+            max_counter,
+            decay_rate,
+            counter: Mutex::new(Counter {
+                value: 0f64,
+                updated: Instant::now(),
+                usage: Usage::default(),
+            }),
+        }
+    }
+
+    /// Creates the rate limiter of an API tier (starter, intermediate, or pro),
+    /// defaulting to the starter tier.
+    pub fn for_tier(tier: &str) -> Self {
+        match tier {
+            "intermediate" => Self::new(20f64, 0.5),
+            "pro" => Self::new(20f64, 1f64),
+            _ => Self::new(15f64, 0.33),
+        }
+    }
+
+    /// Returns the maximum value of the counter.
+    pub fn max_counter(&self) -> f64 {
+        self.max_counter
+    }
+
+    /// Returns the decay of the counter in points per second.
+    pub fn decay_rate(&self) -> f64 {
+        self.decay_rate
+    }
+
+    /// Returns the cost of a request to a private endpoint. Ledger and trade
+    /// history queries cost 2 points, all other requests 1 point.
+    pub fn cost(endpoint: &str) -> f64 {
+        match endpoint {
+            "/0/private/TradesHistory"
+            | "/0/private/QueryTrades"
+            | "/0/private/Ledgers"
+            | "/0/private/QueryLedgers" => 2f64,
+            _ => 1f64,
+        }
+    }
+
+    /// Waits until a request of the given cost can be sent without exceeding
+    /// the maximum counter and adds the cost to the counter.
+    pub async fn acquire(&self, cost: f64) {
+        let mut counter = self.counter.lock().await;
+        let now: Instant = Instant::now();
+        counter.value = (counter.value
+            - now.duration_since(counter.updated).as_secs_f64() * self.decay_rate)
+            .max(0f64);
+        counter.updated = now;
+
+        let excess: f64 = counter.value + cost - self.max_counter;
+        if excess > 0f64 {
+            let wait: Duration = Duration::from_secs_f64(excess / self.decay_rate);
+            debug!(
+                "Waiting {:.1}s for the API counter to decay ({:.1} of {} points used)",
+                wait.as_secs_f64(),
+                counter.value,
+                self.max_counter
+            );
+            tokio::time::sleep(wait).await;
+            counter.value = self.max_counter - cost;
+            counter.updated = Instant::now();
+            counter.usage.waited += wait;
+        }
+        counter.value += cost;
+        counter.usage.requests += 1;
+        counter.usage.points += cost;
+    }
+
+    /// Estimates how long requests of the given total cost will take, based on
+    /// the current counter.
+    pub async fn estimate(&self, cost: f64) -> Duration {
+        let counter = self.counter.lock().await;
+        let value: f64 =
+            (counter.value - counter.updated.elapsed().as_secs_f64() * self.decay_rate).max(0f64);
+        Duration::from_secs_f64((value + cost - self.max_counter).max(0f64) / self.decay_rate)
+    }
+
+    /// Marks the counter as exhausted, e.g. after Kraken rejected a request
+    /// because the counter was already increased by other applications using
+    /// the same API key.
+    pub async fn exhaust(&self) {
+        let mut counter = self.counter.lock().await;
+        counter.value = self.max_counter;
+        counter.updated = Instant::now();
+        counter.usage.rejected += 1;
+    }
+
+    /// Returns the current value of the counter.
+    pub async fn counter(&self) -> f64 {
+        let counter = self.counter.lock().await;
+        (counter.value - counter.updated.elapsed().as_secs_f64() * self.decay_rate).max(0f64)
+    }
+
+    /// Restores the counter of an earlier run, e.g. of an interrupted fetch,
+    /// decayed by the time passed since.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value of the counter of the earlier run.
+    /// * `age` - The time passed since the value was reached.
+    ///
+    pub async fn restore(&self, value: f64, age: Duration) {
+        let mut counter = self.counter.lock().await;
+        counter.value = (value - age.as_secs_f64() * self.decay_rate).clamp(0f64, self.max_counter);
+        counter.updated = Instant::now();
+    }
+
+    /// Returns the requests sent so far.
+    pub async fn usage(&self) -> Usage {
+        self.counter.lock().await.usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn bursts_until_the_maximum_and_then_waits() {
+        let limiter: RateLimiter = RateLimiter::new(10f64, 1f64);
+        let start: Instant = Instant::now();
+        for _ in 0..5 {
+            limiter.acquire(2f64).await;
+        }
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        limiter.acquire(2f64).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+        let usage: Usage = limiter.usage().await;
+        assert_eq!(usage.requests, 6);
+        assert_eq!(usage.points, 12f64);
+        assert_eq!(usage.waited, Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn counter_decays_over_time() {
+        let limiter: RateLimiter = RateLimiter::new(10f64, 1f64);
+        for _ in 0..5 {
+            limiter.acquire(2f64).await;
+        }
+        assert_eq!(limiter.estimate(4f64).await, Duration::from_secs(4));
+        tokio::time::advance(Duration::from_secs(3)).await;
+        assert_eq!(limiter.counter().await, 7f64);
+        assert_eq!(limiter.estimate(4f64).await, Duration::from_secs(1));
+        // Requests within the decayed points don't wait.
+        let start: Instant = Instant::now();
+        limiter.acquire(3f64).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn exhausted_counter_waits_for_the_decay() {
+        let limiter: RateLimiter = RateLimiter::new(10f64, 0.5);
+        limiter.exhaust().await;
+        assert_eq!(limiter.usage().await.rejected, 1);
+        let start: Instant = Instant::now();
+        limiter.acquire(1f64).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn restores_the_decayed_counter() {
+        let limiter: RateLimiter = RateLimiter::new(10f64, 1f64);
+        limiter.restore(8f64, Duration::from_secs(5)).await;
+        assert_eq!(limiter.counter().await, 3f64);
+        limiter.restore(30f64, Duration::ZERO).await;
+        assert_eq!(limiter.counter().await, 10f64);
+    }
+
+    #[test]
+    fn history_queries_cost_two_points() {
+        assert_eq!(RateLimiter::cost("/0/private/TradesHistory"), 2f64);
+        assert_eq!(RateLimiter::cost("/0/private/Ledgers"), 2f64);
+        assert_eq!(RateLimiter::cost("/0/private/ClosedOrders"), 1f64);
+    }
+}
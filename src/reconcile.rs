@@ -0,0 +1,238 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module reconciles the balance computed from the trades with the actual
+balance of the account (`--reconcile`), as reported by the Balance endpoint,
+to catch gaps in the data that don't cause any error. Differences are
+attributed to their likely causes: the ledger entries of the asset that are
+not explained by the trades, e.g. deposits, withdrawals, staking rewards or
+trades of other pairs, and the balances held in staking or earn strategies,
+which Kraken reports under their own names (e.g. `XBT.F` or `DOT.S`).
+*/
+
+use crate::kraken::{KrakenAPI, Trade};
+use crate::ledger::LedgerEntry;
+use crate::precision::{AssetPair, Precision};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[derive(Deserialize, Debug)]
+struct BalanceResponse {
+    error: Vec<String>,
+    result: Option<HashMap<String, String>>,
+}
+
+/// Fetches the balance of every asset of the account.
+pub async fn fetch_balances(api: &KrakenAPI) -> Result<HashMap<String, f64>, String> {
+    let response: BalanceResponse =
+        serde_json::from_str(&api.request("/0/private/Balance", vec![]).await)
+            .map_err(|e| format!("Failed to parse response: {}", e))?;
+    response
+        .result
+        .ok_or_else(|| format!("Error fetching the balances: {:?}", response.error))?
+        .into_iter()
+        .map(|(asset, balance)| {
+            balance
+                .parse()
+                .map(|balance: f64| (asset.clone(), balance))
+                .map_err(|_| format!("Invalid balance of {}: {}", asset, balance))
+        })
+        .collect()
+}
+
+/// The ledger entries of a type that the trades don't explain.
+#[derive(Debug, Default)]
+pub struct Unexplained {
+    pub entries: usize,
+    /// The net amount of the entries, i.e. their amounts less their fees.
+    pub amount: f64,
+}
+
+/// The computed balance of an asset compared with the balance of the account.
+#[derive(Debug)]
+pub struct Reconciliation {
+    pub computed: f64,
+    pub actual: f64,
+    /// The balances held in staking or earn strategies by their name.
+    pub staked: Vec<(String, f64)>,
+    /// The ledger entries not explained by the trades by their type (and
+    /// subtype), if the ledger was fetched.
+    pub unexplained: Option<BTreeMap<String, Unexplained>>,
+}
+
+impl Reconciliation {
+    /// Returns the actual balance less the computed one.
+    pub fn difference(&self, precision: &Precision) -> f64 {
+        precision.base(self.actual - self.computed)
+    }
+}
+
+/// Reconciles the computed balance of the base asset of a trading pair with
+/// the balances of the account.
+///
+/// # Arguments
+///
+/// * `computed` - The balance computed from the trades.
+/// * `balances` - The balances of the account by asset.
+/// * `asset_pair` - The trading pair.
+/// * `trades` - The trades the balance was computed from.
+/// * `entries` - The ledger entries of the base asset, if fetched.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn reconcile(
+    computed: f64,
+    balances: &HashMap<String, f64>,
+    asset_pair: &AssetPair,
+    trades: &[Trade],
+    entries: Option<&[LedgerEntry]>,
+    precision: &Precision,
+) -> Reconciliation {
+    let code: String = asset_pair.base_code();
+    let mut staked: Vec<(String, f64)> = balances
+        .iter()
+        .filter(|(asset, balance)| {
+            **balance != 0f64
+                && asset
+                    .split_once('.')
+                    .is_some_and(|(name, _)| name == asset_pair.base || name == code)
+        })
+        .map(|(asset, balance)| (asset.clone(), precision.base(*balance)))
+        .collect();
+    staked.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let unexplained = entries.map(|entries| {
+        let txids: HashSet<&str> = trades.iter().map(|trade| trade.txid.as_str()).collect();
+        let mut unexplained: BTreeMap<String, Unexplained> = BTreeMap::new();
+        for entry in entries
+            .iter()
+            .filter(|entry| !txids.contains(entry.refid.as_str()))
+        {
+            let amount: f64 = entry.amount.parse::<f64>().unwrap_or(0f64)
+                - entry.fee.parse::<f64>().unwrap_or(0f64);
+            if amount == 0f64 {
+                continue;
+            }
+            let kind: String = match entry.subtype.as_str() {
+                "" => entry.entry_type.clone(),
+                subtype => format!("{}/{}", entry.entry_type, subtype),
+            };
+            let group: &mut Unexplained = unexplained.entry(kind).or_default();
+            group.entries += 1;
+            group.amount = precision.base(group.amount + amount);
+        }
+        unexplained
+    });
+
+    Reconciliation {
+        computed,
+        actual: precision.base(
+            balances
+                .get(&asset_pair.base)
+                .or_else(|| balances.get(&code))
+                .copied()
+                .unwrap_or(0f64),
+        ),
+        staked,
+        unexplained,
+    }
+}
+
+/// Returns the likely cause of the ledger entries of a type.
+fn cause(kind: &str) -> &'static str {
+    if matches!(
+        kind,
+        "earn/allocation" | "earn/deallocation" | "earn/migration"
+    ) {
+        return "allocations to earn strategies, held under a name of their own";
+    }
+    match kind.split('/').next().unwrap_or_default() {
+        "deposit" => "deposits, pass --treat-transfers to model them",
+        "withdrawal" => "withdrawals, pass --treat-transfers to model them",
+        "transfer" => "transfers between accounts, pass --treat-transfers to model them",
+        "staking" | "earn" | "dividend" | "airdrop" | "credit" => {
+            "income, pass --income-lots to add it to the holdings"
+        }
+        "trade" | "spend" | "receive" => "trades of other pairs of the asset",
+        "margin" | "rollover" | "settled" => "margin positions",
+        _ => "other movements",
+    }
+}
+
+/// Prints the reconciliation and the likely causes of a difference.
+///
+/// # Arguments
+///
+/// * `reconciliation` - The reconciliation of the balance.
+/// * `asset` - The name of the base asset.
+/// * `until_end` - Whether the trades end before now, since the account
+///   balance is the current one.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn print_reconciliation(
+    reconciliation: &Reconciliation,
+    asset: &str,
+    until_end: bool,
+    precision: &Precision,
+) {
+    let difference: f64 = reconciliation.difference(precision);
+    println!("Reconciliation against the account balance ({}):", asset);
+    println!(
+        "{:<20} {:>20}",
+        "Computed",
+        precision.fmt_base(reconciliation.computed)
+    );
+    println!(
+        "{:<20} {:>20}",
+        "Account",
+        precision.fmt_base(reconciliation.actual)
+    );
+    println!(
+        "{:<20} {:>20}",
+        "Difference",
+        precision.fmt_base(difference)
+    );
+    for (name, balance) in &reconciliation.staked {
+        println!(
+            "{:<20} {:>20}  (held in staking or earn)",
+            name,
+            precision.fmt_base(*balance)
+        );
+    }
+    if difference == 0f64 {
+        println!("The computed balance matches the account balance.");
+        return;
+    }
+
+    println!("Likely causes:");
+    if until_end {
+        println!("  The trades end before now, while the account balance is the current one.");
+    }
+    match &reconciliation.unexplained {
+        Some(unexplained) => {
+            for (kind, group) in unexplained {
+                println!(
+                    "  {:<18} {:>20}  {} {}: {}",
+                    kind,
+                    precision.fmt_base(group.amount),
+                    group.entries,
+                    if group.entries == 1 { "entry" } else { "entries" },
+                    cause(kind)
+                );
+            }
+            let rest: f64 = precision
+                .base(difference - unexplained.values().map(|group| group.amount).sum::<f64>());
+            if rest != 0f64 {
+                println!(
+                    "  {:<18} {:>20}  history missing from the trades and the ledger, e.g. before --start or since the fetch",
+                    "unexplained",
+                    precision.fmt_base(rest)
+                );
+            }
+        }
+        None => println!(
+            "  Deposits, withdrawals, staking rewards, trades of other pairs or missing history. Pass --balance-check to find where the history diverges."
+        ),
+    }
+}
@@ -0,0 +1,1052 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module contains the writers for the reports generated by the tool. Every
+report starts with the metadata of the run that produced it.
+*/
+
+use crate::fifo::{Disposal, TradePnL};
+use crate::fiscal::YearStart;
+use crate::kraken::{ClosedOrder, Trade};
+use crate::precision::Precision;
+use crate::returns::DailyValuation;
+use crate::slippage::trade_slippage;
+use crate::tax::TaxRules;
+use chrono::DateTime;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// The effective parameters of a run, embedded into every generated report so
+/// that it remains self-describing long after it was created.
+#[derive(Serialize, Debug)]
+pub struct ReportMetadata {
+    pub tool: String,
+    pub version: String,
+    pub generated_at: String,
+    pub symbol: String,
+    /// The base and quote asset of the pair (e.g., XXBT and ZEUR).
+    pub base: String,
+    pub quote: String,
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub year: Option<u32>,
+    /// The first day of the fiscal years if they aren't the calendar years.
+    pub fiscal_year_start: Option<String>,
+    /// The user reference ids the trades are filtered by.
+    pub userrefs: Vec<i32>,
+    /// The order ids the trades are filtered by.
+    pub ordertxids: Vec<String>,
+    /// The client order ids the trades are filtered by.
+    pub cl_ord_ids: Vec<String>,
+    /// The prefixes of the client order ids the trades are filtered by.
+    pub cl_ord_id_prefixes: Vec<String>,
+    /// The order types the trades are filtered by.
+    pub ordertypes: Vec<String>,
+    /// The side the trades are filtered by, if any.
+    pub side: Option<String>,
+    pub tier: String,
+    pub method: String,
+    pub tax_rules: Option<String>,
+    /// Whether the wash sales are flagged or deferred, if at all.
+    pub wash_sales: Option<String>,
+    /// The currency the disposals are converted into, if any.
+    pub tax_currency: Option<String>,
+    /// The source of the FX rates the disposals are converted at.
+    pub fx_source: Option<String>,
+    /// The price band the listed trades are filtered by, if any.
+    pub price_band: Option<String>,
+    /// Whether base and quote currency are swapped.
+    pub inverted: bool,
+    /// Whether the fills of an order are listed as one trade.
+    pub aggregated_fills: bool,
+    /// The holdings seeded before the first trade, if any.
+    pub opening_balance: Option<String>,
+    /// The ids of the trades excluded from the computation.
+    pub excluded_txids: Vec<String>,
+    pub trades: usize,
+    pub data_digest: String,
+}
+
+impl ReportMetadata {
+    /// Returns the ids of the excluded trades as comma-separated list, or `-`
+    /// if no trade is excluded.
+    pub fn excluded_txids(&self) -> String {
+        list(&self.excluded_txids)
+    }
+
+    /// Returns the filters of the orders, e.g. `userref 1,2; side buy`,
+    /// or `-` if the trades are not filtered by their orders.
+    pub fn order_filter(&self) -> String {
+        let filters: Vec<String> = [
+            ("userref", list(&self.userrefs)),
+            ("ordertxid", list(&self.ordertxids)),
+            ("cl_ord_id", list(&self.cl_ord_ids)),
+            ("cl_ord_id_prefix", list(&self.cl_ord_id_prefixes)),
+            ("ordertype", list(&self.ordertypes)),
+            ("side", list(self.side.as_slice())),
+        ]
+        .into_iter()
+        .filter(|(_, values)| values != "-")
+        .map(|(name, values)| format!("{} {}", name, values))
+        .collect();
+        if filters.is_empty() {
+            "-".to_string()
+        } else {
+            filters.join("; ")
+        }
+    }
+
+    /// Returns the metadata as `# key: value` lines suitable as a comment
+    /// block on top of a CSV file.
+    pub fn to_csv_comments(&self) -> String {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        [
+            format!("# tool: {} {}", self.tool, self.version),
+            format!("# generated_at: {}", self.generated_at),
+            format!("# symbol: {}", self.symbol),
+            format!("# base: {}", self.base),
+            format!("# quote: {}", self.quote),
+            format!("# start: {}", optional(&self.start)),
+            format!("# end: {}", optional(&self.end)),
+            format!("# year: {}", optional(&self.year.map(|y| y.to_string()))),
+            format!("# fiscal_year_start: {}", optional(&self.fiscal_year_start)),
+            format!("# userref: {}", list(&self.userrefs)),
+            format!("# ordertxid: {}", list(&self.ordertxids)),
+            format!("# cl_ord_id: {}", list(&self.cl_ord_ids)),
+            format!("# cl_ord_id_prefix: {}", list(&self.cl_ord_id_prefixes)),
+            format!("# ordertype: {}", list(&self.ordertypes)),
+            format!("# side: {}", optional(&self.side)),
+            format!("# tier: {}", self.tier),
+            format!("# method: {}", self.method),
+            format!("# tax_rules: {}", optional(&self.tax_rules)),
+            format!("# wash_sales: {}", optional(&self.wash_sales)),
+            format!("# tax_currency: {}", optional(&self.tax_currency)),
+            format!("# fx_source: {}", optional(&self.fx_source)),
+            format!("# price_band: {}", optional(&self.price_band)),
+            format!("# inverted: {}", self.inverted),
+            format!("# aggregated_fills: {}", self.aggregated_fills),
+            format!("# opening_balance: {}", optional(&self.opening_balance)),
+            format!("# excluded_txids: {}", self.excluded_txids()),
+            format!("# trades: {}", self.trades),
+            format!("# data_digest: sha256:{}", self.data_digest),
+        ]
+        .join("\n")
+    }
+}
+
+/// Returns the values as comma-separated list, or `-` if there are none.
+fn list<T: ToString>(values: &[T]) -> String {
+    if values.is_empty() {
+        "-".to_string()
+    } else {
+        values
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+}
+
+/// Computes a SHA-256 digest over the trades that serve as input for a report.
+///
+/// The digest only depends on the trade data itself, so two reports generated
+/// from the same trades can be identified as such, regardless of when they
+/// were created.
+pub fn compute_data_digest(trades: &[Trade]) -> String {
+    let mut digest: DataDigest = DataDigest::default();
+    for trade in trades {
+        digest.update(trade);
+    }
+    digest.finish()
+}
+
+/// The digest of `compute_data_digest` computed one trade after the other.
+#[derive(Default)]
+pub struct DataDigest {
+    hasher: Sha256,
+}
+
+impl DataDigest {
+    /// Adds the next trade to the digest.
+    pub fn update(&mut self, trade: &Trade) {
+        self.hasher.update(
+            format!(
+                "{},{},{},{},{},{},{},{},{}\n",
+                trade.time,
+                trade.pair,
+                trade.side,
+                trade.price,
+                trade.fee,
+                trade.vol,
+                trade.cost,
+                trade.ordertype,
+                trade.ordertxid,
+            )
+            .as_bytes(),
+        );
+    }
+
+    /// Returns the digest as hexadecimal string.
+    pub fn finish(self) -> String {
+        self.hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Formats a UNIX timestamp as human-readable UTC date and time.
+pub fn format_time(time: f64) -> String {
+    DateTime::from_timestamp_nanos((time * 1e9) as i64)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+/// The layout of the generated CSV files, e.g. to match the locale of the
+/// spreadsheet software they are opened with.
+#[derive(Debug, Clone)]
+pub struct CsvFormat {
+    pub delimiter: char,
+    /// Whether numbers are written with a decimal comma instead of a point.
+    pub decimal_comma: bool,
+    /// The strftime format of the times.
+    pub date_format: String,
+}
+
+impl Default for CsvFormat {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            decimal_comma: false,
+            date_format: "%Y-%m-%d %H:%M:%S".to_string(),
+        }
+    }
+}
+
+impl CsvFormat {
+    /// Formats a UNIX timestamp as UTC date and time.
+    pub fn time(&self, time: f64) -> String {
+        DateTime::from_timestamp_nanos((time * 1e9) as i64)
+            .format(&self.date_format)
+            .to_string()
+    }
+
+    /// Formats a number, replacing the decimal point if requested.
+    pub fn number(&self, value: &str) -> String {
+        if self.decimal_comma {
+            value.replace('.', ",")
+        } else {
+            value.to_string()
+        }
+    }
+
+    /// Joins the fields of a row, quoting the fields that contain the
+    /// delimiter, a quote or a line break.
+    pub fn row<S: AsRef<str>>(&self, fields: &[S]) -> String {
+        fields
+            .iter()
+            .map(|field| {
+                let field: &str = field.as_ref();
+                if field.contains([self.delimiter, '"', '\n', '\r']) {
+                    format!("\"{}\"", field.replace('"', "\"\""))
+                } else {
+                    field.to_string()
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(&self.delimiter.to_string())
+    }
+}
+
+/// Merges the fills of every order into one trade for the listing of the
+/// trades. The FIFO lot engine still matches the individual fills.
+///
+/// # Arguments
+///
+/// * `trades` - The trades sorted by time.
+/// * `trade_pnl` - The realized PnL of the trades that disposed of lots.
+/// * `precision` - The precision of the base and quote currency.
+///
+/// # Returns
+///
+/// The trades with one trade for the buys or sells of every order at the time
+/// of its last fill, its volume-weighted price and the sums of the volume,
+/// cost and fee, as well as the realized PnL of the merged trades. Other
+/// trades, e.g. credits, are kept as they are.
+///
+pub fn aggregate_fills(
+    trades: &[Trade],
+    trade_pnl: &HashMap<String, TradePnL>,
+    precision: &Precision,
+) -> (Vec<Trade>, HashMap<String, TradePnL>) {
+    let is_fill = |trade: &Trade| trade.side == "buy" || trade.side == "sell";
+    let key = |trade: &Trade| (trade.ordertxid.clone(), trade.side.clone());
+    // The index of the last fill of every order.
+    let last_fills: HashMap<(String, String), usize> = trades
+        .iter()
+        .enumerate()
+        .filter(|(_, trade)| is_fill(trade))
+        .map(|(index, trade)| (key(trade), index))
+        .collect();
+    let mut fills: HashMap<(String, String), Vec<&Trade>> = HashMap::new();
+    let mut aggregated: Vec<Trade> = Vec::new();
+    let mut aggregated_pnl: HashMap<String, TradePnL> = HashMap::new();
+    for (index, trade) in trades.iter().enumerate() {
+        if !is_fill(trade) {
+            aggregated.push(trade.clone());
+            if let Some(pnl) = trade_pnl.get(&trade.txid) {
+                aggregated_pnl.insert(trade.txid.clone(), pnl.clone());
+            }
+            continue;
+        }
+        let order: &mut Vec<&Trade> = fills.entry(key(trade)).or_default();
+        order.push(trade);
+        if last_fills[&key(trade)] != index {
+            continue;
+        }
+        if order.len() == 1 {
+            aggregated.push(trade.clone());
+            if let Some(pnl) = trade_pnl.get(&trade.txid) {
+                aggregated_pnl.insert(trade.txid.clone(), pnl.clone());
+            }
+            continue;
+        }
+        // The sums keep the decimals Kraken states the values with.
+        let sum = |value: fn(&Trade) -> &str| -> (f64, String) {
+            let total: f64 = order
+                .iter()
+                .map(|fill| value(fill).parse::<f64>().unwrap())
+                .sum();
+            (total, format!("{:.*}", decimals(value(trade)), total))
+        };
+        let (vol, vol_text): (f64, String) = sum(|fill| &fill.vol);
+        let (cost, cost_text): (f64, String) = sum(|fill| &fill.cost);
+        let base_fee: Option<f64> = order
+            .iter()
+            .any(|fill| fill.base_fee.is_some())
+            .then(|| precision.base(order.iter().filter_map(|fill| fill.base_fee).sum()));
+        let realized: Vec<&TradePnL> = order
+            .iter()
+            .filter_map(|fill| trade_pnl.get(&fill.txid))
+            .collect();
+        if let Some(last) = realized.last() {
+            aggregated_pnl.insert(
+                trade.txid.clone(),
+                TradePnL {
+                    cost_basis: precision.quote(realized.iter().map(|pnl| pnl.cost_basis).sum()),
+                    realized_pnl: precision
+                        .quote(realized.iter().map(|pnl| pnl.realized_pnl).sum()),
+                    cumulative_realized_pnl: last.cumulative_realized_pnl,
+                },
+            );
+        }
+        aggregated.push(Trade {
+            price: match vol > 0f64 {
+                true => format!("{:.*}", decimals(&trade.price), cost / vol),
+                false => trade.price.clone(),
+            },
+            vol: vol_text,
+            cost: cost_text,
+            fee: sum(|fill| &fill.fee).1,
+            base_fee,
+            // The fills of an order may have made and taken liquidity.
+            maker: order
+                .iter()
+                .map(|fill| fill.maker)
+                .reduce(|a, b| if a == b { a } else { None })
+                .flatten(),
+            ..trade.clone()
+        });
+    }
+    (aggregated, aggregated_pnl)
+}
+
+/// Returns the number of decimals of a number.
+fn decimals(value: &str) -> usize {
+    value
+        .split_once('.')
+        .map_or(0, |(_, decimals)| decimals.len())
+}
+
+/// Writes the trades to a CSV file.
+///
+/// # Arguments
+///
+/// * `trades` - A reference to a vector of trades to be written to the CSV
+///   file.
+/// * `trade_pnl` - The realized PnL of the trades that disposed of lots, whose
+///   cost basis, realized and cumulative realized PnL are added to them.
+/// * `orders` - The closed orders by their id, whose prices, times and status
+///   are added to their trades, if requested.
+/// * `precision` - The precision used to format the PnL.
+/// * `file_path` - The path of the CSV file to write the trades to, or `-` to
+///   write them to stdout.
+/// * `metadata` - The parameters of the run, written as a comment block on top
+///   of the file.
+/// * `format` - The delimiter, decimal separator and date format of the file.
+///
+/// This function writes the trades to a CSV file with the specified file path.
+/// The CSV file starts with the report metadata, followed by a header row and
+/// each trade is written as a row in the CSV file. The time field is converted
+/// to a human-readable format before being written to the file.
+pub fn write_trades_to_csv(
+    trades: &[Trade],
+    trade_pnl: &HashMap<String, TradePnL>,
+    orders: Option<&HashMap<String, ClosedOrder>>,
+    precision: &Precision,
+    file_path: &str,
+    metadata: &ReportMetadata,
+    format: &CsvFormat,
+) {
+    let mut csv: TradesCsv = TradesCsv::create(file_path, metadata, format, precision, orders);
+    for trade in trades {
+        csv.write(trade, trade_pnl.get(&trade.txid));
+    }
+}
+
+/// The writer of the trades CSV, which writes every row as it is passed, so
+/// the trades can be streamed into the file.
+pub struct TradesCsv<'a> {
+    file: Box<dyn Write>,
+    format: &'a CsvFormat,
+    precision: &'a Precision,
+    /// The closed orders whose details are added to their trades, if
+    /// requested.
+    orders: Option<&'a HashMap<String, ClosedOrder>>,
+}
+
+impl<'a> TradesCsv<'a> {
+    /// Creates the CSV file and writes the metadata and the header.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of the CSV file, or `-` to write to stdout.
+    /// * `metadata` - The parameters of the run.
+    /// * `format` - The delimiter, decimal separator and date format.
+    /// * `precision` - The precision used to format the PnL.
+    /// * `orders` - The closed orders by their id, whose prices, times and
+    ///   status are added to their trades, if requested.
+    ///
+    pub fn create(
+        file_path: &str,
+        metadata: &ReportMetadata,
+        format: &'a CsvFormat,
+        precision: &'a Precision,
+        orders: Option<&'a HashMap<String, ClosedOrder>>,
+    ) -> Self {
+        let mut file: Box<dyn Write> = if file_path == "-" {
+            Box::new(BufWriter::new(std::io::stdout().lock()))
+        } else {
+            Box::new(BufWriter::new(
+                File::create(file_path).expect("Could not create file"),
+            ))
+        };
+        writeln!(file, "{}", metadata.to_csv_comments()).expect("Failed to write metadata to CSV!");
+        let mut header: Vec<&str> = vec![
+            "time",
+            "pair",
+            "side",
+            "price",
+            "fee",
+            "vol",
+            "cost",
+            "ordertype",
+            "ordertxid",
+            "cost_basis",
+            "realized_pnl",
+            "cumulative_realized_pnl",
+        ];
+        if orders.is_some() {
+            header.extend([
+                "order_price",
+                "order_price2",
+                "order_avg_price",
+                "order_status",
+                "order_opened",
+                "order_closed",
+                "slippage",
+            ]);
+        }
+        writeln!(file, "{}", format.row(&header)).expect("Failed to write header to CSV!");
+        Self {
+            file,
+            format,
+            precision,
+            orders,
+        }
+    }
+
+    /// Writes the row of a trade.
+    ///
+    /// # Arguments
+    ///
+    /// * `trade` - The trade.
+    /// * `pnl` - The realized PnL of the trade, if it disposed of lots.
+    ///
+    pub fn write(&mut self, trade: &Trade, pnl: Option<&TradePnL>) {
+        let format: &CsvFormat = self.format;
+        // Only the trades that disposed of lots realized any PnL.
+        let [cost_basis, realized_pnl, cumulative_realized_pnl]: [String; 3] = match pnl {
+            Some(pnl) => [
+                pnl.cost_basis,
+                pnl.realized_pnl,
+                pnl.cumulative_realized_pnl,
+            ]
+            .map(|value| format.number(&self.precision.fmt_quote(value))),
+            None => Default::default(),
+        };
+        let mut row: Vec<String> = vec![
+            format.time(trade.time),
+            trade.pair.clone(),
+            trade.side.clone(),
+            format.number(&trade.price),
+            format.number(&trade.fee),
+            format.number(&trade.vol),
+            format.number(&trade.cost),
+            trade.ordertype.clone(),
+            trade.ordertxid.clone(),
+            cost_basis,
+            realized_pnl,
+            cumulative_realized_pnl,
+        ];
+        // The trades of other sources, e.g. adjustments, have no closed order.
+        if let Some(orders) = self.orders {
+            row.extend(match orders.get(&trade.ordertxid) {
+                Some(order) => {
+                    let time = |time: f64| {
+                        if time > 0f64 {
+                            format.time(time)
+                        } else {
+                            String::new()
+                        }
+                    };
+                    let slippage: String = trade_slippage(trade, Some(order))
+                        .map(|(slippage, _)| format.number(&self.precision.fmt_quote(slippage)))
+                        .unwrap_or_default();
+                    [
+                        format.number(&order.price),
+                        format.number(&order.price2),
+                        format.number(&order.avg_price),
+                        order.status.clone(),
+                        time(order.opentm),
+                        time(order.closetm),
+                        slippage,
+                    ]
+                }
+                None => Default::default(),
+            });
+        }
+        writeln!(self.file, "{}", format.row(&row)).expect("Failed to write trades to CSV!");
+    }
+}
+
+/// Writes the disposals to a CSV or JSON file.
+///
+/// # Arguments
+///
+/// * `disposals` - The disposals to be written.
+/// * `file_path` - The path of the file. If the path ends with `.json`, a JSON
+///   document with a `metadata` object and a `disposals` array is written,
+///   otherwise a CSV file.
+/// * `metadata` - The parameters of the run.
+/// * `precision` - The precision used to format amounts and values in the CSV
+///   file.
+/// * `format` - The delimiter, decimal separator and date format of the CSV
+///   file.
+///
+/// Each row of the CSV file represents one (partial) lot consumed by a sell.
+/// Disposals without a matching acquisition have an empty acquisition date.
+pub fn write_disposals(
+    disposals: &[Disposal],
+    file_path: &str,
+    metadata: &ReportMetadata,
+    precision: &Precision,
+    format: &CsvFormat,
+) {
+    let mut file: File = File::create(file_path).expect("Could not create file");
+
+    if file_path.ends_with(".json") {
+        #[derive(Serialize)]
+        struct DisposalReport<'a> {
+            metadata: &'a ReportMetadata,
+            disposals: &'a [Disposal],
+        }
+        serde_json::to_writer_pretty(
+            &mut file,
+            &DisposalReport {
+                metadata,
+                disposals,
+            },
+        )
+        .expect("Failed to write disposals to JSON!");
+        return;
+    }
+
+    let mut csv: DisposalsCsv = DisposalsCsv::new(file, metadata, format, precision);
+    for disposal in disposals {
+        csv.write(disposal);
+    }
+}
+
+/// The writer of the CSV of the disposals, which writes every row as it is
+/// passed, so the disposals can be streamed into the file.
+pub struct DisposalsCsv<'a> {
+    file: BufWriter<File>,
+    format: &'a CsvFormat,
+    precision: &'a Precision,
+}
+
+impl<'a> DisposalsCsv<'a> {
+    /// Creates the CSV file and writes the metadata and the header.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of the CSV file.
+    /// * `metadata` - The parameters of the run.
+    /// * `format` - The delimiter, decimal separator and date format.
+    /// * `precision` - The precision used to format amounts and values.
+    ///
+    pub fn create(
+        file_path: &str,
+        metadata: &ReportMetadata,
+        format: &'a CsvFormat,
+        precision: &'a Precision,
+    ) -> Self {
+        let file: File = File::create(file_path).expect("Could not create file");
+        Self::new(file, metadata, format, precision)
+    }
+
+    /// Writes the metadata and the header to a created file.
+    fn new(
+        file: File,
+        metadata: &ReportMetadata,
+        format: &'a CsvFormat,
+        precision: &'a Precision,
+    ) -> Self {
+        let mut file: BufWriter<File> = BufWriter::new(file);
+        writeln!(file, "{}", metadata.to_csv_comments()).expect("Failed to write metadata to CSV!");
+        writeln!(
+            file,
+            "{}",
+            format.row(&[
+                "acquisition_date",
+                "acquisition_ordertxid",
+                "disposal_date",
+                "disposal_ordertxid",
+                "amount",
+                "acquisition_cost",
+                "proceeds",
+                "gain",
+                "classification",
+                "wash_sale_disallowed_loss",
+                "wash_sale_replacements",
+                "disposal_ordertype",
+                "disposal_liquidity"
+            ])
+        )
+        .expect("Failed to write header to CSV!");
+        Self {
+            file,
+            format,
+            precision,
+        }
+    }
+
+    /// Writes the row of a disposal.
+    pub fn write(&mut self, disposal: &Disposal) {
+        let (format, precision): (&CsvFormat, &Precision) = (self.format, self.precision);
+        writeln!(
+            self.file,
+            "{}",
+            format.row(&[
+                disposal
+                    .acquisition_time
+                    .map(|time| format.time(time))
+                    .unwrap_or_default(),
+                disposal.acquisition_ordertxid.clone().unwrap_or_default(),
+                format.time(disposal.disposal_time),
+                disposal.disposal_ordertxid.clone(),
+                format.number(&precision.fmt_base(disposal.amount)),
+                format.number(&precision.fmt_quote(disposal.acquisition_cost)),
+                format.number(&precision.fmt_quote(disposal.proceeds)),
+                format.number(&precision.fmt_quote(disposal.gain)),
+                disposal.classification.clone().unwrap_or_default(),
+                disposal
+                    .wash_sale
+                    .as_ref()
+                    .map(|wash_sale| {
+                        format.number(&precision.fmt_quote(wash_sale.disallowed_loss))
+                    })
+                    .unwrap_or_default(),
+                disposal
+                    .wash_sale
+                    .as_ref()
+                    .map(|wash_sale| wash_sale.replacement_ordertxids.join(" "))
+                    .unwrap_or_default(),
+                disposal.disposal_ordertype.clone(),
+                match disposal.disposal_maker {
+                    Some(true) => "maker",
+                    Some(false) => "taker",
+                    None => "",
+                }
+                .to_string(),
+            ])
+        )
+        .expect("Failed to write disposals to CSV!");
+    }
+}
+
+/// Writes the daily valuation series to a CSV file.
+///
+/// # Arguments
+///
+/// * `series` - The daily valuation series.
+/// * `file_path` - The path of the CSV file.
+/// * `metadata` - The parameters of the run.
+/// * `precision` - The precision used to format amounts and values.
+/// * `format` - The delimiter and the decimal separator of the file. The dates
+///   are always written as `YYYY-MM-DD`.
+///
+/// Every row states the source the price of the day was taken from, so the
+/// valuations can be audited.
+pub fn write_valuations(
+    series: &[DailyValuation],
+    file_path: &str,
+    metadata: &ReportMetadata,
+    precision: &Precision,
+    format: &CsvFormat,
+) {
+    let mut file: File = File::create(file_path).expect("Could not create file");
+    writeln!(file, "{}", metadata.to_csv_comments()).expect("Failed to write metadata to CSV!");
+    writeln!(
+        file,
+        "{}",
+        format.row(&["date", "balance", "price", "value", "flow", "source"])
+    )
+    .expect("Failed to write header to CSV!");
+
+    for day in series {
+        writeln!(
+            file,
+            "{}",
+            format.row(&[
+                day.date.to_string(),
+                format.number(&precision.fmt_base(day.balance)),
+                format.number(&day.price.to_string()),
+                format.number(&precision.fmt_quote(day.value)),
+                format.number(&precision.fmt_quote(day.flow)),
+                day.source.clone(),
+            ])
+        )
+        .expect("Failed to write valuations to CSV!");
+    }
+}
+
+/// Prints a table with the first `left` columns aligned left and all other
+/// columns, which are expected to be numbers, aligned right.
+pub fn print_table(header: &[String], rows: &[Vec<String>], left: usize) {
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            std::iter::once(header)
+                .chain(rows.iter().map(|row| row.as_slice()))
+                .map(|row| row[column].len())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    for row in std::iter::once(header).chain(rows.iter().map(|row| row.as_slice())) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(column, (cell, width))| {
+                if column < left {
+                    format!("{:<width$}", cell, width = width)
+                } else {
+                    format!("{:>width$}", cell, width = width)
+                }
+            })
+            .collect();
+        println!("{}", cells.join("  "));
+    }
+}
+
+/// The PnL of a trading pair within the portfolio summary.
+#[derive(Debug)]
+pub struct PortfolioEntry {
+    pub symbol: String,
+    pub realized_pnl: f64,
+    /// The currency of the realized PnL, which is the tax currency if set.
+    pub realized_currency: String,
+    pub unrealized_pnl: f64,
+    /// The currency of the unrealized PnL, which is the quote currency.
+    pub unrealized_currency: String,
+    /// The holdings in the base currency.
+    pub balance: f64,
+    pub base_currency: String,
+    /// The fees paid in the quote currency.
+    pub fees: f64,
+    /// The PnL in the portfolio currency, if one is set and the conversion
+    /// succeeded.
+    pub converted: Option<ConvertedPnL>,
+    /// The time the trades were fetched or loaded at.
+    pub loaded_at: f64,
+    pub precision: Precision,
+    /// The warnings of the computation, e.g. of sells exceeding the holdings.
+    pub warnings: Vec<String>,
+}
+
+/// The PnL of a trading pair converted into the portfolio currency at the
+/// latest exchange rates.
+#[derive(Debug)]
+pub struct ConvertedPnL {
+    pub currency: String,
+    pub realized_pnl: f64,
+    pub unrealized_pnl: f64,
+    pub fees: f64,
+    /// The value of the holdings.
+    pub value: f64,
+}
+
+/// Prints the PnL of every trading pair and the totals per currency, since the
+/// PnL of pairs with different quote currencies can't be added up. If every
+/// pair was converted into the portfolio currency, the consolidated PnL, fees
+/// and value of the holdings follow.
+///
+/// # Arguments
+///
+/// * `entries` - The PnL of the trading pairs in the order they were computed.
+///
+pub fn print_portfolio_summary(entries: &[PortfolioEntry]) {
+    // The totals are rounded to the highest precision of the pairs in the
+    // respective currency.
+    let mut totals: BTreeMap<&str, (f64, f64, u32)> = BTreeMap::new();
+    for entry in entries {
+        let realized = totals.entry(&entry.realized_currency).or_default();
+        realized.0 += entry.realized_pnl;
+        realized.2 = realized.2.max(entry.precision.quote);
+        let unrealized = totals.entry(&entry.unrealized_currency).or_default();
+        unrealized.1 += entry.unrealized_pnl;
+        unrealized.2 = unrealized.2.max(entry.precision.quote);
+    }
+
+    println!("Portfolio summary:");
+    let header: Vec<String> = vec![
+        "Pair".to_string(),
+        "Realized PnL".to_string(),
+        "Unrealized PnL".to_string(),
+    ];
+    let mut rows: Vec<Vec<String>> = entries
+        .iter()
+        .map(|entry| {
+            vec![
+                entry.symbol.clone(),
+                format!(
+                    "{} {}",
+                    entry.precision.fmt_quote(entry.realized_pnl),
+                    entry.realized_currency
+                ),
+                format!(
+                    "{} {}",
+                    entry.precision.fmt_quote(entry.unrealized_pnl),
+                    entry.unrealized_currency
+                ),
+            ]
+        })
+        .collect();
+    rows.extend(
+        totals
+            .iter()
+            .map(|(currency, (realized, unrealized, decimals))| {
+                let precision = Precision {
+                    base: 0,
+                    quote: *decimals,
+                };
+                vec![
+                    format!("Total {}", currency),
+                    format!("{} {}", precision.fmt_quote(*realized), currency),
+                    format!("{} {}", precision.fmt_quote(*unrealized), currency),
+                ]
+            }),
+    );
+    print_table(&header, &rows, 1);
+
+    let converted: Option<Vec<(&PortfolioEntry, &ConvertedPnL)>> = entries
+        .iter()
+        .map(|entry| entry.converted.as_ref().map(|converted| (entry, converted)))
+        .collect();
+    let Some(converted) = converted.filter(|converted| !converted.is_empty()) else {
+        return;
+    };
+    let currency: &str = &converted[0].1.currency;
+    let precision = Precision {
+        base: 0,
+        quote: entries
+            .iter()
+            .map(|entry| entry.precision.quote)
+            .max()
+            .unwrap_or_default(),
+    };
+    println!("Portfolio in {} (at the latest exchange rates):", currency);
+    let header: Vec<String> = ["Pair", "Realized PnL", "Unrealized PnL", "Fees", "Value"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    let row = |label: String, values: [f64; 4]| -> Vec<String> {
+        std::iter::once(label)
+            .chain(values.iter().map(|value| precision.fmt_quote(*value)))
+            .collect()
+    };
+    let mut rows: Vec<Vec<String>> = converted
+        .iter()
+        .map(|(entry, pnl)| {
+            row(
+                entry.symbol.clone(),
+                [pnl.realized_pnl, pnl.unrealized_pnl, pnl.fees, pnl.value],
+            )
+        })
+        .collect();
+    let total = |value: fn(&ConvertedPnL) -> f64| -> f64 {
+        converted.iter().map(|(_, pnl)| value(pnl)).sum()
+    };
+    rows.push(row(
+        "Total".to_string(),
+        [
+            total(|pnl| pnl.realized_pnl),
+            total(|pnl| pnl.unrealized_pnl),
+            total(|pnl| pnl.fees),
+            total(|pnl| pnl.value),
+        ],
+    ));
+    print_table(&header, &rows, 1);
+}
+
+/// The realized PnL of the disposals of a month.
+#[derive(Debug, Default)]
+pub struct MonthlyTotals {
+    pub disposals: usize,
+    pub amount: f64,
+    pub proceeds: f64,
+    pub cost: f64,
+    pub gain: f64,
+}
+
+/// Sums up the disposals by month of the disposal.
+///
+/// # Arguments
+///
+/// * `disposals` - The per-lot disposals.
+/// * `precision` - The precision of the base and quote currency.
+///
+/// # Returns
+///
+/// The totals by month (e.g., 2024-03).
+///
+pub fn monthly_breakdown(
+    disposals: &[Disposal],
+    precision: &Precision,
+) -> BTreeMap<String, MonthlyTotals> {
+    let mut months: BTreeMap<String, MonthlyTotals> = BTreeMap::new();
+    for disposal in disposals {
+        let month: String = DateTime::from_timestamp(disposal.disposal_time as i64, 0)
+            .map(|time| time.format("%Y-%m").to_string())
+            .unwrap_or_default();
+        let totals: &mut MonthlyTotals = months.entry(month).or_default();
+        totals.disposals += 1;
+        totals.amount = precision.base(totals.amount + disposal.amount);
+        totals.proceeds = precision.quote(totals.proceeds + disposal.proceeds);
+        totals.cost = precision.quote(totals.cost + disposal.acquisition_cost);
+        totals.gain = precision.quote(totals.gain + disposal.gain);
+    }
+    months
+}
+
+/// Prints the realized PnL per year of disposal.
+///
+/// # Arguments
+///
+/// * `disposals` - The disposals, classified if `tax_rules` is set.
+/// * `tax_rules` - The tax regime whose categories are listed per year.
+/// * `year_start` - The first day of the fiscal years.
+/// * `asset` - The name of the base currency.
+/// * `precision` - The precision used to format amounts and values.
+///
+pub fn print_yearly_summary(
+    disposals: &[Disposal],
+    tax_rules: Option<TaxRules>,
+    year_start: YearStart,
+    asset: &str,
+    precision: &Precision,
+) {
+    #[derive(Default)]
+    struct Totals {
+        amount: f64,
+        proceeds: f64,
+        cost: f64,
+        gain: f64,
+        gain_by_category: BTreeMap<String, f64>,
+    }
+    let categories: &[&str] = tax_rules.map(|rules| rules.categories()).unwrap_or(&[]);
+
+    let mut years: BTreeMap<i32, Totals> = BTreeMap::new();
+    for disposal in disposals {
+        let year: i32 = year_start.year_of(disposal.disposal_time);
+        let totals: &mut Totals = years.entry(year).or_default();
+        totals.amount = precision.base(totals.amount + disposal.amount);
+        totals.proceeds = precision.quote(totals.proceeds + disposal.proceeds);
+        totals.cost = precision.quote(totals.cost + disposal.acquisition_cost);
+        totals.gain = precision.quote(totals.gain + disposal.gain);
+        if let Some(classification) = &disposal.classification {
+            let gain: &mut f64 = totals
+                .gain_by_category
+                .entry(classification.clone())
+                .or_default();
+            *gain = precision.quote(*gain + disposal.gain);
+        }
+    }
+
+    let mut header: Vec<String> = [
+        "Year",
+        &format!("Sold ({})", asset),
+        "Proceeds",
+        "Cost",
+        "Realized PnL",
+    ]
+    .iter()
+    .map(|column| column.to_string())
+    .collect();
+    header.extend(categories.iter().map(|category| category.to_string()));
+    let rows: Vec<Vec<String>> = years
+        .iter()
+        .map(|(year, totals)| {
+            let mut row: Vec<String> = vec![
+                year_start.label(*year),
+                precision.fmt_base(totals.amount),
+                precision.fmt_quote(totals.proceeds),
+                precision.fmt_quote(totals.cost),
+                precision.fmt_quote(totals.gain),
+            ];
+            row.extend(categories.iter().map(|category| {
+                precision.fmt_quote(
+                    totals
+                        .gain_by_category
+                        .get(*category)
+                        .copied()
+                        .unwrap_or_default(),
+                )
+            }));
+            row
+        })
+        .collect();
+
+    print_table(&header, &rows, 1);
+    if rows.is_empty() {
+        println!("No disposals.");
+    }
+}
@@ -0,0 +1,298 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module computes return metrics of the position in the base currency from
+its daily valuation series, which makes the performance comparable to funds
+and benchmarks:
+
+- The time-weighted return (TWR) chains the daily returns and thus eliminates
+  the effect of the size and timing of buys and sells.
+- The money-weighted return (the annualized internal rate of return, IRR)
+  accounts for them and reflects the return on the capital actually invested.
+
+Buys are treated as capital flowing into the position, sells as capital
+flowing out of it. Flows are assumed to happen at the end of the day.
+*/
+
+use crate::kraken::Trade;
+use crate::prices::Prices;
+use chrono::{DateTime, Days, NaiveDate};
+
+/// The valuation of the position at the end of a day.
+#[derive(Debug, Clone)]
+pub struct DailyValuation {
+    pub date: NaiveDate,
+    pub balance: f64,
+    pub price: f64,
+    /// The balance valued at the price of the day.
+    pub value: f64,
+    /// The net capital that flowed into the position on that day, i.e. the
+    /// cost of buys minus the proceeds of sells.
+    pub flow: f64,
+    /// The source of the price. Days without a price from any source keep the
+    /// price of the previous day.
+    pub source: String,
+}
+
+/// Builds the daily valuation series from the first trade until `to`.
+///
+/// # Arguments
+///
+/// * `trades` - The trades sorted by time.
+/// * `prices` - The daily prices of the trading pair.
+/// * `to` - The last day of the series.
+///
+pub fn daily_valuations(
+    trades: &[Trade],
+    prices: &Prices,
+    to: NaiveDate,
+) -> Result<Vec<DailyValuation>, String> {
+    let date_of = |time: f64| DateTime::from_timestamp_nanos((time * 1e9) as i64).date_naive();
+    let Some(first_trade) = trades.first() else {
+        return Ok(Vec::new());
+    };
+
+    let mut series: Vec<DailyValuation> = Vec::new();
+    let mut trades = trades.iter().peekable();
+    let mut balance: f64 = 0f64;
+    let mut last_price: Option<(f64, String)> = None;
+    let mut date: NaiveDate = date_of(first_trade.time);
+    while date <= to {
+        let mut flow: f64 = 0f64;
+        while let Some(trade) = trades.next_if(|trade| date_of(trade.time) <= date) {
+            let amount: f64 = trade.vol.parse().unwrap();
+            let trade_price: f64 = trade.price.parse().unwrap();
+            let (fee, base_fee): (f64, f64) = match trade.base_fee {
+                Some(base_fee) => (0f64, base_fee),
+                None => (trade.fee.parse().unwrap(), 0f64),
+            };
+            if trade.side == "credit" {
+                balance += amount;
+            } else if trade.side == "opening" {
+                balance += amount;
+                flow += trade.cost.parse::<f64>().unwrap();
+            } else if trade.side == "buy" {
+                balance += amount - base_fee;
+                flow += amount * trade_price + fee;
+            } else if trade.side == "sell" {
+                balance -= amount + base_fee;
+                flow -= amount * trade_price - fee;
+            } else if trade.side == "lost" {
+                balance -= amount;
+            }
+        }
+        let (close, source): (f64, String) = match prices.price_at(&date) {
+            Some((close, source)) => {
+                last_price = Some((close, source.to_string()));
+                (close, source.to_string())
+            }
+            None => match &last_price {
+                Some((close, source)) => (*close, format!("{} (previous day)", source)),
+                None => {
+                    return Err(format!(
+                        "No price available for {} from any of the sources ({})!",
+                        date,
+                        prices.sources().join(", ")
+                    ))
+                }
+            },
+        };
+        series.push(DailyValuation {
+            date,
+            balance,
+            price: close,
+            value: balance * close,
+            flow,
+            source,
+        });
+        date = date + Days::new(1);
+    }
+    Ok(series)
+}
+
+/// Parses a period like `2024-01-01:2024-06-30`. Either side may be omitted to
+/// leave the period open.
+pub fn parse_period(period: &str) -> Result<(Option<NaiveDate>, Option<NaiveDate>), String> {
+    let (from, to) = period
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid period '{}', expected FROM:TO!", period))?;
+    let parse = |date: &str| {
+        if date.is_empty() {
+            Ok(None)
+        } else {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map(Some)
+                .map_err(|e| format!("Invalid date '{}': {}", date, e))
+        }
+    };
+    Ok((parse(from)?, parse(to)?))
+}
+
+/// The return metrics over a period.
+#[derive(Debug)]
+pub struct Returns {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub time_weighted: f64,
+    /// The annualized internal rate of return, if it could be determined.
+    pub money_weighted: Option<f64>,
+}
+
+/// Computes the time- and money-weighted return over a period of the
+/// valuation series.
+///
+/// # Arguments
+///
+/// * `series` - The daily valuation series.
+/// * `from` - The first day of the period; the start of the series if `None`.
+/// * `to` - The last day of the period; the end of the series if `None`.
+///
+/// The value of the position at the end of the day before the period is
+/// treated as the initial investment.
+pub fn compute_returns(
+    series: &[DailyValuation],
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Option<Returns> {
+    let initial_value: f64 = from
+        .and_then(|from| series.iter().rev().find(|day| day.date < from))
+        .map(|day| day.value)
+        .unwrap_or(0f64);
+    let period: Vec<&DailyValuation> = series
+        .iter()
+        .filter(|day| from.is_none_or(|from| day.date >= from))
+        .filter(|day| to.is_none_or(|to| day.date <= to))
+        .collect();
+    let (first, last) = (*period.first()?, *period.last()?);
+
+    // Time-weighted return: chain the daily returns while capital was held.
+    let mut growth: f64 = 1f64;
+    let mut previous_value: f64 = initial_value;
+    for day in &period {
+        if previous_value > 0f64 {
+            growth *= (day.value - day.flow) / previous_value;
+        }
+        previous_value = day.value;
+    }
+
+    // Money-weighted return: the rate at which the discounted flows out of the
+    // investor's pocket equal the final value.
+    let start: NaiveDate = first.date.pred_opt().unwrap();
+    let mut cash_flows: Vec<(f64, f64)> = vec![(0f64, -initial_value)];
+    for day in &period {
+        let years: f64 = (day.date - start).num_days() as f64 / 365f64;
+        cash_flows.push((years, -day.flow));
+    }
+    cash_flows.push(((last.date - start).num_days() as f64 / 365f64, last.value));
+
+    Some(Returns {
+        from: first.date,
+        to: last.date,
+        time_weighted: growth - 1f64,
+        money_weighted: internal_rate_of_return(&cash_flows),
+    })
+}
+
+/// Solves the net present value of the cash flows `(years, amount)` for zero
+/// by bisection.
+fn internal_rate_of_return(cash_flows: &[(f64, f64)]) -> Option<f64> {
+    let npv = |rate: f64| -> f64 {
+        cash_flows
+            .iter()
+            .map(|(years, amount)| amount / (1f64 + rate).powf(*years))
+            .sum()
+    };
+    let (mut low, mut high): (f64, f64) = (-0.9999, 1e4);
+    if npv(low).signum() == npv(high).signum() {
+        return None;
+    }
+    for _ in 0..200 {
+        let mid: f64 = (low + high) / 2f64;
+        if npv(mid).signum() == npv(low).signum() {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    Some((low + high) / 2f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the valuation of a day of 2024.
+    fn day(ordinal: u32, value: f64, flow: f64) -> DailyValuation {
+        DailyValuation {
+            date: NaiveDate::from_yo_opt(2024, ordinal).unwrap(),
+            balance: 1f64,
+            price: value,
+            value,
+            flow,
+            source: "test".to_string(),
+        }
+    }
+
+    #[test]
+    fn chains_the_daily_returns() {
+        let series: Vec<DailyValuation> = vec![
+            day(1, 100f64, 100f64),
+            day(2, 110f64, 0f64),
+            day(3, 121f64, 0f64),
+        ];
+        let returns: Returns = compute_returns(&series, None, None).unwrap();
+        assert!((returns.time_weighted - 0.21).abs() < 1e-9);
+        assert_eq!(returns.from, series[0].date);
+        assert_eq!(returns.to, series[2].date);
+    }
+
+    #[test]
+    fn time_weighted_return_ignores_the_flows() {
+        // The price doubles, the position is doubled at the top, and the
+        // price falls back: the prices are unchanged, but money was lost.
+        let series: Vec<DailyValuation> = vec![
+            day(1, 100f64, 100f64),
+            day(183, 400f64, 200f64),
+            day(366, 200f64, 0f64),
+        ];
+        let returns: Returns = compute_returns(&series, None, None).unwrap();
+        assert!(returns.time_weighted.abs() < 1e-9);
+        assert!(returns.money_weighted.unwrap() < 0f64);
+    }
+
+    #[test]
+    fn money_weighted_return_is_annualized() {
+        // 100 invested at the end of the first day are worth 110 a year
+        // later.
+        let series: Vec<DailyValuation> = vec![day(1, 100f64, 100f64), day(366, 110f64, 0f64)];
+        let returns: Returns = compute_returns(&series, None, None).unwrap();
+        assert!((returns.money_weighted.unwrap() - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn value_before_the_period_is_the_initial_investment() {
+        let series: Vec<DailyValuation> = vec![
+            day(1, 100f64, 100f64),
+            day(2, 110f64, 0f64),
+            day(3, 121f64, 0f64),
+        ];
+        let returns: Returns = compute_returns(&series, Some(series[2].date), None).unwrap();
+        assert!((returns.time_weighted - 0.1).abs() < 1e-9);
+        assert!(compute_returns(&series, NaiveDate::from_yo_opt(2024, 4), None).is_none());
+    }
+
+    #[test]
+    fn parses_the_period() {
+        let date = |day: u32| NaiveDate::from_ymd_opt(2024, 1, day);
+        assert_eq!(
+            parse_period("2024-01-01:2024-01-31"),
+            Ok((date(1), date(31)))
+        );
+        assert_eq!(parse_period(":2024-01-31"), Ok((None, date(31))));
+        assert_eq!(parse_period("2024-01-01:"), Ok((date(1), None)));
+        assert!(parse_period("2024-01-01").is_err());
+        assert!(parse_period("2024-13-01:").is_err());
+    }
+}
@@ -0,0 +1,187 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module checks the exposure of the open position against the limits of the
+`[risk]` section of the configuration file. The open lots are valued at the
+live price of Kraken's public Ticker endpoint, and the position is stated as a
+percentage of the configured portfolio value, which is given in the quote
+currency of the trading pair.
+
+The check runs once per invocation; scheduling it, e.g. via cron, turns it into
+a recurring risk check.
+*/
+
+use crate::fifo::Lot;
+use crate::kraken::KrakenAPI;
+use crate::precision::{AssetPair, Precision};
+use log::warn;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Deserialize, Debug)]
+struct TickerResponse {
+    error: Vec<String>,
+    result: Option<HashMap<String, Value>>,
+}
+
+/// The limits the exposure is checked against.
+#[derive(Debug, Clone)]
+pub struct RiskLimits {
+    /// The value of the portfolio in the quote currency.
+    pub portfolio_value: f64,
+    /// The maximum size of a position as a percentage of the portfolio value.
+    pub max_position: Option<f64>,
+    /// The maximum sizes of the positions in single trading pairs, which take
+    /// precedence over `max_position`.
+    pub limits: HashMap<String, f64>,
+}
+
+impl RiskLimits {
+    /// Returns the maximum size of the position in a trading pair, if any.
+    pub fn limit(&self, symbol: &str) -> Option<f64> {
+        self.limits.get(symbol).copied().or(self.max_position)
+    }
+}
+
+/// The exposure of the open position of a trading pair.
+#[derive(Debug)]
+pub struct Exposure {
+    /// The amount of the base currency held, negative for short positions.
+    pub amount: f64,
+    /// The cost of the open lots.
+    pub cost: f64,
+    /// The live price.
+    pub price: f64,
+    /// The value of the position at the live price, negative for short
+    /// positions.
+    pub value: f64,
+    /// The absolute value of the position as a percentage of the portfolio
+    /// value.
+    pub share: f64,
+    /// The maximum share of the position, if any.
+    pub limit: Option<f64>,
+}
+
+impl Exposure {
+    /// Returns whether the position exceeds its limit.
+    pub fn exceeds_limit(&self) -> bool {
+        self.limit.is_some_and(|limit| self.share > limit)
+    }
+}
+
+/// Fetches the price of the last trade of a trading pair.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `pair` - The name of the trading pair (e.g., XXBTZEUR).
+///
+pub async fn fetch_ticker_price(api: &KrakenAPI, pair: &str) -> Result<f64, String> {
+    let response: TickerResponse = serde_json::from_str(
+        &api.public_request("/0/public/Ticker", vec![("pair", pair.to_string())])
+            .await,
+    )
+    .map_err(|e| format!("Failed to parse response: {}", e))?;
+    // Kraken keys the result by the pair's name, which may differ from the
+    // requested alternative name, so the only entry is taken.
+    response
+        .result
+        .and_then(|result| result.into_values().next())
+        .and_then(|ticker| ticker["c"][0].as_str().and_then(|c| c.parse().ok()))
+        .ok_or_else(|| format!("Error fetching the price of {}: {:?}", pair, response.error))
+}
+
+/// Computes the exposure of the open lots.
+///
+/// # Arguments
+///
+/// * `symbol` - The trading pair.
+/// * `open_lots` - The open lots.
+/// * `price` - The live price.
+/// * `limits` - The portfolio value and the limits.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn compute_exposure(
+    symbol: &str,
+    open_lots: &[Lot],
+    price: f64,
+    limits: &RiskLimits,
+    precision: &Precision,
+) -> Exposure {
+    let amount: f64 = precision.base(open_lots.iter().map(|lot| lot.amount).sum());
+    let cost: f64 = precision.quote(open_lots.iter().map(|lot| lot.cost).sum());
+    let value: f64 = precision.quote(amount * price);
+    let share: f64 = if limits.portfolio_value == 0f64 {
+        0f64
+    } else {
+        value.abs() / limits.portfolio_value * 100f64
+    };
+    Exposure {
+        amount,
+        cost,
+        price,
+        value,
+        share,
+        limit: limits.limit(symbol),
+    }
+}
+
+/// Prints the exposure and warns if it exceeds the limit.
+///
+/// # Arguments
+///
+/// * `exposure` - The exposure of the open position.
+/// * `portfolio_value` - The portfolio value the share refers to.
+/// * `asset_pair` - The assets of the trading pair.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn print_exposure(
+    exposure: &Exposure,
+    portfolio_value: f64,
+    asset_pair: &AssetPair,
+    precision: &Precision,
+) {
+    println!(
+        "Exposure at the live price of {} {}:",
+        precision.fmt_quote(exposure.price),
+        asset_pair.quote
+    );
+    println!(
+        "  Position: {} {} ({})",
+        precision.fmt_base(exposure.amount),
+        asset_pair.base,
+        if exposure.amount < 0f64 {
+            "short"
+        } else {
+            "long"
+        }
+    );
+    println!(
+        "  Value: {} {} (cost {}, unrealized PnL {})",
+        precision.fmt_quote(exposure.value),
+        asset_pair.quote,
+        precision.fmt_quote(exposure.cost),
+        precision.fmt_quote(exposure.value - exposure.cost)
+    );
+    println!(
+        "  Share of the portfolio value of {} {}: {:.2}%{}",
+        precision.fmt_quote(portfolio_value),
+        asset_pair.quote,
+        exposure.share,
+        exposure
+            .limit
+            .map(|limit| format!(" (limit {:.2}%)", limit))
+            .unwrap_or_default()
+    );
+    if exposure.exceeds_limit() {
+        warn!(
+            "The position in {} of {:.2}% exceeds the limit of {:.2}% of the portfolio value!",
+            asset_pair.base,
+            exposure.share,
+            exposure.limit.unwrap_or_default()
+        );
+    }
+}
@@ -0,0 +1,86 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module contains the run registry and the guard against concurrent runs.
+
+Every completed run is recorded in the cache together with its arguments, its
+duration, the API usage and the files it produced. Runs against the same
+account share Kraken's API counter and would exceed it when running at the
+same time, so every run holds a lock file next to the cache for the duration
+of the run.
+*/
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+
+/// A completed run.
+#[derive(Debug)]
+pub struct Run {
+    /// The start of the run as RFC 3339 timestamp.
+    pub started_at: String,
+    /// The account identifier, empty if the run didn't access the API.
+    pub account: String,
+    pub arguments: String,
+    pub symbols: Vec<String>,
+    /// The duration in seconds.
+    pub duration: f64,
+    pub requests: usize,
+    /// The sum of the costs of the private requests.
+    pub api_points: f64,
+    /// The time spent waiting for the API counter to decay in seconds.
+    pub rate_limit_wait: f64,
+    /// The number of requests rejected because of the rate limit.
+    pub rate_limited: usize,
+    /// The files written by the run.
+    pub outputs: Vec<String>,
+}
+
+/// A lock preventing concurrent runs against the same account. The lock is
+/// released when the guard is dropped or the process exits.
+#[derive(Debug)]
+pub struct RunLock {
+    _file: File,
+}
+
+/// Returns the path of the lock file of an account.
+fn lock_path(cache_path: &Path, account: &str) -> PathBuf {
+    cache_path.with_file_name(format!("run-{}.lock", account))
+}
+
+/// Locks an account for the duration of a run.
+///
+/// # Arguments
+///
+/// * `cache_path` - The path of the cache, the lock file is created next to it.
+/// * `account` - The account identifier.
+///
+/// # Returns
+///
+/// The lock or an error if another run against the same account is in
+/// progress.
+///
+pub fn lock_account(cache_path: &Path, account: &str) -> Result<RunLock, String> {
+    let path: PathBuf = lock_path(cache_path, account);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let file: File = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    match file.try_lock() {
+        Ok(()) => Ok(RunLock { _file: file }),
+        Err(TryLockError::WouldBlock) => Err(format!(
+            "Another run against the same account is in progress (lock: {}), pass --force to run anyway!",
+            path.display()
+        )),
+        Err(TryLockError::Error(e)) => {
+            Err(format!("Failed to lock {}: {}", path.display(), e))
+        }
+    }
+}
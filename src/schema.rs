@@ -0,0 +1,143 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module implements the versioning of the data that is stored in the layout
+of the Kraken API to be read again later, i.e. the responses recorded as
+fixtures via `--record`, the pages of the checkpoint and the trades and ledger
+entries of the state files. Every stored response or file carries the version
+of its layout, so archives written years ago keep working after Kraken changed
+its schema:
+
+- Fields Kraken added over the years are missing in older archives, and fields
+  it dropped are still in them. The structs the entries are parsed into thus
+  default every missing field and ignore unknown ones.
+- Fields Kraken renamed or restructured are migrated by `upgrade`, one version
+  after the other, before the archive is parsed.
+
+Version 0 denotes the archives stored before they were versioned, i.e. the
+bare responses of fixtures, the checkpoint pages and the state files without a
+version.
+*/
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The version of the layout of the archives stored by this version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A stored response together with the version of its layout.
+#[derive(Serialize, Deserialize)]
+struct Versioned {
+    schema: u32,
+    /// The response, embedded as JSON so the fixtures stay readable, or as a
+    /// string if it isn't JSON.
+    response: Value,
+}
+
+/// Wraps a response into the versioned layout of a fixture.
+///
+/// # Arguments
+///
+/// * `response` - The response as received from the API.
+///
+pub fn wrap(response: &str) -> String {
+    let versioned: Versioned = Versioned {
+        schema: SCHEMA_VERSION,
+        response: serde_json::from_str(response)
+            .unwrap_or_else(|_| Value::String(response.to_string())),
+    };
+    serde_json::to_string_pretty(&versioned).unwrap()
+}
+
+/// Unwraps a fixture into the response in the current layout. Fixtures
+/// without a version are taken as the bare responses of version 0.
+///
+/// # Arguments
+///
+/// * `stored` - The content of the fixture.
+///
+pub fn unwrap(stored: &str) -> Result<String, String> {
+    match serde_json::from_str::<Versioned>(stored) {
+        Ok(Versioned {
+            schema,
+            response: Value::String(response),
+        }) => upgrade(&response, schema),
+        Ok(Versioned { schema, response }) => upgrade(&response.to_string(), schema),
+        Err(_) => upgrade(stored, 0),
+    }
+}
+
+/// Returns the version of the layout of a stored archive, i.e. its `schema`
+/// field, or 0 if it has none.
+///
+/// # Arguments
+///
+/// * `stored` - The content of the archive.
+///
+pub fn version_of(stored: &str) -> u32 {
+    serde_json::from_str::<Value>(stored)
+        .ok()
+        .and_then(|value| value.get("schema").and_then(Value::as_u64))
+        .map_or(0, |schema| schema as u32)
+}
+
+/// Migrates a stored archive to the current layout.
+///
+/// # Arguments
+///
+/// * `stored` - The content of the archive.
+/// * `schema` - The version of its layout.
+///
+pub fn upgrade(stored: &str, schema: u32) -> Result<String, String> {
+    if schema > SCHEMA_VERSION {
+        return Err(format!(
+            "The archive was stored with schema version {}, but only versions up to {} are \
+             supported. Update the calculator to read it!",
+            schema, SCHEMA_VERSION
+        ));
+    }
+    // Version 1 only added the version itself, the fields that changed until
+    // then are covered by the defaults of the structs.
+    Ok(stored.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kraken::Trade;
+
+    #[test]
+    fn takes_unversioned_archives_as_version_0() {
+        assert_eq!(version_of(r#"{"tool": "kraken-pnl-calculator"}"#), 0);
+        assert_eq!(version_of(r#"{"schema": 1}"#), 1);
+    }
+
+    #[test]
+    fn unwraps_wrapped_responses() {
+        let response: &str = r#"{"error":[],"result":{"count":0}}"#;
+        assert_eq!(unwrap(&wrap(response)).unwrap(), response);
+        assert_eq!(unwrap("not json").unwrap(), "not json");
+    }
+
+    #[test]
+    fn rejects_archives_of_a_newer_version() {
+        assert!(upgrade("{}", SCHEMA_VERSION).is_ok());
+        assert!(upgrade("{}", SCHEMA_VERSION + 1).is_err());
+    }
+
+    #[test]
+    fn parses_trades_of_an_older_layout() {
+        // A trade without the fields Kraken added later, but with one it
+        // dropped since.
+        let trade: Trade = serde_json::from_str(
+            r#"{"ordertxid": "O1", "pair": "XXBTZEUR", "time": 1.5, "type": "buy",
+                "price": "100", "fee": "0.1", "vol": "1", "cost": "100", "dropped": "x"}"#,
+        )
+        .unwrap();
+        assert_eq!(trade.pair, "XXBTZEUR");
+        assert_eq!(trade.ordertype, "");
+        assert_eq!(trade.margin, "");
+    }
+}
@@ -0,0 +1,248 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module expands the selection of trading pairs. Besides the names of
+single pairs, `--symbol` accepts glob patterns such as `X*ZEUR`, and `--quote`
+selects all pairs traded against a currency. Both are expanded to the pairs the
+account traded within the selected period, which are discovered from the trade
+history, or from the cache if the data is read from it.
+
+The symbols and patterns also match the other names Kraken uses for a pair,
+e.g. XBTEUR or BTC/EUR for XXBTZEUR, and are resolved to the name of the pair,
+which the trade history and the cache refer to.
+
+The `pairs` subcommand lists the pairs found in the account with the number of
+trades and the period they were traded in, so the symbols worth computing are
+known before committing to long runs.
+*/
+
+use crate::cache::Cache;
+use crate::kraken::{fetch_traded_pairs, KrakenAPI, Trade};
+use crate::precision::{fetch_pair_aliases, PairAliases};
+use crate::report::print_table;
+use chrono::DateTime;
+use log::{info, warn};
+use std::collections::BTreeMap;
+
+/// Returns whether a symbol is a glob pattern.
+pub fn is_pattern(symbol: &str) -> bool {
+    symbol.contains(['*', '?'])
+}
+
+/// Returns whether a name matches a glob pattern, where `*` matches any
+/// sequence of characters and `?` any single character.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0usize, 0usize);
+    // The position of the last `*` and the part of the name it matched up to.
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Expands the selected symbols to the traded pairs.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `cache` - The cache to discover the pairs from instead of the trade
+///   history, if the data is read from it.
+/// * `symbols` - The selected symbols, names or aliases of pairs or glob
+///   patterns.
+/// * `quote` - An optional quote currency (e.g., EUR or ZEUR) the pairs must
+///   be traded against.
+/// * `start` - An optional start of the period the pairs were traded in.
+/// * `end` - An optional end of the period the pairs were traded in.
+///
+/// # Returns
+///
+/// The names of the selected pairs, in the order of the symbols and sorted by
+/// name for every pattern, or an error if the pairs cannot be discovered.
+/// Symbols Kraken doesn't know are returned as they are.
+///
+pub async fn expand_symbols(
+    api: &KrakenAPI,
+    cache: Option<&Cache>,
+    symbols: &[String],
+    quote: Option<&str>,
+    start: Option<f64>,
+    end: Option<f64>,
+) -> Result<Vec<String>, String> {
+    let aliases: PairAliases = fetch_pair_aliases(api).await?;
+    let resolve = |symbol: &String| match aliases.canonical(symbol) {
+        Some(name) => {
+            if name != symbol {
+                info!("Resolved {} to the trading pair {}.", symbol, name);
+            }
+            name.to_string()
+        }
+        None => symbol.clone(),
+    };
+    if quote.is_none() && !symbols.iter().any(|symbol| is_pattern(symbol)) {
+        return Ok(symbols.iter().map(resolve).collect());
+    }
+    // The trade history may state the pairs by another name.
+    let mut traded: Vec<String> = match cache {
+        Some(cache) => cache
+            .pairs()
+            .map_err(|e| format!("Failed to read the cached pairs: {}", e))?,
+        None => fetch_traded_pairs(api, start, end).await,
+    }
+    .iter()
+    .map(|pair| aliases.canonical(pair).unwrap_or(pair).to_string())
+    .collect();
+    traded.sort();
+    traded.dedup();
+
+    let mut selected: Vec<String> = Vec::new();
+    let mut select = |pair: &String| {
+        if !selected.contains(pair) {
+            selected.push(pair.clone());
+        }
+    };
+    for symbol in symbols {
+        if is_pattern(symbol) {
+            let matches: Vec<&String> = traded
+                .iter()
+                .filter(|pair| {
+                    aliases.get(pair).map_or_else(
+                        || glob_match(symbol, pair),
+                        |asset_pair| {
+                            asset_pair
+                                .aliases()
+                                .iter()
+                                .any(|alias| glob_match(symbol, alias))
+                        },
+                    )
+                })
+                .collect();
+            if matches.is_empty() {
+                warn!("No traded pair matches {}.", symbol);
+            }
+            matches.into_iter().for_each(&mut select);
+        } else {
+            select(&resolve(symbol));
+        }
+    }
+
+    if let Some(quote) = quote {
+        let mut filtered: Vec<String> = Vec::new();
+        for pair in selected {
+            match aliases.get(&pair) {
+                Some(asset_pair)
+                    if asset_pair.quote == quote || asset_pair.quote_code() == quote =>
+                {
+                    filtered.push(pair)
+                }
+                Some(_) => {}
+                None => warn!("Unknown trading pair {}, skipping it.", pair),
+            }
+        }
+        selected = filtered;
+    }
+    if !selected.is_empty() {
+        info!("Selected the trading pairs {}.", selected.join(", "));
+    }
+    Ok(selected)
+}
+
+/// The trades of a pair found in the account.
+#[derive(Debug)]
+pub struct TradedPair {
+    /// The name of the pair (e.g., XXBTZEUR).
+    pub pair: String,
+    /// The common name of the pair (e.g., BTC/EUR), if Kraken knows it.
+    pub common_name: Option<String>,
+    pub trades: usize,
+    pub first_trade: f64,
+    pub last_trade: f64,
+}
+
+/// Summarizes the trades by pair.
+///
+/// # Arguments
+///
+/// * `trades` - The trades of all pairs sorted by time.
+/// * `aliases` - The names of the listed pairs, which the names of the traded
+///   pairs are resolved with.
+///
+/// # Returns
+///
+/// The traded pairs sorted by name.
+///
+pub fn summarize_traded_pairs(trades: &[Trade], aliases: &PairAliases) -> Vec<TradedPair> {
+    let mut pairs: BTreeMap<String, TradedPair> = BTreeMap::new();
+    for trade in trades {
+        let name: &str = aliases.canonical(&trade.pair).unwrap_or(&trade.pair);
+        let pair: &mut TradedPair = pairs.entry(name.to_string()).or_insert_with(|| TradedPair {
+            pair: name.to_string(),
+            common_name: aliases.get(name).map(|asset_pair| {
+                format!("{}/{}", asset_pair.base_code(), asset_pair.quote_code())
+            }),
+            trades: 0,
+            first_trade: trade.time,
+            last_trade: trade.time,
+        });
+        pair.trades += 1;
+        pair.first_trade = pair.first_trade.min(trade.time);
+        pair.last_trade = pair.last_trade.max(trade.time);
+    }
+    pairs.into_values().collect()
+}
+
+/// Prints the traded pairs.
+///
+/// # Arguments
+///
+/// * `pairs` - The traded pairs.
+///
+pub fn print_traded_pairs(pairs: &[TradedPair]) {
+    let date = |time: f64| {
+        DateTime::from_timestamp(time as i64, 0)
+            .map(|time| time.date_naive().to_string())
+            .unwrap_or_default()
+    };
+    let header: Vec<String> = ["Pair", "Name", "Trades", "First trade", "Last trade"]
+        .iter()
+        .map(|column| column.to_string())
+        .collect();
+    let rows: Vec<Vec<String>> = pairs
+        .iter()
+        .map(|pair| {
+            vec![
+                pair.pair.clone(),
+                pair.common_name.clone().unwrap_or_else(|| "-".to_string()),
+                pair.trades.to_string(),
+                date(pair.first_trade),
+                date(pair.last_trade),
+            ]
+        })
+        .collect();
+    print_table(&header, &rows, 2);
+    if pairs.is_empty() {
+        println!("No trades found in the selected period.");
+    } else {
+        println!(
+            "{} trades of {} pairs.",
+            pairs.iter().map(|pair| pair.trades).sum::<usize>(),
+            pairs.len()
+        );
+    }
+}
@@ -0,0 +1,258 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module implements the `serve` subcommand, a small JSON API answering
+queries from the local cache, so dashboards and other tools can get the PnL
+without running the CLI:
+
+- `GET /pairs`: The cached trading pairs.
+- `GET /pnl?symbol=XXBTZEUR&year=2024`: The FIFO PnL of a pair.
+- `GET /trades?symbol=XXBTZEUR&start=2024-01-01&end=2024-12-31`: The trades.
+- `GET /lots?symbol=XXBTZEUR`: The lots that are open after the trades.
+
+Every endpoint except `/pairs` requires `symbol` and accepts `year`, `start`
+and `end`. The trades are read from the cache on every request, so a `fetch`
+running in parallel, e.g. via `--watch`, is picked up right away. Only the
+public API is used, for the precision of the pairs.
+*/
+
+use crate::cache::Cache;
+use crate::fifo::{compute_fifo_pnl, PnL};
+use crate::fiscal::YearStart;
+use crate::http::with_limits;
+use crate::kraken::{KrakenAPI, Trade};
+use crate::precision::{fetch_precision, try_fetch_asset_pair, AssetPair, Precision};
+use axum::extract::{Query as Params, State};
+use axum::http::{Method, StatusCode, Uri};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::NaiveDate;
+use log::info;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+
+/// The parameters of a query.
+struct Query {
+    symbol: String,
+    year: Option<u32>,
+    start: Option<f64>,
+    end: Option<f64>,
+}
+
+/// A response as status and JSON body.
+type Response = (StatusCode, Value);
+
+/// Returns a response with an error.
+fn error(status: StatusCode, message: String) -> Response {
+    (status, json!({"error": message}))
+}
+
+/// Parses the parameters shared by the endpoints of a trading pair.
+fn parse_query(params: &HashMap<String, String>) -> Result<Query, Response> {
+    let symbol: String = params
+        .get("symbol")
+        .map(|symbol| symbol.to_uppercase())
+        .ok_or_else(|| error(StatusCode::BAD_REQUEST, "symbol is required".to_string()))?;
+    let year: Option<u32> = params
+        .get("year")
+        .map(|year| {
+            year.parse()
+                .map_err(|_| error(StatusCode::BAD_REQUEST, format!("Invalid year '{}'", year)))
+        })
+        .transpose()?;
+    // The dates are inclusive, like --start and --end.
+    let timestamp = |name: &str, (hour, min, sec): (u32, u32, u32)| {
+        params
+            .get(name)
+            .map(|date| {
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map(|date| {
+                        date.and_hms_opt(hour, min, sec)
+                            .unwrap()
+                            .and_utc()
+                            .timestamp() as f64
+                    })
+                    .map_err(|_| {
+                        error(
+                            StatusCode::BAD_REQUEST,
+                            format!("Invalid {} '{}', expected YYYY-MM-DD", name, date),
+                        )
+                    })
+            })
+            .transpose()
+    };
+    Ok(Query {
+        symbol,
+        year,
+        start: timestamp("start", (0, 0, 0))?,
+        end: timestamp("end", (23, 59, 59))?,
+    })
+}
+
+/// The JSON API backed by the cache.
+struct Server {
+    api: KrakenAPI,
+    cache: Cache,
+    year_start: YearStart,
+    /// The assets and the precision of the pairs queried so far.
+    pairs: HashMap<String, (AssetPair, Precision)>,
+}
+
+impl Server {
+    /// Answers a request.
+    async fn handle(&mut self, path: &str, params: &HashMap<String, String>) -> Response {
+        if path == "/pairs" {
+            return match self.cache.pairs() {
+                Ok(pairs) => (StatusCode::OK, json!(pairs)),
+                Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            };
+        }
+        if !["/pnl", "/trades", "/lots"].contains(&path) {
+            return error(
+                StatusCode::NOT_FOUND,
+                format!(
+                    "Unknown endpoint {}, expected /pairs, /pnl, /trades or /lots",
+                    path
+                ),
+            );
+        }
+        let query: Query = match parse_query(params) {
+            Ok(query) => query,
+            Err(response) => return response,
+        };
+        let trades: Vec<Trade> = match self
+            .cache
+            .load_trades(&query.symbol, query.start, query.end)
+        {
+            Ok(trades) if trades.is_empty() => {
+                return error(
+                    StatusCode::NOT_FOUND,
+                    format!("No cached trades of {}", query.symbol),
+                )
+            }
+            Ok(trades) => trades,
+            Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        };
+        if path == "/trades" {
+            let trades: Vec<Value> = trades
+                .into_iter()
+                .map(|trade| {
+                    let mut value: Value = json!(trade);
+                    value["txid"] = json!(trade.txid);
+                    value
+                })
+                .collect();
+            return (StatusCode::OK, json!(trades));
+        }
+
+        let Some((asset_pair, precision)) = self.asset_pair(&query.symbol).await else {
+            return error(
+                StatusCode::NOT_FOUND,
+                format!("Unknown trading pair {}", query.symbol),
+            );
+        };
+        let pnl: PnL = compute_fifo_pnl(&trades, query.year, self.year_start, &precision);
+        if path == "/lots" {
+            return (StatusCode::OK, json!(pnl.open_lots));
+        }
+        (
+            StatusCode::OK,
+            json!({
+                "symbol": query.symbol,
+                "base": asset_pair.base_code(),
+                "quote": asset_pair.quote_code(),
+                "year": query.year,
+                "trades": trades.len(),
+                "realized_pnl": pnl.realized_pnl,
+                "unrealized_pnl": pnl.unrealized_pnl,
+                "price": pnl.price,
+                "balance": pnl.balance,
+                "total_buy_volume_base": pnl.total_buy_volume_base,
+                "total_sell_volume_base": pnl.total_sell_volume_base,
+                "total_buy_volume_quote": pnl.total_buy_volume_quote,
+                "total_sell_volume_quote": pnl.total_sell_volume_quote,
+                "total_cost_of_sold_assets": pnl.total_cost_of_sold_assets,
+                "total_value_of_sold_assets": pnl.total_value_of_sold_assets,
+                "disposals": pnl.disposals.len(),
+                "open_lots": pnl.open_lots.len(),
+            }),
+        )
+    }
+
+    /// Returns the assets and the precision of a trading pair, which are
+    /// looked up once.
+    async fn asset_pair(&mut self, symbol: &str) -> Option<(AssetPair, Precision)> {
+        if !self.pairs.contains_key(symbol) {
+            let asset_pair: AssetPair = try_fetch_asset_pair(&self.api, symbol).await?;
+            let precision: Precision = fetch_precision(&self.api, &asset_pair).await;
+            self.pairs
+                .insert(symbol.to_string(), (asset_pair, precision));
+        }
+        self.pairs.get(symbol).cloned()
+    }
+}
+
+/// The server shared by the connections.
+type Shared = Arc<Mutex<Server>>;
+
+/// Answers a GET request of any path.
+async fn answer(
+    State(server): State<Shared>,
+    uri: Uri,
+    Params(params): Params<HashMap<String, String>>,
+) -> (StatusCode, Json<Value>) {
+    let (status, body): Response = server.lock().await.handle(uri.path(), &params).await;
+    info!("GET {} -> {}", uri.path(), status);
+    (status, Json(body))
+}
+
+/// Answers a request of any other method.
+async fn method_not_allowed(method: Method, uri: Uri) -> (StatusCode, Json<Value>) {
+    let (status, body): Response = error(
+        StatusCode::METHOD_NOT_ALLOWED,
+        format!("{} is not supported", method),
+    );
+    info!("{} {} -> {}", method, uri.path(), status);
+    (status, Json(body))
+}
+
+/// Serves the JSON API until the process is stopped. The requests are
+/// answered one after the other.
+///
+/// # Arguments
+///
+/// * `api` - The client of the public Kraken API.
+/// * `cache` - The cache the data is read from.
+/// * `address` - The address to listen on (e.g., 127.0.0.1:8787).
+/// * `year_start` - The first day of the fiscal years `year` refers to.
+///
+pub async fn serve(
+    api: KrakenAPI,
+    cache: Cache,
+    address: &str,
+    year_start: YearStart,
+) -> Result<(), String> {
+    let listener: TcpListener = TcpListener::bind(address)
+        .await
+        .map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+    info!("Serving the JSON API at http://{}", address);
+    let server: Shared = Arc::new(Mutex::new(Server {
+        api,
+        cache,
+        year_start,
+        pairs: HashMap::new(),
+    }));
+    let router: Router = with_limits(
+        Router::new()
+            .fallback(get(answer).fallback(method_not_allowed))
+            .with_state(server),
+    );
+    axum::serve(listener, router)
+        .await
+        .map_err(|e| format!("Failed to serve the JSON API: {}", e))
+}
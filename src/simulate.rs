@@ -0,0 +1,199 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module simulates a sale (`simulate --sell AMOUNT [--price PRICE]`) to
+check its tax consequences before trading: The sale is appended to the trades
+and runs through the lot engine with the options of the run, i.e. the lot
+selection, the wash sale rule and the dust threshold. Nothing is written,
+neither the lots nor any report.
+*/
+
+use crate::cost_basis::LotSelection;
+use crate::fifo::{compute_pnl, Disposal, Lot, PnL, TradePnL};
+use crate::fiscal::YearStart;
+use crate::kraken::Trade;
+use crate::precision::Precision;
+use crate::tax::{classify_disposals, TaxRules};
+use crate::wash_sale::WashSaleRule;
+use chrono::DateTime;
+
+/// The transaction id of the simulated sale.
+const SIMULATED_TXID: &str = "SIMULATED";
+
+/// The outcome of a simulated sale.
+#[derive(Debug)]
+pub struct Simulation {
+    pub amount: f64,
+    pub price: f64,
+    pub time: f64,
+    /// The (parts of) lots the sale disposes of.
+    pub disposals: Vec<Disposal>,
+    pub proceeds: f64,
+    pub cost_basis: f64,
+    pub realized_pnl: f64,
+    /// The realized PnL per category of the tax rules, if any.
+    pub categories: Vec<(&'static str, f64)>,
+    /// The amount the sale exceeds the holdings by, which opens a short lot.
+    pub exceeded: f64,
+    /// The lots left after the sale.
+    pub open_lots: Vec<Lot>,
+}
+
+/// Simulates a sale after the last trade.
+///
+/// # Arguments
+///
+/// * `trades` - The trades the PnL is computed from, sorted by time.
+/// * `pair` - The trading pair.
+/// * `amount` - The amount of the base currency to sell.
+/// * `price` - The price to sell at.
+/// * `precision` - The precision of the base and quote currency.
+/// * `selection` - The selection of the lots to dispose of.
+/// * `wash_sale` - The wash sale rule to apply, if any.
+/// * `dust_threshold` - The amount below which the lots left are swept.
+/// * `rules` - The tax rules to classify the disposals by, if any.
+///
+#[allow(clippy::too_many_arguments)]
+pub fn simulate_sale(
+    trades: &[Trade],
+    pair: &str,
+    amount: f64,
+    price: f64,
+    precision: &Precision,
+    selection: &LotSelection,
+    wash_sale: Option<WashSaleRule>,
+    dust_threshold: f64,
+    rules: Option<TaxRules>,
+) -> Simulation {
+    // The sale happens now, but never before the last trade.
+    let time: f64 = trades
+        .last()
+        .map(|trade| trade.time + 1f64)
+        .unwrap_or(0f64)
+        .max(chrono::Utc::now().timestamp() as f64);
+    let amount: f64 = precision.base(amount);
+    let sale = Trade {
+        txid: SIMULATED_TXID.to_string(),
+        ordertxid: SIMULATED_TXID.to_string(),
+        pair: pair.to_string(),
+        time,
+        side: "sell".to_string(),
+        price: price.to_string(),
+        fee: "0".to_string(),
+        vol: amount.to_string(),
+        cost: precision.quote(amount * price).to_string(),
+        ordertype: "simulated".to_string(),
+        margin: String::new(),
+        misc: String::new(),
+        postxid: String::new(),
+        maker: None,
+        base_fee: None,
+        source: None,
+    };
+    let trades: Vec<Trade> = trades.iter().cloned().chain([sale]).collect();
+    let pnl: PnL = compute_pnl(
+        &trades,
+        None,
+        YearStart::default(),
+        precision,
+        selection,
+        wash_sale,
+        dust_threshold,
+    );
+    let mut disposals: Vec<Disposal> = pnl
+        .disposals
+        .into_iter()
+        .filter(|disposal| disposal.disposal_ordertxid == SIMULATED_TXID)
+        .collect();
+    let categories: Vec<(&'static str, f64)> = match rules {
+        Some(rules) => classify_disposals(rules, &mut disposals),
+        None => Vec::new(),
+    };
+    let trade_pnl: TradePnL = pnl
+        .trade_pnl
+        .get(SIMULATED_TXID)
+        .cloned()
+        .unwrap_or_default();
+    Simulation {
+        amount,
+        price,
+        time,
+        proceeds: precision.quote(disposals.iter().map(|disposal| disposal.proceeds).sum()),
+        cost_basis: trade_pnl.cost_basis,
+        realized_pnl: trade_pnl.realized_pnl,
+        disposals,
+        categories,
+        exceeded: pnl
+            .short_sales
+            .iter()
+            .filter(|sale| sale.ordertxid == SIMULATED_TXID)
+            .map(|sale| sale.amount)
+            .sum(),
+        open_lots: pnl.open_lots,
+    }
+}
+
+/// Returns the UTC date of a UNIX timestamp as text.
+fn format_date(time: f64) -> String {
+    DateTime::from_timestamp_nanos((time * 1e9) as i64)
+        .date_naive()
+        .to_string()
+}
+
+/// Prints the outcome of a simulated sale.
+///
+/// # Arguments
+///
+/// * `simulation` - The simulated sale.
+/// * `asset` - The name of the base asset.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn print_simulation(simulation: &Simulation, asset: &str, precision: &Precision) {
+    println!(
+        "Simulated sale of {} {} at {} on {}:",
+        precision.fmt_base(simulation.amount),
+        asset,
+        simulation.price,
+        format_date(simulation.time)
+    );
+    println!(
+        "{:<12} {:>18} {:>16} {:>16} {:>16}  Category",
+        "Acquired",
+        format!("Amount ({})", asset),
+        "Cost",
+        "Proceeds",
+        "Gain"
+    );
+    for disposal in &simulation.disposals {
+        println!(
+            "{:<12} {:>18} {:>16} {:>16} {:>16}  {}",
+            disposal
+                .acquisition_time
+                .map(format_date)
+                .unwrap_or_else(|| "-".to_string()),
+            precision.fmt_base(disposal.amount),
+            precision.fmt_quote(disposal.acquisition_cost),
+            precision.fmt_quote(disposal.proceeds),
+            precision.fmt_quote(disposal.gain),
+            disposal.classification.as_deref().unwrap_or("-")
+        );
+    }
+    println!("Proceeds: {}", precision.fmt_quote(simulation.proceeds));
+    println!("Cost Basis: {}", precision.fmt_quote(simulation.cost_basis));
+    println!(
+        "Realized PnL: {}",
+        precision.fmt_quote(simulation.realized_pnl)
+    );
+    for (category, pnl) in &simulation.categories {
+        println!("  {}: {}", category, precision.fmt_quote(*pnl));
+    }
+    if simulation.exceeded > 0f64 {
+        println!(
+            "Exceeding the holdings by: {} {}",
+            precision.fmt_base(simulation.exceeded),
+            asset
+        );
+    }
+}
@@ -0,0 +1,183 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module computes the slippage of the trades against the prices of their
+orders, i.e. the cost hidden in the execution price, which the PnL includes
+without stating it.
+
+The order price is the primary price of the closed order: the limit price of
+limit and iceberg orders and the trigger price of stop-loss and take-profit
+orders, also of their limit variants. Market orders and trailing stops have no
+fixed price to compare the fills to, so they are counted, but not valued. The
+slippage is positive if a buy was filled above or a sell below the order
+price, and negative if the fill improved on it.
+*/
+
+use crate::fiscal::YearStart;
+use crate::kraken::{ClosedOrder, Trade};
+use crate::precision::Precision;
+use crate::report::print_table;
+use std::collections::{BTreeMap, HashMap};
+
+/// The types of orders whose primary price is a fixed price to compare the
+/// fills to.
+const PRICED_ORDER_TYPES: [&str; 6] = [
+    "limit",
+    "iceberg",
+    "stop-loss",
+    "take-profit",
+    "stop-loss-limit",
+    "take-profit-limit",
+];
+
+/// Returns the slippage of a trade against the price of its order, in the
+/// quote currency.
+///
+/// # Arguments
+///
+/// * `trade` - The trade.
+/// * `order` - The closed order of the trade, if known.
+///
+/// # Returns
+///
+/// The slippage and the value of the trade at the order price, or `None` if
+/// the order or its price is not known.
+///
+pub fn trade_slippage(trade: &Trade, order: Option<&ClosedOrder>) -> Option<(f64, f64)> {
+    if !PRICED_ORDER_TYPES.contains(&trade.ordertype.as_str()) {
+        return None;
+    }
+    let order_price: f64 = order?
+        .price
+        .parse::<f64>()
+        .ok()
+        .filter(|price| *price > 0f64)?;
+    let (price, vol): (f64, f64) = (trade.price.parse().ok()?, trade.vol.parse().ok()?);
+    let slippage: f64 = match trade.side.as_str() {
+        "buy" => (price - order_price) * vol,
+        "sell" => (order_price - price) * vol,
+        _ => return None,
+    };
+    Some((slippage, order_price * vol))
+}
+
+/// The slippage of the trades of an order type.
+#[derive(Debug, Default)]
+pub struct SlippageTotals {
+    pub trades: usize,
+    /// The value of the trades at the prices of their orders.
+    pub volume: f64,
+    pub slippage: f64,
+    /// The number of trades filled at a better price than ordered.
+    pub improved: usize,
+}
+
+impl SlippageTotals {
+    /// Returns the slippage in basis points of the volume.
+    pub fn bps(&self) -> f64 {
+        if self.volume > 0f64 {
+            self.slippage / self.volume * 10_000f64
+        } else {
+            0f64
+        }
+    }
+}
+
+/// The slippage by order type.
+#[derive(Debug, Default)]
+pub struct Slippage {
+    pub groups: BTreeMap<String, SlippageTotals>,
+    /// The number of trades without an order price, e.g. of market orders or
+    /// of orders that are not known.
+    pub unpriced: usize,
+}
+
+/// Sums up the slippage of the trades by the type of their orders.
+///
+/// # Arguments
+///
+/// * `trades` - The trades the PnL was computed from. Credits and opening
+///   balances are ignored.
+/// * `orders` - The closed orders by their id.
+/// * `year` - An optional year to filter the trades by, like the disposals.
+/// * `year_start` - The first day of the fiscal years.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn compute_slippage(
+    trades: &[Trade],
+    orders: &HashMap<String, ClosedOrder>,
+    year: Option<u32>,
+    year_start: YearStart,
+    precision: &Precision,
+) -> Slippage {
+    let mut slippage: Slippage = Slippage::default();
+    for trade in trades {
+        if !year_start.contains(year, trade.time) || (trade.side != "buy" && trade.side != "sell") {
+            continue;
+        }
+        let Some((cost, volume)) = trade_slippage(trade, orders.get(&trade.ordertxid)) else {
+            slippage.unpriced += 1;
+            continue;
+        };
+        let totals: &mut SlippageTotals =
+            slippage.groups.entry(trade.ordertype.clone()).or_default();
+        totals.trades += 1;
+        totals.volume = precision.quote(totals.volume + volume);
+        totals.slippage = precision.quote(totals.slippage + cost);
+        if cost < 0f64 {
+            totals.improved += 1;
+        }
+    }
+    slippage
+}
+
+/// Prints the slippage by order type and in total.
+///
+/// # Arguments
+///
+/// * `slippage` - The slippage by order type.
+/// * `quote` - The quote currency of the trading pair.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn print_slippage(slippage: &Slippage, quote: &str, precision: &Precision) {
+    println!("Slippage against the order prices (positive if filled at a worse price):");
+    let header: Vec<String> = vec![
+        "Order type".to_string(),
+        "Trades".to_string(),
+        "Improved".to_string(),
+        format!("Volume ({})", quote),
+        format!("Slippage ({})", quote),
+        "Slippage (bps)".to_string(),
+    ];
+    let mut total: SlippageTotals = SlippageTotals::default();
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for (ordertype, totals) in &slippage.groups {
+        total.trades += totals.trades;
+        total.improved += totals.improved;
+        total.volume = precision.quote(total.volume + totals.volume);
+        total.slippage = precision.quote(total.slippage + totals.slippage);
+        rows.push(row(ordertype, totals, precision));
+    }
+    rows.push(row("total", &total, precision));
+    print_table(&header, &rows, 1);
+    if slippage.unpriced > 0 {
+        println!(
+            "{} trades have no order price to compare to, e.g. those of market orders.",
+            slippage.unpriced
+        );
+    }
+}
+
+/// Returns the row of the totals of an order type.
+fn row(ordertype: &str, totals: &SlippageTotals, precision: &Precision) -> Vec<String> {
+    vec![
+        ordertype.to_string(),
+        totals.trades.to_string(),
+        totals.improved.to_string(),
+        precision.fmt_quote(totals.volume),
+        precision.fmt_quote(totals.slippage),
+        format!("{:.2}", totals.bps()),
+    ]
+}
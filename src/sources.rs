@@ -0,0 +1,235 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module merges the trades imported via `--import` into the trades fetched
+from the Kraken API. Every trade is tagged with its source: `api` for the
+trades of the API, otherwise the exchange of the CSV export.
+
+A trade present in two sources, e.g. in a trades export of Kraken and in the
+trade history of the API, would otherwise be counted twice. Two trades of
+different sources overlap if they have the same id, or the same side, amount
+and price within one second. Of the two, the trade of the preferred source
+(`--prefer-source`) is kept, and the fields they disagree on are noted. The
+decisions are listed in the data-quality report, so they can be reviewed.
+*/
+
+use crate::kraken::Trade;
+use crate::precision::Precision;
+use crate::report::print_table;
+use chrono::DateTime;
+use std::collections::{BTreeMap, HashMap};
+
+/// The maximum difference in seconds between the times of overlapping trades
+/// without the same id, since the exports round the times.
+const TIME_TOLERANCE: f64 = 1f64;
+
+/// The source whose trade is kept if two sources overlap.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourcePreference {
+    /// The trades fetched from the Kraken API.
+    Api,
+    /// The trades of the CSV exports.
+    Import,
+}
+
+impl SourcePreference {
+    pub const NAMES: [&'static str; 2] = ["api", "import"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "api" => Some(SourcePreference::Api),
+            "import" => Some(SourcePreference::Import),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the source of a trade, `api` or the exchange it was imported from.
+pub fn source_of(trade: &Trade) -> &'static str {
+    trade.source.unwrap_or("api")
+}
+
+/// The decision about two overlapping trades.
+#[derive(Debug, Clone)]
+pub struct MergeDecision {
+    pub time: f64,
+    pub kept: (&'static str, String),
+    pub dropped: (&'static str, String),
+    /// Why the trades were considered the same.
+    pub reason: &'static str,
+    /// The fields the trades disagree on, empty if they are identical.
+    pub conflicts: Vec<&'static str>,
+}
+
+/// The data-quality report of the merge.
+#[derive(Debug, Default)]
+pub struct DataQuality {
+    /// The number of trades kept by source.
+    pub kept: BTreeMap<&'static str, usize>,
+    pub decisions: Vec<MergeDecision>,
+}
+
+/// Returns the fields two trades disagree on, at the precision of the base
+/// and quote currency, since the exports round the values differently.
+fn conflicts(a: &Trade, b: &Trade, precision: &Precision) -> Vec<&'static str> {
+    let number = |value: &str| value.parse::<f64>().ok();
+    let base = |value: &str| number(value).map(|value| precision.base(value));
+    let quote = |value: &str| number(value).map(|value| precision.quote(value));
+    let mut fields: Vec<&'static str> = Vec::new();
+    if a.side != b.side {
+        fields.push("side");
+    }
+    for (field, differs) in [
+        ("price", number(&a.price) != number(&b.price)),
+        ("vol", base(&a.vol) != base(&b.vol)),
+        ("cost", quote(&a.cost) != quote(&b.cost)),
+        ("fee", quote(&a.fee) != quote(&b.fee)),
+    ] {
+        if differs {
+            fields.push(field);
+        }
+    }
+    if (a.time - b.time).abs() > TIME_TOLERANCE {
+        fields.push("time");
+    }
+    fields
+}
+
+/// Returns the key of a trade to find the trades of other sources it may
+/// overlap, its side, amount and price.
+fn overlap_key(trade: &Trade) -> (String, String, String) {
+    let number = |value: &str| {
+        value
+            .parse::<f64>()
+            .map(|value| format!("{:.8}", value))
+            .unwrap_or_else(|_| value.to_string())
+    };
+    (trade.side.clone(), number(&trade.vol), number(&trade.price))
+}
+
+/// Merges the imported trades into the fetched trades, keeping one trade of
+/// every pair of overlapping trades of different sources.
+///
+/// # Arguments
+///
+/// * `trades` - The trades fetched from the API, or loaded from the cache.
+/// * `imports` - The trades imported from CSV exports.
+/// * `preference` - The source whose trade is kept if the API and an export
+///   overlap; of two exports, the trade of the one passed first is kept.
+/// * `precision` - The precision of the base and quote currency.
+///
+/// # Returns
+///
+/// The merged trades sorted by time and the data-quality report.
+///
+pub fn merge_sources(
+    trades: Vec<Trade>,
+    imports: Vec<Trade>,
+    preference: SourcePreference,
+    precision: &Precision,
+) -> (Vec<Trade>, DataQuality) {
+    let mut merged: Vec<Trade> = trades;
+    let mut by_id: HashMap<String, usize> = HashMap::new();
+    let mut by_key: HashMap<(String, String, String), Vec<usize>> = HashMap::new();
+    for (index, trade) in merged.iter().enumerate() {
+        by_id.insert(trade.txid.clone(), index);
+        by_key.entry(overlap_key(trade)).or_default().push(index);
+    }
+    let mut decisions: Vec<MergeDecision> = Vec::new();
+    for trade in imports {
+        let key: (String, String, String) = overlap_key(&trade);
+        let overlap: Option<(usize, &'static str)> = by_id
+            .get(&trade.txid)
+            .map(|index| (*index, "same id"))
+            .or_else(|| {
+                by_key.get(&key).and_then(|indices| {
+                    indices
+                        .iter()
+                        .find(|index| {
+                            let other: &Trade = &merged[**index];
+                            other.source != trade.source
+                                && (other.time - trade.time).abs() <= TIME_TOLERANCE
+                        })
+                        .map(|index| (*index, "same side, amount and price"))
+                })
+            });
+        let Some((index, reason)) = overlap else {
+            by_id.insert(trade.txid.clone(), merged.len());
+            by_key.entry(key).or_default().push(merged.len());
+            merged.push(trade);
+            continue;
+        };
+        let other: &Trade = &merged[index];
+        let replace: bool = preference == SourcePreference::Import && other.source.is_none();
+        let (kept, dropped): (&Trade, &Trade) = if replace {
+            (&trade, other)
+        } else {
+            (other, &trade)
+        };
+        decisions.push(MergeDecision {
+            time: kept.time,
+            kept: (source_of(kept), kept.txid.clone()),
+            dropped: (source_of(dropped), dropped.txid.clone()),
+            reason,
+            conflicts: conflicts(kept, dropped, precision),
+        });
+        if replace {
+            by_id.insert(trade.txid.clone(), index);
+            merged[index] = trade;
+        }
+    }
+    merged.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    let mut kept: BTreeMap<&'static str, usize> = BTreeMap::new();
+    for trade in &merged {
+        *kept.entry(source_of(trade)).or_default() += 1;
+    }
+    (merged, DataQuality { kept, decisions })
+}
+
+/// Prints the data-quality report of the merge.
+///
+/// # Arguments
+///
+/// * `quality` - The report.
+/// * `symbol` - The trading pair.
+///
+pub fn print_data_quality(quality: &DataQuality, symbol: &str) {
+    println!(
+        "Data quality of the trades of {} ({}):",
+        symbol,
+        quality
+            .kept
+            .iter()
+            .map(|(source, count)| format!("{} from {}", count, source))
+            .collect::<Vec<String>>()
+            .join(", ")
+    );
+    if quality.decisions.is_empty() {
+        println!("  No trades overlap between the sources.");
+        return;
+    }
+    let header: Vec<String> = ["Time", "Kept", "Dropped", "Matched by", "Conflicts"]
+        .map(str::to_string)
+        .to_vec();
+    let rows: Vec<Vec<String>> = quality
+        .decisions
+        .iter()
+        .map(|decision| {
+            vec![
+                DateTime::from_timestamp(decision.time as i64, 0)
+                    .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default(),
+                format!("{} {}", decision.kept.0, decision.kept.1),
+                format!("{} {}", decision.dropped.0, decision.dropped.1),
+                decision.reason.to_string(),
+                match decision.conflicts.is_empty() {
+                    true => "none".to_string(),
+                    false => decision.conflicts.join(", "),
+                },
+            ]
+        })
+        .collect();
+    print_table(&header, &rows, 5);
+}
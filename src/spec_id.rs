@@ -0,0 +1,299 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module implements the strategy of the specific identification (spec-ID)
+method, which jurisdictions like the US permit instead of FIFO. The lots a
+sell disposes of are identified by:
+
+1. A lots mapping, a CSV file with the columns `sell` (the id of the sell or
+   of its order), `lot` (the order the lot was acquired by) and optionally
+   `amount`, which assigns specific lots to specific sells.
+2. A rule for the rest of the amount:
+   - `fifo`: The oldest lots first.
+   - `minimize-gain`: The lots of the highest cost per unit first, which
+     realizes the smallest gain. As this also realizes the largest loss,
+     `maximize-loss` is the same rule.
+   - `maximize-gain`: The lots of the lowest cost per unit first, e.g. to use
+     up a tax-free allowance.
+
+Buys always close short lots in FIFO order.
+*/
+
+use crate::cost_basis::{CostBasisStrategy, Fifo, Hifo, Lofo};
+use crate::fifo::Lot;
+use crate::kraken::Trade;
+use crate::precision::Precision;
+use log::warn;
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The lots assigned to the sells, by the id of the sell or its order, as the
+/// order of the lot and the amount, if limited.
+pub type LotsMapping = HashMap<String, Vec<(String, Option<f64>)>>;
+
+/// The rule the lots of a sell are selected by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelectionRule {
+    #[default]
+    Fifo,
+    MinimizeGain,
+    MaximizeGain,
+}
+
+impl SelectionRule {
+    /// Parses a rule like `fifo` or `minimize-gain`.
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "fifo" => Ok(SelectionRule::Fifo),
+            "minimize-gain" | "maximize-loss" => Ok(SelectionRule::MinimizeGain),
+            "maximize-gain" => Ok(SelectionRule::MaximizeGain),
+            _ => Err(format!(
+                "Unknown lot selection '{}', expected fifo, minimize-gain, maximize-loss or maximize-gain!",
+                name
+            )),
+        }
+    }
+
+    /// Returns the name of the rule.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SelectionRule::Fifo => "fifo",
+            SelectionRule::MinimizeGain => "minimize-gain",
+            SelectionRule::MaximizeGain => "maximize-gain",
+        }
+    }
+
+    /// Returns the strategy selecting the lots by the rule.
+    pub fn strategy(&self) -> Arc<dyn CostBasisStrategy> {
+        match self {
+            SelectionRule::Fifo => Arc::new(Fifo),
+            SelectionRule::MinimizeGain => Arc::new(Hifo),
+            SelectionRule::MaximizeGain => Arc::new(Lofo),
+        }
+    }
+}
+
+/// The specific identification: The lots assigned to a sell by the mapping
+/// first, then the rule.
+#[derive(Debug, Clone, Default)]
+pub struct SpecId {
+    pub rule: SelectionRule,
+    pub mapping: LotsMapping,
+}
+
+impl CostBasisStrategy for SpecId {
+    fn name(&self) -> String {
+        match self.mapping.is_empty() {
+            true => format!("Spec-ID ({})", self.rule.name()),
+            false => format!("Spec-ID (lots mapping, then {})", self.rule.name()),
+        }
+    }
+
+    fn select(
+        &self,
+        lots: &VecDeque<Lot>,
+        trade: &Trade,
+        amount: f64,
+        precision: &Precision,
+    ) -> Vec<(usize, f64)> {
+        let rule: Arc<dyn CostBasisStrategy> = self.rule.strategy();
+        let Some(assigned) = self
+            .mapping
+            .get(&trade.txid)
+            .or_else(|| self.mapping.get(&trade.ordertxid))
+        else {
+            return rule.select(lots, trade, amount, precision);
+        };
+
+        // The lots as left by the assigned amounts, which the rule selects
+        // the rest from.
+        let mut left: VecDeque<Lot> = lots.clone();
+        let mut selected: Vec<(usize, f64)> = Vec::new();
+        let mut remaining: f64 = amount;
+        for (ordertxid, wanted) in assigned {
+            let mut wanted: f64 = wanted.unwrap_or(f64::INFINITY);
+            while wanted > 0f64 && remaining > 0f64 {
+                let Some(index) = left
+                    .iter()
+                    .position(|lot| lot.amount > 0f64 && lot.ordertxid == *ordertxid)
+                else {
+                    if wanted.is_finite() {
+                        warn!(
+                            "The lot of order {} assigned to sell {} is not open (anymore), selecting by {} instead.",
+                            ordertxid,
+                            trade.txid,
+                            self.rule.name()
+                        );
+                    }
+                    break;
+                };
+                let lot: &mut Lot = &mut left[index];
+                let taken: f64 = wanted.min(remaining).min(lot.amount);
+                lot.cost = precision.quote(lot.cost - (lot.cost / lot.amount) * taken);
+                lot.amount = precision.base(lot.amount - taken);
+                wanted -= taken;
+                remaining = precision.base(remaining - taken);
+                selected.push((index, taken));
+            }
+        }
+        if remaining > 0f64 {
+            selected.extend(rule.select(&left, trade, remaining, precision));
+        }
+        selected
+    }
+}
+
+/// Reads a lots mapping.
+///
+/// # Arguments
+///
+/// * `path` - The CSV file with the columns `sell`, `lot` and optionally
+///   `amount`.
+///
+pub fn read_lots_mapping(path: &Path) -> Result<LotsMapping, String> {
+    let content: String = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut lines = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or_else(|| format!("{} is empty!", path.display()))?
+        .split(',')
+        .map(str::trim)
+        .collect();
+    let column = |name: &str| header.iter().position(|column| *column == name);
+    let (sell_column, lot_column) = column("sell")
+        .zip(column("lot"))
+        .ok_or_else(|| format!("{} lacks the columns sell and lot!", path.display()))?;
+    let amount_column: Option<usize> = column("amount");
+
+    let mut mapping: LotsMapping = HashMap::new();
+    for line in lines {
+        let values: Vec<&str> = line.split(',').map(str::trim).collect();
+        let invalid = || format!("Invalid line in {}: {}", path.display(), line);
+        let sell: &str = values
+            .get(sell_column)
+            .filter(|sell| !sell.is_empty())
+            .ok_or_else(invalid)?;
+        let lot: &str = values
+            .get(lot_column)
+            .filter(|lot| !lot.is_empty())
+            .ok_or_else(invalid)?;
+        let amount: Option<f64> = match amount_column.and_then(|column| values.get(column)) {
+            Some(amount) if !amount.is_empty() => Some(
+                amount
+                    .parse()
+                    .ok()
+                    .filter(|amount: &f64| *amount > 0f64)
+                    .ok_or_else(invalid)?,
+            ),
+            _ => None,
+        };
+        mapping
+            .entry(sell.to_string())
+            .or_default()
+            .push((lot.to_string(), amount));
+    }
+    Ok(mapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRECISION: Precision = Precision { base: 8, quote: 2 };
+
+    /// Returns an open lot of an order bought at a price per unit.
+    fn lot(ordertxid: &str, amount: f64, price: f64) -> Lot {
+        Lot {
+            amount,
+            cost: amount * price,
+            time: 1704067200f64,
+            ordertxid: ordertxid.to_string(),
+            ordertype: "limit".to_string(),
+            maker: None,
+        }
+    }
+
+    /// Returns the lots of three orders, bought at 100, 300 and 200.
+    fn lots() -> VecDeque<Lot> {
+        VecDeque::from([
+            lot("O1", 1f64, 100f64),
+            lot("O2", 1f64, 300f64),
+            lot("O3", 1f64, 200f64),
+        ])
+    }
+
+    fn sell() -> Trade {
+        Trade {
+            txid: "T4".to_string(),
+            ordertxid: "O4".to_string(),
+            side: "sell".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn selects_by_the_rule() {
+        for (rule, index) in [
+            (SelectionRule::Fifo, 0),
+            (SelectionRule::MinimizeGain, 1),
+            (SelectionRule::MaximizeGain, 0),
+        ] {
+            let spec_id: SpecId = SpecId {
+                rule,
+                mapping: LotsMapping::new(),
+            };
+            let selected: Vec<(usize, f64)> = spec_id.select(&lots(), &sell(), 1f64, &PRECISION);
+            assert_eq!(selected, vec![(index, 1f64)], "{}", rule.name());
+        }
+    }
+
+    #[test]
+    fn selects_the_assigned_lots_first() {
+        // Half of the lot of O3 is assigned to the order of the sell, the
+        // rest is selected by the rule.
+        let spec_id: SpecId = SpecId {
+            rule: SelectionRule::MinimizeGain,
+            mapping: LotsMapping::from([("O4".to_string(), vec![("O3".to_string(), Some(0.5))])]),
+        };
+        let selected: Vec<(usize, f64)> = spec_id.select(&lots(), &sell(), 2f64, &PRECISION);
+        assert_eq!(selected, vec![(2, 0.5), (1, 1f64), (2, 0.5)]);
+    }
+
+    #[test]
+    fn falls_back_to_the_rule_for_closed_lots() {
+        let spec_id: SpecId = SpecId {
+            rule: SelectionRule::Fifo,
+            mapping: LotsMapping::from([("T4".to_string(), vec![("O9".to_string(), None)])]),
+        };
+        let selected: Vec<(usize, f64)> = spec_id.select(&lots(), &sell(), 1f64, &PRECISION);
+        assert_eq!(selected, vec![(0, 1f64)]);
+    }
+
+    #[test]
+    fn reads_the_lots_mapping() {
+        let path: std::path::PathBuf = std::env::temp_dir().join("kraken-pnl-spec-id-test.csv");
+        std::fs::write(
+            &path,
+            "# Assigned lots\nsell,lot,amount\nT4,O3,0.5\nT4,O2,\nO5,O1,1\n",
+        )
+        .unwrap();
+        let mapping: LotsMapping = read_lots_mapping(&path).unwrap();
+        assert_eq!(
+            mapping["T4"],
+            vec![("O3".to_string(), Some(0.5)), ("O2".to_string(), None)]
+        );
+        assert_eq!(mapping["O5"], vec![("O1".to_string(), Some(1f64))]);
+        std::fs::write(&path, "sell,lot,amount\nT4,O3,-1\n").unwrap();
+        assert!(read_lots_mapping(&path).is_err());
+        std::fs::write(&path, "sell,amount\nT4,1\n").unwrap();
+        assert!(read_lots_mapping(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,198 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module exports the complete state of the cache, i.e. all trades and
+ledger entries together with the open lots of every trading pair, to a single
+portable JSON file and imports it again, e.g. to move the data to another
+machine or to attach it to a support request.
+
+The open lots are part of the export for reference only. They are derived from
+the trades, so importing a file only restores the trades and ledger entries.
+The file carries the version of its layout, so files exported before Kraken
+changed the fields of the trades or ledger entries can still be imported (see
+the `schema` module).
+*/
+
+use crate::cache::Cache;
+use crate::fifo::{compute_fifo_pnl, Lot};
+use crate::fiscal::YearStart;
+use crate::kraken::{KrakenAPI, Trade};
+use crate::ledger::LedgerEntry;
+use crate::precision::{fetch_precision, try_fetch_asset_pair};
+use crate::schema::{upgrade, version_of, SCHEMA_VERSION};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+/// A trade including its id.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TradeRecord {
+    pub txid: String,
+    #[serde(flatten)]
+    pub trade: Trade,
+}
+
+/// A ledger entry including its id.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LedgerRecord {
+    pub id: String,
+    #[serde(flatten)]
+    pub entry: LedgerEntry,
+}
+
+/// The data of a trading pair.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PairState {
+    pub trades: Vec<TradeRecord>,
+    /// The lots that are open after processing all trades (FIFO).
+    #[serde(default)]
+    pub open_lots: Vec<Lot>,
+}
+
+/// The content of a state file.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct State {
+    pub tool: String,
+    pub version: String,
+    /// The version of the layout of the file, 0 for files exported before
+    /// they were versioned.
+    #[serde(default)]
+    pub schema: u32,
+    pub exported_at: String,
+    /// The trades and open lots by trading pair.
+    pub pairs: BTreeMap<String, PairState>,
+    /// The ledger entries by asset.
+    pub ledger: BTreeMap<String, Vec<LedgerRecord>>,
+}
+
+/// Exports the cache to a JSON file.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client, used to look up the precision of the
+///   trading pairs for computing the open lots. No credentials are required.
+/// * `cache` - The cache.
+/// * `file_path` - The path of the JSON file.
+///
+/// # Returns
+///
+/// The number of exported trades and ledger entries.
+///
+pub async fn export_state(
+    api: &KrakenAPI,
+    cache: &Cache,
+    file_path: &Path,
+) -> Result<(usize, usize), String> {
+    let mut state = State {
+        tool: env!("CARGO_PKG_NAME").to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        schema: SCHEMA_VERSION,
+        exported_at: chrono::Utc::now().to_rfc3339(),
+        pairs: BTreeMap::new(),
+        ledger: BTreeMap::new(),
+    };
+    let (mut trades_count, mut entries_count) = (0usize, 0usize);
+
+    for pair in cache.pairs().map_err(|e| e.to_string())? {
+        let trades: Vec<Trade> = cache
+            .load_trades(&pair, None, None)
+            .map_err(|e| e.to_string())?;
+        let open_lots: Vec<Lot> = match try_fetch_asset_pair(api, &pair).await {
+            Some(asset_pair) => {
+                let precision = fetch_precision(api, &asset_pair).await;
+                compute_fifo_pnl(&trades, None, YearStart::default(), &precision).open_lots
+            }
+            None => {
+                warn!(
+                    "Unknown trading pair {}, not exporting its open lots.",
+                    pair
+                );
+                Vec::new()
+            }
+        };
+        trades_count += trades.len();
+        let trades: Vec<TradeRecord> = trades
+            .into_iter()
+            .map(|trade| TradeRecord {
+                txid: trade.txid.clone(),
+                trade,
+            })
+            .collect();
+        state.pairs.insert(pair, PairState { trades, open_lots });
+    }
+    for asset in cache.ledger_assets().map_err(|e| e.to_string())? {
+        let entries: Vec<LedgerRecord> = cache
+            .load_ledger_entries(&asset, None, None)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|entry| LedgerRecord {
+                id: entry.id.clone(),
+                entry,
+            })
+            .collect();
+        entries_count += entries.len();
+        state.ledger.insert(asset, entries);
+    }
+
+    let file: File = File::create(file_path)
+        .map_err(|e| format!("Failed to create {}: {}", file_path.display(), e))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &state)
+        .map_err(|e| format!("Failed to write {}: {}", file_path.display(), e))?;
+    Ok((trades_count, entries_count))
+}
+
+/// Imports a JSON file written by [`export_state`] into the cache. Trades and
+/// ledger entries that are already cached are replaced. Files of an older
+/// layout are migrated before they are imported.
+///
+/// # Returns
+///
+/// The number of imported trades and ledger entries.
+///
+pub fn import_state(cache: &mut Cache, file_path: &Path) -> Result<(usize, usize), String> {
+    let stored: String = fs::read_to_string(file_path)
+        .map_err(|e| format!("Failed to open {}: {}", file_path.display(), e))?;
+    let content: String = upgrade(&stored, version_of(&stored))
+        .map_err(|e| format!("{}: {}", file_path.display(), e))?;
+    let state: State = serde_json::from_str(&content)
+        .map_err(|e| format!("Invalid {}: {}", file_path.display(), e))?;
+    let (mut trades_count, mut entries_count) = (0usize, 0usize);
+
+    for (pair, pair_state) in state.pairs {
+        let trades: Vec<Trade> = pair_state
+            .trades
+            .into_iter()
+            .map(|record| Trade {
+                txid: record.txid,
+                ..record.trade
+            })
+            .collect();
+        if let Some(trade) = trades.iter().find(|trade| trade.pair != pair) {
+            return Err(format!(
+                "Trade {} of {} is listed under {}!",
+                trade.txid, trade.pair, pair
+            ));
+        }
+        cache.store_trades(&trades).map_err(|e| e.to_string())?;
+        trades_count += trades.len();
+    }
+    for (asset, records) in state.ledger {
+        let entries: Vec<LedgerEntry> = records
+            .into_iter()
+            .map(|record| LedgerEntry {
+                id: record.id,
+                ..record.entry
+            })
+            .collect();
+        cache
+            .store_ledger_entries(&asset, &entries)
+            .map_err(|e| e.to_string())?;
+        entries_count += entries.len();
+    }
+    Ok((trades_count, entries_count))
+}
@@ -0,0 +1,291 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module contains the statistics of `stats`: the trade velocity of the
+cached trading pairs, the API usage recorded in the run registry and the
+projected cost of fetching the cached history again, so heavy users can plan
+tier upgrades and schedule their syncs within the rate budget.
+
+Kraken returns the trade history of all pairs at once, so every trading pair
+of a run pages through the full history, while ledger entries are queried per
+asset. The projection is based on the cached data only, trades of pairs that
+were never fetched are not known.
+*/
+
+use crate::kraken::{Trade, PAGE_SIZE};
+use crate::ledger::LedgerEntry;
+use crate::rate_limit::RateLimiter;
+use crate::report::print_table;
+use crate::runs::Run;
+use chrono::{DateTime, NaiveDate};
+use std::collections::BTreeMap;
+
+/// The API tiers in ascending order of their rate budget.
+const TIERS: [&str; 3] = ["starter", "intermediate", "pro"];
+
+/// Returns the UTC date of a UNIX timestamp.
+fn date(time: f64) -> NaiveDate {
+    DateTime::from_timestamp_nanos((time * 1e9) as i64).date_naive()
+}
+
+/// The trade velocity of a trading pair.
+#[derive(Debug)]
+pub struct Velocity {
+    pub pair: String,
+    pub trades: usize,
+    pub first: f64,
+    pub last: f64,
+    /// The average number of trades per day since the first trade.
+    pub per_day: f64,
+    /// The number of trades within the last 30 days.
+    pub last_30_days: usize,
+    /// The day with the most trades and their number.
+    pub busiest_day: (NaiveDate, usize),
+}
+
+/// Computes the trade velocity of a trading pair.
+///
+/// # Arguments
+///
+/// * `pair` - The trading pair.
+/// * `trades` - The trades of the pair sorted by time.
+/// * `now` - The UNIX timestamp the velocity is computed at.
+///
+/// # Returns
+///
+/// The velocity, or `None` if there are no trades.
+///
+pub fn trade_velocity(pair: &str, trades: &[Trade], now: f64) -> Option<Velocity> {
+    let (first, last) = (trades.first()?.time, trades.last()?.time);
+    let mut per_date: BTreeMap<NaiveDate, usize> = BTreeMap::new();
+    for trade in trades {
+        *per_date.entry(date(trade.time)).or_default() += 1;
+    }
+    // Earlier days win ties as the map is sorted.
+    let busiest_day: (NaiveDate, usize) = per_date
+        .iter()
+        .fold(
+            None,
+            |busiest: Option<(NaiveDate, usize)>, (day, count)| match busiest {
+                Some((_, most)) if most >= *count => busiest,
+                _ => Some((*day, *count)),
+            },
+        )
+        .unwrap();
+    let days: f64 = ((now - first) / 86400f64).max(1f64);
+    Some(Velocity {
+        pair: pair.to_string(),
+        trades: trades.len(),
+        first,
+        last,
+        per_day: trades.len() as f64 / days,
+        last_30_days: trades
+            .iter()
+            .filter(|trade| trade.time > now - 30f64 * 86400f64)
+            .count(),
+        busiest_day,
+    })
+}
+
+/// The API usage of the recorded runs.
+#[derive(Debug, Default)]
+pub struct ApiUsage {
+    pub runs: usize,
+    pub requests: usize,
+    pub points: f64,
+    /// The time spent waiting for the API counter to decay in seconds.
+    pub waited: f64,
+    pub rate_limited: usize,
+    /// The runs that sent private requests, i.e. didn't only read the cache.
+    pub fetching_runs: usize,
+}
+
+/// Sums up the API usage of the recorded runs.
+pub fn api_usage(runs: &[Run]) -> ApiUsage {
+    runs.iter()
+        .fold(ApiUsage::default(), |usage, run| ApiUsage {
+            runs: usage.runs + 1,
+            requests: usage.requests + run.requests,
+            points: usage.points + run.api_points,
+            waited: usage.waited + run.rate_limit_wait,
+            rate_limited: usage.rate_limited + run.rate_limited,
+            fetching_runs: usage.fetching_runs + usize::from(run.api_points > 0f64),
+        })
+}
+
+/// The projected cost of fetching the cached history of all pairs.
+#[derive(Debug)]
+pub struct Projection {
+    /// The number of days from now the fetch is projected for.
+    pub days: u32,
+    pub requests: usize,
+    pub points: f64,
+    /// The duration of the fetch per tier in seconds, assuming the counter is
+    /// empty when the fetch starts.
+    pub seconds: Vec<(&'static str, f64)>,
+}
+
+/// Projects the cost of fetching the cached trades and ledger entries again.
+///
+/// # Arguments
+///
+/// * `velocities` - The trade velocity of the cached trading pairs.
+/// * `ledger` - The number of cached ledger entries and the average entries
+///   per day by asset.
+/// * `days` - The number of days from now, during which the trades and
+///   ledger entries keep growing at their average rate.
+///
+pub fn project_fetch(velocities: &[Velocity], ledger: &[(usize, f64)], days: u32) -> Projection {
+    let grown = |count: usize, per_day: f64| count + (per_day * days as f64).round() as usize;
+    let pages = |count: usize| count.div_ceil(PAGE_SIZE).max(1);
+    let trades: usize = grown(
+        velocities.iter().map(|velocity| velocity.trades).sum(),
+        velocities.iter().map(|velocity| velocity.per_day).sum(),
+    );
+    let trade_requests: usize = velocities.len() * pages(trades);
+    let ledger_requests: usize = ledger
+        .iter()
+        .map(|(count, per_day)| pages(grown(*count, *per_day)))
+        .sum();
+    let points: f64 = trade_requests as f64 * RateLimiter::cost("/0/private/TradesHistory")
+        + ledger_requests as f64 * RateLimiter::cost("/0/private/Ledgers");
+    Projection {
+        days,
+        requests: trade_requests + ledger_requests,
+        points,
+        seconds: TIERS
+            .iter()
+            .map(|tier| {
+                let limiter: RateLimiter = RateLimiter::for_tier(tier);
+                (
+                    *tier,
+                    (points - limiter.max_counter()).max(0f64) / limiter.decay_rate(),
+                )
+            })
+            .collect(),
+    }
+}
+
+/// Returns the number of ledger entries and the average entries per day since
+/// the first one.
+pub fn ledger_velocity(entries: &[LedgerEntry], now: f64) -> (usize, f64) {
+    match entries.first() {
+        Some(first) => (
+            entries.len(),
+            entries.len() as f64 / ((now - first.time) / 86400f64).max(1f64),
+        ),
+        None => (0, 0f64),
+    }
+}
+
+/// Prints the trade velocity, the API usage and the projected fetch cost.
+///
+/// # Arguments
+///
+/// * `velocities` - The trade velocity of the cached trading pairs.
+/// * `usage` - The API usage of the recorded runs.
+/// * `projections` - The projected fetch cost now and in the future.
+/// * `tier` - The configured API tier, which is highlighted.
+///
+pub fn print_stats(
+    velocities: &[Velocity],
+    usage: &ApiUsage,
+    projections: &[Projection],
+    tier: &str,
+) {
+    println!("Trade velocity:");
+    let header: Vec<String> = [
+        "Pair",
+        "First trade",
+        "Last trade",
+        "Trades",
+        "Per day",
+        "Per week",
+        "Last 30 days",
+        "Busiest day",
+    ]
+    .iter()
+    .map(|column| column.to_string())
+    .collect();
+    let rows: Vec<Vec<String>> = velocities
+        .iter()
+        .map(|velocity| {
+            vec![
+                velocity.pair.clone(),
+                date(velocity.first).to_string(),
+                date(velocity.last).to_string(),
+                velocity.trades.to_string(),
+                format!("{:.2}", velocity.per_day),
+                format!("{:.2}", velocity.per_day * 7f64),
+                velocity.last_30_days.to_string(),
+                format!("{} ({})", velocity.busiest_day.0, velocity.busiest_day.1),
+            ]
+        })
+        .collect();
+    print_table(&header, &rows, 3);
+    if rows.is_empty() {
+        println!("No cached trades.");
+    }
+
+    println!();
+    println!("API usage of {} recorded runs:", usage.runs);
+    println!(
+        "Runs fetching from the API: {}, requests: {}, points: {}",
+        usage.fetching_runs, usage.requests, usage.points
+    );
+    if usage.fetching_runs > 0 {
+        println!(
+            "Average per fetching run: {:.1} requests, {:.1} points",
+            usage.requests as f64 / usage.fetching_runs as f64,
+            usage.points / usage.fetching_runs as f64
+        );
+    }
+    println!(
+        "Waited for the API counter: {:.1}s, rejected requests: {}",
+        usage.waited, usage.rate_limited
+    );
+
+    println!();
+    println!("Projected cost of fetching the cached history of all pairs:");
+    let mut header: Vec<String> = vec![
+        "When".to_string(),
+        "Requests".to_string(),
+        "Points".to_string(),
+    ];
+    header.extend(TIERS.iter().map(|name| {
+        format!(
+            "{}{}",
+            name,
+            if *name == tier || (*name == "starter" && !TIERS.contains(&tier)) {
+                " *"
+            } else {
+                ""
+            }
+        )
+    }));
+    let rows: Vec<Vec<String>> = projections
+        .iter()
+        .map(|projection| {
+            let mut row: Vec<String> = vec![
+                if projection.days == 0 {
+                    "now".to_string()
+                } else {
+                    format!("in {} days", projection.days)
+                },
+                projection.requests.to_string(),
+                projection.points.to_string(),
+            ];
+            row.extend(
+                projection
+                    .seconds
+                    .iter()
+                    .map(|(_, seconds)| format!("{:.0}s", seconds)),
+            );
+            row
+        })
+        .collect();
+    print_table(&header, &rows, 1);
+    println!("* The configured tier. The durations assume the API counter is empty at the start.");
+}
@@ -0,0 +1,261 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module computes the PnL of every strategy of a trading pair, i.e. of the
+orders sharing a user reference id, as placed by most grid and trading bots,
+or a client order id matched by a pattern (e.g., `^(grid-\w+)-` for the client
+order ids `grid-eur-1`, `grid-eur-2` and so on).
+
+Every strategy holds its own lots, so its trades are computed on their own as
+if selected via `--userref`. The trades of the other orders, as well as the
+credits, adjustments, imports and transfers, form a group of their own. The
+total sums up the strategies, which differs from the PnL of the pair as a
+whole, since the sells of one strategy don't dispose of the lots of another.
+*/
+
+use crate::cost_basis::LotSelection;
+use crate::fees::summarize_fees;
+use crate::fifo::{compute_pnl, PnL};
+use crate::fiscal::YearStart;
+use crate::kraken::{ClosedOrder, Trade};
+use crate::precision::Precision;
+use crate::wash_sale::WashSaleRule;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+
+/// What the orders of a strategy share.
+#[derive(Debug, Clone)]
+pub enum StrategyKey {
+    /// The user reference id.
+    Userref,
+    /// The client order id, or the part of it matched by the first group of a
+    /// pattern, or the whole match if the pattern has no group.
+    ClOrdId(Option<Regex>),
+}
+
+impl StrategyKey {
+    /// The names of the keys as passed via `--group-by`.
+    pub const NAMES: [&'static str; 2] = ["userref", "cl-ord-id"];
+
+    /// Returns the key of its name and the pattern of the client order ids,
+    /// if any.
+    pub fn from_name(name: &str, pattern: Option<&str>) -> Result<Self, String> {
+        match (name, pattern) {
+            ("userref", None) => Ok(Self::Userref),
+            ("userref", Some(_)) => {
+                Err("--cl-ord-id-pattern requires --group-by cl-ord-id!".to_string())
+            }
+            ("cl-ord-id", pattern) => pattern
+                .map(|pattern| {
+                    Regex::new(pattern)
+                        .map_err(|e| format!("Invalid --cl-ord-id-pattern {}: {}", pattern, e))
+                })
+                .transpose()
+                .map(Self::ClOrdId),
+            _ => Err(format!("Unknown strategy key {}!", name)),
+        }
+    }
+
+    /// Returns the names of the strategies by the id of the order, leaving
+    /// out the orders that belong to none.
+    pub fn strategies(&self, orders: &HashMap<String, ClosedOrder>) -> HashMap<String, String> {
+        orders
+            .iter()
+            .filter_map(|(ordertxid, ids)| {
+                let name: String = match self {
+                    Self::Userref => ids.userref?.to_string(),
+                    Self::ClOrdId(pattern) => {
+                        let id: &str = ids.cl_ord_id.as_deref()?;
+                        match pattern {
+                            Some(pattern) => {
+                                let captures = pattern.captures(id)?;
+                                captures.get(1).or(captures.get(0))?.as_str().to_string()
+                            }
+                            None => id.to_string(),
+                        }
+                    }
+                };
+                Some((ordertxid.clone(), name))
+            })
+            .collect()
+    }
+
+    /// Returns the title of a strategy, or of the orders of none if no name
+    /// is given.
+    fn title(&self, name: Option<&str>) -> String {
+        match (self, name) {
+            (Self::Userref, Some(name)) => format!("Strategy of userref {}", name),
+            (Self::Userref, None) => "Orders without userref".to_string(),
+            (Self::ClOrdId(_), Some(name)) => format!("Strategy of client order ids {}", name),
+            (Self::ClOrdId(None), None) => "Orders without client order id".to_string(),
+            (Self::ClOrdId(Some(_)), None) => {
+                "Orders without a matching client order id".to_string()
+            }
+        }
+    }
+}
+
+/// The PnL of the orders of one strategy.
+#[derive(Debug, Default)]
+pub struct StrategyPnL {
+    /// The name of the strategy, none for the orders of no strategy.
+    pub name: Option<String>,
+    pub trades: usize,
+    pub realized_pnl: f64,
+    pub unrealized_pnl: f64,
+    pub fees: f64,
+    pub balance: f64,
+}
+
+/// Computes the PnL of the trades grouped by the strategies of their orders.
+///
+/// # Arguments
+///
+/// * `trades` - The trades the PnL of the pair was computed from, sorted by
+///   time.
+/// * `strategies` - The names of the strategies by the id of the order.
+/// * `price` - The price the open lots are valued at.
+/// * `until` - The time until which the trades make up the open lots, if the
+///   lots are valued at an earlier date than the last trade.
+/// * `year` - An optional year to filter the realized PnL and the fees by.
+/// * `year_start` - The first day of the fiscal years.
+/// * `precision` - The precision of the base and quote currency.
+/// * `selection` - How the lots to dispose of are selected.
+/// * `wash_sale` - The wash sale rule to apply, if any.
+/// * `dust_threshold` - The amount below which the remainders of lots are
+///   swept as dust.
+///
+/// # Returns
+///
+/// The PnL by strategy, sorted by the name, numerically for user reference
+/// ids, with the orders of no strategy last.
+///
+#[allow(clippy::too_many_arguments)]
+pub fn group_by_strategy(
+    trades: &[Trade],
+    strategies: &HashMap<String, String>,
+    price: f64,
+    until: Option<f64>,
+    year: Option<u32>,
+    year_start: YearStart,
+    precision: &Precision,
+    selection: &LotSelection,
+    wash_sale: Option<WashSaleRule>,
+    dust_threshold: f64,
+) -> Vec<StrategyPnL> {
+    let mut groups: BTreeMap<Option<&String>, Vec<Trade>> = BTreeMap::new();
+    for trade in trades {
+        groups
+            .entry(strategies.get(&trade.ordertxid))
+            .or_default()
+            .push(trade.clone());
+    }
+    let compute = |trades: &[Trade]| -> PnL {
+        compute_pnl(
+            trades,
+            year,
+            year_start,
+            precision,
+            selection,
+            wash_sale,
+            dust_threshold,
+        )
+    };
+    let mut pnl_by_strategy: Vec<StrategyPnL> = groups
+        .into_iter()
+        .map(|(name, trades)| {
+            let pnl: PnL = compute(&trades);
+            // The lots are those open at the valuation date, if it precedes
+            // the last trade.
+            let count: usize = until
+                .map(|until| trades.partition_point(|trade| trade.time < until))
+                .unwrap_or(trades.len());
+            let open: Option<PnL> = (count < trades.len()).then(|| compute(&trades[..count]));
+            let open: &PnL = open.as_ref().unwrap_or(&pnl);
+            StrategyPnL {
+                name: name.cloned(),
+                trades: trades.len(),
+                realized_pnl: pnl.realized_pnl,
+                unrealized_pnl: precision.quote(
+                    open.open_lots
+                        .iter()
+                        .map(|lot| lot.amount * price - lot.cost)
+                        .sum(),
+                ),
+                balance: open.balance,
+                fees: summarize_fees(&trades, year, year_start, precision)
+                    .total
+                    .fees,
+            }
+        })
+        .collect();
+    // The orders of no strategy are listed last.
+    pnl_by_strategy.sort_by_key(|strategy| {
+        (
+            strategy.name.is_none(),
+            strategy
+                .name
+                .as_ref()
+                .and_then(|name| name.parse::<i64>().ok()),
+        )
+    });
+    pnl_by_strategy
+}
+
+/// Prints the PnL of every strategy and their total.
+///
+/// # Arguments
+///
+/// * `strategies` - The PnL by strategy.
+/// * `key` - What the orders of a strategy share.
+/// * `base` - The base currency of the trading pair.
+/// * `quote` - The quote currency of the trading pair.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn print_strategies(
+    strategies: &[StrategyPnL],
+    key: &StrategyKey,
+    base: &str,
+    quote: &str,
+    precision: &Precision,
+) {
+    let print = |title: &str, strategy: &StrategyPnL| {
+        println!("{}:", title);
+        println!("  Trades: {}", strategy.trades);
+        println!(
+            "  Realized PnL: {} {}",
+            precision.fmt_quote(strategy.realized_pnl),
+            quote
+        );
+        println!(
+            "  Unrealized PnL: {} {}",
+            precision.fmt_quote(strategy.unrealized_pnl),
+            quote
+        );
+        println!("  Fees: {} {}", precision.fmt_quote(strategy.fees), quote);
+        println!(
+            "  Balance: {} {}",
+            precision.fmt_base(strategy.balance),
+            base
+        );
+    };
+    let mut total: StrategyPnL = StrategyPnL::default();
+    for strategy in strategies {
+        print(&key.title(strategy.name.as_deref()), strategy);
+        total.trades += strategy.trades;
+        total.realized_pnl += strategy.realized_pnl;
+        total.unrealized_pnl += strategy.unrealized_pnl;
+        total.fees += strategy.fees;
+        total.balance += strategy.balance;
+    }
+    total.realized_pnl = precision.quote(total.realized_pnl);
+    total.unrealized_pnl = precision.quote(total.unrealized_pnl);
+    total.fees = precision.quote(total.fees);
+    total.balance = precision.base(total.balance);
+    print(
+        "Total of the strategies (every one holding its own lots)",
+        &total,
+    );
+}
@@ -0,0 +1,230 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module streams the own trades from the `ownTrades` channel of Kraken's
+private WebSocket API, which pushes every fill as soon as it happens. The
+channel requires a token obtained via the REST API. The WebSocket connection
+is handled by tokio-tungstenite, which answers the pings of the server and
+limits the size of the frames and messages it accepts.
+
+The stream only carries the trades, so the ledger entries and the closed orders
+stay at the state of the REST fetch at the start.
+*/
+
+use crate::kraken::{KrakenAPI, Trade};
+use crate::precision::{fetch_asset_pair, AssetPair};
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, info};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// The URL of Kraken's private WebSocket API.
+pub const DEFAULT_WS_URL: &str = "wss://ws-auth.kraken.com";
+
+/// The maximum size of a message of the channel, and thus of its frames. The
+/// updates of the own trades are far smaller, so a larger message is refused
+/// rather than buffered.
+const MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+#[derive(Deserialize, Debug)]
+struct TokenResult {
+    token: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    error: Vec<String>,
+    result: Option<TokenResult>,
+}
+
+/// Fetches the token authenticating the subscription to the private channels.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+///
+pub async fn fetch_websocket_token(api: &KrakenAPI) -> Result<String, String> {
+    let response: TokenResponse =
+        serde_json::from_str(&api.request("/0/private/GetWebSocketsToken", vec![]).await)
+            .map_err(|e| format!("Failed to parse response: {}", e))?;
+    response
+        .result
+        .map(|result| result.token)
+        .ok_or_else(|| format!("Error fetching the WebSocket token: {:?}", response.error))
+}
+
+/// Fetches a token and subscribes to the own trades of the selected trading
+/// pairs.
+///
+/// # Arguments
+///
+/// * `api` - The Kraken API client.
+/// * `url` - The URL of the WebSocket API.
+/// * `symbols` - The selected trading pairs.
+///
+pub async fn subscribe_own_trades(
+    api: &KrakenAPI,
+    url: &str,
+    symbols: &[String],
+) -> Result<OwnTrades, String> {
+    let mut pairs: HashMap<String, String> = HashMap::new();
+    for symbol in symbols {
+        let asset_pair: AssetPair = fetch_asset_pair(api, symbol).await;
+        pairs.insert(asset_pair.wsname, asset_pair.name);
+    }
+    let token: String = fetch_websocket_token(api).await?;
+    OwnTrades::subscribe(url, &token, pairs).await
+}
+
+/// The subscription to the `ownTrades` channel.
+pub struct OwnTrades {
+    websocket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    /// The names of the selected trading pairs by their WebSocket name (e.g.,
+    /// XBT/EUR), which the channel uses.
+    pairs: HashMap<String, String>,
+}
+
+impl OwnTrades {
+    /// Connects to the WebSocket API and subscribes to the own trades.
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The URL of the WebSocket API (e.g., wss://ws-auth.kraken.com).
+    /// * `token` - The token fetched via `fetch_websocket_token`.
+    /// * `pairs` - The names of the selected trading pairs by their WebSocket
+    ///   name, the trades of all other pairs are skipped.
+    ///
+    pub async fn subscribe(
+        url: &str,
+        token: &str,
+        pairs: HashMap<String, String>,
+    ) -> Result<Self, String> {
+        if !url.starts_with("ws://") && !url.starts_with("wss://") {
+            return Err(format!(
+                "Unsupported URL '{}', expected the scheme ws or wss!",
+                url
+            ));
+        }
+        let config: WebSocketConfig = WebSocketConfig::default()
+            .max_message_size(Some(MAX_MESSAGE_SIZE))
+            .max_frame_size(Some(MAX_MESSAGE_SIZE));
+        let (websocket, _) = tokio_tungstenite::connect_async_with_config(url, Some(config), false)
+            .await
+            .map_err(|e| format!("Failed to connect to {}: {}", url, e))?;
+        let mut own_trades = OwnTrades { websocket, pairs };
+        own_trades
+            .send_text(
+                &json!({
+                    "event": "subscribe",
+                    "subscription": {"name": "ownTrades", "token": token, "snapshot": false},
+                })
+                .to_string(),
+            )
+            .await?;
+        loop {
+            let message: Value = own_trades.next_message().await?;
+            match message["event"].as_str() {
+                Some("subscriptionStatus") if message["status"] == "subscribed" => break,
+                Some("subscriptionStatus") => {
+                    return Err(format!(
+                        "Failed to subscribe to the own trades: {}",
+                        message["errorMessage"].as_str().unwrap_or("unknown error")
+                    ))
+                }
+                _ => debug!("Skipping {}", message),
+            }
+        }
+        info!("Subscribed to the own trades at {}.", url);
+        Ok(own_trades)
+    }
+
+    async fn send_text(&mut self, text: &str) -> Result<(), String> {
+        self.websocket
+            .send(Message::text(text))
+            .await
+            .map_err(|e| format!("Failed to send to the WebSocket: {}", e))
+    }
+
+    /// Reads the next text message, while the pings are answered by
+    /// tokio-tungstenite.
+    async fn next_message(&mut self) -> Result<Value, String> {
+        loop {
+            let message: Message = self
+                .websocket
+                .next()
+                .await
+                .ok_or("The server closed the WebSocket connection!")?
+                .map_err(|e| format!("Failed to read from the WebSocket: {}", e))?;
+            match message {
+                Message::Text(text) => {
+                    return serde_json::from_str(&text)
+                        .map_err(|e| format!("Failed to parse message: {}", e))
+                }
+                Message::Close(_) => {
+                    return Err("The server closed the WebSocket connection!".to_string())
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Waits for the next fills of the selected trading pairs.
+    ///
+    /// # Returns
+    ///
+    /// The trades of an update, which name the trading pair like the REST API
+    /// (e.g., XXBTZEUR).
+    ///
+    pub async fn next_trades(&mut self) -> Result<Vec<Trade>, String> {
+        loop {
+            let message: Value = self.next_message().await?;
+            // Updates are arrays of the trades, the channel name and the
+            // sequence number, events like heartbeats are objects.
+            if message[1] != "ownTrades" {
+                continue;
+            }
+            let trades: Vec<Trade> = message[0]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_object)
+                .flatten()
+                .filter_map(|(txid, trade)| self.parse_trade(txid, trade))
+                .collect();
+            if !trades.is_empty() {
+                return Ok(trades);
+            }
+        }
+    }
+
+    /// Converts a trade of the channel, returning `None` for other pairs.
+    fn parse_trade(&self, txid: &str, trade: &Value) -> Option<Trade> {
+        let pair: &String = self.pairs.get(trade["pair"].as_str()?)?;
+        let field = |name: &str| trade[name].as_str().unwrap_or_default().to_string();
+        Some(Trade {
+            txid: txid.to_string(),
+            ordertxid: field("ordertxid"),
+            pair: pair.clone(),
+            time: trade["time"].as_str()?.parse().ok()?,
+            side: field("type"),
+            price: field("price"),
+            fee: field("fee"),
+            vol: field("vol"),
+            cost: field("cost"),
+            ordertype: field("ordertype"),
+            margin: field("margin"),
+            misc: String::new(),
+            postxid: field("postxid"),
+            maker: trade["maker"].as_bool(),
+            base_fee: None,
+            source: None,
+        })
+    }
+}
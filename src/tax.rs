@@ -0,0 +1,465 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module classifies disposals according to the rules of a tax regime,
+based on how long the consumed lot was held, and ages the open lots to show
+when their unrealized PnL becomes long-term.
+*/
+
+use crate::fifo::{Disposal, Lot};
+use crate::precision::Precision;
+use chrono::{DateTime, Months, NaiveDate};
+
+/// The supported tax regimes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TaxRules {
+    /// Germany, § 23 EStG: Gains from private sales are tax-free if the asset
+    /// was held for more than one year.
+    De,
+    /// United States: Gains from assets held for more than one year are
+    /// long-term capital gains, all others are short-term capital gains.
+    Us,
+}
+
+impl TaxRules {
+    /// The values accepted by `--tax-rules`.
+    pub const NAMES: [&'static str; 2] = ["de", "us"];
+
+    /// Returns the tax rules for one of `NAMES`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "de" => Some(Self::De),
+            "us" => Some(Self::Us),
+            _ => None,
+        }
+    }
+
+    /// Returns the name as accepted by `--tax-rules`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::De => "de",
+            Self::Us => "us",
+        }
+    }
+
+    /// Returns the categories a disposal can be classified as.
+    pub fn categories(&self) -> &'static [&'static str] {
+        match self {
+            Self::De => &["taxable", "tax-free"],
+            Self::Us => &["short-term", "long-term"],
+        }
+    }
+
+    /// Classifies a single disposal.
+    ///
+    /// Disposals without a known acquisition are always classified as held
+    /// short, since the holding period cannot be proven.
+    pub fn classify(&self, disposal: &Disposal) -> &'static str {
+        let held_longer_than_one_year: bool = disposal
+            .acquisition_time
+            .is_some_and(|time| is_long_term(date(time), date(disposal.disposal_time)));
+        match (self, held_longer_than_one_year) {
+            (Self::De, false) => "taxable",
+            (Self::De, true) => "tax-free",
+            (Self::Us, false) => "short-term",
+            (Self::Us, true) => "long-term",
+        }
+    }
+}
+
+/// Returns the UTC date of a UNIX timestamp.
+fn date(time: f64) -> NaiveDate {
+    DateTime::from_timestamp_nanos((time * 1e9) as i64).date_naive()
+}
+
+/// Adds a number of calendar years to a date. Acquisitions on February 29th
+/// end their holding period on February 28th of the respective year.
+fn add_years(date: NaiveDate, years: u32) -> NaiveDate {
+    date.checked_add_months(Months::new(12 * years)).unwrap()
+}
+
+/// Returns whether an asset acquired at a date and sold at another one was
+/// held for more than one year.
+fn is_long_term(acquired: NaiveDate, sold: NaiveDate) -> bool {
+    sold > add_years(acquired, 1)
+}
+
+/// Classifies every disposal and returns the realized PnL per category.
+///
+/// # Arguments
+///
+/// * `rules` - The tax rules to apply.
+/// * `disposals` - The disposals to classify. Their `classification` is set
+///   accordingly.
+///
+/// # Returns
+///
+/// The realized PnL per category in the order of [`TaxRules::categories`].
+pub fn classify_disposals(rules: TaxRules, disposals: &mut [Disposal]) -> Vec<(&'static str, f64)> {
+    let mut totals: Vec<(&'static str, f64)> = rules
+        .categories()
+        .iter()
+        .map(|category| (*category, 0f64))
+        .collect();
+    for disposal in disposals {
+        let category: &'static str = rules.classify(disposal);
+        if let Some((_, total)) = totals.iter_mut().find(|(name, _)| *name == category) {
+            *total += disposal.gain;
+        }
+        disposal.classification = Some(category.to_string());
+    }
+    totals
+}
+
+/// Prints the disposals as line items in the layout of IRS Form 8949, grouped
+/// into short-term (Part I) and long-term (Part II) transactions.
+///
+/// # Arguments
+///
+/// * `disposals` - The classified disposals.
+/// * `asset` - The name of the base asset used in the description.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn print_form_8949_items(disposals: &[Disposal], asset: &str, precision: &Precision) {
+    for (part, category) in [("Part I", "short-term"), ("Part II", "long-term")] {
+        println!("Form 8949 {} ({} transactions):", part, category);
+        println!(
+            "{:<28} {:<14} {:<14} {:>16} {:>16} {:<8} {:>16} {:>16}",
+            "(a) Description",
+            "(b) Acquired",
+            "(c) Sold",
+            "(d) Proceeds",
+            "(e) Cost",
+            "(f) Code",
+            "(g) Adjustment",
+            "(h) Gain"
+        );
+        let mut total_gain: f64 = 0f64;
+        for disposal in disposals
+            .iter()
+            .filter(|disposal| disposal.classification.as_deref() == Some(category))
+        {
+            // Wash sales have the code W and, if the loss was deferred, the
+            // disallowed loss as adjustment.
+            let code: &str = if disposal.wash_sale.is_some() {
+                "W"
+            } else {
+                ""
+            };
+            let adjustment: String = disposal
+                .wash_sale
+                .as_ref()
+                .filter(|wash_sale| wash_sale.deferred)
+                .map(|wash_sale| precision.fmt_quote(wash_sale.disallowed_loss))
+                .unwrap_or_default();
+            println!(
+                "{:<28} {:<14} {:<14} {:>16} {:>16} {:<8} {:>16} {:>16}",
+                format!("{} {}", precision.fmt_base(disposal.amount), asset),
+                disposal
+                    .acquisition_time
+                    .map(|time| date(time).format("%m/%d/%Y").to_string())
+                    .unwrap_or_else(|| "VARIOUS".to_string()),
+                date(disposal.disposal_time).format("%m/%d/%Y"),
+                precision.fmt_quote(disposal.proceeds),
+                precision.fmt_quote(disposal.acquisition_cost),
+                code,
+                adjustment,
+                precision.fmt_quote(disposal.gain),
+            );
+            total_gain += disposal.gain;
+        }
+        println!(
+            "Total {} gain: {}",
+            category,
+            precision.fmt_quote(total_gain)
+        );
+    }
+}
+
+/// The open lots of an age bucket.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AgeBucket {
+    pub amount: f64,
+    pub cost: f64,
+    pub unrealized_pnl: f64,
+}
+
+impl AgeBucket {
+    fn add(&mut self, lot: &Lot, price: f64, precision: &Precision) {
+        self.amount = precision.base(self.amount + lot.amount);
+        self.cost = precision.quote(self.cost + lot.cost);
+        self.unrealized_pnl = precision.quote(self.unrealized_pnl + lot.amount * price - lot.cost);
+    }
+}
+
+/// The unrealized PnL of the open lots by their age.
+#[derive(Debug)]
+pub struct LotAging {
+    /// The date the lots are aged at.
+    pub date: NaiveDate,
+    /// The lots held for less than three months, three to twelve months and
+    /// more than twelve months.
+    pub buckets: [(&'static str, AgeBucket); 3],
+    /// The number of days considered for `becoming_long_term`.
+    pub days: u32,
+    /// The lots that are held for more than one year within the next `days`.
+    pub becoming_long_term: AgeBucket,
+}
+
+/// Buckets the unrealized PnL of the open lots by their age.
+///
+/// # Arguments
+///
+/// * `lots` - The open lots.
+/// * `price` - The price the lots are valued at.
+/// * `on` - The date the lots are aged at.
+/// * `days` - The number of days to look ahead for lots becoming long-term.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn age_open_lots(
+    lots: &[Lot],
+    price: f64,
+    on: NaiveDate,
+    days: u32,
+    precision: &Precision,
+) -> LotAging {
+    let mut aging = LotAging {
+        date: on,
+        buckets: [
+            ("< 3 months", AgeBucket::default()),
+            ("3-12 months", AgeBucket::default()),
+            ("> 12 months", AgeBucket::default()),
+        ],
+        days,
+        becoming_long_term: AgeBucket::default(),
+    };
+    let horizon: NaiveDate = on + chrono::Days::new(days as u64);
+    for lot in lots {
+        let acquired: NaiveDate = date(lot.time);
+        let bucket: usize = if is_long_term(acquired, on) {
+            2
+        } else if on > acquired.checked_add_months(Months::new(3)).unwrap() {
+            1
+        } else {
+            0
+        };
+        aging.buckets[bucket].1.add(lot, price, precision);
+        if bucket != 2 && is_long_term(acquired, horizon) {
+            aging.becoming_long_term.add(lot, price, precision);
+        }
+    }
+    aging
+}
+
+/// Prints the unrealized PnL of the open lots by their age.
+///
+/// # Arguments
+///
+/// * `aging` - The aged lots.
+/// * `rules` - The tax rules naming the long-term category, if any.
+/// * `asset` - The name of the base asset.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn print_lot_aging(
+    aging: &LotAging,
+    rules: Option<TaxRules>,
+    asset: &str,
+    precision: &Precision,
+) {
+    println!("Unrealized PnL by lot age as of {}:", aging.date);
+    println!(
+        "{:<12} {:>18} {:>16} {:>16}",
+        "Age",
+        format!("Amount ({})", asset),
+        "Cost",
+        "Unrealized PnL"
+    );
+    for (label, bucket) in &aging.buckets {
+        println!(
+            "{:<12} {:>18} {:>16} {:>16}",
+            label,
+            precision.fmt_base(bucket.amount),
+            precision.fmt_quote(bucket.cost),
+            precision.fmt_quote(bucket.unrealized_pnl)
+        );
+    }
+    let long_term: &str = rules.map_or("long-term", |rules| rules.categories()[1]);
+    println!(
+        "Becoming {} within {} days: {} {} with an unrealized PnL of {}",
+        long_term,
+        aging.days,
+        precision.fmt_base(aging.becoming_long_term.amount),
+        asset,
+        precision.fmt_quote(aging.becoming_long_term.unrealized_pnl)
+    );
+}
+
+/// Prints every open lot with its unrealized PnL and holding period, to plan
+/// which lots to sell.
+///
+/// # Arguments
+///
+/// * `lots` - The open lots, oldest first.
+/// * `price` - The price the lots are valued at.
+/// * `on` - The date the holding periods are computed at.
+/// * `rules` - The tax rules naming the long-term category, if any.
+/// * `asset` - The name of the base asset.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn print_open_lots(
+    lots: &[Lot],
+    price: f64,
+    on: NaiveDate,
+    rules: Option<TaxRules>,
+    asset: &str,
+    precision: &Precision,
+) {
+    let long_term: &str = rules.map_or("long-term", |rules| rules.categories()[1]);
+    println!("Open lots as of {}:", on);
+    println!(
+        "{:<12} {:>18} {:>14} {:>14} {:>16} {:>8}  {} from",
+        "Acquired",
+        format!("Amount ({})", asset),
+        "Unit Cost",
+        "Value",
+        "Unrealized PnL",
+        "Days",
+        long_term
+    );
+    for lot in lots {
+        let acquired: NaiveDate = date(lot.time);
+        let value: f64 = precision.quote(lot.amount * price);
+        // The first day the lot was held for more than one year.
+        let from: NaiveDate = add_years(acquired, 1) + chrono::Days::new(1);
+        println!(
+            "{:<12} {:>18} {:>14} {:>14} {:>16} {:>8}  {}",
+            acquired,
+            precision.fmt_base(lot.amount),
+            precision.fmt_quote(lot.cost / lot.amount),
+            precision.fmt_quote(value),
+            precision.fmt_quote(value - lot.cost),
+            (on - acquired).num_days(),
+            if from <= on {
+                "yes".to_string()
+            } else {
+                from.to_string()
+            }
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRECISION: Precision = Precision { base: 8, quote: 2 };
+
+    /// Returns the UNIX timestamp of noon (UTC) of a date.
+    fn time(year: i32, month: u32, day: u32) -> f64 {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+            .and_utc()
+            .timestamp() as f64
+    }
+
+    /// Returns a disposal of a lot acquired and sold at the given times.
+    fn disposal(acquired: Option<f64>, sold: f64, gain: f64) -> Disposal {
+        Disposal {
+            amount: 1f64,
+            acquisition_time: acquired,
+            acquisition_ordertxid: acquired.map(|_| "O1".to_string()),
+            acquisition_cost: 100f64,
+            disposal_time: sold,
+            disposal_ordertxid: "O2".to_string(),
+            disposal_ordertype: "limit".to_string(),
+            disposal_maker: None,
+            proceeds: 100f64 + gain,
+            gain,
+            classification: None,
+            wash_sale: None,
+        }
+    }
+
+    #[test]
+    fn held_for_more_than_one_year() {
+        let acquired: f64 = time(2023, 1, 15);
+        for (sold, de, us) in [
+            (time(2023, 7, 1), "taxable", "short-term"),
+            // The anniversary of the acquisition is still within the year.
+            (time(2024, 1, 15), "taxable", "short-term"),
+            (time(2024, 1, 16), "tax-free", "long-term"),
+        ] {
+            let disposal: Disposal = disposal(Some(acquired), sold, 10f64);
+            assert_eq!(TaxRules::De.classify(&disposal), de);
+            assert_eq!(TaxRules::Us.classify(&disposal), us);
+        }
+    }
+
+    #[test]
+    fn acquisitions_on_february_29th() {
+        // The year ends on February 28th of the following year.
+        let acquired: f64 = time(2024, 2, 29);
+        let disposal_on = |sold: f64| disposal(Some(acquired), sold, 10f64);
+        assert_eq!(
+            TaxRules::De.classify(&disposal_on(time(2025, 2, 28))),
+            "taxable"
+        );
+        assert_eq!(
+            TaxRules::De.classify(&disposal_on(time(2025, 3, 1))),
+            "tax-free"
+        );
+        assert_eq!(
+            TaxRules::Us.classify(&disposal_on(time(2025, 3, 1))),
+            "long-term"
+        );
+    }
+
+    #[test]
+    fn unknown_acquisitions_are_held_short() {
+        let disposal: Disposal = disposal(None, time(2030, 1, 1), 10f64);
+        assert_eq!(TaxRules::De.classify(&disposal), "taxable");
+        assert_eq!(TaxRules::Us.classify(&disposal), "short-term");
+    }
+
+    #[test]
+    fn sums_the_gains_per_category() {
+        let mut disposals: Vec<Disposal> = vec![
+            disposal(Some(time(2023, 1, 1)), time(2023, 6, 1), 10f64),
+            disposal(Some(time(2022, 1, 1)), time(2023, 6, 1), 20f64),
+            disposal(Some(time(2023, 2, 1)), time(2023, 6, 1), -5f64),
+        ];
+        let totals: Vec<(&str, f64)> = classify_disposals(TaxRules::De, &mut disposals);
+        assert_eq!(totals, vec![("taxable", 5f64), ("tax-free", 20f64)]);
+        assert_eq!(disposals[1].classification.as_deref(), Some("tax-free"));
+    }
+
+    #[test]
+    fn ages_the_open_lots() {
+        let lot = |time: f64| Lot {
+            amount: 1f64,
+            cost: 100f64,
+            time,
+            ordertxid: "O1".to_string(),
+            ordertype: "limit".to_string(),
+            maker: None,
+        };
+        let lots: Vec<Lot> = vec![
+            lot(time(2024, 5, 1)),
+            lot(time(2024, 1, 1)),
+            lot(time(2023, 6, 1)),
+            lot(time(2023, 1, 1)),
+        ];
+        let on: NaiveDate = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let aging: LotAging = age_open_lots(&lots, 150f64, on, 30, &PRECISION);
+        assert_eq!(aging.buckets[0].1.amount, 1f64);
+        assert_eq!(aging.buckets[1].1.amount, 2f64);
+        assert_eq!(aging.buckets[2].1.amount, 1f64);
+        assert_eq!(aging.buckets[2].1.unrealized_pnl, 50f64);
+        // The lot of 2023-06-01 is held for more than one year on 2024-06-02.
+        assert_eq!(aging.becoming_long_term.amount, 1f64);
+    }
+}
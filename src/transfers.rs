@@ -0,0 +1,201 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module models the transfers of an asset from the ledger, i.e. deposits,
+withdrawals and transfers between Kraken accounts and subaccounts, according
+to a policy (`--treat-transfers`):
+
+- `keep-basis`: Transfers to and from the own wallets and accounts carry
+  their lots along, so they neither dispose of nor acquire anything. A
+  deposit only acquires the amount exceeding what was withdrawn before, at no
+  cost, since its origin is unknown. The fees of the transfers are disposals
+  at their value.
+- `dispose`: Withdrawals dispose of the lots at their value, and deposits
+  acquire new lots at their value, e.g. for transfers to and from other
+  people.
+
+The values are taken from the daily prices of the days of the transfers.
+*/
+
+use crate::kraken::Trade;
+use crate::ledger::LedgerEntry;
+use crate::precision::Precision;
+use crate::prices::Prices;
+use log::warn;
+
+/// The treatment of deposits and withdrawals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferPolicy {
+    /// Transfers carry their lots along.
+    KeepBasis,
+    /// Withdrawals are disposals and deposits acquisitions at their value.
+    Dispose,
+}
+
+impl TransferPolicy {
+    /// The values accepted by `--treat-transfers`.
+    pub const NAMES: [&'static str; 2] = ["keep-basis", "dispose"];
+
+    /// Returns the policy for one of `NAMES`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "keep-basis" => Some(Self::KeepBasis),
+            "dispose" => Some(Self::Dispose),
+            _ => None,
+        }
+    }
+}
+
+/// Returns whether a ledger entry is a deposit, a withdrawal or a transfer
+/// between accounts.
+pub fn is_transfer(entry: &LedgerEntry) -> bool {
+    matches!(
+        entry.entry_type.as_str(),
+        "deposit" | "withdrawal" | "transfer"
+    ) && entry
+        .amount
+        .parse::<f64>()
+        .is_ok_and(|amount| amount != 0f64)
+}
+
+/// Returns a trade referring to a ledger entry.
+///
+/// # Arguments
+///
+/// * `entry` - The ledger entry, which the trade refers to by its `txid` so
+///   the balance cross-check considers the entry explained.
+/// * `pair` - The trading pair.
+/// * `side` - The side of the trade.
+/// * `amount` - The amount of the trade.
+/// * `price` - The price of the trade.
+/// * `kind` - The kind of the transfer, stated as the order type.
+/// * `precision` - The precision of the base and quote currency.
+///
+fn transfer_trade(
+    entry: &LedgerEntry,
+    pair: &str,
+    side: &str,
+    amount: f64,
+    price: f64,
+    kind: &str,
+    precision: &Precision,
+) -> Trade {
+    Trade {
+        txid: entry.refid.clone(),
+        ordertxid: entry.id.clone(),
+        pair: pair.to_string(),
+        time: entry.time,
+        side: side.to_string(),
+        price: price.to_string(),
+        fee: "0".to_string(),
+        vol: amount.to_string(),
+        cost: precision.quote(amount * price).to_string(),
+        ordertype: kind.to_string(),
+        margin: String::new(),
+        misc: String::new(),
+        postxid: String::new(),
+        maker: None,
+        base_fee: None,
+        source: None,
+    }
+}
+
+/// Converts the transfers of an asset into trades according to a policy.
+///
+/// With `keep-basis`, the transferred amounts are trades of the sides
+/// `withdrawal` and `deposit`, which leave the lots of the FIFO lot engine
+/// unchanged. The excess of a deposit over the amount withdrawn before is a
+/// trade of the side `credit` at no cost, and the fee of a transfer a sell of
+/// its own. With `dispose`, withdrawals are sells and deposits buys, which
+/// the fee is charged with.
+///
+/// # Arguments
+///
+/// * `entries` - The ledger entries of the base asset of the trading pair,
+///   sorted by time.
+/// * `pair` - The trading pair.
+/// * `policy` - The treatment of the transfers.
+/// * `prices` - The daily prices the transfers are valued at.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn transfer_trades(
+    entries: &[LedgerEntry],
+    pair: &str,
+    policy: TransferPolicy,
+    prices: &Prices,
+    precision: &Precision,
+) -> Vec<Trade> {
+    let mut trades: Vec<Trade> = Vec::new();
+    // The amount withdrawn that was not deposited again.
+    let mut withdrawn: f64 = 0f64;
+    for entry in entries.iter().filter(|entry| is_transfer(entry)) {
+        let amount: f64 = entry.amount.parse().unwrap();
+        let fee: f64 = entry.fee.parse().unwrap_or(0f64);
+        let price: f64 = match prices.price_at_time(entry.time) {
+            Some((price, _)) => price,
+            None => {
+                warn!(
+                    "No price is known for the {} {} of {}, valuing it at zero.",
+                    entry.entry_type, entry.id, pair
+                );
+                0f64
+            }
+        };
+        let kind: &str = if amount > 0f64 {
+            "deposit"
+        } else {
+            "withdrawal"
+        };
+        if policy == TransferPolicy::Dispose {
+            let side: &str = if amount > 0f64 { "buy" } else { "sell" };
+            let mut trade: Trade =
+                transfer_trade(entry, pair, side, amount.abs(), price, kind, precision);
+            trade.base_fee = Some(fee).filter(|fee| *fee > 0f64);
+            trades.push(trade);
+            continue;
+        }
+
+        if amount > 0f64 {
+            let returned: f64 = precision.base(amount.min(withdrawn));
+            withdrawn = precision.base(withdrawn - returned);
+            if returned > 0f64 {
+                trades.push(transfer_trade(
+                    entry, pair, "deposit", returned, 0f64, kind, precision,
+                ));
+            }
+            let excess: f64 = precision.base(amount - returned);
+            if excess > 0f64 {
+                warn!(
+                    "The deposit {} of {} exceeds the amount withdrawn before by {}, adding it at no cost.",
+                    entry.id,
+                    pair,
+                    precision.fmt_base(excess)
+                );
+                trades.push(transfer_trade(
+                    entry, pair, "credit", excess, 0f64, kind, precision,
+                ));
+            }
+        } else {
+            withdrawn = precision.base(withdrawn - amount);
+            trades.push(transfer_trade(
+                entry,
+                pair,
+                "withdrawal",
+                -amount,
+                0f64,
+                kind,
+                precision,
+            ));
+        }
+        // The fee is disposed of, other than the amount transferred.
+        if fee > 0f64 {
+            let mut fee_trade: Trade =
+                transfer_trade(entry, pair, "sell", fee, price, "transfer-fee", precision);
+            fee_trade.txid = format!("{}-fee", entry.refid);
+            trades.push(fee_trade);
+        }
+    }
+    trades
+}
@@ -0,0 +1,459 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module implements the `tui` subcommand, an interactive dashboard of the
+cached pairs in the terminal with panels for the summary of the PnL, the
+trades, the open lots and the realized PnL by month. Like `serve`, it reads
+the trades from the local cache and computes the FIFO PnL, and only the public
+API is used, for the precision of the pairs.
+
+The keys:
+
+- `←`/`→` or `h`/`l`: Select the previous or next pair.
+- `[`/`]`: Select the previous or next (fiscal) year, or all years.
+- `Tab`: Focus the next panel.
+- `↑`/`↓`, `PgUp`/`PgDn`, `Home`/`End`: Scroll the focused panel.
+- `q` or `Esc`: Quit.
+*/
+
+use crate::cache::Cache;
+use crate::fifo::{compute_fifo_pnl, PnL, TradePnL};
+use crate::fiscal::YearStart;
+use crate::kraken::{KrakenAPI, Trade};
+use crate::precision::{fetch_precision, try_fetch_asset_pair, AssetPair, Precision};
+use crate::report::{format_time, monthly_breakdown, MonthlyTotals};
+use log::warn;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table, TableState, Tabs};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The rows scrolled by `PgUp` and `PgDn`.
+const PAGE: u16 = 10;
+
+/// A cached trading pair.
+struct Pair {
+    symbol: String,
+    asset_pair: AssetPair,
+    precision: Precision,
+    /// The cached trades, sorted by time.
+    trades: Vec<Trade>,
+    /// The (fiscal) years of the trades.
+    years: Vec<i32>,
+}
+
+/// The scrollable panels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Panel {
+    Trades,
+    Lots,
+    Months,
+}
+
+impl Panel {
+    /// Returns the panel focused after this one.
+    fn next(self) -> Self {
+        match self {
+            Panel::Trades => Panel::Lots,
+            Panel::Lots => Panel::Months,
+            Panel::Months => Panel::Trades,
+        }
+    }
+}
+
+/// The state of the dashboard.
+struct Dashboard {
+    pairs: Vec<Pair>,
+    year_start: YearStart,
+    /// The index of the selected pair.
+    pair: usize,
+    /// The index of the selected year among the years of the pair, none for
+    /// all years.
+    year: Option<usize>,
+    focus: Panel,
+    /// The PnL of the selected pair and year.
+    pnl: PnL,
+    trades: TableState,
+    lots: TableState,
+    months: TableState,
+}
+
+impl Dashboard {
+    /// Returns the dashboard of the pairs, showing all years of the first
+    /// one.
+    fn new(pairs: Vec<Pair>, year_start: YearStart) -> Self {
+        let mut dashboard: Dashboard = Dashboard {
+            pairs,
+            year_start,
+            pair: 0,
+            year: None,
+            focus: Panel::Trades,
+            pnl: PnL::default(),
+            trades: TableState::default(),
+            lots: TableState::default(),
+            months: TableState::default(),
+        };
+        dashboard.update();
+        dashboard
+    }
+
+    /// Returns the selected year, if any.
+    fn year(&self) -> Option<u32> {
+        self.year
+            .map(|index| self.pairs[self.pair].years[index] as u32)
+    }
+
+    /// Computes the PnL of the selected pair and year and scrolls the panels
+    /// back to the top.
+    fn update(&mut self) {
+        let pair: &Pair = &self.pairs[self.pair];
+        self.pnl = compute_fifo_pnl(&pair.trades, self.year(), self.year_start, &pair.precision);
+        self.trades = TableState::default().with_selected(Some(0));
+        self.lots = TableState::default().with_selected(Some(0));
+        self.months = TableState::default().with_selected(Some(0));
+    }
+
+    /// Handles a key, returning whether to keep running.
+    fn handle(&mut self, code: KeyCode) -> bool {
+        let pairs: usize = self.pairs.len();
+        let years: usize = self.pairs[self.pair].years.len();
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Left | KeyCode::Char('h') => {
+                self.pair = (self.pair + pairs - 1) % pairs;
+                self.year = None;
+                self.update();
+            }
+            KeyCode::Right | KeyCode::Char('l') => {
+                self.pair = (self.pair + 1) % pairs;
+                self.year = None;
+                self.update();
+            }
+            // All years come before the first and after the last year.
+            KeyCode::Char('[') => {
+                self.year = match self.year {
+                    None => years.checked_sub(1),
+                    Some(0) => None,
+                    Some(index) => Some(index - 1),
+                };
+                self.update();
+            }
+            KeyCode::Char(']') => {
+                self.year = match self.year {
+                    None if years > 0 => Some(0),
+                    Some(index) if index + 1 < years => Some(index + 1),
+                    _ => None,
+                };
+                self.update();
+            }
+            KeyCode::Tab => self.focus = self.focus.next(),
+            code => {
+                let state: &mut TableState = match self.focus {
+                    Panel::Trades => &mut self.trades,
+                    Panel::Lots => &mut self.lots,
+                    Panel::Months => &mut self.months,
+                };
+                match code {
+                    KeyCode::Up | KeyCode::Char('k') => state.select_previous(),
+                    KeyCode::Down | KeyCode::Char('j') => state.select_next(),
+                    KeyCode::PageUp => state.scroll_up_by(PAGE),
+                    KeyCode::PageDown => state.scroll_down_by(PAGE),
+                    KeyCode::Home => state.select_first(),
+                    KeyCode::End => state.select_last(),
+                    _ => {}
+                }
+            }
+        }
+        true
+    }
+
+    /// Returns the block of a panel, highlighted if focused.
+    fn block(&self, panel: Panel, title: String) -> Block<'static> {
+        let block: Block = Block::bordered().title(title);
+        if panel == self.focus {
+            block.border_style(Style::new().fg(Color::Cyan))
+        } else {
+            block
+        }
+    }
+
+    /// Draws the dashboard.
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, summary, body, footer]: [Rect; 4] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(6),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [left, right]: [Rect; 2] =
+            Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)])
+                .areas(body);
+        let [lots, months]: [Rect; 2] =
+            Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(right);
+
+        let pair: &Pair = &self.pairs[self.pair];
+        let year: String = match self.year() {
+            Some(year) => self.year_start.label(year as i32),
+            None => "all years".to_string(),
+        };
+        let [tabs, selected_year]: [Rect; 2] = Layout::horizontal([
+            Constraint::Fill(1),
+            Constraint::Length(year.len() as u16 + 8),
+        ])
+        .areas(header);
+        frame.render_widget(
+            Tabs::new(self.pairs.iter().map(|pair| pair.symbol.clone()))
+                .select(self.pair)
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+            tabs,
+        );
+        frame.render_widget(
+            Paragraph::new(format!("Year: {}", year)).right_aligned(),
+            selected_year,
+        );
+
+        frame.render_widget(self.summary(pair), summary);
+        let trades: Table = self.trade_table(pair);
+        frame.render_stateful_widget(trades, left, &mut self.trades);
+        let pair: &Pair = &self.pairs[self.pair];
+        let open_lots: Table = self.lot_table(pair);
+        frame.render_stateful_widget(open_lots, lots, &mut self.lots);
+        let pair: &Pair = &self.pairs[self.pair];
+        let monthly: Table = self.month_table(pair);
+        frame.render_stateful_widget(monthly, months, &mut self.months);
+
+        frame.render_widget(
+            Paragraph::new("q quit  ←/→ pair  [/] year  Tab panel  ↑/↓ PgUp/PgDn Home/End scroll")
+                .style(Style::new().add_modifier(Modifier::DIM)),
+            footer,
+        );
+    }
+
+    /// Returns the summary of the PnL.
+    fn summary(&self, pair: &Pair) -> Paragraph<'static> {
+        let (base, quote): (String, String) =
+            (pair.asset_pair.base_code(), pair.asset_pair.quote_code());
+        let precision: &Precision = &pair.precision;
+        let pnl: &PnL = &self.pnl;
+        Paragraph::new(vec![
+            Line::from(format!(
+                "Realized PnL: {} {}    Unrealized PnL: {} {} (at the price of the last trade, {})",
+                precision.fmt_quote(pnl.realized_pnl),
+                quote,
+                precision.fmt_quote(pnl.unrealized_pnl),
+                quote,
+                pnl.price
+            )),
+            Line::from(format!(
+                "Balance: {} {}    Open lots: {}    Disposals: {}",
+                precision.fmt_base(pnl.balance),
+                base,
+                pnl.open_lots.len(),
+                pnl.disposals.len()
+            )),
+            Line::from(format!(
+                "Bought: {} {} for {} {}    Sold: {} {} for {} {}",
+                precision.fmt_base(pnl.total_buy_volume_base),
+                base,
+                precision.fmt_quote(pnl.total_buy_volume_quote),
+                quote,
+                precision.fmt_base(pnl.total_sell_volume_base),
+                base,
+                precision.fmt_quote(pnl.total_sell_volume_quote),
+                quote
+            )),
+            Line::from(format!(
+                "Cost of the sold assets: {} {}    Value of the sold assets: {} {}",
+                precision.fmt_quote(pnl.total_cost_of_sold_assets),
+                quote,
+                precision.fmt_quote(pnl.total_value_of_sold_assets),
+                quote
+            )),
+        ])
+        .block(Block::bordered().title(format!("Summary of {}", pair.symbol)))
+    }
+
+    /// Returns the table of the trades of the selected year.
+    fn trade_table(&self, pair: &Pair) -> Table<'static> {
+        let rows: Vec<Row> = pair
+            .trades
+            .iter()
+            .filter(|trade| self.year_start.contains(self.year(), trade.time))
+            .map(|trade| {
+                let realized: String = self
+                    .pnl
+                    .trade_pnl
+                    .get(&trade.txid)
+                    .map(|trade_pnl: &TradePnL| pair.precision.fmt_quote(trade_pnl.realized_pnl))
+                    .unwrap_or_default();
+                Row::new(vec![
+                    format_time(trade.time),
+                    trade.side.clone(),
+                    trade.vol.clone(),
+                    trade.price.clone(),
+                    trade.cost.clone(),
+                    trade.fee.clone(),
+                    realized,
+                ])
+            })
+            .collect();
+        let title: String = format!("Trades ({})", rows.len());
+        table(
+            rows,
+            &[
+                "Time",
+                "Side",
+                "Volume",
+                "Price",
+                "Cost",
+                "Fee",
+                "Realized PnL",
+            ],
+            &[20, 6, 12, 10, 12, 10, 12],
+        )
+        .block(self.block(Panel::Trades, title))
+    }
+
+    /// Returns the table of the open lots, valued at the price of the last
+    /// trade.
+    fn lot_table(&self, pair: &Pair) -> Table<'static> {
+        let precision: &Precision = &pair.precision;
+        let rows: Vec<Row> = self
+            .pnl
+            .open_lots
+            .iter()
+            .map(|lot| {
+                let value: f64 = precision.quote(lot.amount * self.pnl.price);
+                Row::new(vec![
+                    format_time(lot.time),
+                    precision.fmt_base(lot.amount),
+                    precision.fmt_quote(precision.quote(lot.cost / lot.amount)),
+                    precision.fmt_quote(value),
+                    precision.fmt_quote(precision.quote(value - lot.cost)),
+                ])
+            })
+            .collect();
+        let title: String = format!("Open lots ({})", rows.len());
+        table(
+            rows,
+            &["Acquired", "Amount", "Unit Cost", "Value", "Unrealized"],
+            &[20, 14, 12, 12, 12],
+        )
+        .block(self.block(Panel::Lots, title))
+    }
+
+    /// Returns the table of the realized PnL by month.
+    fn month_table(&self, pair: &Pair) -> Table<'static> {
+        let precision: &Precision = &pair.precision;
+        let months: BTreeMap<String, MonthlyTotals> =
+            monthly_breakdown(&self.pnl.disposals, precision);
+        let rows: Vec<Row> = months
+            .into_iter()
+            .map(|(month, totals)| {
+                Row::new(vec![
+                    month,
+                    totals.disposals.to_string(),
+                    precision.fmt_base(totals.amount),
+                    precision.fmt_quote(totals.proceeds),
+                    precision.fmt_quote(totals.cost),
+                    precision.fmt_quote(totals.gain),
+                ])
+            })
+            .collect();
+        table(
+            rows,
+            &[
+                "Month",
+                "Disposals",
+                "Sold",
+                "Proceeds",
+                "Cost",
+                "Realized PnL",
+            ],
+            &[8, 9, 14, 12, 12, 12],
+        )
+        .block(self.block(Panel::Months, "Realized PnL by month".to_string()))
+    }
+}
+
+/// Returns a table with a header, highlighting the selected row.
+///
+/// # Arguments
+///
+/// * `rows` - The rows of the table.
+/// * `header` - The names of the columns.
+/// * `widths` - The widths of the columns.
+///
+fn table(rows: Vec<Row<'static>>, header: &[&'static str], widths: &[u16]) -> Table<'static> {
+    Table::new(rows, widths.iter().map(|width| Constraint::Length(*width)))
+        .header(Row::new(header.to_vec()).style(Style::new().add_modifier(Modifier::BOLD)))
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+}
+
+/// Runs the dashboard until it is quit.
+fn run(terminal: &mut DefaultTerminal, dashboard: &mut Dashboard) -> Result<(), String> {
+    loop {
+        terminal
+            .draw(|frame| dashboard.draw(frame))
+            .map_err(|e| format!("Failed to draw the dashboard: {}", e))?;
+        if let Event::Key(key) =
+            event::read().map_err(|e| format!("Failed to read the input: {}", e))?
+        {
+            if key.kind == KeyEventKind::Press && !dashboard.handle(key.code) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Shows the dashboard of the cached pairs until it is quit.
+///
+/// # Arguments
+///
+/// * `api` - The client of the public Kraken API.
+/// * `cache` - The cache the trades are read from.
+/// * `year_start` - The first day of the fiscal years.
+///
+pub async fn show_dashboard(
+    api: &KrakenAPI,
+    cache: &Cache,
+    year_start: YearStart,
+) -> Result<(), String> {
+    let mut pairs: Vec<Pair> = Vec::new();
+    for symbol in cache.pairs().map_err(|e| e.to_string())? {
+        let trades: Vec<Trade> = cache
+            .load_trades(&symbol, None, None)
+            .map_err(|e| e.to_string())?;
+        let Some(asset_pair) = try_fetch_asset_pair(api, &symbol).await else {
+            warn!("Skipping the unknown trading pair {}.", symbol);
+            continue;
+        };
+        let precision: Precision = fetch_precision(api, &asset_pair).await;
+        let years: BTreeSet<i32> = trades
+            .iter()
+            .map(|trade| year_start.year_of(trade.time))
+            .collect();
+        pairs.push(Pair {
+            symbol,
+            asset_pair,
+            precision,
+            trades,
+            years: years.into_iter().collect(),
+        });
+    }
+    if pairs.is_empty() {
+        return Err("No cached trades, run `fetch` first!".to_string());
+    }
+
+    let mut dashboard: Dashboard = Dashboard::new(pairs, year_start);
+    let mut terminal: DefaultTerminal = ratatui::init();
+    let result: Result<(), String> = run(&mut terminal, &mut dashboard);
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,330 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module implements the `self-update` subcommand. Releases are built by
+cargo-dist in the release workflow, which signs every `.tar.gz` archive with
+the release key via minisign and uploads the archive along with its
+`.minisig` signature to the GitHub release. The updater looks up the latest
+release, downloads the archive matching the running platform, verifies its
+signature against the public key pinned below and replaces the current
+executable in place.
+
+A checksum published next to the archive would not prove anything, since
+whoever could replace the archive could replace the checksum too, so only the
+signature of the pinned key is accepted. The trusted comment of the signature,
+which the signature covers as well, names the archive and the tag of its
+release. It has to match the release the archive was downloaded from, so the
+validly signed archive of an older release can't be passed off as the latest
+one to downgrade the installation.
+*/
+
+use flate2::read::GzDecoder;
+use log::info;
+use minisign_verify::{PublicKey, Signature};
+use reqwest::Client;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const RELEASES_URL: &str =
+    "https://api.github.com/repos/btschwertfeger/kraken-pnl-calculator/releases/latest";
+
+/// The minisign public key the release archives are signed with, also found
+/// in `minisign.pub`.
+const RELEASE_PUBLIC_KEY: &str = "RWShG3pa0RzKG34ZmD0r1+Q3YuEjEZRCf/K4MZVRniXiEBkqQpagP+B0";
+
+#[derive(Deserialize, Debug)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// Returns the target triple of the running binary as used by cargo-dist to
+/// name the release artifacts.
+fn target_triple() -> Result<String, String> {
+    let os = match env::consts::OS {
+        "linux" => "unknown-linux-gnu",
+        "macos" => "apple-darwin",
+        "windows" => "pc-windows-msvc",
+        other => return Err(format!("Unsupported operating system: {}", other)),
+    };
+    match env::consts::ARCH {
+        arch @ ("x86_64" | "aarch64") => Ok(format!("{}-{}", arch, os)),
+        other => Err(format!("Unsupported architecture: {}", other)),
+    }
+}
+
+/// Parses a version like `v1.2.3` or `1.2.3` into its numeric components.
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.trim_start_matches('v').splitn(3, '.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    let patch = parts
+        .next()?
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()?;
+    Some((major, minor, patch))
+}
+
+/// Downloads the given URL and returns the response body.
+async fn download(client: &Client, url: &str) -> Result<Vec<u8>, String> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to download {}: {}", url, e))?;
+    if !response.status().is_success() {
+        return Err(format!("Failed to download {}: {}", url, response.status()));
+    }
+    response
+        .bytes()
+        .await
+        .map(|bytes| bytes.to_vec())
+        .map_err(|e| format!("Failed to read {}: {}", url, e))
+}
+
+/// Verifies the minisign signature of a downloaded archive against a public
+/// key, and that its trusted comment names the archive and the tag of the
+/// release it was downloaded from.
+///
+/// # Arguments
+///
+/// * `public_key` - The minisign public key, i.e. the pinned release key.
+/// * `archive` - The content of the archive.
+/// * `signature` - The content of the `.minisig` file of the archive.
+/// * `archive_name` - The name of the archive.
+/// * `tag` - The tag of the release.
+///
+fn verify_signature(
+    public_key: &str,
+    archive: &[u8],
+    signature: &[u8],
+    archive_name: &str,
+    tag: &str,
+) -> Result<(), String> {
+    let public_key: PublicKey = PublicKey::from_base64(public_key)
+        .map_err(|e| format!("Invalid release public key: {}", e))?;
+    let signature: Signature = Signature::decode(&String::from_utf8_lossy(signature))
+        .map_err(|e| format!("Invalid signature: {}", e))?;
+    public_key
+        .verify(archive, &signature, false)
+        .map_err(|e| format!("The signature does not match the release key: {}", e))?;
+    // The release workflow signs `<archive> <tag>` as trusted comment.
+    let expected: String = format!("{} {}", archive_name, tag);
+    if signature.trusted_comment() != expected {
+        return Err(format!(
+            "The signature was made for '{}', not for '{}'",
+            signature.trusted_comment(),
+            expected
+        ));
+    }
+    Ok(())
+}
+
+/// Extracts the executable named `binary_name` from a `.tar.gz` archive.
+fn extract_binary(archive: &[u8], binary_name: &str) -> Result<Vec<u8>, String> {
+    let mut tar = tar::Archive::new(GzDecoder::new(archive));
+    let entries = tar
+        .entries()
+        .map_err(|e| format!("Failed to read archive: {}", e))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| format!("Failed to read archive: {}", e))?;
+        let path = entry
+            .path()
+            .map_err(|e| format!("Failed to read archive: {}", e))?
+            .into_owned();
+        if path.file_name().and_then(|name| name.to_str()) == Some(binary_name) {
+            let mut content = Vec::new();
+            entry
+                .read_to_end(&mut content)
+                .map_err(|e| format!("Failed to extract {}: {}", binary_name, e))?;
+            return Ok(content);
+        }
+    }
+    Err(format!("The archive does not contain {}!", binary_name))
+}
+
+/// Replaces the executable at `current` with `content`.
+///
+/// The running executable is moved aside first, since Windows does not allow
+/// overwriting a binary that is currently being executed.
+fn replace_executable(current: &Path, content: &[u8]) -> Result<(), String> {
+    let new_path: PathBuf = current.with_extension("new");
+    let old_path: PathBuf = current.with_extension("old");
+
+    fs::write(&new_path, content)
+        .map_err(|e| format!("Failed to write {}: {}", new_path.display(), e))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&new_path, fs::Permissions::from_mode(0o755))
+            .map_err(|e| format!("Failed to set permissions: {}", e))?;
+    }
+    fs::rename(current, &old_path)
+        .map_err(|e| format!("Failed to move {} aside: {}", current.display(), e))?;
+    if let Err(e) = fs::rename(&new_path, current) {
+        // Restore the previous binary so the installation is never left broken.
+        let _ = fs::rename(&old_path, current);
+        return Err(format!("Failed to install the new binary: {}", e));
+    }
+    // This may fail on Windows while the old binary is still running; it is
+    // cleaned up by the next update in that case.
+    let _ = fs::remove_file(&old_path);
+    Ok(())
+}
+
+/// Checks GitHub for a newer release and installs it in place of the running
+/// executable.
+///
+/// # Arguments
+///
+/// * `check_only` - Only report whether an update is available without
+///   installing it.
+///
+pub async fn self_update(check_only: bool) -> Result<(), String> {
+    let current_version = env!("CARGO_PKG_VERSION");
+    let client = Client::builder()
+        .user_agent(format!("{}/{}", env!("CARGO_PKG_NAME"), current_version))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+
+    info!("Checking for updates...");
+    let release: Release = serde_json::from_slice(&download(&client, RELEASES_URL).await?)
+        .map_err(|e| format!("Failed to parse release information: {}", e))?;
+
+    let latest = parse_version(&release.tag_name)
+        .ok_or_else(|| format!("Invalid release version: {}", release.tag_name))?;
+    if parse_version(current_version).is_some_and(|current| current >= latest) {
+        println!("Already up to date (version {}).", current_version);
+        return Ok(());
+    }
+    println!(
+        "Version {} is available (installed: {}).",
+        release.tag_name, current_version
+    );
+    if check_only {
+        return Ok(());
+    }
+
+    let archive_name = format!("{}-{}.tar.gz", env!("CARGO_PKG_NAME"), target_triple()?);
+    let find_asset = |name: &str| {
+        release
+            .assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or_else(|| format!("The release does not provide {}!", name))
+    };
+    let archive_asset = find_asset(&archive_name)?;
+    let signature_asset = find_asset(&format!("{}.minisig", archive_name))?;
+
+    println!("Downloading {}...", archive_asset.name);
+    let archive = download(&client, &archive_asset.browser_download_url).await?;
+    let signature = download(&client, &signature_asset.browser_download_url).await?;
+    verify_signature(
+        RELEASE_PUBLIC_KEY,
+        &archive,
+        &signature,
+        &archive_name,
+        &release.tag_name,
+    )
+    .map_err(|e| format!("Refusing to install {}: {}", archive_name, e))?;
+
+    let binary_name = format!("{}{}", env!("CARGO_PKG_NAME"), env::consts::EXE_SUFFIX);
+    let binary = extract_binary(&archive, &binary_name)?;
+    let current = env::current_exe()
+        .map_err(|e| format!("Failed to locate the running executable: {}", e))?;
+    replace_executable(&current, &binary)?;
+    println!(
+        "Updated {} to version {}.",
+        current.display(),
+        release.tag_name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A test key, not the release key, and the signature of `ARCHIVE` made
+    /// with it for the archive `ARCHIVE_NAME` of the release v1.0.0.
+    const PUBLIC_KEY: &str = "RWQBAgMEBQYHCAOhB7/zzhC+HXDdGOdLwJln5NYwm6UNXx3chmQSVTG4";
+    const ARCHIVE: &[u8] = b"archive";
+    const ARCHIVE_NAME: &str = "kraken-pnl-calculator-x86_64-unknown-linux-gnu.tar.gz";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQBAgMEBQYHCMII/Xp0KsQJ9ttI3XD/1+JeNmIJklTzFNoxWRpLMQcyvZ8MPzvy+423EqjIatlbENXgcCYbFkIVAj+VG2U3Cw4=
+trusted comment: kraken-pnl-calculator-x86_64-unknown-linux-gnu.tar.gz v1.0.0
+TVeIArODIS//aQWQfp5cRUEsTfnlI3FZaqdtT0jpbk1OG+xpesLqPqNeLKeH4GXzzEvhPfExhhLJMUzdDU4CBw==
+";
+
+    #[test]
+    fn accepts_the_signature_of_the_release() {
+        let result = verify_signature(
+            PUBLIC_KEY,
+            ARCHIVE,
+            SIGNATURE.as_bytes(),
+            ARCHIVE_NAME,
+            "v1.0.0",
+        );
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_mismatched_trusted_comment() {
+        // A validly signed archive of another release, e.g. an older one
+        // served instead of the latest.
+        let result = verify_signature(
+            PUBLIC_KEY,
+            ARCHIVE,
+            SIGNATURE.as_bytes(),
+            ARCHIVE_NAME,
+            "v1.1.0",
+        );
+        assert!(result.unwrap_err().contains("was made for"));
+        // A validly signed archive of another target.
+        let result = verify_signature(
+            PUBLIC_KEY,
+            ARCHIVE,
+            SIGNATURE.as_bytes(),
+            "kraken-pnl-calculator-aarch64-apple-darwin.tar.gz",
+            "v1.0.0",
+        );
+        assert!(result.unwrap_err().contains("was made for"));
+    }
+
+    #[test]
+    fn rejects_a_modified_archive() {
+        let result = verify_signature(
+            PUBLIC_KEY,
+            b"modified",
+            SIGNATURE.as_bytes(),
+            ARCHIVE_NAME,
+            "v1.0.0",
+        );
+        assert!(result.unwrap_err().contains("does not match"));
+    }
+
+    #[test]
+    fn rejects_a_signature_of_another_key() {
+        let result = verify_signature(
+            RELEASE_PUBLIC_KEY,
+            ARCHIVE,
+            SIGNATURE.as_bytes(),
+            ARCHIVE_NAME,
+            "v1.0.0",
+        );
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,605 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module implements the `verify` subcommand, which runs every method of the
+lot engine over trade fixtures and checks the invariants of the results:
+
+- The balance is conserved: It equals the amount bought less the amount sold,
+  and the amount of the open lots.
+- The cost basis is not negative: No lot is acquired or disposed of at a
+  negative cost, and no long lot is left with one.
+- The realized PnL is consistent: It equals the sum of the gains of the
+  disposals and the value of the sold assets less their cost, and the
+  realized and unrealized PnL add up to the same total for every method, as
+  the methods only shift the PnL between both.
+
+A fixture is either a state file written by `state export`, so the own data
+can be validated, or a response of the TradesHistory endpoint, e.g. as served
+by `mock-kraken --fixtures` or recorded via `--record`. Both are migrated from
+the layout they were stored in (see the `schema` module). The precision is
+taken from the decimals of the trades. Next to a fixture, a golden file
+(`<fixture>.golden`) states the expected results of every method as text,
+which `--bless` writes. The anonymized fixtures of `fixtures/corpus` are
+checked this way in CI.
+
+`--random N` checks the invariants on N randomly generated histories,
+including sells exceeding the holdings, which only depend on `--seed`.
+*/
+
+use crate::cost_basis::{strategy, CostBasisStrategy, LotSelection, METHODS};
+use crate::fifo::{compute_pnl, PnL};
+use crate::fiscal::YearStart;
+use crate::kraken::Trade;
+use crate::precision::Precision;
+use crate::schema::{unwrap, upgrade, version_of};
+use crate::state::State;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct TradesHistoryResult {
+    trades: HashMap<String, Trade>,
+}
+
+#[derive(Deserialize)]
+struct TradesHistoryResponse {
+    result: TradesHistoryResult,
+}
+
+/// The formats of the fixtures.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Fixture {
+    State(State),
+    TradesHistory(TradesHistoryResponse),
+}
+
+/// Loads the trades of a fixture, i.e. a state file written by
+/// `state export` or a response of the TradesHistory endpoint.
+///
+/// # Arguments
+///
+/// * `path` - The path of the fixture.
+///
+/// # Returns
+///
+/// The trades by trading pair, sorted by time.
+///
+pub fn load_fixture(path: &Path) -> Result<BTreeMap<String, Vec<Trade>>, String> {
+    let stored: String = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    // Recorded responses are unwrapped from their versioned layout, state
+    // files carry their version themselves.
+    let content: String = unwrap(&stored)
+        .and_then(|content| upgrade(&content, version_of(&content)))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let fixture: Fixture = serde_json::from_str(&content).map_err(|_| {
+        format!(
+            "{} is neither a state file nor a TradesHistory response!",
+            path.display()
+        )
+    })?;
+    let trades: Vec<Trade> = match fixture {
+        Fixture::State(state) => state
+            .pairs
+            .into_values()
+            .flat_map(|pair| pair.trades)
+            .map(|record| Trade {
+                txid: record.txid,
+                ..record.trade
+            })
+            .collect(),
+        Fixture::TradesHistory(response) => response
+            .result
+            .trades
+            .into_iter()
+            .map(|(txid, trade)| Trade { txid, ..trade })
+            .collect(),
+    };
+    let mut pairs: BTreeMap<String, Vec<Trade>> = BTreeMap::new();
+    for trade in trades {
+        pairs.entry(trade.pair.clone()).or_default().push(trade);
+    }
+    for trades in pairs.values_mut() {
+        trades.sort_by(|a, b| a.time.total_cmp(&b.time).then(a.txid.cmp(&b.txid)));
+    }
+    Ok(pairs)
+}
+
+/// Returns the precision of trades, from the decimals of their amounts and
+/// values.
+fn infer_precision(trades: &[Trade]) -> Precision {
+    let decimals = |value: &String| {
+        value
+            .split_once('.')
+            .map_or(0, |(_, decimals)| decimals.len() as u32)
+    };
+    Precision {
+        base: trades
+            .iter()
+            .map(|trade| decimals(&trade.vol))
+            .max()
+            .unwrap_or(8),
+        quote: trades
+            .iter()
+            .flat_map(|trade| [decimals(&trade.cost), decimals(&trade.fee)])
+            .max()
+            .unwrap_or(2),
+    }
+}
+
+/// Returns every method of the lot engine.
+fn methods() -> Vec<Arc<dyn CostBasisStrategy>> {
+    METHODS.iter().map(|name| strategy(name).unwrap()).collect()
+}
+
+/// Runs every method over the trades of a pair and checks the invariants of
+/// the results.
+///
+/// # Arguments
+///
+/// * `trades` - The trades, sorted by time.
+/// * `precision` - The precision of the base and quote currency.
+///
+/// # Returns
+///
+/// The PnL of every method and the violations of the invariants.
+///
+pub fn check_invariants(
+    trades: &[Trade],
+    precision: &Precision,
+) -> (Vec<(String, PnL)>, Vec<String>) {
+    // The results are rounded at every step, which adds up.
+    let steps: f64 = (trades.len() + 1) as f64;
+    let base_tolerance: f64 = steps * 10f64.powi(-(precision.base as i32));
+    let quote_tolerance: f64 = steps * 10f64.powi(-(precision.quote as i32));
+
+    let traded = |side: &str| -> f64 {
+        trades
+            .iter()
+            .filter(|trade| trade.side == side)
+            .map(|trade| trade.vol.parse::<f64>().unwrap_or(0f64))
+            .sum()
+    };
+    let expected_balance: f64 = traded("buy") - traded("sell");
+
+    let mut results: Vec<(String, PnL)> = Vec::new();
+    let mut violations: Vec<String> = Vec::new();
+    for method in methods() {
+        let name: String = method.name();
+        let pnl: PnL = compute_pnl(
+            trades,
+            None,
+            YearStart::default(),
+            precision,
+            &LotSelection { strategy: method },
+            None,
+            0f64,
+        );
+        let mut violated = |invariant: &str, actual: f64, expected: f64, decimals: u32| {
+            violations.push(format!(
+                "{}: {} is {:.*}, expected {:.*}",
+                name, invariant, decimals as usize, actual, decimals as usize, expected
+            ));
+        };
+
+        if (pnl.balance - expected_balance).abs() > base_tolerance {
+            violated("the balance", pnl.balance, expected_balance, precision.base);
+        }
+        let lots: f64 = pnl.open_lots.iter().map(|lot| lot.amount).sum();
+        if (lots - pnl.balance).abs() > base_tolerance {
+            violated(
+                "the amount of the open lots",
+                lots,
+                pnl.balance,
+                precision.base,
+            );
+        }
+        if let Some(disposal) = pnl
+            .disposals
+            .iter()
+            .find(|disposal| disposal.acquisition_cost < 0f64)
+        {
+            violated(
+                &format!(
+                    "the cost of the disposal of {}",
+                    disposal.disposal_ordertxid
+                ),
+                disposal.acquisition_cost,
+                0f64,
+                precision.quote,
+            );
+        }
+        if let Some(lot) = pnl
+            .open_lots
+            .iter()
+            .find(|lot| lot.amount > 0f64 && lot.cost < 0f64)
+        {
+            violated(
+                &format!("the cost of the open lot of {}", lot.ordertxid),
+                lot.cost,
+                0f64,
+                precision.quote,
+            );
+        }
+        let gains: f64 = pnl.disposals.iter().map(|disposal| disposal.gain).sum();
+        if (pnl.realized_pnl - gains).abs() > quote_tolerance {
+            violated(
+                "the realized PnL (sum of the gains)",
+                pnl.realized_pnl,
+                gains,
+                precision.quote,
+            );
+        }
+        let sold: f64 = pnl.total_value_of_sold_assets - pnl.total_cost_of_sold_assets;
+        if (pnl.realized_pnl - sold).abs() > quote_tolerance {
+            violated(
+                "the realized PnL (value less cost of the sold assets)",
+                pnl.realized_pnl,
+                sold,
+                precision.quote,
+            );
+        }
+        if let Some((first, first_pnl)) = results.first() {
+            let total: f64 = pnl.realized_pnl + pnl.unrealized_pnl;
+            let expected: f64 = first_pnl.realized_pnl + first_pnl.unrealized_pnl;
+            if (total - expected).abs() > quote_tolerance {
+                violated(
+                    &format!("the realized plus unrealized PnL (as with {})", first),
+                    total,
+                    expected,
+                    precision.quote,
+                );
+            }
+        }
+        results.push((name, pnl));
+    }
+    (results, violations)
+}
+
+/// Returns the lines of the golden file of a pair.
+fn golden_lines(pair: &str, results: &[(String, PnL)], precision: &Precision) -> Vec<String> {
+    results
+        .iter()
+        .map(|(method, pnl)| {
+            format!(
+                "{} {}: realized {}, unrealized {}, balance {}, {} disposals, {} open lots",
+                pair,
+                method,
+                precision.fmt_quote(pnl.realized_pnl),
+                precision.fmt_quote(pnl.unrealized_pnl),
+                precision.fmt_base(pnl.balance),
+                pnl.disposals.len(),
+                pnl.open_lots.len()
+            )
+        })
+        .collect()
+}
+
+/// Verifies a fixture against the invariants and its golden file.
+///
+/// # Arguments
+///
+/// * `path` - The path of the fixture.
+/// * `bless` - Whether to write the golden file instead of comparing it.
+///
+/// # Returns
+///
+/// Whether the fixture passed.
+///
+fn verify_fixture(path: &Path, bless: bool) -> Result<bool, String> {
+    let mut passed: bool = true;
+    let mut lines: Vec<String> = Vec::new();
+    let pairs: BTreeMap<String, Vec<Trade>> = load_fixture(path)?;
+    for (pair, trades) in &pairs {
+        let precision: Precision = infer_precision(trades);
+        let (results, violations) = check_invariants(trades, &precision);
+        for violation in &violations {
+            println!("{}: {} {}", path.display(), pair, violation);
+        }
+        passed &= violations.is_empty();
+        lines.extend(golden_lines(pair, &results, &precision));
+    }
+
+    let golden_path: PathBuf = path.with_extension("golden");
+    let actual: String = lines.join("\n") + "\n";
+    if bless {
+        fs::write(&golden_path, &actual)
+            .map_err(|e| format!("Failed to write {}: {}", golden_path.display(), e))?;
+        println!("{}: wrote {}", path.display(), golden_path.display());
+    } else if let Ok(expected) = fs::read_to_string(&golden_path) {
+        if expected != actual {
+            passed = false;
+            println!(
+                "{}: differs from {}:",
+                path.display(),
+                golden_path.display()
+            );
+            let expected: Vec<&str> = expected.lines().collect();
+            for line in expected
+                .iter()
+                .filter(|line| !lines.contains(&line.to_string()))
+            {
+                println!("  - {}", line);
+            }
+            for line in lines
+                .iter()
+                .filter(|line| !expected.contains(&line.as_str()))
+            {
+                println!("  + {}", line);
+            }
+        }
+    }
+    if passed {
+        println!(
+            "{}: {} trades of {}: ok",
+            path.display(),
+            pairs.values().map(Vec::len).sum::<usize>(),
+            pairs.keys().cloned().collect::<Vec<String>>().join(", ")
+        );
+    }
+    Ok(passed)
+}
+
+/// A deterministic pseudo-random number generator, so the generated histories
+/// only depend on the seed.
+struct Random(u64);
+
+impl Random {
+    /// Returns a number in the range [0, 1).
+    fn next(&mut self) -> f64 {
+        // The constants of Knuth's MMIX linear congruential generator.
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns a number in the range [from, to).
+    fn between(&mut self, from: f64, to: f64) -> f64 {
+        from + self.next() * (to - from)
+    }
+}
+
+/// Returns a random history of trades, whose sells may exceed the holdings.
+fn random_history(random: &mut Random, index: usize) -> Vec<Trade> {
+    let mut price: f64 = random.between(1f64, 50000f64);
+    let mut time: f64 = 1704067200f64;
+    let trades: usize = 1 + (random.next() * 60f64) as usize;
+    (0..trades)
+        .map(|number| {
+            time += random.between(1f64, 86400f64 * 7f64).round();
+            price = (price * random.between(0.9, 1.1)).max(0.01);
+            // Some amounts are tiny, to leave dust.
+            let vol: f64 = match random.next() < 0.1 {
+                true => random.between(0.00000001, 0.00001),
+                false => random.between(0.001, 2f64),
+            };
+            let cost: f64 = vol * price;
+            Trade {
+                txid: format!("T{:03}-{:04}", index, number),
+                ordertxid: format!("O{:03}-{:04}", index, number),
+                pair: "XXBTZEUR".to_string(),
+                time,
+                side: if random.next() < 0.4 { "sell" } else { "buy" }.to_string(),
+                price: format!("{:.2}", price),
+                fee: format!("{:.5}", cost * random.between(0f64, 0.0026)),
+                vol: format!("{:.8}", vol),
+                cost: format!("{:.5}", cost),
+                ordertype: "limit".to_string(),
+                margin: String::new(),
+                misc: String::new(),
+                postxid: String::new(),
+                maker: None,
+                base_fee: None,
+                source: None,
+            }
+        })
+        .collect()
+}
+
+/// Verifies the fixtures and random histories, printing the results.
+///
+/// # Arguments
+///
+/// * `paths` - The paths of the fixtures.
+/// * `bless` - Whether to write the golden files instead of comparing them.
+/// * `random` - The number of random histories to check.
+/// * `seed` - The seed of the random histories.
+///
+/// # Returns
+///
+/// Whether everything passed.
+///
+pub fn verify(paths: &[PathBuf], bless: bool, random: usize, seed: u64) -> Result<bool, String> {
+    if paths.is_empty() && random == 0 {
+        return Err("Nothing to verify, pass fixtures or --random N!".to_string());
+    }
+    let mut passed: bool = true;
+    for path in paths {
+        passed &= verify_fixture(path, bless)?;
+    }
+
+    let mut generator: Random = Random(seed);
+    let mut failed: usize = 0;
+    for index in 0..random {
+        let trades: Vec<Trade> = random_history(&mut generator, index);
+        let precision: Precision = infer_precision(&trades);
+        let (_, violations) = check_invariants(&trades, &precision);
+        for violation in &violations {
+            println!("random history {} (seed {}): {}", index, seed, violation);
+        }
+        failed += usize::from(!violations.is_empty());
+    }
+    if random > 0 {
+        match failed {
+            0 => println!("{} random histories (seed {}): ok", random, seed),
+            _ => println!(
+                "{} of {} random histories (seed {}) violate the invariants",
+                failed, random, seed
+            ),
+        }
+    }
+    Ok(passed && failed == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Returns the path of a fixture of the corpus.
+    fn corpus(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/corpus")
+            .join(name)
+    }
+
+    /// Returns a spot trade of XXBTZEUR, with the amount in satoshis and the
+    /// price and fee in cents.
+    fn trade(number: usize, side: &str, sats: u64, cents: u64, fee_cents: u64) -> Trade {
+        let vol: f64 = sats as f64 / 1e8;
+        let price: f64 = cents as f64 / 100f64;
+        Trade {
+            txid: format!("T{:04}", number),
+            ordertxid: format!("O{:04}", number),
+            pair: "XXBTZEUR".to_string(),
+            time: 1704067200f64 + number as f64 * 60f64,
+            side: side.to_string(),
+            price: format!("{:.2}", price),
+            fee: format!("{:.2}", fee_cents as f64 / 100f64),
+            vol: format!("{:.8}", vol),
+            cost: format!("{:.2}", vol * price),
+            ordertype: "limit".to_string(),
+            margin: String::new(),
+            misc: String::new(),
+            postxid: String::new(),
+            maker: None,
+            base_fee: None,
+            source: None,
+        }
+    }
+
+    /// The fills of a history: whether it is a sell, the amount in satoshis,
+    /// the price in cents and the fee in cents.
+    fn fills() -> impl Strategy<Value = Vec<(bool, u64, u64, u64)>> {
+        prop::collection::vec(
+            (
+                prop::bool::weighted(0.4),
+                1u64..200_000_000,
+                100u64..10_000_000,
+                0u64..500,
+            ),
+            1..60,
+        )
+    }
+
+    #[test]
+    fn loads_a_trades_history_response() {
+        let pairs: BTreeMap<String, Vec<Trade>> = load_fixture(&corpus("spot.json")).unwrap();
+        assert_eq!(
+            pairs.keys().cloned().collect::<Vec<String>>(),
+            vec!["XETHZEUR", "XXBTZEUR"]
+        );
+        assert_eq!(pairs.values().map(Vec::len).sum::<usize>(), 65);
+        for trades in pairs.values() {
+            assert!(trades.windows(2).all(|pair| pair[0].time <= pair[1].time));
+            assert!(trades.iter().all(|trade| !trade.txid.is_empty()));
+        }
+        assert!(pairs["XXBTZEUR"]
+            .iter()
+            .any(|trade| trade.txid == "TA0000-ANON"));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path: PathBuf = std::env::temp_dir().join("kraken-pnl-verify-test.json");
+        fs::write(&path, "[1, 2, 3]").unwrap();
+        let error: String = load_fixture(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(error.contains("is neither a state file nor a TradesHistory response"));
+        assert!(load_fixture(&corpus("missing.json"))
+            .unwrap_err()
+            .starts_with("Failed to read"));
+    }
+
+    #[test]
+    fn corpus_matches_the_golden_files() {
+        for name in ["dust.json", "short.json", "spot.json"] {
+            assert_eq!(verify_fixture(&corpus(name), false), Ok(true), "{}", name);
+        }
+    }
+
+    #[test]
+    fn random_histories_keep_the_invariants() {
+        let mut random: Random = Random(42);
+        for index in 0..100 {
+            let trades: Vec<Trade> = random_history(&mut random, index);
+            let (_, violations) = check_invariants(&trades, &infer_precision(&trades));
+            assert!(violations.is_empty(), "{:?}", violations);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn histories_keep_the_invariants(fills in fills()) {
+            let trades: Vec<Trade> = fills
+                .iter()
+                .enumerate()
+                .map(|(number, (sell, sats, cents, fee))| {
+                    trade(number, if *sell { "sell" } else { "buy" }, *sats, *cents, *fee)
+                })
+                .collect();
+            let precision: Precision = Precision { base: 8, quote: 2 };
+            let (results, violations) = check_invariants(&trades, &precision);
+            prop_assert!(violations.is_empty(), "{:?}", violations);
+            prop_assert_eq!(results.len(), METHODS.len());
+        }
+
+        #[test]
+        fn split_fills_keep_the_pnl(fills in fills()) {
+            // The sells dispose of a share of the holdings, so no short lots
+            // are opened. Every fill is split into two halves.
+            let mut balance: u64 = 0u64;
+            let mut filled: Vec<Trade> = Vec::new();
+            let mut split: Vec<Trade> = Vec::new();
+            for (sell, sats, cents, fee) in fills {
+                let side: &str = if sell { "sell" } else { "buy" };
+                let sats: u64 = match sell {
+                    true => balance * (sats % 100 + 1) / 100,
+                    false => sats,
+                } / 2 * 2;
+                if sats == 0 {
+                    continue;
+                }
+                let fee: u64 = fee / 2 * 2;
+                balance = if sell { balance - sats } else { balance + sats };
+                filled.push(trade(filled.len(), side, sats, cents, fee));
+                for _ in 0..2 {
+                    split.push(trade(split.len(), side, sats / 2, cents, fee / 2));
+                }
+            }
+            let precision: Precision = Precision { base: 8, quote: 2 };
+            // The results are rounded at every step, which adds up.
+            let tolerance: f64 = (split.len() + 1) as f64 * 0.01;
+            let (filled, _) = check_invariants(&filled, &precision);
+            let (split, _) = check_invariants(&split, &precision);
+            for ((method, filled), (_, split)) in filled.iter().zip(&split) {
+                prop_assert_eq!(filled.balance, split.balance, "{}", method);
+                prop_assert!(
+                    (filled.realized_pnl - split.realized_pnl).abs() <= tolerance,
+                    "{}: realized {} vs. {}",
+                    method,
+                    filled.realized_pnl,
+                    split.realized_pnl
+                );
+            }
+        }
+    }
+}
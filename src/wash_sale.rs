@@ -0,0 +1,331 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module implements the wash-sale rule of the US tax rules. A loss is
+disallowed to the extent the sold amount is replaced by buys within 30 days
+before or after the sale. The disposals at a loss with replacements are
+flagged, and with `--wash-sale`, the disallowed loss is deferred into the
+basis of the replacement lots, so it is realized once they are sold.
+
+The replacements are matched in the order they were bought, and every amount
+bought replaces a sold amount only once. Buys before the sale only replace the
+amount of them that is still held at the time of the sale.
+*/
+
+use crate::fifo::{Disposal, Lot};
+use crate::kraken::Trade;
+use crate::precision::Precision;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// The seconds before and after a sale in which buys replace the sold amount.
+const WINDOW: f64 = 30f64 * 86400f64;
+
+/// Whether wash sales are only flagged or their losses deferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WashSaleRule {
+    Flag,
+    Defer,
+}
+
+impl WashSaleRule {
+    /// Returns the name of the rule, as stated in the reports.
+    pub fn name(&self) -> &'static str {
+        match self {
+            WashSaleRule::Flag => "flagged",
+            WashSaleRule::Defer => "deferred",
+        }
+    }
+}
+
+/// The wash sale of a disposal at a loss.
+#[derive(Serialize, Debug, Clone)]
+pub struct WashSale {
+    /// The part of the loss that is disallowed, as a positive value.
+    pub disallowed_loss: f64,
+    /// The orders of the replacement buys.
+    pub replacement_ordertxids: Vec<String>,
+    /// Whether the disallowed loss was deferred into the replacement lots,
+    /// and thus removed from the gain of the disposal.
+    pub deferred: bool,
+}
+
+/// The state of the wash-sale rule while the trades are processed.
+pub struct WashSales<'a> {
+    rule: WashSaleRule,
+    /// All buys sorted by time, which includes the buys following the sales,
+    /// so the buys within the window of a sale are found by binary search.
+    buys: Vec<&'a Trade>,
+    /// The amounts of the buys that replaced sold amounts already, by order.
+    used: HashMap<String, f64>,
+    /// The losses deferred into the buys that follow the sales, by order.
+    deferred: HashMap<String, f64>,
+}
+
+impl<'a> WashSales<'a> {
+    /// Creates the state of the wash-sale rule.
+    ///
+    /// # Arguments
+    ///
+    /// * `rule` - Whether to flag or to defer the wash sales.
+    /// * `trades` - The trades the PnL is computed for, sorted by time.
+    ///
+    pub fn new(rule: WashSaleRule, trades: &'a [Trade]) -> Self {
+        let mut buys: Vec<&Trade> = trades.iter().filter(|t| t.side == "buy").collect();
+        buys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self {
+            rule,
+            buys,
+            used: HashMap::new(),
+            deferred: HashMap::new(),
+        }
+    }
+
+    /// Checks the disposals of a sell for wash sales and flags them.
+    ///
+    /// # Arguments
+    ///
+    /// * `sell` - The sell.
+    /// * `disposals` - The disposals of the sell.
+    /// * `lots` - The lots that are open after the sell. The disallowed
+    ///   losses replaced by them are added to their cost if deferred.
+    /// * `precision` - The precision of the base and quote currency.
+    ///
+    /// # Returns
+    ///
+    /// The deferred loss, which is removed from the realized PnL of the sell.
+    ///
+    pub fn check(
+        &mut self,
+        sell: &Trade,
+        disposals: &mut [Disposal],
+        lots: &mut VecDeque<Lot>,
+        precision: &Precision,
+    ) -> f64 {
+        let mut total_deferred: f64 = 0f64;
+        for disposal in disposals.iter_mut().filter(|d| d.gain < 0f64) {
+            let mut remaining: f64 = disposal.amount;
+            let mut disallowed_loss: f64 = 0f64;
+            let mut replacement_ordertxids: Vec<String> = Vec::new();
+            let window: &[&Trade] = window(&self.buys, sell);
+            for (ordertxid, bought_before) in replacements(window, sell, disposal) {
+                if remaining <= 0f64 {
+                    break;
+                }
+                let available: f64 = match bought_before {
+                    true => lots
+                        .iter()
+                        .filter(|lot| lot.amount > 0f64 && lot.ordertxid == ordertxid)
+                        .map(|lot| lot.amount)
+                        .sum(),
+                    false => window
+                        .iter()
+                        .filter(|t| t.ordertxid == ordertxid)
+                        .map(|t| t.vol.parse::<f64>().unwrap())
+                        .sum(),
+                };
+                let used: &mut f64 = self.used.entry(ordertxid.clone()).or_default();
+                let replaced: f64 = precision.base(remaining.min(available - *used));
+                if replaced <= 0f64 {
+                    continue;
+                }
+                *used = precision.base(*used + replaced);
+                remaining = precision.base(remaining - replaced);
+                let loss: f64 = precision.quote(-disposal.gain * (replaced / disposal.amount));
+                disallowed_loss = precision.quote(disallowed_loss + loss);
+                replacement_ordertxids.push(ordertxid.clone());
+                if self.rule == WashSaleRule::Defer {
+                    if bought_before {
+                        defer_into_lots(lots, &ordertxid, loss, precision);
+                    } else {
+                        let deferred: &mut f64 = self.deferred.entry(ordertxid).or_default();
+                        *deferred = precision.quote(*deferred + loss);
+                    }
+                }
+            }
+            if disallowed_loss <= 0f64 {
+                continue;
+            }
+            let deferred: bool = self.rule == WashSaleRule::Defer;
+            if deferred {
+                disposal.gain = precision.quote(disposal.gain + disallowed_loss);
+                total_deferred = precision.quote(total_deferred + disallowed_loss);
+            }
+            disposal.wash_sale = Some(WashSale {
+                disallowed_loss,
+                replacement_ordertxids,
+                deferred,
+            });
+        }
+        total_deferred
+    }
+
+    /// Returns the loss deferred into a buy, which is added to its cost. It is
+    /// added to the first trade of the order only.
+    pub fn deferred_loss(&mut self, buy: &Trade) -> f64 {
+        self.deferred.remove(&buy.ordertxid).unwrap_or(0f64)
+    }
+}
+
+/// Returns the buys within 30 days before or after a sale.
+///
+/// # Arguments
+///
+/// * `buys` - The buys sorted by time.
+/// * `sell` - The sell.
+///
+fn window<'a, 'b>(buys: &'b [&'a Trade], sell: &Trade) -> &'b [&'a Trade] {
+    let start: usize = buys.partition_point(|t| t.time < sell.time - WINDOW);
+    let end: usize = buys.partition_point(|t| t.time <= sell.time + WINDOW);
+    &buys[start..end]
+}
+
+/// Returns the orders of the buys within the window of a sale, in the order
+/// they were bought, and whether they were bought before it.
+fn replacements(window: &[&Trade], sell: &Trade, disposal: &Disposal) -> Vec<(String, bool)> {
+    let mut seen: HashSet<&str> = HashSet::new();
+    window
+        .iter()
+        .filter(|trade| disposal.acquisition_ordertxid.as_ref() != Some(&trade.ordertxid))
+        .filter(|trade| seen.insert(&trade.ordertxid))
+        .map(|trade| (trade.ordertxid.clone(), trade.time <= sell.time))
+        .collect()
+}
+
+/// Adds a deferred loss to the cost of the open lots of an order, in
+/// proportion to their amounts.
+fn defer_into_lots(lots: &mut VecDeque<Lot>, ordertxid: &str, loss: f64, precision: &Precision) {
+    let open: f64 = lots
+        .iter()
+        .filter(|lot| lot.amount > 0f64 && lot.ordertxid == ordertxid)
+        .map(|lot| lot.amount)
+        .sum();
+    for lot in lots
+        .iter_mut()
+        .filter(|lot| lot.amount > 0f64 && lot.ordertxid == ordertxid)
+    {
+        lot.cost = precision.quote(lot.cost + loss * (lot.amount / open));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cost_basis::{strategy, LotSelection};
+    use crate::fifo::{compute_pnl, PnL};
+    use crate::fiscal::YearStart;
+
+    const PRECISION: Precision = Precision { base: 8, quote: 2 };
+
+    /// The time of the sell at a loss.
+    const SALE: f64 = 1706745600f64;
+
+    /// Returns a trade of one BTC.
+    fn trade(number: usize, side: &str, price: f64, time: f64) -> Trade {
+        Trade {
+            txid: format!("T{:04}", number),
+            ordertxid: format!("O{:04}", number),
+            pair: "XXBTZEUR".to_string(),
+            time,
+            side: side.to_string(),
+            price: format!("{:.2}", price),
+            fee: "0.00".to_string(),
+            vol: "1.00000000".to_string(),
+            cost: format!("{:.2}", price),
+            ordertype: "limit".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the PnL of the trades by FIFO under a wash-sale rule.
+    fn pnl(trades: &[Trade], rule: WashSaleRule) -> PnL {
+        compute_pnl(
+            trades,
+            None,
+            YearStart::default(),
+            &PRECISION,
+            &LotSelection {
+                strategy: strategy("fifo").unwrap(),
+            },
+            Some(rule),
+            0f64,
+        )
+    }
+
+    /// Returns the trades of a buy at 100, its sale at 80, and a buy at 90 at
+    /// a time relative to the sale.
+    fn trades_replaced_at(offset: f64) -> Vec<Trade> {
+        let mut trades: Vec<Trade> = vec![
+            trade(0, "buy", 100f64, SALE - 60f64 * 86400f64),
+            trade(1, "sell", 80f64, SALE),
+            trade(2, "buy", 90f64, SALE + offset),
+        ];
+        trades.sort_by(|a, b| a.time.total_cmp(&b.time));
+        trades
+    }
+
+    #[test]
+    fn window_includes_its_boundaries() {
+        let buys: Vec<Trade> = vec![
+            trade(0, "buy", 90f64, SALE - WINDOW - 1f64),
+            trade(1, "buy", 90f64, SALE - WINDOW),
+            trade(2, "buy", 90f64, SALE + WINDOW),
+            trade(3, "buy", 90f64, SALE + WINDOW + 1f64),
+        ];
+        let buys: Vec<&Trade> = buys.iter().collect();
+        let ordertxids: Vec<&str> = window(&buys, &trade(4, "sell", 80f64, SALE))
+            .iter()
+            .map(|buy| buy.ordertxid.as_str())
+            .collect();
+        assert_eq!(ordertxids, vec!["O0001", "O0002"]);
+    }
+
+    #[test]
+    fn flags_a_loss_replaced_within_30_days() {
+        let pnl: PnL = pnl(&trades_replaced_at(10f64 * 86400f64), WashSaleRule::Flag);
+        let wash_sale: &WashSale = pnl.disposals[0].wash_sale.as_ref().unwrap();
+        assert_eq!(wash_sale.disallowed_loss, 20f64);
+        assert_eq!(wash_sale.replacement_ordertxids, vec!["O0002"]);
+        assert!(!wash_sale.deferred);
+        assert_eq!(pnl.realized_pnl, -20f64);
+    }
+
+    #[test]
+    fn defers_the_loss_into_the_replacement() {
+        let pnl: PnL = pnl(&trades_replaced_at(10f64 * 86400f64), WashSaleRule::Defer);
+        assert!(pnl.disposals[0].wash_sale.as_ref().unwrap().deferred);
+        assert_eq!(pnl.disposals[0].gain, 0f64);
+        assert_eq!(pnl.realized_pnl, 0f64);
+        assert_eq!(pnl.open_lots[0].cost, 110f64);
+    }
+
+    #[test]
+    fn replacements_at_the_window_boundary() {
+        for (offset, replaced) in [
+            (-WINDOW - 1f64, false),
+            (-WINDOW, true),
+            (WINDOW, true),
+            (WINDOW + 1f64, false),
+        ] {
+            let pnl: PnL = pnl(&trades_replaced_at(offset), WashSaleRule::Flag);
+            let disposal: &Disposal = pnl
+                .disposals
+                .iter()
+                .find(|disposal| disposal.gain < 0f64)
+                .unwrap();
+            assert_eq!(disposal.wash_sale.is_some(), replaced, "{}", offset);
+        }
+    }
+
+    #[test]
+    fn gains_are_no_wash_sales() {
+        let mut trades: Vec<Trade> = trades_replaced_at(10f64 * 86400f64);
+        trades[1].price = "120.00".to_string();
+        trades[1].cost = "120.00".to_string();
+        let pnl: PnL = pnl(&trades, WashSaleRule::Flag);
+        assert!(pnl.disposals[0].wash_sale.is_none());
+        assert_eq!(pnl.realized_pnl, 20f64);
+    }
+}
@@ -0,0 +1,96 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module implements the watch mode, which keeps the tool running and
+recomputes the PnL on an interval. The trades and ledger entries fetched by an
+iteration are kept, so the following ones only fetch what happened since,
+with some overlap for trades that show up late in the history.
+*/
+
+use crate::kraken::Trade;
+use crate::ledger::LedgerEntry;
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::io::IsTerminal;
+use std::time::Duration;
+
+/// How far an incremental fetch reaches back before the previous one, since
+/// trades may show up in the history a moment after their execution.
+pub const OVERLAP: f64 = 300f64;
+
+/// Parses the interval of the watch mode, a number followed by `s`, `m`, `h`
+/// or `d`, where a bare number are seconds.
+///
+/// # Arguments
+///
+/// * `value` - The interval (e.g., 15m).
+///
+pub fn parse_interval(value: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "Invalid interval '{}', expected a number followed by s, m, h or d (e.g., 15m)!",
+            value
+        )
+    };
+    let value: &str = value.trim();
+    let (number, unit): (&str, u64) = match value.char_indices().last() {
+        Some((index, 's')) => (&value[..index], 1),
+        Some((index, 'm')) => (&value[..index], 60),
+        Some((index, 'h')) => (&value[..index], 3600),
+        Some((index, 'd')) => (&value[..index], 86400),
+        _ => (value, 1),
+    };
+    let number: u64 = number.trim().parse().map_err(|_| invalid())?;
+    if number == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(number * unit))
+}
+
+/// Adds newly fetched trades to those of a previous iteration, skipping the
+/// ones fetched before, and keeps them sorted by time.
+pub fn merge_trades(trades: &mut Vec<Trade>, fetched: Vec<Trade>) {
+    let known: HashSet<String> = trades.iter().map(|trade| trade.txid.clone()).collect();
+    trades.extend(
+        fetched
+            .into_iter()
+            .filter(|trade| !known.contains(&trade.txid)),
+    );
+    trades.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+}
+
+/// Adds newly fetched ledger entries to those of a previous iteration,
+/// skipping the ones fetched before, and keeps them sorted by time.
+pub fn merge_ledger_entries(entries: &mut Vec<LedgerEntry>, fetched: Vec<LedgerEntry>) {
+    let known: HashSet<String> = entries.iter().map(|entry| entry.id.clone()).collect();
+    entries.extend(
+        fetched
+            .into_iter()
+            .filter(|entry| !known.contains(&entry.id)),
+    );
+    entries.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+}
+
+/// Starts the output of an iteration. On a terminal the screen is cleared so
+/// the output is refreshed in place, otherwise, e.g. when appending to a log
+/// file, the iterations are separated by a line.
+///
+/// # Arguments
+///
+/// * `at` - The time the iteration started.
+/// * `refresh` - When the output is refreshed (e.g., every 900s).
+///
+pub fn start_iteration(at: DateTime<Utc>, refresh: &str) {
+    if std::io::stdout().is_terminal() {
+        print!("\x1b[2J\x1b[H");
+    } else {
+        println!("{}", "=".repeat(80));
+    }
+    println!(
+        "PnL as of {}, refreshed {} (press Ctrl+C to stop)",
+        at.format("%Y-%m-%d %H:%M:%S UTC"),
+        refresh
+    );
+}
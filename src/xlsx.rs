@@ -0,0 +1,330 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+This module writes the trades and disposals of a trading pair to an Excel
+workbook with the sheets Summary, Monthly, Disposals and Trades. Amounts are
+stored as numbers formatted with the precision of their currency, and times as
+date cells, so the workbook can be used without converting any text.
+
+The sheets are collected as rows of cells first and written via
+rust_xlsxwriter, which creates the XML documents and the ZIP archive.
+*/
+
+use crate::fifo::PnL;
+use crate::kraken::Trade;
+use crate::precision::{AssetPair, Precision};
+use crate::report::{monthly_breakdown, ReportMetadata};
+use rust_xlsxwriter::{Workbook, Worksheet, XlsxError};
+
+/// The number formats of the numeric cells.
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Base,
+    Quote,
+    Integer,
+}
+
+/// A cell of a sheet.
+#[derive(Debug)]
+enum Cell {
+    Text(String),
+    Number(f64, Format),
+    /// A UNIX timestamp, stored as date cell.
+    Date(f64),
+    Empty,
+}
+
+impl Cell {
+    fn text(text: &str) -> Self {
+        Cell::Text(text.to_string())
+    }
+
+    /// Parses an amount returned by Kraken as text.
+    fn amount(amount: &str, format: Format) -> Self {
+        amount
+            .parse()
+            .map(|amount| Cell::Number(amount, format))
+            .unwrap_or_else(|_| Cell::text(amount))
+    }
+}
+
+/// A sheet, whose first row is the header.
+struct Sheet {
+    name: &'static str,
+    /// The width of every column in characters.
+    widths: Vec<u32>,
+    rows: Vec<Vec<Cell>>,
+}
+
+/// Writes the workbook of a trading pair.
+///
+/// # Arguments
+///
+/// * `file_path` - The path of the XLSX file.
+/// * `metadata` - The parameters of the run.
+/// * `pnl` - The computed PnL.
+/// * `trades` - The trades the PnL was computed from.
+/// * `asset_pair` - The assets of the trading pair.
+/// * `precision` - The precision of the base and quote currency.
+///
+pub fn write_xlsx(
+    file_path: &str,
+    metadata: &ReportMetadata,
+    pnl: &PnL,
+    trades: &[Trade],
+    asset_pair: &AssetPair,
+    precision: &Precision,
+) -> Result<(), String> {
+    let headers =
+        |names: &[&str]| -> Vec<Cell> { names.iter().map(|name| Cell::text(name)).collect() };
+    let base: String = format!("({})", asset_pair.base);
+    let quote: String = format!("({})", asset_pair.quote);
+    // The disposals may be converted into the currency taxes are filed in.
+    let disposal_quote: String = metadata
+        .tax_currency
+        .as_ref()
+        .map_or_else(|| quote.clone(), |currency| format!("({})", currency));
+
+    let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+    let mut summary: Vec<Vec<Cell>> = vec![headers(&["Figure", "Value"])];
+    for (label, value, format) in [
+        ("Realized PnL", pnl.realized_pnl, Format::Quote),
+        ("Unrealized PnL", pnl.unrealized_pnl, Format::Quote),
+        ("Balance", pnl.balance, Format::Base),
+        (
+            "Total Buy Volume (Base)",
+            pnl.total_buy_volume_base,
+            Format::Base,
+        ),
+        (
+            "Total Sell Volume (Base)",
+            pnl.total_sell_volume_base,
+            Format::Base,
+        ),
+        (
+            "Total Buy Volume (Quote)",
+            pnl.total_buy_volume_quote,
+            Format::Quote,
+        ),
+        (
+            "Total Sell Volume (Quote)",
+            pnl.total_sell_volume_quote,
+            Format::Quote,
+        ),
+        (
+            "Total Cost of Sold Assets",
+            pnl.total_cost_of_sold_assets,
+            Format::Quote,
+        ),
+        (
+            "Total Value of Sold Assets",
+            pnl.total_value_of_sold_assets,
+            Format::Quote,
+        ),
+    ] {
+        summary.push(vec![Cell::text(label), Cell::Number(value, format)]);
+    }
+    summary.push(vec![Cell::Empty, Cell::Empty]);
+    for (key, value) in [
+        (
+            "Generated by",
+            format!("{} {}", metadata.tool, metadata.version),
+        ),
+        ("Generated at", metadata.generated_at.clone()),
+        ("Symbol", metadata.symbol.clone()),
+        (
+            "Base / quote",
+            format!("{} / {}", metadata.base, metadata.quote),
+        ),
+        ("Start", optional(&metadata.start)),
+        ("End", optional(&metadata.end)),
+        (
+            "Year",
+            optional(&metadata.year.map(|year| year.to_string())),
+        ),
+        ("Fiscal year start", optional(&metadata.fiscal_year_start)),
+        ("Method", metadata.method.clone()),
+        ("Tax rules", optional(&metadata.tax_rules)),
+        ("Tax currency", optional(&metadata.tax_currency)),
+        ("FX source", optional(&metadata.fx_source)),
+        ("Price band", optional(&metadata.price_band)),
+        ("Opening balance", optional(&metadata.opening_balance)),
+        ("Orders", metadata.order_filter()),
+        ("Excluded trades", metadata.excluded_txids()),
+        ("Trades", metadata.trades.to_string()),
+        ("Data digest", format!("sha256:{}", metadata.data_digest)),
+    ] {
+        summary.push(vec![Cell::text(key), Cell::Text(value)]);
+    }
+
+    let mut monthly: Vec<Vec<Cell>> = vec![headers(&[
+        "Month",
+        "Disposals",
+        &format!("Amount {}", base),
+        &format!("Proceeds {}", disposal_quote),
+        &format!("Cost {}", disposal_quote),
+        &format!("Gain {}", disposal_quote),
+    ])];
+    for (month, totals) in monthly_breakdown(&pnl.disposals, precision) {
+        monthly.push(vec![
+            Cell::Text(month),
+            Cell::Number(totals.disposals as f64, Format::Integer),
+            Cell::Number(totals.amount, Format::Base),
+            Cell::Number(totals.proceeds, Format::Quote),
+            Cell::Number(totals.cost, Format::Quote),
+            Cell::Number(totals.gain, Format::Quote),
+        ]);
+    }
+
+    let mut disposals: Vec<Vec<Cell>> = vec![headers(&[
+        "Acquired",
+        "Acquisition order",
+        "Disposed",
+        "Disposal order",
+        &format!("Amount {}", base),
+        &format!("Cost {}", disposal_quote),
+        &format!("Proceeds {}", disposal_quote),
+        &format!("Gain {}", disposal_quote),
+        "Classification",
+    ])];
+    for disposal in &pnl.disposals {
+        disposals.push(vec![
+            disposal.acquisition_time.map_or(Cell::Empty, Cell::Date),
+            disposal
+                .acquisition_ordertxid
+                .as_deref()
+                .map_or(Cell::Empty, Cell::text),
+            Cell::Date(disposal.disposal_time),
+            Cell::text(&disposal.disposal_ordertxid),
+            Cell::Number(disposal.amount, Format::Base),
+            Cell::Number(disposal.acquisition_cost, Format::Quote),
+            Cell::Number(disposal.proceeds, Format::Quote),
+            Cell::Number(disposal.gain, Format::Quote),
+            disposal
+                .classification
+                .as_deref()
+                .map_or(Cell::Empty, Cell::text),
+        ]);
+    }
+
+    let mut trade_rows: Vec<Vec<Cell>> = vec![headers(&[
+        "Time",
+        "Trade",
+        "Order",
+        "Side",
+        "Order type",
+        &format!("Price {}", quote),
+        &format!("Volume {}", base),
+        &format!("Cost {}", quote),
+        &format!("Fee {}", quote),
+    ])];
+    for trade in trades {
+        trade_rows.push(vec![
+            Cell::Date(trade.time),
+            Cell::text(&trade.txid),
+            Cell::text(&trade.ordertxid),
+            Cell::text(&trade.side),
+            Cell::text(&trade.ordertype),
+            Cell::amount(&trade.price, Format::Quote),
+            Cell::amount(&trade.vol, Format::Base),
+            Cell::amount(&trade.cost, Format::Quote),
+            Cell::amount(&trade.fee, Format::Quote),
+        ]);
+    }
+
+    let sheets: Vec<Sheet> = vec![
+        Sheet {
+            name: "Summary",
+            widths: vec![28, 70],
+            rows: summary,
+        },
+        Sheet {
+            name: "Monthly",
+            widths: vec![10, 10, 18, 16, 16, 16],
+            rows: monthly,
+        },
+        Sheet {
+            name: "Disposals",
+            widths: vec![20, 22, 20, 22, 18, 16, 16, 16, 16],
+            rows: disposals,
+        },
+        Sheet {
+            name: "Trades",
+            widths: vec![20, 22, 22, 8, 12, 16, 18, 16, 14],
+            rows: trade_rows,
+        },
+    ];
+    write_workbook(file_path, &sheets, precision)
+        .map_err(|e| format!("Failed to write {}: {}", file_path, e))
+}
+
+/// Writes the sheets to a workbook, with frozen header rows.
+///
+/// # Arguments
+///
+/// * `file_path` - The path of the XLSX file.
+/// * `sheets` - The sheets.
+/// * `precision` - The precision of the base and quote currency, which the
+///   amounts are formatted with.
+///
+fn write_workbook(
+    file_path: &str,
+    sheets: &[Sheet],
+    precision: &Precision,
+) -> Result<(), XlsxError> {
+    let decimals = |decimals: u32| match decimals {
+        0 => "0".to_string(),
+        decimals => format!("0.{}", "0".repeat(decimals as usize)),
+    };
+    let header = rust_xlsxwriter::Format::new().set_bold();
+    let date = rust_xlsxwriter::Format::new().set_num_format("yyyy-mm-dd hh:mm:ss");
+    let base = rust_xlsxwriter::Format::new().set_num_format(decimals(precision.base));
+    let quote = rust_xlsxwriter::Format::new().set_num_format(decimals(precision.quote));
+    let integer = rust_xlsxwriter::Format::new().set_num_format("0");
+
+    let mut workbook: Workbook = Workbook::new();
+    for sheet in sheets {
+        let worksheet: &mut Worksheet = workbook.add_worksheet();
+        worksheet.set_name(sheet.name)?;
+        worksheet.set_freeze_panes(1, 0)?;
+        for (column, width) in sheet.widths.iter().enumerate() {
+            worksheet.set_column_width(column as u16, *width)?;
+        }
+        for (row, cells) in sheet.rows.iter().enumerate() {
+            let row: u32 = row as u32;
+            for (column, cell) in cells.iter().enumerate() {
+                let column: u16 = column as u16;
+                match cell {
+                    Cell::Text(text) if row == 0 => {
+                        worksheet.write_string_with_format(row, column, text, &header)?;
+                    }
+                    Cell::Text(text) => {
+                        worksheet.write_string(row, column, text)?;
+                    }
+                    Cell::Number(value, format) => {
+                        let format: &rust_xlsxwriter::Format = match format {
+                            Format::Base => &base,
+                            Format::Quote => &quote,
+                            Format::Integer => &integer,
+                        };
+                        worksheet.write_number_with_format(row, column, *value, format)?;
+                    }
+                    // Excel counts the days since 1899-12-30, which is 25569
+                    // days before the UNIX epoch.
+                    Cell::Date(time) => {
+                        worksheet.write_number_with_format(
+                            row,
+                            column,
+                            time / 86400f64 + 25569f64,
+                            &date,
+                        )?;
+                    }
+                    Cell::Empty => {}
+                }
+            }
+        }
+    }
+    workbook.save(file_path)
+}
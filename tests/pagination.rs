@@ -0,0 +1,94 @@
+/* -*- coding: utf-8 -*-
+
+Copyright (C) 2025 Benjamin Thomas Schwertfeger
+GitHub: https://github.com/btschwertfeger
+
+These tests run the pagination against the mock of the Kraken API, which is
+only built with the `mock-kraken` feature:
+
+    cargo test --features mock-kraken
+*/
+
+#![cfg(feature = "mock-kraken")]
+
+use kraken_pnl_calculator::kraken::{fetch_account_trades, KrakenAPI, Trade};
+use kraken_pnl_calculator::rate_limit::RateLimiter;
+use std::collections::HashSet;
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// A running mock of the Kraken API, which is stopped when dropped.
+struct Mock {
+    process: Child,
+    url: String,
+}
+
+impl Mock {
+    /// Starts the mock on a free port and waits until it accepts connections.
+    fn start(args: &[&str]) -> Self {
+        let port: u16 = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let process: Child = Command::new(env!("CARGO_BIN_EXE_mock-kraken"))
+            .args(["--port", &port.to_string()])
+            .args(args)
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        for _ in 0..100 {
+            if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        Mock {
+            process,
+            url: format!("http://127.0.0.1:{}", port),
+        }
+    }
+}
+
+impl Drop for Mock {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Fetches the trades of all pairs from the mock.
+async fn fetch(mock: &Mock) -> Vec<Trade> {
+    let api: KrakenAPI = KrakenAPI::new(
+        "key".to_string(),
+        "c2VjcmV0".to_string(),
+        RateLimiter::for_tier(""),
+    )
+    .with_base_url(Some(mock.url.clone()));
+    fetch_account_trades(&api, None, None).await
+}
+
+#[tokio::test]
+async fn fetches_all_pages() {
+    let mock: Mock = Mock::start(&["--trades", "160"]);
+    assert_eq!(fetch(&mock).await.len(), 160);
+}
+
+#[tokio::test]
+async fn failed_page_continues_with_time_windows() {
+    let mock: Mock = Mock::start(&["--trades", "160", "--fail-at", "50"]);
+    let trades: Vec<Trade> = fetch(&mock).await;
+    let txids: HashSet<&str> = trades.iter().map(|trade| trade.txid.as_str()).collect();
+    assert_eq!(trades.len(), 160);
+    assert_eq!(txids.len(), 160);
+}
+
+#[tokio::test]
+async fn dropped_connection_continues_with_time_windows() {
+    let mock: Mock = Mock::start(&["--trades", "160", "--drop-at", "50"]);
+    let trades: Vec<Trade> = fetch(&mock).await;
+    let txids: HashSet<&str> = trades.iter().map(|trade| trade.txid.as_str()).collect();
+    assert_eq!(trades.len(), 160);
+    assert_eq!(txids.len(), 160);
+}